@@ -0,0 +1,302 @@
+//! Writer for the `BOMStore` format installer packages use to describe the
+//! contents of their payload.
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::io::Write;
+
+const HEADER_SIZE: usize = 512;
+const PATHS_PER_BLOCK: usize = 256;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BomKind {
+    File = 1,
+    Directory = 2,
+    Link = 3,
+}
+
+#[derive(Clone, Debug)]
+pub struct BomEntry {
+    /// Path relative to the install location, for example `./Foo.app/Contents`.
+    pub path: String,
+    pub kind: BomKind,
+    /// Permission bits including the file type.
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+    pub mtime: u32,
+    pub size: u32,
+    /// The `cksum` crc of the file contents or link target.
+    pub checksum: u32,
+    pub link: Option<String>,
+}
+
+impl BomEntry {
+    fn path_info(&self) -> Vec<u8> {
+        let mut buf = vec![self.kind as u8, 1];
+        buf.extend_from_slice(&3u16.to_be_bytes());
+        buf.extend_from_slice(&self.mode.to_be_bytes());
+        buf.extend_from_slice(&self.uid.to_be_bytes());
+        buf.extend_from_slice(&self.gid.to_be_bytes());
+        buf.extend_from_slice(&self.mtime.to_be_bytes());
+        buf.extend_from_slice(&self.size.to_be_bytes());
+        buf.push(1);
+        buf.extend_from_slice(&self.checksum.to_be_bytes());
+        if let Some(link) = self.link.as_ref() {
+            buf.extend_from_slice(&(link.len() as u32 + 1).to_be_bytes());
+            buf.extend_from_slice(link.as_bytes());
+            buf.push(0);
+        } else {
+            buf.extend_from_slice(&0u32.to_be_bytes());
+        }
+        buf
+    }
+}
+
+/// Block storage, block 0 is reserved as the null block.
+struct Blocks(Vec<Vec<u8>>);
+
+impl Blocks {
+    fn push(&mut self, block: Vec<u8>) -> u32 {
+        self.0.push(block);
+        self.0.len() as u32 - 1
+    }
+
+    fn tree(&mut self, child: u32, block_size: u32, path_count: u32) -> u32 {
+        let mut buf = b"tree".to_vec();
+        buf.extend_from_slice(&1u32.to_be_bytes());
+        buf.extend_from_slice(&child.to_be_bytes());
+        buf.extend_from_slice(&block_size.to_be_bytes());
+        buf.extend_from_slice(&path_count.to_be_bytes());
+        buf.push(0);
+        self.push(buf)
+    }
+
+    fn paths(&mut self, leaf: bool, indices: &[(u32, u32)], forward: u32, backward: u32) -> u32 {
+        let mut buf = vec![];
+        buf.extend_from_slice(&(leaf as u16).to_be_bytes());
+        buf.extend_from_slice(&(indices.len() as u16).to_be_bytes());
+        buf.extend_from_slice(&forward.to_be_bytes());
+        buf.extend_from_slice(&backward.to_be_bytes());
+        for (index0, index1) in indices {
+            buf.extend_from_slice(&index0.to_be_bytes());
+            buf.extend_from_slice(&index1.to_be_bytes());
+        }
+        self.push(buf)
+    }
+}
+
+/// Writes a bom listing `entries`. Parent directories must precede their
+/// children.
+pub fn write_bom<W: Write>(w: &mut W, entries: &[BomEntry]) -> Result<()> {
+    let mut blocks = Blocks(vec![vec![]]);
+
+    let mut ids = HashMap::new();
+    let mut indices = Vec::with_capacity(entries.len());
+    for (i, entry) in entries.iter().enumerate() {
+        let id = i as u32 + 1;
+        let (parent, name) = match entry.path.rsplit_once('/') {
+            Some((parent, name)) => {
+                let parent = *ids
+                    .get(parent)
+                    .with_context(|| format!("missing parent of {}", entry.path))?;
+                (parent, name)
+            }
+            None => (0, entry.path.as_str()),
+        };
+        ids.insert(entry.path.as_str(), id);
+
+        let path_info = blocks.push(entry.path_info());
+        let mut info = id.to_be_bytes().to_vec();
+        info.extend_from_slice(&path_info.to_be_bytes());
+        let info = blocks.push(info);
+        let mut file = parent.to_be_bytes().to_vec();
+        file.extend_from_slice(name.as_bytes());
+        file.push(0);
+        let file = blocks.push(file);
+        indices.push((info, file));
+    }
+
+    // leaves are linked to their siblings, so their block indices need to be
+    // known before writing them.
+    let chunks = indices.chunks(PATHS_PER_BLOCK).collect::<Vec<_>>();
+    anyhow::ensure!(
+        chunks.len() <= PATHS_PER_BLOCK,
+        "too many paths in bom: {}",
+        entries.len()
+    );
+    let first_leaf = blocks.0.len() as u32;
+    let mut leaves = vec![];
+    for (i, chunk) in chunks.iter().enumerate() {
+        let leaf = first_leaf + i as u32;
+        let forward = if i + 1 < chunks.len() { leaf + 1 } else { 0 };
+        let backward = if i > 0 { leaf - 1 } else { 0 };
+        blocks.paths(true, chunk, forward, backward);
+        leaves.push((leaf, chunk.last().unwrap().1));
+    }
+    let root = match leaves.len() {
+        0 => blocks.paths(true, &[], 0, 0),
+        1 => leaves[0].0,
+        _ => blocks.paths(false, &leaves, 0, 0),
+    };
+
+    let mut bom_info = vec![];
+    for value in [1, entries.len() as u32, 1, 0, 0, 0, 0] {
+        bom_info.extend_from_slice(&value.to_be_bytes());
+    }
+    let bom_info = blocks.push(bom_info);
+    let paths = blocks.tree(root, 4096, entries.len() as u32);
+    let hl_index = blocks.paths(true, &[], 0, 0);
+    let hl_index = blocks.tree(hl_index, 4096, 0);
+    let v_tree = blocks.paths(true, &[], 0, 0);
+    let v_tree = blocks.tree(v_tree, 128, 0);
+    let mut v_index = vec![];
+    for value in [1, v_tree, 0] {
+        v_index.extend_from_slice(&value.to_be_bytes());
+    }
+    v_index.push(0);
+    let v_index = blocks.push(v_index);
+    let size64 = blocks.paths(true, &[], 0, 0);
+    let size64 = blocks.tree(size64, 128, 0);
+
+    let vars = [
+        ("BomInfo", bom_info),
+        ("Paths", paths),
+        ("HLIndex", hl_index),
+        ("VIndex", v_index),
+        ("Size64", size64),
+    ];
+    let mut vars_data = (vars.len() as u32).to_be_bytes().to_vec();
+    for (name, index) in vars {
+        vars_data.extend_from_slice(&index.to_be_bytes());
+        vars_data.push(name.len() as u8);
+        vars_data.extend_from_slice(name.as_bytes());
+    }
+
+    let mut data = vec![];
+    let mut index = (blocks.0.len() as u32).to_be_bytes().to_vec();
+    for block in &blocks.0 {
+        let (offset, len) = if block.is_empty() {
+            (0, 0)
+        } else {
+            ((HEADER_SIZE + data.len()) as u32, block.len() as u32)
+        };
+        index.extend_from_slice(&offset.to_be_bytes());
+        index.extend_from_slice(&len.to_be_bytes());
+        data.extend_from_slice(block);
+    }
+    // free list
+    index.extend_from_slice(&2u32.to_be_bytes());
+    index.extend_from_slice(&[0; 16]);
+
+    let vars_offset = HEADER_SIZE + data.len();
+    let index_offset = vars_offset + vars_data.len();
+    let mut header = b"BOMStore".to_vec();
+    for value in [
+        1,
+        blocks.0.len() - 1,
+        index_offset,
+        index.len(),
+        vars_offset,
+        vars_data.len(),
+    ] {
+        header.extend_from_slice(&(value as u32).to_be_bytes());
+    }
+    header.resize(HEADER_SIZE, 0);
+
+    w.write_all(&header)?;
+    w.write_all(&data)?;
+    w.write_all(&vars_data)?;
+    w.write_all(&index)?;
+    Ok(())
+}
+
+/// Computes the POSIX `cksum` crc of `data`.
+pub fn cksum(data: &[u8]) -> u32 {
+    fn update(crc: u32, byte: u8) -> u32 {
+        let mut crc = crc ^ ((byte as u32) << 24);
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    }
+    let mut crc = data.iter().fold(0, |crc, byte| update(crc, *byte));
+    let mut len = data.len();
+    while len > 0 {
+        crc = update(crc, len as u8);
+        len >>= 8;
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_cksum() {
+        assert_eq!(cksum(b""), 4294967295);
+        assert_eq!(cksum(b"123456789"), 930766865);
+    }
+
+    #[test]
+    fn test_bom() -> Result<()> {
+        let mut entries = vec![BomEntry {
+            path: ".".into(),
+            kind: BomKind::Directory,
+            mode: 0o40755,
+            uid: 0,
+            gid: 80,
+            mtime: 0,
+            size: 0,
+            checksum: 0,
+            link: None,
+        }];
+        for i in 0..300 {
+            entries.push(BomEntry {
+                path: format!("./file{}", i),
+                kind: BomKind::File,
+                mode: 0o100644,
+                uid: 0,
+                gid: 0,
+                mtime: 0,
+                size: 1,
+                checksum: cksum(b"a"),
+                link: None,
+            });
+        }
+        let mut bom = vec![];
+        write_bom(&mut bom, &entries)?;
+        assert_eq!(&bom[..8], b"BOMStore");
+
+        let block = |index: u32| {
+            let index_offset = read_u32(&bom, 16) as usize;
+            let entry = index_offset + 4 + index as usize * 8;
+            let offset = read_u32(&bom, entry) as usize;
+            &bom[offset..offset + read_u32(&bom, entry + 4) as usize]
+        };
+        let vars_offset = read_u32(&bom, 24) as usize;
+        assert_eq!(read_u32(&bom, vars_offset), 5);
+        assert_eq!(&bom[vars_offset + 9..vars_offset + 16], b"BomInfo");
+        let paths = read_u32(&bom, vars_offset + 16);
+        assert_eq!(&bom[vars_offset + 21..vars_offset + 26], b"Paths");
+
+        let tree = block(paths);
+        assert_eq!(&tree[..4], b"tree");
+        assert_eq!(read_u32(tree, 16), 301);
+        let root = block(read_u32(tree, 8));
+        assert_eq!(root[..4], [0, 0, 0, 2]);
+        let leaf = block(read_u32(root, 20));
+        assert_eq!(u16::from_be_bytes([leaf[2], leaf[3]]), 45);
+        let file = block(read_u32(leaf, 12 + 44 * 8 + 4));
+        assert_eq!(file, b"\0\0\0\x01file299\0");
+        Ok(())
+    }
+}
@@ -0,0 +1,471 @@
+use crate::ds_store::{self, DsRecord, DsValue};
+use crate::AppBundle;
+use anyhow::{Context, Result};
+use apple_dmg::{BlkxChunk, BlkxTable, ChunkType, KolyTrailer, Partition, Plist};
+use fatfs::{Dir, FatType, FileSystem, FormatVolumeOptions, FsOptions, ReadWriteSeek};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use fscommon::BufStream;
+use gpt::mbr::{PartRecord, ProtectiveMBR};
+use plist::{Dictionary, Value};
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+const SECTOR_SIZE: u64 = 512;
+/// FAT32 requires at least 65525 clusters.
+const MIN_SECTORS: u64 = 0x11000;
+/// Sectors compressed into a single chunk of the disk image.
+const CHUNK_SECTORS: u64 = 2048;
+/// FAT32 stores file sizes as 32 bit integers.
+const MAX_FILE_SIZE: u64 = u32::MAX as u64;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DmgPosition {
+    pub x: u32,
+    pub y: u32,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DmgWindow {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Finder window layout of a disk image.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DmgLayout {
+    /// Background image, it is shown unscaled at 72 dpi.
+    pub background: Option<PathBuf>,
+    pub window: DmgWindow,
+    pub icon_size: u32,
+    pub app_position: DmgPosition,
+    pub applications_position: DmgPosition,
+}
+
+impl Default for DmgLayout {
+    fn default() -> Self {
+        Self {
+            background: None,
+            window: DmgWindow {
+                x: 200,
+                y: 120,
+                width: 640,
+                height: 400,
+            },
+            icon_size: 128,
+            app_position: DmgPosition { x: 160, y: 200 },
+            applications_position: DmgPosition { x: 480, y: 200 },
+        }
+    }
+}
+
+impl AppBundle {
+    /// Creates a disk image containing the app bundle and a link to
+    /// `/Applications`, laid out according to `layout`.
+    pub fn create_dmg(&self, path: &Path, layout: &DmgLayout) -> Result<()> {
+        let name = self
+            .info
+            .cf_bundle_name
+            .as_ref()
+            .context("missing bundle name")?;
+        let app_name = format!("{}.app", name);
+        let mut volume_label = [b' '; 11];
+        let end = volume_label.len().min(name.len());
+        volume_label[..end].copy_from_slice(&name.as_bytes()[..end]);
+        let volume = std::str::from_utf8(&volume_label)
+            .unwrap_or(name)
+            .trim_end()
+            .to_string();
+
+        let background = if let Some(background) = layout.background.as_ref() {
+            let ext = background
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .unwrap_or("png");
+            let data = std::fs::read(background)
+                .with_context(|| format!("failed to read {}", background.display()))?;
+            Some((format!("background.{}", ext), data))
+        } else {
+            None
+        };
+        let ds_store = ds_store::write_ds_store(self.ds_store_records(
+            &volume,
+            &app_name,
+            layout,
+            background.as_ref().map(|(file_name, _)| file_name.as_str()),
+        )?)?;
+
+        let mut size = dir_size(&self.appdir)? + ds_store.len() as u64 + SECTOR_SIZE;
+        if let Some((_, data)) = background.as_ref() {
+            size += data.len() as u64 + SECTOR_SIZE;
+        }
+        let total_sectors = dmg_sectors(size);
+
+        let total_sectors: u32 = total_sectors
+            .try_into()
+            .context("disk image exceeds the maximum FAT32 volume size")?;
+
+        // The file system is built in a sparse file next to the disk image
+        // instead of in memory, and compressed into the image sector by sector.
+        let fat32_path = path.with_extension("fat32");
+        let mut fat32 = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&fat32_path)?;
+        let result = (|| {
+            fat32.set_len(total_sectors as u64 * SECTOR_SIZE)?;
+            {
+                let volume_options = FormatVolumeOptions::new()
+                    .volume_label(volume_label)
+                    .fat_type(FatType::Fat32)
+                    .bytes_per_sector(SECTOR_SIZE as u16)
+                    .bytes_per_cluster(SECTOR_SIZE as u32)
+                    .total_sectors(total_sectors);
+                let mut disk = BufStream::new(&mut fat32);
+                fatfs::format_volume(&mut disk, volume_options)?;
+                let fs = FileSystem::new(disk, FsOptions::new())?;
+                let root = fs.root_dir();
+                add_dir(&self.appdir, &root.create_dir(&app_name)?)?;
+                root.create_file("Applications")?
+                    .write_all(&symlink("/Applications")?)?;
+                if let Some((file_name, data)) = background.as_ref() {
+                    root.create_dir(".background")?
+                        .create_file(file_name)?
+                        .write_all(data)?;
+                }
+                root.create_file(".DS_Store")?.write_all(&ds_store)?;
+            }
+            fat32.rewind()?;
+            let mut dmg = UdifWriter::new(BufWriter::new(File::create(path)?));
+            let mut mbr = ProtectiveMBR::new();
+            let mut partition = PartRecord::new_protective(Some(total_sectors));
+            partition.os_type = 11;
+            mbr.set_partition(0, partition);
+            let mbr = mbr.as_bytes()?;
+            dmg.add_partition("Master Boot Record (MBR : 0)", &mut &mbr[..])?;
+            dmg.add_partition("FAT32 (FAT32 : 1)", &mut fat32)?;
+            dmg.finish()
+        })();
+        std::fs::remove_file(&fat32_path).ok();
+        result
+    }
+
+    fn ds_store_records(
+        &self,
+        volume: &str,
+        app_name: &str,
+        layout: &DmgLayout,
+        background: Option<&str>,
+    ) -> Result<Vec<DsRecord>> {
+        let window = layout.window;
+        let mut bwsp = Dictionary::new();
+        for key in [
+            "ContainerShowSidebar",
+            "PreviewPaneVisibility",
+            "ShowPathbar",
+            "ShowSidebar",
+            "ShowStatusBar",
+            "ShowTabView",
+            "ShowToolbar",
+        ] {
+            bwsp.insert(key.into(), false.into());
+        }
+        bwsp.insert(
+            "WindowBounds".into(),
+            format!(
+                "{{{{{}, {}}}, {{{}, {}}}}}",
+                window.x, window.y, window.width, window.height
+            )
+            .into(),
+        );
+
+        let mut icvp = Dictionary::new();
+        icvp.insert("arrangeBy".into(), "none".into());
+        for key in [
+            "backgroundColorBlue",
+            "backgroundColorGreen",
+            "backgroundColorRed",
+        ] {
+            icvp.insert(key.into(), 1.0.into());
+        }
+        if let Some(background) = background {
+            let path = format!(".background/{}", background);
+            icvp.insert(
+                "backgroundImageAlias".into(),
+                Value::Data(ds_store::alias(volume, &path)),
+            );
+            icvp.insert("backgroundType".into(), 2.into());
+        } else {
+            icvp.insert("backgroundType".into(), 0.into());
+        }
+        icvp.insert("gridOffsetX".into(), 0.0.into());
+        icvp.insert("gridOffsetY".into(), 0.0.into());
+        icvp.insert("gridSpacing".into(), 100.0.into());
+        icvp.insert("iconSize".into(), (layout.icon_size as f64).into());
+        icvp.insert("labelOnBottom".into(), true.into());
+        icvp.insert("showIconPreview".into(), true.into());
+        icvp.insert("showItemInfo".into(), false.into());
+        icvp.insert("textSize".into(), 12.0.into());
+        icvp.insert("viewOptionsVersion".into(), 1.into());
+
+        let plist = |dict: Dictionary| -> Result<Vec<u8>> {
+            let mut buf = vec![];
+            Value::Dictionary(dict).to_writer_binary(&mut buf)?;
+            Ok(buf)
+        };
+        Ok(vec![
+            DsRecord::new(".", b"bwsp", DsValue::Blob(plist(bwsp)?)),
+            DsRecord::new(".", b"icvp", DsValue::Blob(plist(icvp)?)),
+            DsRecord::new(".", b"vSrn", DsValue::Long(1)),
+            DsRecord::new(".", b"vstl", DsValue::Type(*b"icnv")),
+            DsRecord::iloc(app_name, layout.app_position.x, layout.app_position.y),
+            DsRecord::iloc(
+                "Applications",
+                layout.applications_position.x,
+                layout.applications_position.y,
+            ),
+        ])
+    }
+}
+
+/// Estimates the space used by `dir`, accounting for cluster slack and
+/// directory entries.
+fn dir_size(dir: &Path) -> Result<u64> {
+    let mut size = SECTOR_SIZE;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        size += SECTOR_SIZE;
+        if file_type.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else if file_type.is_file() {
+            size += entry.metadata()?.len().div_ceil(SECTOR_SIZE) * SECTOR_SIZE;
+        } else if file_type.is_symlink() {
+            size += 1067_u64.div_ceil(SECTOR_SIZE) * SECTOR_SIZE;
+        }
+    }
+    Ok(size)
+}
+
+fn dmg_sectors(size: u64) -> u64 {
+    let clusters = size.div_ceil(SECTOR_SIZE);
+    // leave some free space for the file system
+    let clusters = clusters + clusters / 10 + 2048;
+    let fat = (clusters * 4).div_ceil(SECTOR_SIZE) * 2;
+    (clusters + fat + 32).max(MIN_SECTORS)
+}
+
+// https://wiki.samba.org/index.php/UNIX_Extensions#Storing_symlinks_on_Windows_servers
+fn symlink(target: &str) -> Result<Vec<u8>> {
+    let xsym = format!(
+        "XSym\n{:04}\n{:x}\n{}\n",
+        target.len(),
+        md5::compute(target.as_bytes()),
+        target,
+    );
+    let mut xsym = xsym.into_bytes();
+    anyhow::ensure!(xsym.len() <= 1067, "symlink target too long {}", target);
+    xsym.resize(1067, b' ');
+    Ok(xsym)
+}
+
+fn add_dir<T: ReadWriteSeek>(src: &Path, dest: &Dir<'_, T>) -> Result<()> {
+    for entry in std::fs::read_dir(src)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let file_name = file_name
+            .to_str()
+            .with_context(|| format!("invalid file name {:?}", file_name))?;
+        let source = src.join(file_name);
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            let d = dest.create_dir(file_name)?;
+            add_dir(&source, &d)?;
+        } else if file_type.is_file() {
+            let len = entry.metadata()?.len();
+            anyhow::ensure!(
+                len <= MAX_FILE_SIZE,
+                "{} is {} bytes, which exceeds the 4 GiB file size limit of FAT32 disk images",
+                source.display(),
+                len
+            );
+            let mut f = dest.create_file(file_name)?;
+            std::io::copy(&mut File::open(source)?, &mut f)?;
+        } else if file_type.is_symlink() {
+            let target = std::fs::read_link(&source)?;
+            let target = target
+                .to_str()
+                .with_context(|| format!("invalid symlink {}", source.display()))?;
+            let xsym = symlink(target)?;
+            dest.create_file(file_name)?.write_all(&xsym)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes a UDIF disk image, compressing partitions one chunk at a time.
+struct UdifWriter<W: Write + Seek> {
+    plist: Plist,
+    w: W,
+    data_hasher: crc32fast::Hasher,
+    main_hasher: crc32fast::Hasher,
+    sector_number: u64,
+    compressed_offset: u64,
+}
+
+impl<W: Write + Seek> UdifWriter<W> {
+    fn new(w: W) -> Self {
+        Self {
+            plist: Plist::default(),
+            w,
+            data_hasher: crc32fast::Hasher::new(),
+            main_hasher: crc32fast::Hasher::new(),
+            sector_number: 0,
+            compressed_offset: 0,
+        }
+    }
+
+    fn add_partition(&mut self, name: &str, r: &mut impl Read) -> Result<()> {
+        let id = self.plist.partitions().len() as u32;
+        let start_sector = self.sector_number;
+        let mut hasher = crc32fast::Hasher::new();
+        let mut chunks = vec![];
+        let mut buf = vec![];
+        loop {
+            buf.clear();
+            r.take(CHUNK_SECTORS * SECTOR_SIZE).read_to_end(&mut buf)?;
+            if buf.is_empty() {
+                break;
+            }
+            anyhow::ensure!(
+                (buf.len() as u64).is_multiple_of(SECTOR_SIZE),
+                "partition is not a multiple of the sector size"
+            );
+            hasher.update(&buf);
+            let mut encoder = ZlibEncoder::new(vec![], Compression::best());
+            encoder.write_all(&buf)?;
+            let compressed = encoder.finish()?;
+            let sector_count = buf.len() as u64 / SECTOR_SIZE;
+            self.w.write_all(&compressed)?;
+            self.data_hasher.update(&compressed);
+            chunks.push(BlkxChunk::new(
+                ChunkType::Zlib,
+                self.sector_number,
+                sector_count,
+                self.compressed_offset,
+                compressed.len() as u64,
+            ));
+            self.sector_number += sector_count;
+            self.compressed_offset += compressed.len() as u64;
+        }
+        let mut table = BlkxTable::new(id, start_sector, hasher.finalize());
+        for chunk in chunks {
+            table.add_chunk(chunk);
+        }
+        table.add_chunk(BlkxChunk::term(self.sector_number, self.compressed_offset));
+        self.main_hasher.update(&table.checksum.data[..4]);
+        self.plist
+            .add_partition(Partition::new(id as i32 - 1, name.to_string(), table));
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        let mut xml = vec![];
+        plist::to_writer_xml(&mut xml, &self.plist)?;
+        let pos = self.w.stream_position()?;
+        let koly = KolyTrailer::new(
+            pos,
+            self.sector_number,
+            pos,
+            xml.len() as _,
+            self.data_hasher.finalize(),
+            self.main_hasher.finalize(),
+        );
+        self.w.write_all(&xml)?;
+        koly.write_to(&mut self.w)?;
+        self.w.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InfoPlist;
+    use apple_dmg::{DmgReader, UdifChecksum};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_dmg() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path();
+        let exe = dir.join("test");
+        std::fs::write(&exe, vec![1; 100_000])?;
+        let background = dir.join("background.png");
+        std::fs::write(&background, b"png")?;
+
+        let mut app = AppBundle::new(
+            dir,
+            InfoPlist {
+                cf_bundle_name: Some("Test".into()),
+                ..Default::default()
+            },
+        )?;
+        app.add_executable(&exe)?;
+        app.finish(None)?;
+        let path = dir.join("Test.dmg");
+        let layout = DmgLayout {
+            background: Some(background),
+            ..Default::default()
+        };
+        app.create_dmg(&path, &layout)?;
+
+        let mut dmg = DmgReader::open(&path)?;
+        assert_eq!(dmg.data_checksum()?, u32::from(dmg.koly().data_fork_digest));
+        assert_eq!(
+            dmg.partition_table(1)?.checksum,
+            UdifChecksum::new(crc32fast::hash(&dmg.partition_data(1)?))
+        );
+        assert!(!path.with_extension("fat32").exists());
+        let fs = FileSystem::new(Cursor::new(dmg.partition_data(1)?), FsOptions::new())?;
+        let root = fs.root_dir();
+        let mut names = root
+            .iter()
+            .map(|entry| Ok(entry?.file_name()))
+            .collect::<Result<Vec<_>>>()?;
+        names.sort();
+        assert_eq!(
+            names,
+            [".DS_Store", ".background", "Applications", "Test.app"]
+        );
+        let mut link = vec![];
+        root.open_file("Applications")?.read_to_end(&mut link)?;
+        assert_eq!(link.len(), 1067);
+        assert!(link.starts_with(b"XSym\n0013\n"));
+        let mut exe = vec![];
+        root.open_file("Test.app/Contents/MacOS/test")?
+            .read_to_end(&mut exe)?;
+        assert_eq!(exe.len(), 100_000);
+        let mut png = vec![];
+        root.open_file(".background/background.png")?
+            .read_to_end(&mut png)?;
+        assert_eq!(png, b"png");
+        Ok(())
+    }
+
+    #[test]
+    fn test_dmg_sectors() {
+        assert_eq!(dmg_sectors(0), MIN_SECTORS);
+        let size = 1024 * 1024 * 1024;
+        assert!(dmg_sectors(size) * SECTOR_SIZE > size);
+    }
+}
@@ -0,0 +1,238 @@
+//! Writer for the `.DS_Store` files Finder uses to store the window layout
+//! of a directory.
+use anyhow::Result;
+
+const PAGE_SIZE: usize = 4096;
+
+pub enum DsValue {
+    Long(u32),
+    Type([u8; 4]),
+    Blob(Vec<u8>),
+}
+
+pub struct DsRecord {
+    pub name: String,
+    pub code: [u8; 4],
+    pub value: DsValue,
+}
+
+impl DsRecord {
+    pub fn new(name: &str, code: &[u8; 4], value: DsValue) -> Self {
+        Self {
+            name: name.into(),
+            code: *code,
+            value,
+        }
+    }
+
+    /// Position of an icon in the window.
+    pub fn iloc(name: &str, x: u32, y: u32) -> Self {
+        let mut blob = vec![];
+        blob.extend_from_slice(&x.to_be_bytes());
+        blob.extend_from_slice(&y.to_be_bytes());
+        blob.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0, 0]);
+        Self::new(name, b"Iloc", DsValue::Blob(blob))
+    }
+
+    fn write(&self, buf: &mut Vec<u8>) {
+        let name = self.name.encode_utf16().collect::<Vec<_>>();
+        buf.extend_from_slice(&(name.len() as u32).to_be_bytes());
+        for c in name {
+            buf.extend_from_slice(&c.to_be_bytes());
+        }
+        buf.extend_from_slice(&self.code);
+        match &self.value {
+            DsValue::Long(value) => {
+                buf.extend_from_slice(b"long");
+                buf.extend_from_slice(&value.to_be_bytes());
+            }
+            DsValue::Type(ty) => {
+                buf.extend_from_slice(b"type");
+                buf.extend_from_slice(ty);
+            }
+            DsValue::Blob(blob) => {
+                buf.extend_from_slice(b"blob");
+                buf.extend_from_slice(&(blob.len() as u32).to_be_bytes());
+                buf.extend_from_slice(blob);
+            }
+        }
+    }
+}
+
+/// Writes a store with a single b-tree leaf node containing `records`.
+///
+/// The buddy allocator is laid out as follows, offsets are relative to the
+/// end of the file magic:
+///
+/// - `0`: header
+/// - `32`: the `DSDB` block pointing to the b-tree
+/// - `2048`: the allocator info block
+/// - `4096`: the b-tree leaf node
+pub fn write_ds_store(mut records: Vec<DsRecord>) -> Result<Vec<u8>> {
+    records.sort_by_key(|record| (record.name.to_lowercase(), record.code));
+    let mut node = vec![];
+    node.extend_from_slice(&0u32.to_be_bytes());
+    node.extend_from_slice(&(records.len() as u32).to_be_bytes());
+    for record in &records {
+        record.write(&mut node);
+    }
+    anyhow::ensure!(node.len() <= PAGE_SIZE, ".DS_Store records too large");
+
+    let mut dsdb = vec![];
+    // root node, levels, records, nodes, page size
+    for value in [2, 0, records.len() as u32, 1, PAGE_SIZE as u32] {
+        dsdb.extend_from_slice(&value.to_be_bytes());
+    }
+
+    // block addresses encode the offset and log2 of the size.
+    let addresses = [2048 | 11, 32 | 5, 4096 | 12];
+    let mut info = vec![];
+    info.extend_from_slice(&(addresses.len() as u32).to_be_bytes());
+    info.extend_from_slice(&0u32.to_be_bytes());
+    for i in 0..256 {
+        let address = addresses.get(i).copied().unwrap_or_default();
+        info.extend_from_slice(&(address as u32).to_be_bytes());
+    }
+    info.extend_from_slice(&1u32.to_be_bytes());
+    info.push(4);
+    info.extend_from_slice(b"DSDB");
+    info.extend_from_slice(&1u32.to_be_bytes());
+    // empty free lists
+    info.extend_from_slice(&[0; 32 * 4]);
+
+    let mut store = vec![0; 4 + 2 * PAGE_SIZE];
+    store[..4].copy_from_slice(&1u32.to_be_bytes());
+    store[4..8].copy_from_slice(b"Bud1");
+    store[8..12].copy_from_slice(&2048u32.to_be_bytes());
+    store[12..16].copy_from_slice(&2048u32.to_be_bytes());
+    store[16..20].copy_from_slice(&2048u32.to_be_bytes());
+    store[20..24].copy_from_slice(&0x100cu32.to_be_bytes());
+    store[4 + 32..4 + 32 + dsdb.len()].copy_from_slice(&dsdb);
+    store[4 + 2048..4 + 2048 + info.len()].copy_from_slice(&info);
+    store[4 + PAGE_SIZE..4 + PAGE_SIZE + node.len()].copy_from_slice(&node);
+    Ok(store)
+}
+
+/// Creates a version 2 alias record referring to `path` on the volume
+/// `volume`, which Finder resolves using the volume name and path.
+pub fn alias(volume: &str, path: &str) -> Vec<u8> {
+    fn pascal(buf: &mut Vec<u8>, s: &str, len: usize) {
+        let bytes = &s.as_bytes()[..s.len().min(len)];
+        buf.push(bytes.len() as u8);
+        buf.extend_from_slice(bytes);
+        buf.resize(buf.len() + len - bytes.len(), 0);
+    }
+    fn utf16(s: &str) -> Vec<u8> {
+        let chars = s.encode_utf16().collect::<Vec<_>>();
+        let mut buf = (chars.len() as u16).to_be_bytes().to_vec();
+        for c in chars {
+            buf.extend_from_slice(&c.to_be_bytes());
+        }
+        buf
+    }
+    let components = path.split('/').collect::<Vec<_>>();
+    let file_name = components[components.len() - 1];
+    let parent = if components.len() > 1 {
+        components[components.len() - 2]
+    } else {
+        ""
+    };
+
+    let mut buf = vec![0; 4];
+    // record size
+    buf.extend_from_slice(&0u16.to_be_bytes());
+    buf.extend_from_slice(&2u16.to_be_bytes());
+    // kind file
+    buf.extend_from_slice(&0u16.to_be_bytes());
+    pascal(&mut buf, volume, 27);
+    // volume creation date and file system
+    buf.extend_from_slice(&[0; 6]);
+    // ejectable disk
+    buf.extend_from_slice(&5u16.to_be_bytes());
+    // parent cnid
+    buf.extend_from_slice(&0u32.to_be_bytes());
+    pascal(&mut buf, file_name, 63);
+    // cnid, creation date, creator and type code
+    buf.extend_from_slice(&[0; 16]);
+    // levels from and to
+    buf.extend_from_slice(&(-1i16).to_be_bytes());
+    buf.extend_from_slice(&(-1i16).to_be_bytes());
+    // volume attributes, file system id and reserved
+    buf.extend_from_slice(&[0; 16]);
+
+    let carbon_path = format!("{}:{}", volume, components.join(":"));
+    let tags: [(i16, Vec<u8>); 6] = [
+        (0, parent.as_bytes().to_vec()),
+        (2, carbon_path.into_bytes()),
+        (14, utf16(file_name)),
+        (15, utf16(volume)),
+        (18, format!("/{}", path).into_bytes()),
+        (19, format!("/Volumes/{}", volume).into_bytes()),
+    ];
+    for (tag, data) in tags {
+        buf.extend_from_slice(&tag.to_be_bytes());
+        buf.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        buf.extend_from_slice(&data);
+        if data.len() % 2 == 1 {
+            buf.push(0);
+        }
+    }
+    buf.extend_from_slice(&(-1i16).to_be_bytes());
+    buf.extend_from_slice(&0u16.to_be_bytes());
+
+    let len = buf.len() as u16;
+    buf[4..6].copy_from_slice(&len.to_be_bytes());
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_ds_store() -> Result<()> {
+        let store = write_ds_store(vec![
+            DsRecord::iloc("Test.app", 160, 200),
+            DsRecord::new(".", b"vSrn", DsValue::Long(1)),
+            DsRecord::iloc("Applications", 480, 200),
+        ])?;
+        assert_eq!(&store[4..8], b"Bud1");
+        let info = 4 + read_u32(&store, 8) as usize;
+        assert_eq!(read_u32(&store, info), 3);
+        let dsdb_address = read_u32(&store, info + 8 + 4);
+        let dsdb = 4 + (dsdb_address & !0x1f) as usize;
+        assert_eq!(read_u32(&store, dsdb + 8), 3);
+        let node_address = read_u32(&store, info + 8 + 4 * read_u32(&store, dsdb) as usize);
+        let node = 4 + (node_address & !0x1f) as usize;
+        assert_eq!(read_u32(&store, node), 0);
+        assert_eq!(read_u32(&store, node + 4), 3);
+        // records are sorted case insensitively
+        assert_eq!(read_u32(&store, node + 8), 1);
+        assert_eq!(&store[node + 12..node + 14], b"\0.");
+        assert_eq!(&store[node + 14..node + 22], b"vSrnlong");
+        assert_eq!(&store[node + 30..node + 32], b"\0A");
+        Ok(())
+    }
+
+    #[test]
+    fn test_alias() {
+        let alias = alias("Test", ".background/background.png");
+        assert_eq!(
+            alias.len(),
+            u16::from_be_bytes([alias[4], alias[5]]) as usize
+        );
+        assert_eq!(&alias[6..8], &[0, 2]);
+        assert_eq!(alias[10], 4);
+        assert_eq!(&alias[11..15], b"Test");
+        assert_eq!(alias[50], 14);
+        assert_eq!(&alias[51..65], b"background.png");
+        assert_eq!(&alias[150..152], &[0, 0]);
+        assert_eq!(&alias[152..154], &[0, 11]);
+        assert_eq!(&alias[154..165], b".background");
+        assert_eq!(&alias[alias.len() - 4..], &[0xff, 0xff, 0, 0]);
+    }
+}
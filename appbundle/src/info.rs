@@ -1,7 +1,9 @@
+use anyhow::{Context, Result};
+use plist::{Dictionary, Value};
 use serde::{Deserialize, Serialize};
 
 // NOTE: keep fields alphabetically ordered.
-#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct InfoPlist {
     /// The default language and region for the bundle, as a
@@ -12,6 +14,9 @@ pub struct InfoPlist {
     /// on the iOS Home screen.
     #[serde(rename(serialize = "CFBundleDisplayName"))]
     pub cf_bundle_display_name: Option<String>,
+    /// The document types supported by the bundle.
+    #[serde(rename(serialize = "CFBundleDocumentTypes"))]
+    pub cf_bundle_document_types: Option<Vec<CfBundleDocumentType>>,
     /// The entry point of the bundle.
     #[serde(rename(serialize = "CFBundleExecutable"))]
     pub cf_bundle_executable: Option<String>,
@@ -49,6 +54,9 @@ pub struct InfoPlist {
     #[serde(rename(serialize = "CFBundleSupportedPlatforms"))]
     #[serde(default)]
     pub cf_bundle_supported_platforms: Option<Vec<String>>,
+    /// The URL schemes the bundle handles.
+    #[serde(rename(serialize = "CFBundleURLTypes"))]
+    pub cf_bundle_url_types: Option<Vec<CfBundleUrlType>>,
     /// The version of the build that identifies an iteration of the
     /// bundle.
     #[serde(rename(serialize = "CFBundleVersion"))]
@@ -71,9 +79,17 @@ pub struct InfoPlist {
     #[serde(rename(serialize = "DTXcodeBuild"))]
     pub dt_xcode_build: Option<String>,
 
+    /// A boolean value indicating whether the app uses encryption
+    /// that is not exempt from export compliance documentation.
+    #[serde(rename(serialize = "ITSAppUsesNonExemptEncryption"))]
+    pub its_app_uses_non_exempt_encryption: Option<bool>,
+
     /// The category that best describes your app for the App Store.
     #[serde(rename(serialize = "LSApplicationCategoryType"))]
     pub ls_application_category_type: Option<String>,
+    /// The URL schemes the app can query with `canOpenURL:`.
+    #[serde(rename(serialize = "LSApplicationQueriesSchemes"))]
+    pub ls_application_queries_schemes: Option<Vec<String>>,
     /// The minimum version of the operating system required for
     /// the app to run in macOS.
     #[serde(rename(serialize = "LSMinimumSystemVersion"))]
@@ -81,34 +97,122 @@ pub struct InfoPlist {
     /// A boolean value indicating whether the app must run in iOS.
     #[serde(rename(serialize = "LSRequiresIPhoneOS"))]
     pub ls_requires_ios: Option<bool>,
+    /// A boolean value indicating whether the app may open documents
+    /// in place instead of a copy.
+    #[serde(rename(serialize = "LSSupportsOpeningDocumentsInPlace"))]
+    pub ls_supports_opening_documents_in_place: Option<bool>,
 
     /// The minimum version of the operating system required for
     /// the app to run in iOS, iPadOS, tvOS, and watchOS.
     #[serde(rename(serialize = "MinimumOSVersion"))]
     pub minimum_os_version: Option<String>,
 
+    /// A message that tells the user why the app needs access to
+    /// Bluetooth.
+    #[serde(rename(serialize = "NSBluetoothAlwaysUsageDescription"))]
+    pub ns_bluetooth_always_usage_description: Option<String>,
+    /// A message that tells the user why the app is requesting
+    /// access to the user's calendar data.
+    #[serde(rename(serialize = "NSCalendarsUsageDescription"))]
+    pub ns_calendars_usage_description: Option<String>,
     /// A message that tells the user why the app is requesting
     /// access to the device's camera.
     #[serde(rename(serialize = "NSCameraUsageDescription"))]
     pub ns_camera_usage_description: Option<String>,
+    /// A message that tells the user why the app is requesting
+    /// access to the user's contacts.
+    #[serde(rename(serialize = "NSContactsUsageDescription"))]
+    pub ns_contacts_usage_description: Option<String>,
+    /// A message that tells the user why the app is requesting
+    /// the ability to authenticate with Face ID.
+    #[serde(rename(serialize = "NSFaceIDUsageDescription"))]
+    pub ns_face_id_usage_description: Option<String>,
     /// A human-readable copyright notice for the bundle.
     #[serde(rename(serialize = "NSHumanReadableCopyright"))]
     pub ns_human_readable_copyright: Option<String>,
+    /// A message that tells the user why the app is requesting
+    /// access to the local network.
+    #[serde(rename(serialize = "NSLocalNetworkUsageDescription"))]
+    pub ns_local_network_usage_description: Option<String>,
+    /// A message that tells the user why the app is requesting
+    /// access to the user's location at all times.
+    #[serde(rename(serialize = "NSLocationAlwaysAndWhenInUseUsageDescription"))]
+    pub ns_location_always_and_when_in_use_usage_description: Option<String>,
+    /// A message that tells the user why the app is requesting
+    /// access to the user's location while in use.
+    #[serde(rename(serialize = "NSLocationWhenInUseUsageDescription"))]
+    pub ns_location_when_in_use_usage_description: Option<String>,
+    /// A message that tells the user why the app is requesting
+    /// access to the device's microphone.
+    #[serde(rename(serialize = "NSMicrophoneUsageDescription"))]
+    pub ns_microphone_usage_description: Option<String>,
+    /// A message that tells the user why the app is requesting
+    /// access to the device's motion data.
+    #[serde(rename(serialize = "NSMotionUsageDescription"))]
+    pub ns_motion_usage_description: Option<String>,
+    /// A message that tells the user why the app is requesting
+    /// add-only access to the user's photo library.
+    #[serde(rename(serialize = "NSPhotoLibraryAddUsageDescription"))]
+    pub ns_photo_library_add_usage_description: Option<String>,
+    /// A message that tells the user why the app is requesting
+    /// access to the user's photo library.
+    #[serde(rename(serialize = "NSPhotoLibraryUsageDescription"))]
+    pub ns_photo_library_usage_description: Option<String>,
+    /// A message that tells the user why the app is requesting
+    /// to send user data to Apple's speech recognition servers.
+    #[serde(rename(serialize = "NSSpeechRecognitionUsageDescription"))]
+    pub ns_speech_recognition_usage_description: Option<String>,
+    /// A message that tells the user why the app is requesting
+    /// permission to track them across apps and websites.
+    #[serde(rename(serialize = "NSUserTrackingUsageDescription"))]
+    pub ns_user_tracking_usage_description: Option<String>,
 
+    /// The services the app provides that require it to continue
+    /// running in the background.
+    #[serde(rename(serialize = "UIBackgroundModes"))]
+    pub ui_background_modes: Option<Vec<String>>,
     #[serde(rename(serialize = "UIDeviceFamily"))]
     pub ui_device_family: Option<Vec<u64>>,
+    /// A boolean value indicating whether the app shares files
+    /// through the Files app and iTunes.
+    #[serde(rename(serialize = "UIFileSharingEnabled"))]
+    pub ui_file_sharing_enabled: Option<bool>,
     #[serde(rename(serialize = "UILaunchScreen"))]
     pub ui_launch_screen: Option<UiLaunchScreen>,
     #[serde(rename(serialize = "UILaunchStoryboardName"))]
     pub ui_launch_storyboard_name: Option<String>,
     #[serde(rename(serialize = "UIRequiredDeviceCapabilities"))]
     pub ui_required_device_capabilities: Option<Vec<String>>,
+    /// A boolean value indicating whether the app opts out of
+    /// iPad multitasking.
+    #[serde(rename(serialize = "UIRequiresFullScreen"))]
+    pub ui_requires_full_screen: Option<bool>,
     #[serde(rename(serialize = "UISupportedInterfaceOrientations~ipad"))]
     #[serde(default)]
     pub ui_supported_interface_orientations_ipad: Vec<String>,
     #[serde(rename(serialize = "UISupportedInterfaceOrientations~iphone"))]
     #[serde(default)]
     pub ui_supported_interface_orientations_iphone: Vec<String>,
+
+    /// Additional keys that are written to the `Info.plist` as is.
+    /// Values in this map override the typed keys above.
+    #[serde(default, skip_serializing)]
+    pub extra: Dictionary,
+}
+
+impl InfoPlist {
+    /// Returns the plist dictionary including the [`InfoPlist::extra`] keys.
+    pub fn to_dictionary(&self) -> Result<Dictionary> {
+        let mut buf = vec![];
+        plist::to_writer_xml(&mut buf, self)?;
+        let mut dict = Value::from_reader_xml(&*buf)?
+            .into_dictionary()
+            .context("invalid Info.plist")?;
+        for (key, value) in &self.extra {
+            dict.insert(key.clone(), value.clone());
+        }
+        Ok(dict)
+    }
 }
 
 #[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
@@ -128,6 +232,48 @@ pub struct UiLaunchScreen {
     pub ui_toolbar: Option<bool>,
 }
 
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CfBundleUrlType {
+    /// The app's role with respect to the URL.
+    #[serde(rename(serialize = "CFBundleTypeRole"))]
+    pub cf_bundle_type_role: Option<String>,
+    /// The icon of the URL type.
+    #[serde(rename(serialize = "CFBundleURLIconFile"))]
+    pub cf_bundle_url_icon_file: Option<String>,
+    /// The abstract name of the URL type.
+    #[serde(rename(serialize = "CFBundleURLName"))]
+    pub cf_bundle_url_name: Option<String>,
+    /// The URL schemes, such as `myapp`, handled by the app.
+    #[serde(rename(serialize = "CFBundleURLSchemes"))]
+    #[serde(default)]
+    pub cf_bundle_url_schemes: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CfBundleDocumentType {
+    /// The file extensions of the document type (macOS).
+    #[serde(rename(serialize = "CFBundleTypeExtensions"))]
+    pub cf_bundle_type_extensions: Option<Vec<String>>,
+    /// The icon files of the document type.
+    #[serde(rename(serialize = "CFBundleTypeIconFiles"))]
+    pub cf_bundle_type_icon_files: Option<Vec<String>>,
+    /// The abstract name of the document type.
+    #[serde(rename(serialize = "CFBundleTypeName"))]
+    pub cf_bundle_type_name: Option<String>,
+    /// The app's role with respect to the document type.
+    #[serde(rename(serialize = "CFBundleTypeRole"))]
+    pub cf_bundle_type_role: Option<String>,
+    /// The ranking of the app among the handlers of the type.
+    #[serde(rename(serialize = "LSHandlerRank"))]
+    pub ls_handler_rank: Option<String>,
+    /// The uniform type identifiers of the document type.
+    #[serde(rename(serialize = "LSItemContentTypes"))]
+    #[serde(default)]
+    pub ls_item_content_types: Vec<String>,
+}
+
 #[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct CfBundleIcons {
@@ -141,3 +287,48 @@ pub struct CfBundlePrimaryIcon {
     #[serde(rename(serialize = "CFBundleIconName"))]
     pub cf_bundle_icon_name: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extra_keys() -> Result<()> {
+        let info: InfoPlist = serde_yaml::from_str(
+            r#"
+cf_bundle_name: "example"
+ns_microphone_usage_description: "Voice chat"
+cf_bundle_url_types:
+  - cf_bundle_url_name: "com.example.example"
+    cf_bundle_url_schemes: ["myapp"]
+extra:
+  CFBundleName: "overridden"
+  GCSupportsControllerUserInteraction: true
+  UIApplicationSceneManifest:
+    UIApplicationSupportsMultipleScenes: false
+"#,
+        )?;
+        let dict = info.to_dictionary()?;
+        assert_eq!(
+            dict.get("NSMicrophoneUsageDescription"),
+            Some(&Value::String("Voice chat".into()))
+        );
+        assert_eq!(
+            dict.get("CFBundleName"),
+            Some(&Value::String("overridden".into()))
+        );
+        assert_eq!(
+            dict.get("GCSupportsControllerUserInteraction"),
+            Some(&Value::Boolean(true))
+        );
+        let url_types = dict.get("CFBundleURLTypes").unwrap().as_array().unwrap();
+        let url_type = url_types[0].as_dictionary().unwrap();
+        assert_eq!(
+            url_type.get("CFBundleURLSchemes"),
+            Some(&Value::Array(vec![Value::String("myapp".into())]))
+        );
+        assert!(dict.get("extra").is_none());
+        assert!(dict.get("NSCameraUsageDescription").is_none());
+        Ok(())
+    }
+}
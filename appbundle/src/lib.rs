@@ -16,10 +16,21 @@ use std::time::{Duration, Instant};
 use x509_certificate::{CapturedX509Certificate, InMemorySigningKeyPair};
 use xcommon::{Scaler, ScalerOpts, Signer};
 
+mod bom;
+mod dmg;
+mod ds_store;
 mod info;
+mod pkg;
+mod sdk;
+mod upload;
 
-pub use info::InfoPlist;
+pub use dmg::{DmgLayout, DmgPosition, DmgWindow};
+pub use info::{CfBundleDocumentType, CfBundleUrlType, InfoPlist};
+pub use sdk::SdkInfo;
+pub use upload::{upload, BuildUploadState, StateDetail, Uploader};
 
+/// Timestamp authority used when the signer doesn't configure one.
+const TIME_STAMP_URL: &str = "http://timestamp.apple.com/ts01";
 const MACOS_ICON_SIZES: [u32; 6] = [16, 32, 64, 128, 256, 512];
 const IOS_ICON_SIZES: [u32; 7] = [58, 76, 80, 120, 152, 167, 1024];
 
@@ -206,7 +217,7 @@ impl AppBundle {
 
     pub fn finish(&self, signer: Option<Signer>) -> Result<()> {
         let path = self.content_dir().join("Info.plist");
-        plist::to_file_xml(path, &self.info)?;
+        Value::Dictionary(self.info.to_dictionary()?).to_file_xml(path)?;
 
         if let Some(signer) = signer {
             println!("signing {}", self.appdir().display());
@@ -225,7 +236,8 @@ impl AppBundle {
                 .set_team_id_from_signing_certificate()
                 .context("signing certificate is missing team id")?;
             if self.development {
-                signing_settings.set_time_stamp_url("http://timestamp.apple.com/ts01")?;
+                signing_settings
+                    .set_time_stamp_url(signer.timestamp_url().unwrap_or(TIME_STAMP_URL))?;
             }
             if let Some(entitlements) = self.entitlements.as_ref() {
                 let mut buf = vec![];
@@ -255,7 +267,7 @@ impl AppBundle {
         signing_settings
             .set_team_id_from_signing_certificate()
             .context("signing certificate is missing team id")?;
-        signing_settings.set_time_stamp_url("http://timestamp.apple.com/ts01")?;
+        signing_settings.set_time_stamp_url(signer.timestamp_url().unwrap_or(TIME_STAMP_URL))?;
         signing_settings.set_binary_identifier(
             SettingsScope::Main,
             self.info.cf_bundle_identifier.as_ref().unwrap(),
@@ -0,0 +1,585 @@
+//! Flat installer packages as produced by `productbuild`.
+//!
+//! A product archive is a xar archive containing a `Distribution` file and a
+//! component package directory with a `Bom`, a gzipped cpio `Payload` and a
+//! `PackageInfo` file.
+use crate::bom::{self, BomEntry, BomKind};
+use crate::{AppBundle, TIME_STAMP_URL};
+use anyhow::{Context, Result};
+use apple_codesign::AppleCertificate;
+use apple_xar::reader::XarReader;
+use apple_xar::signing::XarSigner;
+use cpio_archive::odc::{OdcBuilder, OdcHeader};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use pkcs8::EncodePrivateKey;
+use sha1::{Digest, Sha1};
+use std::fmt::Write as _;
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use x509_certificate::{CapturedX509Certificate, InMemorySigningKeyPair};
+use xcommon::Signer;
+
+const INSTALL_LOCATION: &str = "/Applications";
+/// `/Applications` is owned by `root:admin`.
+const ADMIN_GID: u32 = 80;
+
+struct PayloadEntry {
+    path: String,
+    source: Option<PathBuf>,
+    bom: BomEntry,
+}
+
+impl AppBundle {
+    /// Creates an installer package installing the app bundle to
+    /// `/Applications`, signed with an installer certificate.
+    pub fn create_pkg(&self, path: &Path, signer: Option<&Signer>) -> Result<()> {
+        let time_stamp_url = signer
+            .and_then(|signer| signer.timestamp_url())
+            .unwrap_or(TIME_STAMP_URL);
+        self.write_pkg(path, signer, Some(time_stamp_url))
+    }
+
+    fn write_pkg(
+        &self,
+        path: &Path,
+        signer: Option<&Signer>,
+        time_stamp_url: Option<&str>,
+    ) -> Result<()> {
+        let name = self
+            .info
+            .cf_bundle_name
+            .as_ref()
+            .context("missing bundle name")?;
+        let identifier = self
+            .info
+            .cf_bundle_identifier
+            .as_ref()
+            .context("missing bundle identifier")?;
+        let short_version = self
+            .info
+            .cf_bundle_short_version_string
+            .as_deref()
+            .unwrap_or("1.0");
+        let version = self
+            .info
+            .cf_bundle_version
+            .as_deref()
+            .unwrap_or(short_version);
+
+        let entries = payload_entries(&self.appdir)?;
+        let mut payload = OdcBuilder::new(GzEncoder::new(vec![], Compression::default()));
+        let mut install_bytes = 0;
+        for entry in &entries {
+            let data = match (&entry.source, &entry.bom.link) {
+                (_, Some(link)) => link.as_bytes().to_vec(),
+                (Some(source), None) if entry.bom.kind == BomKind::File => std::fs::read(source)?,
+                _ => vec![],
+            };
+            install_bytes += data.len() as u64;
+            let header = payload.next_header();
+            let header = OdcHeader {
+                mode: entry.bom.mode as u32,
+                uid: entry.bom.uid,
+                gid: entry.bom.gid,
+                nlink: 1,
+                mtime: entry.bom.mtime,
+                file_size: data.len() as u64,
+                name: entry.path.clone(),
+                ..header
+            };
+            payload.append_header_with_data(header, data)?;
+        }
+        let payload = payload.into_inner()?.finish()?;
+        let install_kbytes = install_bytes.div_ceil(1024);
+
+        let mut bom = vec![];
+        let bom_entries = entries
+            .into_iter()
+            .map(|entry| entry.bom)
+            .collect::<Vec<_>>();
+        bom::write_bom(&mut bom, &bom_entries)?;
+
+        let executable = self
+            .info
+            .cf_bundle_executable
+            .as_ref()
+            .map(|exe| self.executable_dir().join(exe));
+        let archs = if let Some(executable) = executable.filter(|exe| exe.exists()) {
+            macho_archs(&std::fs::read(executable)?)
+        } else {
+            vec![]
+        };
+
+        let mut package_info = String::new();
+        writeln!(package_info, r#"<?xml version="1.0" encoding="utf-8"?>"#)?;
+        writeln!(
+            package_info,
+            r#"<pkg-info format-version="2" identifier="{}" version="{}" install-location="{}" auth="root" overwrite-permissions="true">"#,
+            escape(identifier),
+            escape(version),
+            INSTALL_LOCATION,
+        )?;
+        writeln!(
+            package_info,
+            r#"    <payload numberOfFiles="{}" installKBytes="{}"/>"#,
+            bom_entries.len(),
+            install_kbytes,
+        )?;
+        writeln!(package_info, "    <bundle-version>")?;
+        writeln!(
+            package_info,
+            r#"        <bundle id="{0}" CFBundleIdentifier="{0}" path="./{1}.app" CFBundleVersion="{2}" CFBundleShortVersionString="{3}"/>"#,
+            escape(identifier),
+            escape(name),
+            escape(version),
+            escape(short_version),
+        )?;
+        writeln!(package_info, "    </bundle-version>")?;
+        writeln!(package_info, "</pkg-info>")?;
+
+        let mut distribution = String::new();
+        writeln!(distribution, r#"<?xml version="1.0" encoding="utf-8"?>"#)?;
+        writeln!(distribution, r#"<installer-gui-script minSpecVersion="2">"#)?;
+        writeln!(distribution, "    <title>{}</title>", escape(name))?;
+        if archs.is_empty() {
+            writeln!(distribution, r#"    <options customize="never"/>"#)?;
+        } else {
+            writeln!(
+                distribution,
+                r#"    <options customize="never" hostArchitectures="{}"/>"#,
+                archs.join(",")
+            )?;
+        }
+        if let Some(min) = self.info.ls_minimum_system_version.as_ref() {
+            writeln!(distribution, "    <volume-check>")?;
+            writeln!(distribution, "        <allowed-os-versions>")?;
+            writeln!(
+                distribution,
+                r#"            <os-version min="{}"/>"#,
+                escape(min)
+            )?;
+            writeln!(distribution, "        </allowed-os-versions>")?;
+            writeln!(distribution, "    </volume-check>")?;
+        }
+        writeln!(distribution, "    <choices-outline>")?;
+        writeln!(distribution, r#"        <line choice="default">"#)?;
+        writeln!(
+            distribution,
+            r#"            <line choice="{}"/>"#,
+            escape(identifier)
+        )?;
+        writeln!(distribution, "        </line>")?;
+        writeln!(distribution, "    </choices-outline>")?;
+        writeln!(distribution, r#"    <choice id="default"/>"#)?;
+        writeln!(
+            distribution,
+            r#"    <choice id="{0}" visible="false"><pkg-ref id="{0}"/></choice>"#,
+            escape(identifier)
+        )?;
+        writeln!(
+            distribution,
+            r#"    <pkg-ref id="{}" version="{}" onConclusion="none" installKBytes="{}">#{}.pkg</pkg-ref>"#,
+            escape(identifier),
+            escape(version),
+            install_kbytes,
+            escape(name),
+        )?;
+        writeln!(
+            distribution,
+            r#"    <product id="{}" version="{}"/>"#,
+            escape(identifier),
+            escape(short_version),
+        )?;
+        writeln!(distribution, "</installer-gui-script>")?;
+
+        let xar = write_xar(&[
+            XarEntry::File {
+                name: "Distribution".into(),
+                data: distribution.into_bytes(),
+                compress: true,
+            },
+            XarEntry::Directory {
+                name: format!("{}.pkg", name),
+                entries: vec![
+                    XarEntry::File {
+                        name: "Bom".into(),
+                        data: bom,
+                        compress: true,
+                    },
+                    XarEntry::File {
+                        name: "Payload".into(),
+                        data: payload,
+                        compress: false,
+                    },
+                    XarEntry::File {
+                        name: "PackageInfo".into(),
+                        data: package_info.into_bytes(),
+                        compress: true,
+                    },
+                ],
+            },
+        ])?;
+
+        if let Some(signer) = signer {
+            println!("signing {}", path.display());
+            let cert =
+                CapturedX509Certificate::from_der(rasn::der::encode(signer.cert()).unwrap())?;
+            let secret = signer.key().to_pkcs8_der().unwrap();
+            let key = InMemorySigningKeyPair::from_pkcs8_der(secret.as_bytes())?;
+            let chain = cert
+                .apple_root_certificate_chain()
+                .map(|chain| chain.into_iter().skip(1).collect::<Vec<_>>())
+                .unwrap_or_default();
+            let time_stamp_url = time_stamp_url.map(url::Url::parse).transpose()?;
+            let mut signed = vec![];
+            XarSigner::new(XarReader::new(Cursor::new(xar))?).sign(
+                &mut signed,
+                &key,
+                &cert,
+                time_stamp_url.as_ref(),
+                chain.into_iter(),
+            )?;
+            std::fs::write(path, signed)?;
+        } else {
+            std::fs::write(path, xar)?;
+        }
+        Ok(())
+    }
+}
+
+fn payload_entries(appdir: &Path) -> Result<Vec<PayloadEntry>> {
+    let mut entries = vec![PayloadEntry {
+        path: ".".into(),
+        source: None,
+        bom: BomEntry {
+            path: ".".into(),
+            kind: BomKind::Directory,
+            mode: 0o40775,
+            uid: 0,
+            gid: ADMIN_GID,
+            mtime: 0,
+            size: 0,
+            checksum: 0,
+            link: None,
+        },
+    }];
+    let name = appdir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .with_context(|| format!("invalid app bundle path {}", appdir.display()))?;
+    add_payload_entry(&mut entries, appdir, format!("./{}", name))?;
+    Ok(entries)
+}
+
+fn add_payload_entry(entries: &mut Vec<PayloadEntry>, source: &Path, path: String) -> Result<()> {
+    let metadata = std::fs::symlink_metadata(source)?;
+    let mtime = metadata
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|mtime| mtime.as_secs() as u32)
+        .unwrap_or_default();
+    let (kind, size, checksum, link) = if metadata.is_symlink() {
+        let link = std::fs::read_link(source)?;
+        let link = link.to_str().context("invalid symlink")?.replace('\\', "/");
+        (
+            BomKind::Link,
+            link.len() as u32,
+            bom::cksum(link.as_bytes()),
+            Some(link),
+        )
+    } else if metadata.is_dir() {
+        (BomKind::Directory, 0, 0, None)
+    } else {
+        let data = std::fs::read(source)?;
+        (BomKind::File, data.len() as u32, bom::cksum(&data), None)
+    };
+    let mode = match kind {
+        BomKind::File => 0o100000,
+        BomKind::Directory => 0o40000,
+        BomKind::Link => 0o120000,
+    } | permissions(source, &metadata, kind);
+    entries.push(PayloadEntry {
+        path: path.clone(),
+        source: Some(source.to_path_buf()),
+        bom: BomEntry {
+            path: path.clone(),
+            kind,
+            mode,
+            uid: 0,
+            gid: 0,
+            mtime,
+            size,
+            checksum,
+            link,
+        },
+    });
+    if kind == BomKind::Directory {
+        let mut children = std::fs::read_dir(source)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<_>>>()?;
+        children.sort();
+        for child in children {
+            let name = child
+                .file_name()
+                .and_then(|name| name.to_str())
+                .with_context(|| format!("invalid file name {}", child.display()))?;
+            let child_path = format!("{}/{}", path, name);
+            add_payload_entry(entries, &child, child_path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn permissions(_source: &Path, metadata: &std::fs::Metadata, _kind: BomKind) -> u16 {
+    use std::os::unix::fs::PermissionsExt;
+    (metadata.permissions().mode() & 0o7777) as u16
+}
+
+#[cfg(not(unix))]
+fn permissions(source: &Path, _metadata: &std::fs::Metadata, kind: BomKind) -> u16 {
+    let executable = source
+        .parent()
+        .and_then(|parent| parent.file_name())
+        .map(|name| name == "MacOS")
+        .unwrap_or_default();
+    if kind == BomKind::File && !executable {
+        0o644
+    } else {
+        0o755
+    }
+}
+
+/// Returns the installer architecture names of a thin or universal mach-o.
+fn macho_archs(bytes: &[u8]) -> Vec<&'static str> {
+    let read_u32 = |offset: usize, be: bool| {
+        let bytes = bytes.get(offset..offset + 4)?.try_into().unwrap();
+        Some(if be {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+    let cpu_types = match read_u32(0, true) {
+        Some(0xcafe_babe) => {
+            let count = read_u32(4, true).unwrap_or_default() as usize;
+            (0..count)
+                .filter_map(|i| read_u32(8 + i * 20, true))
+                .collect()
+        }
+        Some(0xcffa_edfe) => read_u32(4, false).into_iter().collect(),
+        _ => vec![],
+    };
+    cpu_types
+        .into_iter()
+        .filter_map(|cpu_type| match cpu_type {
+            0x0100_0007 => Some("x86_64"),
+            0x0100_000c => Some("arm64"),
+            _ => None,
+        })
+        .collect()
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+enum XarEntry {
+    File {
+        name: String,
+        data: Vec<u8>,
+        compress: bool,
+    },
+    Directory {
+        name: String,
+        entries: Vec<XarEntry>,
+    },
+}
+
+/// Writes a xar archive with a sha1 checksummed table of contents.
+fn write_xar(entries: &[XarEntry]) -> Result<Vec<u8>> {
+    // the heap starts with the checksum of the table of contents.
+    let mut heap = vec![0; 20];
+    let mut toc = String::new();
+    writeln!(toc, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(toc, "<xar>")?;
+    writeln!(toc, "<toc>")?;
+    writeln!(
+        toc,
+        r#"<checksum style="sha1"><offset>0</offset><size>20</size></checksum>"#
+    )?;
+    writeln!(
+        toc,
+        "<creation-time>{}</creation-time>",
+        chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ")
+    )?;
+    let mut id = 0;
+    write_xar_entries(&mut toc, &mut heap, &mut id, entries)?;
+    writeln!(toc, "</toc>")?;
+    writeln!(toc, "</xar>")?;
+
+    let toc_compressed = zlib(toc.as_bytes())?;
+    heap[..20].copy_from_slice(&Sha1::digest(&toc_compressed));
+
+    let mut xar = b"xar!".to_vec();
+    xar.extend_from_slice(&28u16.to_be_bytes());
+    xar.extend_from_slice(&1u16.to_be_bytes());
+    xar.extend_from_slice(&(toc_compressed.len() as u64).to_be_bytes());
+    xar.extend_from_slice(&(toc.len() as u64).to_be_bytes());
+    // sha1
+    xar.extend_from_slice(&1u32.to_be_bytes());
+    xar.extend_from_slice(&toc_compressed);
+    xar.extend_from_slice(&heap);
+    Ok(xar)
+}
+
+fn write_xar_entries(
+    toc: &mut String,
+    heap: &mut Vec<u8>,
+    id: &mut u64,
+    entries: &[XarEntry],
+) -> Result<()> {
+    for entry in entries {
+        *id += 1;
+        writeln!(toc, r#"<file id="{}">"#, id)?;
+        match entry {
+            XarEntry::File {
+                name,
+                data,
+                compress,
+            } => {
+                let (archived, encoding) = if *compress {
+                    (zlib(data)?, "application/x-gzip")
+                } else {
+                    (data.clone(), "application/octet-stream")
+                };
+                writeln!(toc, "<name>{}</name>", escape(name))?;
+                writeln!(toc, "<type>file</type>")?;
+                writeln!(toc, "<mode>0644</mode>")?;
+                writeln!(
+                    toc,
+                    "<uid>0</uid><gid>0</gid><user>root</user><group>wheel</group>"
+                )?;
+                writeln!(toc, "<data>")?;
+                writeln!(toc, "<length>{}</length>", archived.len())?;
+                writeln!(toc, "<offset>{}</offset>", heap.len())?;
+                writeln!(toc, "<size>{}</size>", data.len())?;
+                writeln!(toc, r#"<encoding style="{}"/>"#, encoding)?;
+                writeln!(
+                    toc,
+                    r#"<extracted-checksum style="sha1">{}</extracted-checksum>"#,
+                    hex(&Sha1::digest(data))
+                )?;
+                writeln!(
+                    toc,
+                    r#"<archived-checksum style="sha1">{}</archived-checksum>"#,
+                    hex(&Sha1::digest(&archived))
+                )?;
+                writeln!(toc, "</data>")?;
+                heap.extend_from_slice(&archived);
+            }
+            XarEntry::Directory { name, entries } => {
+                writeln!(toc, "<name>{}</name>", escape(name))?;
+                writeln!(toc, "<type>directory</type>")?;
+                writeln!(toc, "<mode>0755</mode>")?;
+                writeln!(
+                    toc,
+                    "<uid>0</uid><gid>0</gid><user>root</user><group>wheel</group>"
+                )?;
+                write_xar_entries(toc, heap, id, entries)?;
+            }
+        }
+        writeln!(toc, "</file>")?;
+    }
+    Ok(())
+}
+
+fn zlib(data: &[u8]) -> Result<Vec<u8>> {
+    let mut zlib = ZlibEncoder::new(vec![], Compression::default());
+    zlib.write_all(data)?;
+    Ok(zlib.finish()?)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InfoPlist;
+    use flate2::read::GzDecoder;
+
+    const PEM: &str = include_str!("../assets/test.pem");
+
+    #[test]
+    fn test_pkg() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path();
+        let exe = dir.join("test");
+        let mut macho = 0xfeed_facf_u32.to_le_bytes().to_vec();
+        macho.extend_from_slice(&0x0100_000c_u32.to_le_bytes());
+        std::fs::write(&exe, macho)?;
+
+        let mut app = AppBundle::new(
+            dir,
+            InfoPlist {
+                cf_bundle_name: Some("Test".into()),
+                cf_bundle_identifier: Some("com.example.test".into()),
+                cf_bundle_short_version_string: Some("1.2.0".into()),
+                ls_minimum_system_version: Some("11.0".into()),
+                ..Default::default()
+            },
+        )?;
+        app.add_executable(&exe)?;
+        app.finish(None)?;
+        let path = dir.join("Test.pkg");
+        let signer = Signer::new(PEM)?;
+        app.write_pkg(&path, Some(&signer), None)?;
+
+        let mut xar = XarReader::new(std::fs::File::open(&path)?)?;
+        assert!(xar.verify_table_of_contents_checksum()?);
+        assert!(xar.rsa_signature()?.is_some());
+        let files = xar
+            .files()?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            [
+                "Distribution",
+                "Test.pkg",
+                "Test.pkg/Bom",
+                "Test.pkg/Payload",
+                "Test.pkg/PackageInfo"
+            ]
+        );
+        let distribution = xar.get_file_data_from_path("Distribution")?.unwrap();
+        let distribution = String::from_utf8(distribution)?;
+        assert!(distribution.contains(r#"hostArchitectures="arm64""#));
+        assert!(distribution.contains(r#"<os-version min="11.0"/>"#));
+        let bom = xar.get_file_data_from_path("Test.pkg/Bom")?.unwrap();
+        assert_eq!(&bom[..8], b"BOMStore");
+
+        let payload = xar.get_file_data_from_path("Test.pkg/Payload")?.unwrap();
+        let mut cpio = cpio_archive::reader(GzDecoder::new(Cursor::new(payload)))?;
+        let mut names = vec![];
+        while let Some(header) = cpio.read_next()? {
+            names.push((header.name().to_string(), header.mode()));
+        }
+        assert_eq!(names[0].0, ".");
+        assert_eq!(names[1].0, "./Test.app");
+        assert!(names.iter().any(|(name, mode)| {
+            name == "./Test.app/Contents/MacOS/test" && mode & 0o170000 == 0o100000
+        }));
+        assert!(names
+            .iter()
+            .any(|(name, _)| name == "./Test.app/Contents/Info.plist"));
+        Ok(())
+    }
+}
@@ -0,0 +1,314 @@
+use crate::InfoPlist;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SdkSettings {
+    canonical_name: String,
+    version: String,
+    #[serde(default)]
+    default_properties: DefaultProperties,
+    #[serde(default)]
+    supported_targets: HashMap<String, SupportedTarget>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DefaultProperties {
+    #[serde(rename = "PLATFORM_NAME")]
+    platform_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SupportedTarget {
+    minimum_deployment_target: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SystemVersion {
+    #[serde(rename = "ProductBuildVersion")]
+    product_build_version: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct XcodeVersion {
+    #[serde(rename = "CFBundleShortVersionString")]
+    short_version: String,
+    #[serde(rename = "ProductBuildVersion")]
+    product_build_version: String,
+}
+
+/// Build metadata of an Apple platform SDK.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SdkInfo {
+    /// The canonical name of the SDK, for example `iphoneos17.0`.
+    pub canonical_name: String,
+    /// The platform name of the SDK, for example `iphoneos`.
+    pub platform_name: String,
+    /// The version of the SDK, for example `17.0`.
+    pub version: String,
+    /// The OS build the SDK was created from, for example `21A325`.
+    pub build: Option<String>,
+    /// The lowest deployment target the SDK supports.
+    pub minimum_deployment_target: Option<String>,
+    /// The Xcode version the SDK ships with, for example `1500`.
+    pub xcode: Option<String>,
+    /// The Xcode build the SDK ships with, for example `15A240d`.
+    pub xcode_build: Option<String>,
+}
+
+impl SdkInfo {
+    /// The file the sdk workflow copies the Xcode `version.plist` to.
+    pub const XCODE_VERSION: &'static str = "XcodeVersion.plist";
+
+    const FALLBACK_XCODE: &'static str = "1321";
+    const FALLBACK_XCODE_BUILD: &'static str = "13C100";
+
+    /// Build metadata of the Xcode 13.2.1 sdk for `platform_name`, used when
+    /// no sdk is available to read it from.
+    pub fn fallback(platform_name: &str) -> Option<Self> {
+        let (version, build) = match platform_name {
+            "iphoneos" => ("15.2", "19C51"),
+            "macosx" => ("12.1", "21C46"),
+            _ => return None,
+        };
+        Some(Self {
+            canonical_name: format!("{}{}", platform_name, version),
+            platform_name: platform_name.to_string(),
+            version: version.to_string(),
+            build: Some(build.to_string()),
+            minimum_deployment_target: None,
+            xcode: Some(Self::FALLBACK_XCODE.to_string()),
+            xcode_build: Some(Self::FALLBACK_XCODE_BUILD.to_string()),
+        })
+    }
+
+    /// Reads `SDKSettings.json` or `SDKSettings.plist` from the root of an SDK.
+    pub fn from_path(sdk: &Path) -> Result<Self> {
+        let json = sdk.join("SDKSettings.json");
+        let settings: SdkSettings = if json.exists() {
+            let bytes = std::fs::read(&json)?;
+            serde_json::from_slice(&bytes)
+                .with_context(|| format!("failed to parse {}", json.display()))?
+        } else {
+            let plist = sdk.join("SDKSettings.plist");
+            plist::from_file(&plist)
+                .with_context(|| format!("failed to parse {}", plist.display()))?
+        };
+        let platform_name = settings
+            .default_properties
+            .platform_name
+            .unwrap_or_else(|| {
+                settings
+                    .canonical_name
+                    .trim_end_matches(|c: char| c.is_ascii_digit() || c == '.')
+                    .to_string()
+            });
+        let minimum_deployment_target = settings
+            .supported_targets
+            .get(&platform_name)
+            .and_then(|target| target.minimum_deployment_target.clone());
+
+        let system_version = sdk
+            .join("System")
+            .join("Library")
+            .join("CoreServices")
+            .join("SystemVersion.plist");
+        let build = if system_version.exists() {
+            let version: SystemVersion = plist::from_file(&system_version)
+                .with_context(|| format!("failed to parse {}", system_version.display()))?;
+            Some(version.product_build_version)
+        } else {
+            None
+        };
+
+        // Inside an Xcode installation the SDK lives in
+        // `Xcode.app/Contents/Developer/Platforms/*.platform/Developer/SDKs`.
+        let xcode_version = [
+            Some(sdk.join(Self::XCODE_VERSION)),
+            sdk.ancestors().nth(6).map(|dir| dir.join("version.plist")),
+        ]
+        .into_iter()
+        .flatten()
+        .find(|path| path.exists());
+        let (xcode, xcode_build) = if let Some(path) = xcode_version {
+            let version: XcodeVersion = plist::from_file(&path)
+                .with_context(|| format!("failed to parse {}", path.display()))?;
+            (
+                Some(xcode_version_number(&version.short_version)?),
+                Some(version.product_build_version),
+            )
+        } else {
+            // Sdk tarballs published before `XcodeVersion.plist` was added
+            // don't record which xcode they were packaged from.
+            log::warn!(
+                "{} not found in {}, leaving DTXcode and DTXcodeBuild unset",
+                Self::XCODE_VERSION,
+                sdk.display(),
+            );
+            (None, None)
+        };
+
+        Ok(Self {
+            canonical_name: settings.canonical_name,
+            platform_name,
+            version: settings.version,
+            build,
+            minimum_deployment_target,
+            xcode,
+            xcode_build,
+        })
+    }
+
+    /// Fills the `DT*` keys that aren't set yet and raises the minimum
+    /// os version to the lowest deployment target supported by the SDK.
+    pub fn apply(&self, info: &mut InfoPlist) {
+        info.dt_platform_name
+            .get_or_insert_with(|| self.platform_name.clone());
+        info.dt_platform_version
+            .get_or_insert_with(|| self.version.clone());
+        info.dt_sdk_name
+            .get_or_insert_with(|| self.canonical_name.clone());
+        if let Some(build) = self.build.as_ref() {
+            info.dt_platform_build.get_or_insert_with(|| build.clone());
+            info.dt_sdk_build.get_or_insert_with(|| build.clone());
+        }
+        if let Some(xcode) = self.xcode.as_ref() {
+            info.dt_xcode.get_or_insert_with(|| xcode.clone());
+        }
+        if let Some(xcode_build) = self.xcode_build.as_ref() {
+            info.dt_xcode_build
+                .get_or_insert_with(|| xcode_build.clone());
+        }
+        if let Some(minimum) = self.minimum_deployment_target.as_ref() {
+            let minimum_version = if info.ls_requires_ios == Some(true) {
+                &mut info.minimum_os_version
+            } else {
+                &mut info.ls_minimum_system_version
+            };
+            match minimum_version {
+                Some(version) if version_lt(version, minimum) => {
+                    log::warn!(
+                        "minimum os version {} is not supported by the {} sdk, using {}",
+                        version,
+                        self.canonical_name,
+                        minimum
+                    );
+                    *version = minimum.clone();
+                }
+                Some(_) => {}
+                None => *minimum_version = Some(minimum.clone()),
+            }
+        }
+    }
+}
+
+fn parse_version(version: &str) -> Vec<u32> {
+    version
+        .split('.')
+        .map(|part| part.parse().unwrap_or_default())
+        .collect()
+}
+
+fn version_lt(a: &str, b: &str) -> bool {
+    let (mut a, mut b) = (parse_version(a), parse_version(b));
+    let len = a.len().max(b.len());
+    a.resize(len, 0);
+    b.resize(len, 0);
+    a < b
+}
+
+/// Converts an Xcode version like `13.2.1` to the `DTXcode` format `1321`.
+fn xcode_version_number(version: &str) -> Result<String> {
+    let mut parts = version.split('.');
+    let mut next = || -> Result<u32> {
+        Ok(parts
+            .next()
+            .map(|part| part.parse())
+            .transpose()
+            .with_context(|| format!("invalid xcode version {}", version))?
+            .unwrap_or_default())
+    };
+    let (major, minor, patch) = (next()?, next()?, next()?);
+    Ok(format!("{}{}{}", major, minor, patch))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sdk_info() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path();
+        let sdk = dir.join("iPhoneOS.sdk");
+        let core_services = sdk.join("System").join("Library").join("CoreServices");
+        std::fs::create_dir_all(&core_services)?;
+        std::fs::write(
+            sdk.join("SDKSettings.json"),
+            r#"{
+                "CanonicalName": "iphoneos17.0",
+                "DisplayName": "iOS 17.0",
+                "Version": "17.0",
+                "DefaultProperties": { "PLATFORM_NAME": "iphoneos" },
+                "SupportedTargets": {
+                    "iphoneos": { "MinimumDeploymentTarget": "12.0", "ValidDeploymentTargets": ["12.0"] }
+                }
+            }"#,
+        )?;
+        let mut version = plist::Dictionary::new();
+        version.insert("ProductBuildVersion".into(), "21A325".into());
+        plist::Value::Dictionary(version).to_file_xml(core_services.join("SystemVersion.plist"))?;
+        let mut version = plist::Dictionary::new();
+        version.insert("CFBundleShortVersionString".into(), "15.0".into());
+        version.insert("ProductBuildVersion".into(), "15A240d".into());
+        plist::Value::Dictionary(version).to_file_xml(sdk.join(SdkInfo::XCODE_VERSION))?;
+
+        let sdk = SdkInfo::from_path(&sdk)?;
+        assert_eq!(sdk.platform_name, "iphoneos");
+        assert_eq!(sdk.xcode.as_deref(), Some("1500"));
+
+        let mut info = InfoPlist {
+            ls_requires_ios: Some(true),
+            minimum_os_version: Some("11.0".into()),
+            ..Default::default()
+        };
+        sdk.apply(&mut info);
+        assert_eq!(info.dt_sdk_name.as_deref(), Some("iphoneos17.0"));
+        assert_eq!(info.dt_platform_version.as_deref(), Some("17.0"));
+        assert_eq!(info.dt_sdk_build.as_deref(), Some("21A325"));
+        assert_eq!(info.dt_xcode_build.as_deref(), Some("15A240d"));
+        assert_eq!(info.minimum_os_version.as_deref(), Some("12.0"));
+
+        std::fs::remove_file(dir.join("iPhoneOS.sdk").join(SdkInfo::XCODE_VERSION))?;
+        let sdk = SdkInfo::from_path(&dir.join("iPhoneOS.sdk"))?;
+        assert_eq!(sdk.xcode, None);
+        assert_eq!(sdk.xcode_build, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fallback() {
+        let sdk = SdkInfo::fallback("iphoneos").unwrap();
+        let mut info = InfoPlist::default();
+        sdk.apply(&mut info);
+        assert_eq!(info.dt_sdk_name.as_deref(), Some("iphoneos15.2"));
+        assert_eq!(info.dt_platform_build.as_deref(), Some("19C51"));
+        assert_eq!(info.dt_xcode.as_deref(), Some("1321"));
+        assert_eq!(info.dt_xcode_build.as_deref(), Some("13C100"));
+        assert!(SdkInfo::fallback("appletvos").is_none());
+    }
+
+    #[test]
+    fn test_versions() -> Result<()> {
+        assert_eq!(xcode_version_number("13.2.1")?, "1321");
+        assert_eq!(xcode_version_number("16.0")?, "1600");
+        assert!(version_lt("11.0", "12"));
+        assert!(!version_lt("14.0", "12.0"));
+        assert!(!version_lt("10.15", "10.13"));
+        Ok(())
+    }
+}
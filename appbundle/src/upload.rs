@@ -0,0 +1,487 @@
+use crate::InfoPlist;
+use anyhow::{Context, Result};
+use app_store_connect::{ConnectTokenEncoder, UnifiedApiKey};
+use reqwest::blocking::{Body, Client, RequestBuilder};
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const APP_STORE_CONNECT_URL: &str = "https://api.appstoreconnect.apple.com";
+
+#[derive(Debug, Deserialize)]
+struct Response<T> {
+    data: T,
+}
+
+#[derive(Debug, Deserialize)]
+struct Resource<T> {
+    id: String,
+    #[serde(default)]
+    attributes: T,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BuildUploadFileAttributes {
+    #[serde(default)]
+    upload_operations: Vec<UploadOperation>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UploadOperation {
+    method: String,
+    url: String,
+    length: u64,
+    offset: u64,
+    #[serde(default)]
+    request_headers: Vec<HttpHeader>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HttpHeader {
+    name: String,
+    value: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct BuildUploadAttributes {
+    state: Option<BuildUploadState>,
+}
+
+/// Processing state of a build upload.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct BuildUploadState {
+    /// One of `AWAITING_UPLOAD`, `PROCESSING`, `FAILED` or `COMPLETE`.
+    pub state: String,
+    #[serde(default)]
+    pub errors: Vec<StateDetail>,
+    #[serde(default)]
+    pub warnings: Vec<StateDetail>,
+}
+
+impl BuildUploadState {
+    pub fn is_finished(&self) -> bool {
+        matches!(self.state.as_str(), "COMPLETE" | "FAILED")
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct StateDetail {
+    pub code: Option<String>,
+    pub description: Option<String>,
+}
+
+impl std::fmt::Display for StateDetail {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {}",
+            self.code.as_deref().unwrap_or("UNKNOWN"),
+            self.description.as_deref().unwrap_or_default()
+        )
+    }
+}
+
+/// Uploads `.ipa` and `.pkg` files to App Store Connect using the build
+/// uploads api.
+pub struct Uploader {
+    client: Client,
+    encoder: ConnectTokenEncoder,
+    base_url: String,
+    poll_interval: Duration,
+}
+
+impl Uploader {
+    pub fn from_api_key(api_key: &Path) -> Result<Self> {
+        let encoder = UnifiedApiKey::from_json_path(api_key)?.try_into()?;
+        Self::new(encoder, APP_STORE_CONNECT_URL)
+    }
+
+    pub fn new(encoder: ConnectTokenEncoder, base_url: &str) -> Result<Self> {
+        Ok(Self {
+            client: Client::builder().build()?,
+            encoder,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            poll_interval: Duration::from_secs(10),
+        })
+    }
+
+    pub fn set_poll_interval(&mut self, poll_interval: Duration) {
+        self.poll_interval = poll_interval;
+    }
+
+    fn request(&self, method: Method, path: &str) -> Result<RequestBuilder> {
+        // Tokens are only valid for a couple of minutes and processing can take a while.
+        let token = self.encoder.new_token(300)?;
+        Ok(self
+            .client
+            .request(method, format!("{}{}", self.base_url, path))
+            .bearer_auth(token)
+            .header("Accept", "application/json"))
+    }
+
+    fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T> {
+        let resp = request.send()?;
+        let status = resp.status();
+        let url = resp.url().clone();
+        let body = resp.bytes()?;
+        anyhow::ensure!(
+            status.is_success(),
+            "{} returned status code {}: {}",
+            url,
+            status,
+            String::from_utf8_lossy(&body)
+        );
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Returns the App Store Connect id of the app with `bundle_id`.
+    pub fn app_id(&self, bundle_id: &str) -> Result<String> {
+        let req = self
+            .request(Method::GET, "/v1/apps")?
+            .query(&[("filter[bundleId]", bundle_id)]);
+        let resp: Response<Vec<Resource<Value>>> = self.send(req)?;
+        let app =
+            resp.data.into_iter().next().with_context(|| {
+                format!("no app with bundle id {} in App Store Connect", bundle_id)
+            })?;
+        Ok(app.id)
+    }
+
+    /// Creates a build upload for `app_id` and returns its id.
+    pub fn create_build_upload(
+        &self,
+        app_id: &str,
+        platform: &str,
+        short_version: &str,
+        version: &str,
+    ) -> Result<String> {
+        let body = json!({
+            "data": {
+                "type": "buildUploads",
+                "attributes": {
+                    "cfBundleShortVersionString": short_version,
+                    "cfBundleVersion": version,
+                    "platform": platform,
+                },
+                "relationships": {
+                    "app": { "data": { "type": "apps", "id": app_id } },
+                },
+            },
+        });
+        let req = self.request(Method::POST, "/v1/buildUploads")?.json(&body);
+        let resp: Response<Resource<Value>> = self.send(req)?;
+        Ok(resp.data.id)
+    }
+
+    /// Uploads `path` as the file of build upload `upload_id`. The file is
+    /// streamed from disk one upload operation at a time.
+    pub fn upload_file(&self, upload_id: &str, path: &Path) -> Result<()> {
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .with_context(|| format!("invalid file name {}", path.display()))?;
+        let uti = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("ipa") => "com.apple.ipa",
+            Some("pkg") => "com.apple.pkg",
+            _ => anyhow::bail!("unsupported upload {}", path.display()),
+        };
+        let file_size = std::fs::metadata(path)?.len();
+        let body = json!({
+            "data": {
+                "type": "buildUploadFiles",
+                "attributes": {
+                    "assetType": "ASSET",
+                    "fileName": file_name,
+                    "fileSize": file_size,
+                    "uti": uti,
+                },
+                "relationships": {
+                    "buildUpload": { "data": { "type": "buildUploads", "id": upload_id } },
+                },
+            },
+        });
+        let req = self
+            .request(Method::POST, "/v1/buildUploadFiles")?
+            .json(&body);
+        let file: Response<Resource<BuildUploadFileAttributes>> = self.send(req)?;
+
+        for op in &file.data.attributes.upload_operations {
+            let start = op.offset;
+            let end = start
+                .checked_add(op.length)
+                .filter(|end| *end <= file_size)
+                .context("invalid upload operation")?;
+            let mut part = File::open(path)?;
+            part.seek(SeekFrom::Start(start))?;
+            let mut req = self
+                .client
+                .request(op.method.parse()?, &op.url)
+                .body(Body::sized(part.take(op.length), op.length));
+            for header in &op.request_headers {
+                req = req.header(&header.name, &header.value);
+            }
+            let resp = req.send()?;
+            anyhow::ensure!(
+                resp.status().is_success(),
+                "uploading part {}..{} returned status code {}",
+                start,
+                end,
+                resp.status()
+            );
+        }
+
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut File::open(path)?, &mut hasher)?;
+        let checksum = hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        let body = json!({
+            "data": {
+                "type": "buildUploadFiles",
+                "id": file.data.id,
+                "attributes": {
+                    "sourceFileChecksums": {
+                        "file": { "hash": checksum, "algorithm": "SHA_256" },
+                    },
+                    "uploaded": true,
+                },
+            },
+        });
+        let req = self
+            .request(
+                Method::PATCH,
+                &format!("/v1/buildUploadFiles/{}", file.data.id),
+            )?
+            .json(&body);
+        self.send::<Value>(req)?;
+        Ok(())
+    }
+
+    pub fn build_upload_state(&self, upload_id: &str) -> Result<BuildUploadState> {
+        let req = self.request(Method::GET, &format!("/v1/buildUploads/{}", upload_id))?;
+        let resp: Response<Resource<BuildUploadAttributes>> = self.send(req)?;
+        Ok(resp.data.attributes.state.unwrap_or_default())
+    }
+
+    /// Uploads an `.ipa` or `.pkg` built from `info` and waits for App Store
+    /// Connect to finish processing it.
+    pub fn upload(&self, path: &Path, info: &InfoPlist) -> Result<()> {
+        println!("uploading {}", path.display());
+        let bundle_id = info
+            .cf_bundle_identifier
+            .as_deref()
+            .context("missing bundle identifier")?;
+        let short_version = info
+            .cf_bundle_short_version_string
+            .as_deref()
+            .context("missing bundle short version")?;
+        let version = info.cf_bundle_version.as_deref().unwrap_or(short_version);
+        let platform = if info.ls_requires_ios == Some(true) {
+            "IOS"
+        } else {
+            "MAC_OS"
+        };
+        let app_id = self.app_id(bundle_id)?;
+        let upload_id = self.create_build_upload(&app_id, platform, short_version, version)?;
+        println!("upload id: {}", upload_id);
+        self.upload_file(&upload_id, path)?;
+
+        let start_time = Instant::now();
+        loop {
+            let state = self.build_upload_state(&upload_id)?;
+            let elapsed = start_time.elapsed();
+            println!("poll state after {}s: {}", elapsed.as_secs(), state.state);
+            if state.is_finished() {
+                for warning in &state.warnings {
+                    println!("warning: {}", warning);
+                }
+                if state.state == "FAILED" {
+                    let errors = state
+                        .errors
+                        .iter()
+                        .map(|error| error.to_string())
+                        .collect::<Vec<_>>();
+                    anyhow::bail!("processing failed:\n{}", errors.join("\n"));
+                }
+                break;
+            }
+            std::thread::sleep(self.poll_interval);
+        }
+        Ok(())
+    }
+}
+
+/// Uploads an `.ipa` or `.pkg` to App Store Connect/TestFlight.
+pub fn upload(path: &Path, api_key: &Path, info: &InfoPlist) -> Result<()> {
+    Uploader::from_api_key(api_key)?.upload(path, info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+
+    const API_KEY: &str = include_str!("../assets/test-api-key.p8");
+
+    type Requests = Arc<Mutex<Vec<(String, String, Vec<u8>)>>>;
+
+    fn handle(mut stream: TcpStream, port: u16, requests: Requests) -> Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            let mut parts = line.split_whitespace();
+            let method = parts.next().unwrap_or_default().to_string();
+            let path = parts.next().unwrap_or_default().to_string();
+            let mut len = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header)?;
+                if header.trim().is_empty() {
+                    break;
+                }
+                let (name, value) = header.split_once(':').unwrap();
+                if name.eq_ignore_ascii_case("content-length") {
+                    len = value.trim().parse()?;
+                }
+            }
+            let mut body = vec![0; len];
+            reader.read_exact(&mut body)?;
+            let polls = requests
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(m, p, _)| m == "GET" && p == "/v1/buildUploads/upload")
+                .count();
+            requests
+                .lock()
+                .unwrap()
+                .push((method.clone(), path.clone(), body));
+            let resp = match (method.as_str(), path.as_str()) {
+                ("GET", p) if p.starts_with("/v1/apps?") => {
+                    json!({ "data": [{ "type": "apps", "id": "app" }] })
+                }
+                ("POST", "/v1/buildUploads") => {
+                    json!({ "data": { "type": "buildUploads", "id": "upload" } })
+                }
+                ("POST", "/v1/buildUploadFiles") => json!({
+                    "data": {
+                        "type": "buildUploadFiles",
+                        "id": "file",
+                        "attributes": {
+                            "uploadOperations": [
+                                {
+                                    "method": "PUT",
+                                    "url": format!("http://127.0.0.1:{}/part/0", port),
+                                    "offset": 0,
+                                    "length": 5,
+                                    "requestHeaders": [{ "name": "Content-Type", "value": "application/octet-stream" }],
+                                },
+                                {
+                                    "method": "PUT",
+                                    "url": format!("http://127.0.0.1:{}/part/1", port),
+                                    "offset": 5,
+                                    "length": 6,
+                                },
+                            ],
+                        },
+                    },
+                }),
+                ("PUT", _) => json!({}),
+                ("PATCH", "/v1/buildUploadFiles/file") => {
+                    json!({ "data": { "type": "buildUploadFiles", "id": "file" } })
+                }
+                ("GET", "/v1/buildUploads/upload") => {
+                    let state = if polls == 0 { "PROCESSING" } else { "COMPLETE" };
+                    json!({
+                        "data": {
+                            "type": "buildUploads",
+                            "id": "upload",
+                            "attributes": { "state": { "state": state } },
+                        },
+                    })
+                }
+                _ => anyhow::bail!("unexpected request {} {}", method, path),
+            };
+            let resp = serde_json::to_vec(&resp)?;
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+                resp.len()
+            )?;
+            stream.write_all(&resp)?;
+        }
+    }
+
+    #[test]
+    fn test_upload() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let requests = Requests::default();
+        let requests2 = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let requests = requests2.clone();
+                let stream = stream.unwrap();
+                std::thread::spawn(move || handle(stream, port, requests));
+            }
+        });
+
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path();
+        let ipa = dir.join("example.ipa");
+        std::fs::write(&ipa, b"hello world")?;
+
+        let encoder =
+            ConnectTokenEncoder::from_ecdsa_pem("KEY".into(), "ISSUER".into(), API_KEY.as_bytes())?;
+        let mut uploader = Uploader::new(encoder, &format!("http://127.0.0.1:{}", port))?;
+        uploader.set_poll_interval(Duration::from_millis(10));
+        let info = InfoPlist {
+            cf_bundle_identifier: Some("com.example.example".into()),
+            cf_bundle_short_version_string: Some("1.0.0".into()),
+            ls_requires_ios: Some(true),
+            ..Default::default()
+        };
+        uploader.upload(&ipa, &info)?;
+
+        let requests = requests.lock().unwrap();
+        let parts = requests
+            .iter()
+            .filter(|(method, _, _)| method == "PUT")
+            .flat_map(|(_, _, body)| body.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(parts, b"hello world");
+        let (_, _, create) = &requests[1];
+        let create: Value = serde_json::from_slice(create)?;
+        assert_eq!(create["data"]["attributes"]["platform"], "IOS");
+        assert_eq!(create["data"]["attributes"]["cfBundleVersion"], "1.0.0");
+        let (_, _, commit) = requests
+            .iter()
+            .find(|(method, _, _)| method == "PATCH")
+            .unwrap();
+        let commit: Value = serde_json::from_slice(commit)?;
+        assert_eq!(commit["data"]["attributes"]["uploaded"], true);
+        assert_eq!(
+            requests
+                .iter()
+                .filter(|(_, path, _)| path == "/v1/buildUploads/upload")
+                .count(),
+            2
+        );
+        Ok(())
+    }
+}
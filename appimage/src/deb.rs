@@ -0,0 +1,231 @@
+use crate::package::{EntryKind, PackageConfig};
+use crate::zsync::hex;
+use crate::AppImage;
+use anyhow::Result;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use md5::{Digest, Md5};
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+impl AppImage {
+    /// Creates a debian package at `out` from the contents of the AppDir.
+    /// `arch` is the debian architecture, for example `amd64`.
+    pub fn create_deb(&self, out: &Path, arch: &str, config: &PackageConfig) -> Result<()> {
+        let name = self.name.to_lowercase().replace('_', "-");
+        anyhow::ensure!(
+            name.len() >= 2
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+                && name.starts_with(|c: char| c.is_ascii_alphanumeric()),
+            "invalid debian package name {}",
+            name
+        );
+        let version = config.version()?;
+        let maintainer = config.maintainer()?;
+        let mtime = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+        let mut data = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+        let mut md5sums = String::new();
+        let mut installed_size = 0;
+        let entries = self.package_entries()?;
+        let mut dirs = BTreeSet::new();
+        for entry in &entries {
+            let mut path = Path::new(&entry.path);
+            while let Some(parent) = path.parent() {
+                if parent != Path::new("/") {
+                    dirs.insert(parent.to_path_buf());
+                }
+                path = parent;
+            }
+        }
+        for dir in &dirs {
+            if !entries.iter().any(|entry| Path::new(&entry.path) == dir) {
+                let mut header = tar_header(tar::EntryType::Directory, 0o755, 0, mtime)?;
+                data.append_data(&mut header, dir.strip_prefix("/")?, std::io::empty())?;
+            }
+        }
+        for entry in &entries {
+            let path = Path::new(&entry.path).strip_prefix("/")?;
+            match &entry.kind {
+                EntryKind::Dir => {
+                    let mut header = tar_header(tar::EntryType::Directory, entry.mode, 0, mtime)?;
+                    data.append_data(&mut header, path, std::io::empty())?;
+                }
+                EntryKind::File(source) => {
+                    let bytes = std::fs::read(source)?;
+                    writeln!(md5sums, "{}  {}", hex(&Md5::digest(&bytes)), path.display())?;
+                    installed_size += entry.size;
+                    let mut header =
+                        tar_header(tar::EntryType::Regular, entry.mode, entry.size, mtime)?;
+                    data.append_data(&mut header, path, &*bytes)?;
+                }
+                EntryKind::Symlink(target) => {
+                    let mut header = tar_header(tar::EntryType::Symlink, entry.mode, 0, mtime)?;
+                    header.set_link_name(target)?;
+                    data.append_data(&mut header, path, std::io::empty())?;
+                }
+            }
+        }
+        let data = data.into_inner()?.finish()?;
+
+        let (summary, description) = config.summary(&self.name);
+        let mut control = String::new();
+        writeln!(control, "Package: {}", name)?;
+        writeln!(control, "Version: {}", version)?;
+        writeln!(control, "Architecture: {}", arch)?;
+        writeln!(control, "Maintainer: {}", maintainer)?;
+        writeln!(control, "Installed-Size: {}", installed_size.div_ceil(1024))?;
+        if !config.depends.is_empty() {
+            writeln!(control, "Depends: {}", config.depends.join(", "))?;
+        }
+        writeln!(control, "Section: misc")?;
+        writeln!(control, "Priority: optional")?;
+        if let Some(homepage) = config.homepage.as_ref() {
+            writeln!(control, "Homepage: {}", homepage)?;
+        }
+        writeln!(control, "Description: {}", summary)?;
+        for line in description {
+            if line.trim().is_empty() {
+                writeln!(control, " .")?;
+            } else {
+                writeln!(control, " {}", line)?;
+            }
+        }
+
+        let mut control_tar = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+        for (file_name, contents) in [("control", control), ("md5sums", md5sums)] {
+            let mut header =
+                tar_header(tar::EntryType::Regular, 0o644, contents.len() as u64, mtime)?;
+            control_tar.append_data(&mut header, file_name, contents.as_bytes())?;
+        }
+        let control_tar = control_tar.into_inner()?.finish()?;
+
+        let mut w = BufWriter::new(File::create(out)?);
+        w.write_all(b"!<arch>\n")?;
+        for (file_name, contents) in [
+            ("debian-binary", &b"2.0\n"[..]),
+            ("control.tar.gz", &control_tar),
+            ("data.tar.gz", &data),
+        ] {
+            writeln!(
+                w,
+                "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`",
+                file_name,
+                mtime,
+                0,
+                0,
+                100644,
+                contents.len()
+            )?;
+            w.write_all(contents)?;
+            if contents.len() % 2 == 1 {
+                w.write_all(b"\n")?;
+            }
+        }
+        w.flush()?;
+        Ok(())
+    }
+}
+
+fn tar_header(entry_type: tar::EntryType, mode: u32, size: u64, mtime: u64) -> Result<tar::Header> {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_mode(mode);
+    header.set_size(size);
+    header.set_mtime(mtime);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_username("root")?;
+    header.set_groupname("root")?;
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DesktopEntry;
+    use std::io::Read;
+
+    #[test]
+    fn test_deb() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path();
+        let exe = dir.join("exe");
+        std::fs::write(&exe, b"\x7fELF")?;
+
+        let appimage = AppImage::new(dir, "test_app".into())?;
+        appimage.add_desktop(&DesktopEntry::default())?;
+        appimage.add_file(&exe, Path::new("test_app"))?;
+        appimage.add_file(&exe, Path::new("lib/libfoo.so"))?;
+        let mut config = PackageConfig {
+            version: Some("1.0.0-beta.1".into()),
+            description: Some("A test app".into()),
+            depends: vec!["libc6".into()],
+            ..Default::default()
+        };
+        let out = dir.join("test.deb");
+        assert!(appimage.create_deb(&out, "amd64", &config).is_err());
+        config.maintainer = Some("Test".into());
+        assert!(appimage.create_deb(&out, "amd64", &config).is_err());
+        config.maintainer = Some("Test <test@example.com>".into());
+        appimage.create_deb(&out, "amd64", &config)?;
+
+        let bytes = std::fs::read(&out)?;
+        assert!(bytes.starts_with(b"!<arch>\ndebian-binary   "));
+        let mut members = vec![];
+        let mut pos = 8;
+        while pos < bytes.len() {
+            let header = std::str::from_utf8(&bytes[pos..pos + 60])?;
+            let size: usize = header[48..58].trim().parse()?;
+            members.push((header[..16].trim().to_string(), pos + 60, size));
+            pos += 60 + size + size % 2;
+        }
+        assert_eq!(pos, bytes.len());
+        let names = members.iter().map(|m| m.0.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["debian-binary", "control.tar.gz", "data.tar.gz"]);
+
+        let read_tar =
+            |(_, start, size): &(String, usize, usize)| -> Result<Vec<(String, String)>> {
+                let gz = flate2::read::GzDecoder::new(&bytes[*start..*start + *size]);
+                let mut archive = tar::Archive::new(gz);
+                let mut files = vec![];
+                for entry in archive.entries()? {
+                    let mut entry = entry?;
+                    let path = entry.path()?.display().to_string();
+                    let mut contents = String::new();
+                    if entry.header().entry_type() == tar::EntryType::Symlink {
+                        contents = entry.link_name()?.unwrap().display().to_string();
+                    } else {
+                        entry.read_to_string(&mut contents).ok();
+                    }
+                    files.push((path, contents));
+                }
+                Ok(files)
+            };
+        let control = read_tar(&members[1])?;
+        assert_eq!(control[0].0, "control");
+        let control = &control[0].1;
+        assert!(control.starts_with("Package: test-app\nVersion: 1.0.0~beta.1\n"));
+        assert!(control.contains("Architecture: amd64\n"));
+        assert!(control.contains("Maintainer: Test <test@example.com>\n"));
+        assert!(control.contains("Depends: libc6\n"));
+        assert!(control.ends_with("Description: A test app\n"));
+
+        let data = read_tar(&members[2])?;
+        let paths = data.iter().map(|f| f.0.as_str()).collect::<Vec<_>>();
+        assert!(paths.contains(&"usr/bin"));
+        assert!(paths.contains(&"usr/lib/test_app/test_app"));
+        assert!(paths.contains(&"usr/lib/test_app/lib/libfoo.so"));
+        assert!(paths.contains(&"usr/share/applications/test_app.desktop"));
+        assert!(!paths.iter().any(|p| p.ends_with("AppRun")));
+        let bin = data.iter().find(|f| f.0 == "usr/bin/test_app").unwrap();
+        assert_eq!(bin.1, "../lib/test_app/test_app");
+        Ok(())
+    }
+}
@@ -0,0 +1,283 @@
+use serde::Deserialize;
+use std::fmt::Write;
+
+/// Freedesktop desktop entry of the application.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DesktopEntry {
+    /// Display name, defaults to the package name.
+    pub name: Option<String>,
+    pub generic_name: Option<String>,
+    pub comment: Option<String>,
+    /// Defaults to `Utility`.
+    #[serde(default)]
+    pub categories: Vec<String>,
+    #[serde(default)]
+    pub mime_types: Vec<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    pub startup_wm_class: Option<String>,
+    #[serde(default)]
+    pub terminal: bool,
+}
+
+impl DesktopEntry {
+    /// Returns the desktop entry launching `exec` with icon `icon`.
+    pub fn to_desktop_file(&self, exec: &str, icon: &str) -> String {
+        let mut s = String::new();
+        let list = |values: &[String]| {
+            values
+                .iter()
+                .map(|v| format!("{};", escape_value(v).replace(';', "\\;")))
+                .collect::<String>()
+        };
+        writeln!(s, "[Desktop Entry]").unwrap();
+        writeln!(s, "Version=1.0").unwrap();
+        writeln!(s, "Type=Application").unwrap();
+        writeln!(s, "Terminal={}", self.terminal).unwrap();
+        let name = self.name.as_deref().unwrap_or(exec);
+        writeln!(s, "Name={}", escape_value(name)).unwrap();
+        if let Some(generic_name) = self.generic_name.as_ref() {
+            writeln!(s, "GenericName={}", escape_value(generic_name)).unwrap();
+        }
+        if let Some(comment) = self.comment.as_ref() {
+            writeln!(s, "Comment={}", escape_value(comment)).unwrap();
+        }
+        writeln!(s, "Exec={} %u", escape_value(&quote_exec_arg(exec))).unwrap();
+        writeln!(s, "Icon={}", escape_value(icon)).unwrap();
+        if self.categories.is_empty() {
+            writeln!(s, "Categories=Utility;").unwrap();
+        } else {
+            writeln!(s, "Categories={}", list(&self.categories)).unwrap();
+        }
+        if !self.mime_types.is_empty() {
+            writeln!(s, "MimeType={}", list(&self.mime_types)).unwrap();
+        }
+        if !self.keywords.is_empty() {
+            writeln!(s, "Keywords={}", list(&self.keywords)).unwrap();
+        }
+        if let Some(startup_wm_class) = self.startup_wm_class.as_ref() {
+            writeln!(s, "StartupWMClass={}", escape_value(startup_wm_class)).unwrap();
+        }
+        s
+    }
+}
+
+/// AppStream metadata used by software centers.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Metainfo {
+    /// Reverse dns component id, defaults to the android package name. It
+    /// is also used to name the desktop entry and icons.
+    pub id: Option<String>,
+    /// Defaults to the package description.
+    pub summary: Option<String>,
+    /// Paragraphs of the long description.
+    #[serde(default)]
+    pub description: Vec<String>,
+    pub developer_name: Option<String>,
+    pub homepage: Option<String>,
+    /// Defaults to `CC0-1.0`.
+    pub metadata_license: Option<String>,
+    pub project_license: Option<String>,
+    /// Defaults to the package version.
+    pub version: Option<String>,
+    /// Urls of screenshots.
+    #[serde(default)]
+    pub screenshots: Vec<String>,
+}
+
+impl Metainfo {
+    /// Returns the metainfo xml for the application with desktop entry
+    /// `desktop_id`.
+    pub fn to_xml(&self, id: &str, desktop_id: &str, entry: &DesktopEntry) -> String {
+        let mut s = String::new();
+        writeln!(s, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+        writeln!(s, r#"<component type="desktop-application">"#).unwrap();
+        writeln!(s, "  <id>{}</id>", escape(id)).unwrap();
+        let name = entry.name.as_deref().unwrap_or(id);
+        writeln!(s, "  <name>{}</name>", escape(name)).unwrap();
+        let summary = self
+            .summary
+            .as_deref()
+            .or(entry.comment.as_deref())
+            .unwrap_or(name);
+        writeln!(s, "  <summary>{}</summary>", escape(summary)).unwrap();
+        let metadata_license = self.metadata_license.as_deref().unwrap_or("CC0-1.0");
+        writeln!(
+            s,
+            "  <metadata_license>{}</metadata_license>",
+            escape(metadata_license)
+        )
+        .unwrap();
+        if let Some(project_license) = self.project_license.as_ref() {
+            writeln!(
+                s,
+                "  <project_license>{}</project_license>",
+                escape(project_license)
+            )
+            .unwrap();
+        }
+        if let Some(developer_name) = self.developer_name.as_ref() {
+            writeln!(
+                s,
+                "  <developer_name>{}</developer_name>",
+                escape(developer_name)
+            )
+            .unwrap();
+        }
+        if !self.description.is_empty() {
+            writeln!(s, "  <description>").unwrap();
+            for paragraph in &self.description {
+                writeln!(s, "    <p>{}</p>", escape(paragraph)).unwrap();
+            }
+            writeln!(s, "  </description>").unwrap();
+        }
+        writeln!(
+            s,
+            r#"  <launchable type="desktop-id">{}</launchable>"#,
+            escape(desktop_id)
+        )
+        .unwrap();
+        if let Some(homepage) = self.homepage.as_ref() {
+            writeln!(s, r#"  <url type="homepage">{}</url>"#, escape(homepage)).unwrap();
+        }
+        if !self.screenshots.is_empty() {
+            writeln!(s, "  <screenshots>").unwrap();
+            for (i, screenshot) in self.screenshots.iter().enumerate() {
+                if i == 0 {
+                    writeln!(s, r#"    <screenshot type="default">"#).unwrap();
+                } else {
+                    writeln!(s, "    <screenshot>").unwrap();
+                }
+                writeln!(s, "      <image>{}</image>", escape(screenshot)).unwrap();
+                writeln!(s, "    </screenshot>").unwrap();
+            }
+            writeln!(s, "  </screenshots>").unwrap();
+        }
+        if !entry.mime_types.is_empty() {
+            writeln!(s, "  <provides>").unwrap();
+            for mime_type in &entry.mime_types {
+                writeln!(s, "    <mediatype>{}</mediatype>", escape(mime_type)).unwrap();
+            }
+            writeln!(s, "  </provides>").unwrap();
+        }
+        if let Some(version) = self.version.as_ref() {
+            writeln!(s, "  <releases>").unwrap();
+            writeln!(s, r#"    <release version="{}"/>"#, escape(version)).unwrap();
+            writeln!(s, "  </releases>").unwrap();
+        }
+        writeln!(s, r#"  <content_rating type="oars-1.1"/>"#).unwrap();
+        writeln!(s, "</component>").unwrap();
+        s
+    }
+}
+
+/// Escapes a desktop entry string value.
+fn escape_value(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for (i, c) in s.chars().enumerate() {
+        match c {
+            ' ' if i == 0 => escaped.push_str("\\s"),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Quotes an argument of the `Exec` key if it contains reserved characters.
+fn quote_exec_arg(arg: &str) -> String {
+    let arg = arg.replace('%', "%%");
+    if !arg.contains([
+        ' ', '\t', '\n', '"', '\'', '\\', '>', '<', '~', '|', '&', ';', '$', '*', '?', '#', '(',
+        ')', '`',
+    ]) {
+        return arg;
+    }
+    let mut quoted = String::from('"');
+    for c in arg.chars() {
+        if matches!(c, '"' | '`' | '$' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_desktop_entry() {
+        let entry = DesktopEntry {
+            name: Some("Test App".into()),
+            categories: vec!["Graphics".into(), "Viewer".into()],
+            mime_types: vec!["image/png".into()],
+            startup_wm_class: Some("test".into()),
+            ..Default::default()
+        };
+        let desktop = entry.to_desktop_file("test", "com.example.Test");
+        assert!(desktop.starts_with("[Desktop Entry]\n"));
+        assert!(desktop.contains("Name=Test App\n"));
+        assert!(desktop.contains("Exec=test %u\n"));
+        assert!(desktop.contains("Icon=com.example.Test\n"));
+        assert!(desktop.contains("Categories=Graphics;Viewer;\n"));
+        assert!(desktop.contains("MimeType=image/png;\n"));
+        assert!(desktop.contains("StartupWMClass=test\n"));
+        assert!(!desktop.contains("Keywords"));
+
+        let desktop = DesktopEntry::default().to_desktop_file("test", "test");
+        assert!(desktop.contains("Name=test\n"));
+        assert!(desktop.contains("Categories=Utility;\n"));
+
+        let entry = DesktopEntry {
+            name: Some(" Test\nApp".into()),
+            keywords: vec!["a;b".into()],
+            ..Default::default()
+        };
+        let desktop = entry.to_desktop_file("/opt/my app/test", "test");
+        assert!(desktop.contains("Name=\\sTest\\nApp\n"));
+        assert!(desktop.contains("Keywords=a\\;b;\n"));
+        assert!(desktop.contains("Exec=\"/opt/my app/test\" %u\n"));
+        assert_eq!(quote_exec_arg("100%"), "100%%");
+        assert_eq!(quote_exec_arg("a$b"), "\"a\\$b\"");
+    }
+
+    #[test]
+    fn test_metainfo() {
+        let entry = DesktopEntry {
+            name: Some("Test & App".into()),
+            ..Default::default()
+        };
+        let metainfo = Metainfo {
+            summary: Some("A test app".into()),
+            description: vec!["First".into(), "Second".into()],
+            version: Some("1.0.0".into()),
+            ..Default::default()
+        };
+        let xml = metainfo.to_xml("com.example.Test", "com.example.Test.desktop", &entry);
+        assert!(xml.contains("<id>com.example.Test</id>"));
+        assert!(xml.contains("<name>Test &amp; App</name>"));
+        assert!(xml.contains("<summary>A test app</summary>"));
+        assert!(xml.contains("<metadata_license>CC0-1.0</metadata_license>"));
+        assert!(xml.contains("<p>First</p>\n    <p>Second</p>"));
+        assert!(
+            xml.contains(r#"<launchable type="desktop-id">com.example.Test.desktop</launchable>"#)
+        );
+        assert!(xml.contains(r#"<release version="1.0.0"/>"#));
+        assert!(xml.trim_end().ends_with("</component>"));
+    }
+}
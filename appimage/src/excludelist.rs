@@ -0,0 +1,73 @@
+/// Libraries that are expected to be provided by the host system and must
+/// not be bundled, based on the AppImage project's excludelist.
+///
+/// Bundling glibc or graphics drivers breaks AppImages on systems with a
+/// newer glibc or different gpu drivers.
+pub const EXCLUDED_LIBS: &[&str] = &[
+    // glibc
+    "ld-linux.so.2",
+    "ld-linux-x86-64.so.2",
+    "ld-linux-aarch64.so.1",
+    "libanl.so.1",
+    "libBrokenLocale.so.1",
+    "libc.so.6",
+    "libdl.so.2",
+    "libm.so.6",
+    "libmvec.so.1",
+    "libnsl.so.1",
+    "libnss_compat.so.2",
+    "libnss_dns.so.2",
+    "libnss_files.so.2",
+    "libnss_hesiod.so.2",
+    "libnss_nis.so.2",
+    "libnss_nisplus.so.2",
+    "libpthread.so.0",
+    "libresolv.so.2",
+    "librt.so.1",
+    "libthread_db.so.1",
+    "libutil.so.1",
+    // compiler runtimes
+    "libgcc_s.so.1",
+    "libstdc++.so.6",
+    // graphics drivers
+    "libdrm.so.2",
+    "libEGL.so.1",
+    "libgbm.so.1",
+    "libGL.so.1",
+    "libglapi.so.0",
+    "libGLdispatch.so.0",
+    "libGLESv2.so.2",
+    "libGLX.so.0",
+    "libOpenGL.so.0",
+    "libvulkan.so.1",
+    // display servers
+    "libICE.so.6",
+    "libSM.so.6",
+    "libwayland-client.so.0",
+    "libwayland-cursor.so.0",
+    "libwayland-egl.so.1",
+    "libwayland-server.so.0",
+    "libX11.so.6",
+    "libX11-xcb.so.1",
+    "libxcb.so.1",
+    "libxkbcommon.so.0",
+    // system services
+    "libasound.so.2",
+    "libdbus-1.so.3",
+    "libfontconfig.so.1",
+    "libfreetype.so.6",
+    "libharfbuzz.so.0",
+    "libjack.so.0",
+    "libpipewire-0.3.so.0",
+    "libpulse.so.0",
+    "libudev.so.1",
+    "libusb-1.0.so.0",
+    // misc
+    "libcom_err.so.2",
+    "libexpat.so.1",
+    "libgmp.so.10",
+    "libgpg-error.so.0",
+    "libp11-kit.so.0",
+    "libuuid.so.1",
+    "libz.so.1",
+];
@@ -0,0 +1,223 @@
+use crate::AppImage;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fmt::Write;
+use std::path::Path;
+use std::process::Command;
+
+/// Runtime and sandbox permissions of a flatpak.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FlatpakConfig {
+    pub runtime: String,
+    pub sdk: String,
+    pub runtime_version: String,
+    pub shared: Vec<String>,
+    pub sockets: Vec<String>,
+    pub devices: Vec<String>,
+    pub filesystems: Vec<String>,
+    /// Export a single file bundle, requires `flatpak`. When disabled only
+    /// the build directory is created.
+    pub bundle: bool,
+}
+
+impl Default for FlatpakConfig {
+    fn default() -> Self {
+        Self {
+            runtime: "org.freedesktop.Platform".into(),
+            sdk: "org.freedesktop.Sdk".into(),
+            runtime_version: "23.08".into(),
+            shared: vec!["ipc".into(), "network".into()],
+            sockets: vec!["x11".into(), "wayland".into(), "pulseaudio".into()],
+            devices: vec!["dri".into()],
+            filesystems: vec![],
+            bundle: true,
+        }
+    }
+}
+
+impl FlatpakConfig {
+    fn metadata(&self, id: &str, arch: &str, command: &str) -> String {
+        let mut s = String::new();
+        let list = |values: &[String]| values.iter().map(|v| format!("{};", v)).collect::<String>();
+        writeln!(s, "[Application]").unwrap();
+        writeln!(s, "name={}", id).unwrap();
+        writeln!(
+            s,
+            "runtime={}/{}/{}",
+            self.runtime, arch, self.runtime_version
+        )
+        .unwrap();
+        writeln!(s, "sdk={}/{}/{}", self.sdk, arch, self.runtime_version).unwrap();
+        writeln!(s, "command={}", command).unwrap();
+        writeln!(s).unwrap();
+        writeln!(s, "[Context]").unwrap();
+        for (key, values) in [
+            ("shared", &self.shared),
+            ("sockets", &self.sockets),
+            ("devices", &self.devices),
+            ("filesystems", &self.filesystems),
+        ] {
+            if !values.is_empty() {
+                writeln!(s, "{}={}", key, list(values)).unwrap();
+            }
+        }
+        s
+    }
+}
+
+impl AppImage {
+    /// Creates a flatpak build directory at `dir` from the contents of the
+    /// AppDir. `arch` is the flatpak arch, for example `x86_64`.
+    ///
+    /// The executable is installed to `/app/bin`, libraries to `/app/lib`
+    /// and the desktop entry and icons are exported.
+    pub fn create_flatpak_dir(&self, dir: &Path, arch: &str, config: &FlatpakConfig) -> Result<()> {
+        anyhow::ensure!(
+            self.id.split('.').filter(|c| !c.is_empty()).count() >= 3,
+            "flatpak app ids need at least three components, for example com.example.App, got {}",
+            self.id
+        );
+        std::fs::remove_dir_all(dir).ok();
+        let files = dir.join("files");
+        let bin = files.join("bin");
+        std::fs::create_dir_all(&bin)?;
+
+        let skip = [
+            "AppRun".to_string(),
+            ".DirIcon".to_string(),
+            "usr".to_string(),
+            format!("{}.desktop", self.id),
+            format!("{}.png", self.id),
+        ];
+        for entry in std::fs::read_dir(&self.appdir)? {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let file_name = file_name.to_str().context("invalid file name")?;
+            if skip.iter().any(|name| name == file_name) {
+                continue;
+            }
+            let dest = if file_name == self.name {
+                bin.join(file_name)
+            } else {
+                files.join(file_name)
+            };
+            if entry.file_type()?.is_dir() {
+                std::fs::create_dir_all(&dest)?;
+                xcommon::copy_dir_all(&entry.path(), &dest)?;
+            } else {
+                std::fs::copy(entry.path(), dest)?;
+            }
+        }
+        let share = self.appdir.join("usr").join("share");
+        if share.exists() {
+            std::fs::create_dir_all(files.join("share"))?;
+            xcommon::copy_dir_all(&share, &files.join("share"))?;
+        }
+
+        let export = dir.join("export").join("share");
+        let desktop_file = format!("{}.desktop", self.id);
+        let desktop = files.join("share").join("applications").join(&desktop_file);
+        if desktop.exists() {
+            let desktop = std::fs::read_to_string(desktop)?;
+            let mut exported = String::new();
+            for line in desktop.lines() {
+                if line.starts_with("Exec=") {
+                    writeln!(
+                        exported,
+                        "Exec=/usr/bin/flatpak run --branch=stable --arch={} --command={} --file-forwarding {} @@u %u @@",
+                        arch, self.name, self.id
+                    )?;
+                } else {
+                    writeln!(exported, "{}", line)?;
+                }
+            }
+            writeln!(exported, "X-Flatpak={}", self.id)?;
+            let applications = export.join("applications");
+            std::fs::create_dir_all(&applications)?;
+            std::fs::write(applications.join(desktop_file), exported)?;
+        }
+        let icons = files.join("share").join("icons");
+        if icons.exists() {
+            std::fs::create_dir_all(export.join("icons"))?;
+            xcommon::copy_dir_all(&icons, &export.join("icons"))?;
+        }
+
+        std::fs::write(
+            dir.join("metadata"),
+            config.metadata(&self.id, arch, &self.name),
+        )?;
+        Ok(())
+    }
+
+    /// Exports the flatpak build directory `dir` into a repository and
+    /// creates a single file bundle at `out` using the `flatpak` cli.
+    pub fn create_flatpak_bundle(&self, dir: &Path, arch: &str, out: &Path) -> Result<()> {
+        let repo = dir.with_file_name("flatpak-repo");
+        let status = Command::new("flatpak")
+            .arg("build-export")
+            .arg(format!("--arch={}", arch))
+            .arg(&repo)
+            .arg(dir)
+            .arg("stable")
+            .status()?;
+        anyhow::ensure!(status.success(), "flatpak build-export failed");
+        let status = Command::new("flatpak")
+            .arg("build-bundle")
+            .arg(format!("--arch={}", arch))
+            .arg("--runtime-repo=https://flathub.org/repo/flathub.flatpakrepo")
+            .arg(&repo)
+            .arg(out)
+            .arg(&self.id)
+            .arg("stable")
+            .status()?;
+        anyhow::ensure!(status.success(), "flatpak build-bundle failed");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DesktopEntry;
+
+    #[test]
+    fn test_flatpak_dir() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path();
+        let exe = dir.join("exe");
+        std::fs::write(&exe, b"\x7fELF")?;
+
+        let mut appimage = AppImage::new(dir, "test".into())?;
+        appimage.set_id("com.example.Test".into());
+        appimage.add_apprun()?;
+        appimage.add_desktop(&DesktopEntry::default())?;
+        appimage.add_file(&exe, Path::new("test"))?;
+        appimage.add_file(&exe, Path::new("lib/libfoo.so"))?;
+        let flatpak = dir.join("flatpak");
+        appimage.create_flatpak_dir(&flatpak, "x86_64", &FlatpakConfig::default())?;
+
+        assert!(flatpak.join("files/bin/test").exists());
+        assert!(flatpak.join("files/lib/libfoo.so").exists());
+        assert!(flatpak
+            .join("files/share/applications/com.example.Test.desktop")
+            .exists());
+        assert!(!flatpak.join("files/AppRun").exists());
+        let metadata = std::fs::read_to_string(flatpak.join("metadata"))?;
+        assert!(metadata.starts_with("[Application]\nname=com.example.Test\n"));
+        assert!(metadata.contains("runtime=org.freedesktop.Platform/x86_64/23.08\n"));
+        assert!(metadata.contains("command=test\n"));
+        assert!(metadata.contains("sockets=x11;wayland;pulseaudio;\n"));
+        let desktop = std::fs::read_to_string(
+            flatpak.join("export/share/applications/com.example.Test.desktop"),
+        )?;
+        assert!(desktop.contains("--command=test --file-forwarding com.example.Test @@u %u @@\n"));
+        assert!(desktop.contains("X-Flatpak=com.example.Test\n"));
+
+        appimage.set_id("test".into());
+        assert!(appimage
+            .create_flatpak_dir(&flatpak, "x86_64", &FlatpakConfig::default())
+            .is_err());
+        Ok(())
+    }
+}
@@ -1,17 +1,41 @@
 use anyhow::{Context, Result};
-use std::fs::File;
+use squashfs::Squashfs;
+use std::fs::{File, OpenOptions};
 use std::io::{BufReader, BufWriter, Write};
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use xcommon::Signer;
+use xcommon::{Scaler, ScalerOpts, Signer};
+
+mod deb;
+mod desktop;
+mod excludelist;
+mod flatpak;
+mod package;
+mod pgp;
+mod rpm;
+mod sign;
+mod squashfs;
+mod zsync;
+
+pub use desktop::{DesktopEntry, Metainfo};
+pub use excludelist::EXCLUDED_LIBS;
+pub use flatpak::FlatpakConfig;
+pub use package::PackageConfig;
+pub use squashfs::Compression;
 
 static RUNTIME: &[u8] = include_bytes!("../assets/runtime-x86_64");
 
+const HICOLOR_ICON_SIZES: [u32; 8] = [16, 24, 32, 48, 64, 128, 256, 512];
+
 pub struct AppImage {
     appdir: PathBuf,
     name: String,
+    id: String,
+    symlinks: Vec<(PathBuf, String)>,
+    compression: Compression,
+    update_information: Option<String>,
+    runtime: Option<PathBuf>,
 }
 
 impl AppImage {
@@ -19,45 +43,99 @@ impl AppImage {
         let appdir = build_dir.join(format!("{}.AppDir", name));
         std::fs::remove_dir_all(&appdir).ok();
         std::fs::create_dir_all(&appdir)?;
-        Ok(Self { appdir, name })
+        Ok(Self {
+            appdir,
+            id: name.clone(),
+            name,
+            symlinks: vec![],
+            compression: Compression::default(),
+            update_information: None,
+            runtime: None,
+        })
     }
 
     pub fn appdir(&self) -> &Path {
         &self.appdir
     }
 
-    pub fn add_apprun(&self) -> Result<()> {
+    /// Sets the id used to name the desktop entry, icons and metainfo,
+    /// defaults to the name.
+    pub fn set_id(&mut self, id: String) {
+        self.id = id;
+    }
+
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    /// Uses the runtime at `path` instead of the embedded x86_64 runtime,
+    /// needed for AppImages targeting other architectures.
+    pub fn set_runtime(&mut self, path: PathBuf) {
+        self.runtime = Some(path);
+    }
+
+    /// Sets the update information used by AppImageUpdate, for example
+    /// `gh-releases-zsync|user|repo|latest|App-*-x86_64.AppImage.zsync`.
+    ///
+    /// When set a `.zsync` file is created next to the AppImage.
+    pub fn set_update_information(&mut self, update_information: String) {
+        self.update_information = Some(update_information);
+    }
+
+    pub fn add_apprun(&mut self) -> Result<()> {
+        self.add_symlink(Path::new("AppRun"), &self.name.clone())
+    }
+
+    /// Adds a symlink to the image. It is also created in the AppDir on
+    /// hosts that support symlinks.
+    fn add_symlink(&mut self, path: &Path, target: &str) -> Result<()> {
         #[cfg(unix)]
-        std::os::unix::fs::symlink(&self.name, self.appdir.join("AppRun"))?;
+        std::os::unix::fs::symlink(target, self.appdir.join(path))?;
+        self.symlinks.push((path.to_path_buf(), target.to_string()));
         Ok(())
     }
 
-    pub fn add_desktop(&self) -> Result<()> {
-        let mut f = File::create(self.appdir.join(format!("{}.desktop", &self.name)))?;
-        writeln!(f, "[Desktop Entry]")?;
-        writeln!(f, "Version=1.0")?;
-        writeln!(f, "Type=Application")?;
-        writeln!(f, "Terminal=false")?;
-        writeln!(f, "Name={}", self.name)?;
-        writeln!(f, "Exec={} %u", self.name)?;
-        writeln!(f, "Icon={}", self.name)?;
-        writeln!(f, "Categories=Utility;")?;
+    pub fn add_desktop(&self, entry: &DesktopEntry) -> Result<()> {
+        let desktop = entry.to_desktop_file(&self.name, &self.id);
+        let file_name = format!("{}.desktop", self.id);
+        std::fs::write(self.appdir.join(&file_name), &desktop)?;
+        let applications = self.appdir.join("usr").join("share").join("applications");
+        std::fs::create_dir_all(&applications)?;
+        std::fs::write(applications.join(file_name), desktop)?;
         Ok(())
     }
 
-    pub fn add_icon(&self, path: &Path) -> Result<()> {
-        let ext = path
-            .extension()
-            .map(|ext| ext.to_str())
-            .unwrap_or_default()
-            .context("unsupported extension")?;
-        let name = format!("{}.{}", self.name, ext);
-        self.add_file(path, Path::new(&name))?;
-        #[cfg(unix)]
-        std::os::unix::fs::symlink(name, self.appdir.join(".DirIcon"))?;
+    /// Adds AppStream metadata for the application.
+    pub fn add_metainfo(&self, entry: &DesktopEntry, metainfo: &Metainfo) -> Result<()> {
+        let desktop_id = format!("{}.desktop", self.id);
+        let xml = metainfo.to_xml(&self.id, &desktop_id, entry);
+        let dir = self.appdir.join("usr").join("share").join("metainfo");
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join(format!("{}.metainfo.xml", self.id)), xml)?;
         Ok(())
     }
 
+    /// Adds the icon in the sizes of the hicolor icon theme.
+    pub fn add_icon(&mut self, path: &Path) -> Result<()> {
+        let scaler = Scaler::open(path)?;
+        let icons = self
+            .appdir
+            .join("usr")
+            .join("share")
+            .join("icons")
+            .join("hicolor");
+        let file_name = format!("{}.png", self.id);
+        for size in HICOLOR_ICON_SIZES {
+            let dir = icons.join(format!("{}x{}", size, size)).join("apps");
+            std::fs::create_dir_all(&dir)?;
+            let mut icon = BufWriter::new(File::create(dir.join(&file_name))?);
+            scaler.write(&mut icon, ScalerOpts::new(size))?;
+        }
+        let mut icon = BufWriter::new(File::create(self.appdir.join(&file_name))?);
+        scaler.write(&mut icon, ScalerOpts::new(256))?;
+        self.add_symlink(Path::new(".DirIcon"), &file_name)
+    }
+
     pub fn add_file(&self, path: &Path, name: &Path) -> Result<()> {
         let dest = self.appdir.join(name);
         if let Some(parent) = dest.parent() {
@@ -74,32 +152,108 @@ impl AppImage {
         Ok(())
     }
 
-    pub fn build(self, out: &Path, _signer: Option<Signer>) -> Result<()> {
-        let squashfs = self
-            .appdir
-            .parent()
-            .unwrap()
-            .join(format!("{}.squashfs", self.name));
-        let status = Command::new("mksquashfs")
-            .arg(&self.appdir)
-            .arg(&squashfs)
-            .arg("-root-owned")
-            .arg("-noappend")
-            .arg("-quiet")
-            .status()?;
-        anyhow::ensure!(
-            status.success(),
-            "mksquashfs failed with exit code {:?}",
-            status
-        );
-        let mut squashfs = BufReader::new(File::open(squashfs)?);
-        let mut f = File::create(out)?;
+    pub fn build(self, out: &Path, signer: Option<Signer>) -> Result<()> {
+        let mut squashfs = Squashfs::new(self.compression);
+        squashfs.add_dir_all(&self.appdir)?;
+        for (path, target) in &self.symlinks {
+            squashfs.add_symlink(path, target)?;
+        }
+        let mut f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(out)?;
         #[cfg(unix)]
         f.set_permissions(std::fs::Permissions::from_mode(0o755))?;
-        let mut out = BufWriter::new(&mut f);
-        out.write_all(RUNTIME)?;
-        std::io::copy(&mut squashfs, &mut out)?;
-        // TODO: sign
+        {
+            let mut out = BufWriter::new(&mut f);
+            if let Some(runtime) = self.runtime.as_ref() {
+                let runtime = std::fs::read(runtime)
+                    .with_context(|| format!("failed to read runtime {}", runtime.display()))?;
+                out.write_all(&runtime)?;
+            } else {
+                out.write_all(RUNTIME)?;
+            }
+            squashfs.write(&mut out)?;
+            out.flush()?;
+        }
+        if let Some(update_information) = self.update_information.as_ref() {
+            sign::write_section(
+                &mut f,
+                sign::UPDATE_INFO_SECTION,
+                update_information.as_bytes(),
+            )?;
+        }
+        if let Some(signer) = signer {
+            sign::sign(&mut f, &signer)?;
+        }
+        if self.update_information.is_some() {
+            let file_name = out
+                .file_name()
+                .and_then(|name| name.to_str())
+                .context("invalid appimage file name")?;
+            let zsync = out.with_file_name(format!("{}.zsync", file_name));
+            let mut r = BufReader::new(File::open(out)?);
+            let mut w = BufWriter::new(File::create(zsync)?);
+            zsync::write_zsync(&mut r, &mut w, file_name)?;
+            w.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Verifies the signature of an AppImage and returns the fingerprint
+    /// of the signing key.
+    pub fn verify(path: &Path) -> Result<String> {
+        let mut r = BufReader::new(File::open(path)?);
+        sign::verify(&mut r)
+    }
+
+    /// Returns the update information embedded in an AppImage.
+    pub fn update_information(path: &Path) -> Result<Option<String>> {
+        let mut r = BufReader::new(File::open(path)?);
+        let info = sign::read_section(&mut r, sign::UPDATE_INFO_SECTION)?;
+        if info.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(String::from_utf8(info)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEM: &str = include_str!("../assets/test.pem");
+
+    #[test]
+    fn test_sign() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path();
+        let exe = dir.join("test");
+        std::fs::write(&exe, b"\x7fELF")?;
+
+        let mut appimage = AppImage::new(dir, "test".into())?;
+        appimage.add_apprun()?;
+        appimage.add_desktop(&Default::default())?;
+        appimage.add_file(&exe, Path::new("test"))?;
+        let info = "zsync|https://example.com/test.AppImage.zsync";
+        appimage.set_update_information(info.into());
+        let out = dir.join("test.AppImage");
+        let signer = Signer::new(PEM)?;
+        appimage.build(&out, Some(signer.clone()))?;
+
+        let fingerprint = AppImage::verify(&out)?;
+        assert_eq!(fingerprint, pgp::tests::fingerprint(&signer));
+        assert_eq!(AppImage::update_information(&out)?.as_deref(), Some(info));
+        let zsync = std::fs::read(dir.join("test.AppImage.zsync"))?;
+        assert!(zsync.starts_with(b"zsync: 0.6.2\nFilename: test.AppImage\n"));
+
+        let mut image = std::fs::read(&out)?;
+        let len = image.len();
+        image[len - 1] ^= 1;
+        std::fs::write(&out, image)?;
+        assert!(AppImage::verify(&out).is_err());
         Ok(())
     }
 }
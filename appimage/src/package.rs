@@ -0,0 +1,186 @@
+use crate::AppImage;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Metadata of deb and rpm packages.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackageConfig {
+    /// Defaults to the package version.
+    pub version: Option<String>,
+    /// Defaults to the package description.
+    pub description: Option<String>,
+    /// Maintainer of the deb package in the form `Name <email>`, defaults
+    /// to the metainfo developer name.
+    pub maintainer: Option<String>,
+    /// Defaults to the metainfo homepage.
+    pub homepage: Option<String>,
+    /// Defaults to the metainfo project license.
+    pub license: Option<String>,
+    /// Dependencies of the deb package, for example `libc6 (>= 2.31)`.
+    #[serde(default)]
+    pub depends: Vec<String>,
+    /// Dependencies of the rpm package, for example `glibc`.
+    #[serde(default)]
+    pub requires: Vec<String>,
+}
+
+impl PackageConfig {
+    /// Returns the version with the semver pre-release separator replaced
+    /// by `~`, so pre-releases sort before the release.
+    pub(crate) fn version(&self) -> Result<String> {
+        let version = self.version.as_deref().context("missing package version")?;
+        Ok(version.replace('-', "~"))
+    }
+
+    /// Returns the maintainer, which is required by deb packages.
+    pub(crate) fn maintainer(&self) -> Result<&str> {
+        let maintainer = self
+            .maintainer
+            .as_deref()
+            .context("missing package maintainer, set `linux.package.maintainer`")?;
+        let valid = maintainer
+            .split_once('<')
+            .map(|(name, email)| {
+                !name.trim().is_empty() && email.ends_with('>') && email.contains('@')
+            })
+            .unwrap_or_default();
+        anyhow::ensure!(
+            valid,
+            "invalid package maintainer `{}`, expected `Name <email>`",
+            maintainer
+        );
+        Ok(maintainer)
+    }
+
+    /// Returns the first line of the description and the remaining lines.
+    pub(crate) fn summary<'a>(&'a self, name: &'a str) -> (&'a str, Vec<&'a str>) {
+        let mut lines = self
+            .description
+            .as_deref()
+            .unwrap_or_default()
+            .trim()
+            .lines();
+        let summary = lines.next().filter(|s| !s.is_empty()).unwrap_or(name);
+        (summary, lines.collect())
+    }
+}
+
+pub(crate) enum EntryKind {
+    Dir,
+    File(PathBuf),
+    Symlink(String),
+}
+
+pub(crate) struct Entry {
+    /// Absolute path of the entry in the installed system.
+    pub path: String,
+    pub kind: EntryKind,
+    /// Permission bits without the file type.
+    pub mode: u32,
+    pub size: u64,
+}
+
+impl AppImage {
+    /// Maps the AppDir to the system hierarchy used by deb and rpm packages.
+    ///
+    /// The executable and everything next to it is installed to
+    /// `/usr/lib/<name>` and symlinked from `/usr/bin`, so the `$ORIGIN/lib`
+    /// rpath keeps working. `usr/share` is installed to `/usr/share`.
+    pub(crate) fn package_entries(&self) -> Result<Vec<Entry>> {
+        let prefix = format!("/usr/lib/{}", self.name);
+        let mut entries = vec![
+            Entry {
+                path: prefix.clone(),
+                kind: EntryKind::Dir,
+                mode: 0o755,
+                size: 0,
+            },
+            Entry {
+                path: format!("/usr/bin/{}", self.name),
+                kind: EntryKind::Symlink(format!("../lib/{0}/{0}", self.name)),
+                mode: 0o777,
+                size: 0,
+            },
+        ];
+        let skip = [
+            "AppRun".to_string(),
+            ".DirIcon".to_string(),
+            "usr".to_string(),
+            format!("{}.desktop", self.id),
+            format!("{}.png", self.id),
+        ];
+        for entry in std::fs::read_dir(&self.appdir)? {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let file_name = file_name.to_str().context("invalid file name")?;
+            if skip.iter().any(|name| name == file_name) {
+                continue;
+            }
+            self.walk(
+                &entry.path(),
+                format!("{}/{}", prefix, file_name),
+                &mut entries,
+            )?;
+        }
+        let share = self.appdir.join("usr").join("share");
+        if share.exists() {
+            for entry in std::fs::read_dir(&share)? {
+                let entry = entry?;
+                let file_name = entry.file_name();
+                let file_name = file_name.to_str().context("invalid file name")?;
+                self.walk(
+                    &entry.path(),
+                    format!("/usr/share/{}", file_name),
+                    &mut entries,
+                )?;
+            }
+        }
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(entries)
+    }
+
+    fn walk(&self, source: &Path, path: String, entries: &mut Vec<Entry>) -> Result<()> {
+        let metadata = std::fs::symlink_metadata(source)?;
+        if metadata.file_type().is_symlink() {
+            let target = std::fs::read_link(source)?;
+            let target = target.to_str().context("invalid symlink")?.to_string();
+            entries.push(Entry {
+                path,
+                kind: EntryKind::Symlink(target),
+                mode: 0o777,
+                size: 0,
+            });
+        } else if metadata.is_dir() {
+            for entry in std::fs::read_dir(source)? {
+                let entry = entry?;
+                let file_name = entry.file_name();
+                let file_name = file_name.to_str().context("invalid file name")?;
+                self.walk(&entry.path(), format!("{}/{}", path, file_name), entries)?;
+            }
+            entries.push(Entry {
+                path,
+                kind: EntryKind::Dir,
+                mode: 0o755,
+                size: 0,
+            });
+        } else {
+            #[cfg(unix)]
+            let executable = metadata.permissions().mode() & 0o111 != 0;
+            #[cfg(not(unix))]
+            let executable = false;
+            let file_name = source.file_name().unwrap().to_str().unwrap_or_default();
+            let executable = executable || file_name == self.name || file_name.contains(".so");
+            entries.push(Entry {
+                path,
+                kind: EntryKind::File(source.to_path_buf()),
+                mode: if executable { 0o755 } else { 0o644 },
+                size: metadata.len(),
+            });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,391 @@
+//! Minimal OpenPGP (RFC 4880) support for the signatures appimagetool
+//! creates: an ASCII-armored detached RSA/SHA256 signature and the
+//! ASCII-armored public key of the signer.
+use anyhow::{Context, Result};
+use rsa::{BigUint, PaddingScheme, PublicKey as _, PublicKeyParts, RsaPublicKey};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+use xcommon::Signer;
+
+const SIGNATURE_TAG: u8 = 2;
+const PUBLIC_KEY_TAG: u8 = 6;
+const USER_ID_TAG: u8 = 13;
+
+const RSA: u8 = 1;
+const SHA256: u8 = 8;
+
+const BINARY_SIGNATURE: u8 = 0x00;
+const POSITIVE_CERTIFICATION: u8 = 0x13;
+
+const CREATION_TIME_SUBPACKET: u8 = 2;
+const ISSUER_SUBPACKET: u8 = 16;
+const KEY_FLAGS_SUBPACKET: u8 = 27;
+const ISSUER_FINGERPRINT_SUBPACKET: u8 = 33;
+
+/// Key may certify other keys and sign data.
+const KEY_FLAGS: u8 = 0x03;
+
+const SIGNATURE_ARMOR: &str = "PGP SIGNATURE";
+const PUBLIC_KEY_ARMOR: &str = "PGP PUBLIC KEY BLOCK";
+
+struct Key {
+    created: u32,
+    pubkey: RsaPublicKey,
+}
+
+impl Key {
+    /// The key is created at the start of the certificate validity, so
+    /// that the same certificate always results in the same fingerprint.
+    fn from_signer(signer: &Signer) -> Self {
+        let created = match signer.cert().tbs_certificate.validity.not_before {
+            rasn_pkix::Time::Utc(time) => time.timestamp(),
+            rasn_pkix::Time::General(time) => time.timestamp(),
+        };
+        Self {
+            created: created.clamp(0, u32::MAX as i64) as u32,
+            pubkey: signer.pubkey().clone(),
+        }
+    }
+
+    fn body(&self) -> Vec<u8> {
+        let mut body = vec![4];
+        body.extend_from_slice(&self.created.to_be_bytes());
+        body.push(RSA);
+        body.extend(mpi(&self.pubkey.n().to_bytes_be()));
+        body.extend(mpi(&self.pubkey.e().to_bytes_be()));
+        body
+    }
+
+    /// Data hashed when the key is the subject of a signature.
+    fn hashed(&self) -> Vec<u8> {
+        let body = self.body();
+        let mut hashed = vec![0x99];
+        hashed.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        hashed.extend(body);
+        hashed
+    }
+
+    fn fingerprint(&self) -> [u8; 20] {
+        Sha1::digest(self.hashed()).into()
+    }
+
+    fn key_id(&self) -> [u8; 8] {
+        self.fingerprint()[12..].try_into().unwrap()
+    }
+}
+
+/// Returns the ASCII-armored OpenPGP public key of `signer`, with a user id
+/// derived from the certificate subject.
+pub fn public_key(signer: &Signer) -> Result<String> {
+    let key = Key::from_signer(signer);
+    let subject = signer.subject();
+    let user_id = ["CN", "O"]
+        .iter()
+        .find_map(|name| subject.iter().find(|(n, _)| n == name))
+        .map(|(_, value)| value.as_str())
+        .unwrap_or("AppImage signing key");
+    let mut hashed = key.hashed();
+    hashed.push(0xb4);
+    hashed.extend_from_slice(&(user_id.len() as u32).to_be_bytes());
+    hashed.extend_from_slice(user_id.as_bytes());
+
+    let mut packets = packet(PUBLIC_KEY_TAG, &key.body());
+    packets.extend(packet(USER_ID_TAG, user_id.as_bytes()));
+    packets.extend(signature(
+        signer,
+        &key,
+        POSITIVE_CERTIFICATION,
+        key.created,
+        &[KEY_FLAGS],
+        &hashed,
+    )?);
+    Ok(armor(PUBLIC_KEY_ARMOR, &packets))
+}
+
+/// Returns an ASCII-armored detached signature of `data`.
+pub fn sign(signer: &Signer, data: &[u8]) -> Result<String> {
+    let key = Key::from_signer(signer);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+    let signature = signature(signer, &key, BINARY_SIGNATURE, now, &[], data)?;
+    Ok(armor(SIGNATURE_ARMOR, &signature))
+}
+
+/// Verifies the ASCII-armored detached `signature` of `data` made by the
+/// ASCII-armored public `key` and returns the fingerprint of the key.
+pub fn verify(key: &[u8], signature: &[u8], data: &[u8]) -> Result<String> {
+    let key = dearmor(key, PUBLIC_KEY_ARMOR)?;
+    let (tag, body, _) = read_packet(&key)?;
+    anyhow::ensure!(tag == PUBLIC_KEY_TAG, "expected a public key");
+    anyhow::ensure!(body.first() == Some(&4), "unsupported key version");
+    let created = body.get(1..5).context("invalid public key")?;
+    anyhow::ensure!(body.get(5) == Some(&RSA), "unsupported key algorithm");
+    let (n, rest) = read_mpi(body.get(6..).unwrap_or_default())?;
+    let (e, _) = read_mpi(rest)?;
+    let key = Key {
+        created: u32::from_be_bytes(created.try_into()?),
+        pubkey: RsaPublicKey::new(BigUint::from_bytes_be(n), BigUint::from_bytes_be(e))?,
+    };
+
+    let signature = dearmor(signature, SIGNATURE_ARMOR)?;
+    let (tag, body, _) = read_packet(&signature)?;
+    anyhow::ensure!(tag == SIGNATURE_TAG, "expected a signature");
+    let header = body.get(..6).context("invalid signature")?;
+    anyhow::ensure!(header[0] == 4, "unsupported signature version");
+    anyhow::ensure!(header[1] == BINARY_SIGNATURE, "expected a binary signature");
+    anyhow::ensure!(header[2] == RSA, "unsupported signature algorithm");
+    anyhow::ensure!(header[3] == SHA256, "unsupported signature hash");
+    let hashed_len = 6 + u16::from_be_bytes([header[4], header[5]]) as usize;
+    let trailer = body.get(..hashed_len).context("invalid signature")?;
+    let rest = &body[hashed_len..];
+    let unhashed_len = rest.get(..2).context("invalid signature")?;
+    let unhashed_len = 2 + u16::from_be_bytes([unhashed_len[0], unhashed_len[1]]) as usize;
+    let rest = rest.get(unhashed_len..).context("invalid signature")?;
+    let rest = rest.get(2..).context("invalid signature")?;
+    let (sig, _) = read_mpi(rest)?;
+
+    let digest = signature_digest(data, trailer);
+    // Leading zeros are stripped from the mpi.
+    let mut padded = vec![0; key.pubkey.size().saturating_sub(sig.len())];
+    padded.extend_from_slice(sig);
+    let padding = PaddingScheme::new_pkcs1v15_sign::<Sha256>();
+    key.pubkey
+        .verify(padding, &digest, &padded)
+        .context("invalid signature")?;
+    Ok(hex(&key.fingerprint()))
+}
+
+fn signature_digest(data: &[u8], trailer: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.update(trailer);
+    hasher.update([4, 0xff]);
+    hasher.update((trailer.len() as u32).to_be_bytes());
+    hasher.finalize().to_vec()
+}
+
+fn signature(
+    signer: &Signer,
+    key: &Key,
+    ty: u8,
+    created: u32,
+    key_flags: &[u8],
+    data: &[u8],
+) -> Result<Vec<u8>> {
+    let mut hashed = subpacket(CREATION_TIME_SUBPACKET, &created.to_be_bytes());
+    if !key_flags.is_empty() {
+        hashed.extend(subpacket(KEY_FLAGS_SUBPACKET, key_flags));
+    }
+    let mut fingerprint = vec![4];
+    fingerprint.extend_from_slice(&key.fingerprint());
+    hashed.extend(subpacket(ISSUER_FINGERPRINT_SUBPACKET, &fingerprint));
+    let unhashed = subpacket(ISSUER_SUBPACKET, &key.key_id());
+
+    let mut body = vec![4, ty, RSA, SHA256];
+    body.extend_from_slice(&(hashed.len() as u16).to_be_bytes());
+    body.extend(hashed);
+    let digest = signature_digest(data, &body);
+    let padding = PaddingScheme::new_pkcs1v15_sign::<Sha256>();
+    let signature = signer.key().sign(padding, &digest)?;
+    body.extend_from_slice(&(unhashed.len() as u16).to_be_bytes());
+    body.extend(unhashed);
+    body.extend_from_slice(&digest[..2]);
+    body.extend(mpi(&signature));
+    Ok(packet(SIGNATURE_TAG, &body))
+}
+
+fn mpi(bytes: &[u8]) -> Vec<u8> {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    let bytes = &bytes[start..];
+    let bits = bytes
+        .first()
+        .map(|b| (bytes.len() * 8 - b.leading_zeros() as usize) as u16)
+        .unwrap_or_default();
+    let mut mpi = bits.to_be_bytes().to_vec();
+    mpi.extend_from_slice(bytes);
+    mpi
+}
+
+fn read_mpi(bytes: &[u8]) -> Result<(&[u8], &[u8])> {
+    let bits = bytes.get(..2).context("invalid mpi")?;
+    let len = (u16::from_be_bytes([bits[0], bits[1]]) as usize).div_ceil(8);
+    let mpi = bytes.get(2..2 + len).context("invalid mpi")?;
+    Ok((mpi, &bytes[2 + len..]))
+}
+
+/// Creates a new format packet.
+fn packet(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0xc0 | tag];
+    match body.len() {
+        len if len < 192 => packet.push(len as u8),
+        len if len < 8384 => {
+            let len = len - 192;
+            packet.push((len >> 8) as u8 + 192);
+            packet.push(len as u8);
+        }
+        len => {
+            packet.push(0xff);
+            packet.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// Reads the first packet and returns its tag, body and the remaining
+/// bytes. Partial and indeterminate lengths are not supported.
+fn read_packet(bytes: &[u8]) -> Result<(u8, &[u8], &[u8])> {
+    let (header, rest) = bytes.split_first().context("missing packet")?;
+    anyhow::ensure!(header & 0x80 != 0, "invalid packet header");
+    let (tag, len, rest) = if header & 0x40 != 0 {
+        let tag = header & 0x3f;
+        match *rest.first().context("invalid packet length")? {
+            len @ 0..=191 => (tag, len as usize, &rest[1..]),
+            len @ 192..=223 => {
+                let len2 = *rest.get(1).context("invalid packet length")?;
+                let len = ((len as usize - 192) << 8) + len2 as usize + 192;
+                (tag, len, &rest[2..])
+            }
+            255 => {
+                let len = rest.get(1..5).context("invalid packet length")?;
+                (
+                    tag,
+                    u32::from_be_bytes(len.try_into()?) as usize,
+                    &rest[5..],
+                )
+            }
+            _ => anyhow::bail!("partial packet lengths are not supported"),
+        }
+    } else {
+        let tag = (header >> 2) & 0xf;
+        let size = match header & 3 {
+            0 => 1,
+            1 => 2,
+            2 => 4,
+            _ => anyhow::bail!("indeterminate packet lengths are not supported"),
+        };
+        let len = rest.get(..size).context("invalid packet length")?;
+        let len = len.iter().fold(0, |len, b| (len << 8) | *b as usize);
+        (tag, len, &rest[size..])
+    };
+    let body = rest.get(..len).context("truncated packet")?;
+    Ok((tag, body, &rest[len..]))
+}
+
+fn subpacket(ty: u8, data: &[u8]) -> Vec<u8> {
+    // All subpackets used here are shorter than 192 bytes.
+    let mut subpacket = vec![data.len() as u8 + 1, ty];
+    subpacket.extend_from_slice(data);
+    subpacket
+}
+
+fn crc24(data: &[u8]) -> u32 {
+    let mut crc = 0xb7_04ce_u32;
+    for byte in data {
+        crc ^= (*byte as u32) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x100_0000 != 0 {
+                crc ^= 0x186_4cfb;
+            }
+        }
+    }
+    crc & 0xff_ffff
+}
+
+fn armor(kind: &str, data: &[u8]) -> String {
+    let mut armor = format!("-----BEGIN {}-----\n\n", kind);
+    let encoded = base64::encode(data);
+    for line in encoded.as_bytes().chunks(64) {
+        armor.push_str(std::str::from_utf8(line).unwrap());
+        armor.push('\n');
+    }
+    armor.push('=');
+    armor.push_str(&base64::encode(&crc24(data).to_be_bytes()[1..]));
+    armor.push_str(&format!("\n-----END {}-----\n", kind));
+    armor
+}
+
+fn dearmor(armor: &[u8], kind: &str) -> Result<Vec<u8>> {
+    let armor = std::str::from_utf8(armor)?;
+    let begin = format!("-----BEGIN {}-----", kind);
+    let end = format!("-----END {}-----", kind);
+    let mut lines = armor
+        .lines()
+        .map(|line| line.trim_end())
+        .skip_while(|line| *line != begin)
+        .skip(1)
+        .skip_while(|line| !line.is_empty())
+        .skip(1);
+    let mut encoded = String::new();
+    let mut checksum = None;
+    for line in &mut lines {
+        if line == end {
+            break;
+        } else if let Some(crc) = line.strip_prefix('=') {
+            checksum = Some(crc.to_string());
+        } else {
+            encoded.push_str(line);
+        }
+    }
+    anyhow::ensure!(!encoded.is_empty(), "missing {}", kind.to_lowercase());
+    let data = base64::decode(encoded)?;
+    if let Some(checksum) = checksum {
+        let checksum = base64::decode(checksum)?;
+        anyhow::ensure!(
+            checksum == crc24(&data).to_be_bytes()[1..],
+            "invalid armor checksum"
+        );
+    }
+    Ok(data)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    const PEM: &str = include_str!("../assets/test.pem");
+
+    /// Returns the fingerprint of the OpenPGP key of `signer` as hex.
+    pub fn fingerprint(signer: &Signer) -> String {
+        hex(&Key::from_signer(signer).fingerprint())
+    }
+
+    #[test]
+    fn test_sign() -> Result<()> {
+        let signer = Signer::new(PEM)?;
+        let key = public_key(&signer)?;
+        assert!(key.starts_with("-----BEGIN PGP PUBLIC KEY BLOCK-----\n\n"));
+        let signature = sign(&signer, b"hello world")?;
+        assert!(signature.starts_with("-----BEGIN PGP SIGNATURE-----\n\n"));
+        assert!(signature.ends_with("-----END PGP SIGNATURE-----\n"));
+        let fingerprint = verify(key.as_bytes(), signature.as_bytes(), b"hello world")?;
+        assert_eq!(fingerprint, self::fingerprint(&signer));
+        assert!(verify(key.as_bytes(), signature.as_bytes(), b"hello").is_err());
+        assert!(verify(key.as_bytes(), b"", b"hello world").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_packets() -> Result<()> {
+        for len in [0, 191, 192, 8383, 8384, 70000] {
+            let body = vec![7; len];
+            let packet = packet(USER_ID_TAG, &body);
+            let (tag, parsed, rest) = read_packet(&packet)?;
+            assert_eq!(tag, USER_ID_TAG);
+            assert_eq!(parsed, body);
+            assert!(rest.is_empty());
+        }
+        // old format user id packet with a one byte length
+        let (tag, body, _) = read_packet(&[0xb4, 2, b'h', b'i'])?;
+        assert_eq!((tag, body), (USER_ID_TAG, &b"hi"[..]));
+        assert!(read_packet(&[0xcd, 5, 1]).is_err());
+        assert_eq!(mpi(&[0, 1, 0]), [0, 9, 1, 0]);
+        assert_eq!(crc24(b""), 0xb704ce);
+        Ok(())
+    }
+}
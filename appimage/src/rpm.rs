@@ -0,0 +1,523 @@
+use crate::package::{EntryKind, PackageConfig};
+use crate::zsync::hex;
+use crate::AppImage;
+use anyhow::Result;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const HEADER_MAGIC: [u8; 4] = [0x8e, 0xad, 0xe8, 0x01];
+const LEAD_MAGIC: [u8; 4] = [0xed, 0xab, 0xee, 0xdb];
+
+const TAG_HEADERSIGNATURES: u32 = 62;
+const TAG_HEADERIMMUTABLE: u32 = 63;
+const TAG_HEADERI18NTABLE: u32 = 100;
+
+const SIGTAG_SHA1: u32 = 269;
+const SIGTAG_SHA256: u32 = 273;
+const SIGTAG_SIZE: u32 = 1000;
+const SIGTAG_MD5: u32 = 1004;
+const SIGTAG_PAYLOADSIZE: u32 = 1007;
+
+const TAG_NAME: u32 = 1000;
+const TAG_VERSION: u32 = 1001;
+const TAG_RELEASE: u32 = 1002;
+const TAG_SUMMARY: u32 = 1004;
+const TAG_DESCRIPTION: u32 = 1005;
+const TAG_BUILDTIME: u32 = 1006;
+const TAG_SIZE: u32 = 1009;
+const TAG_LICENSE: u32 = 1014;
+const TAG_GROUP: u32 = 1016;
+const TAG_URL: u32 = 1020;
+const TAG_OS: u32 = 1021;
+const TAG_ARCH: u32 = 1022;
+const TAG_FILESIZES: u32 = 1028;
+const TAG_FILEMODES: u32 = 1030;
+const TAG_FILERDEVS: u32 = 1033;
+const TAG_FILEMTIMES: u32 = 1034;
+const TAG_FILEDIGESTS: u32 = 1035;
+const TAG_FILELINKTOS: u32 = 1036;
+const TAG_FILEFLAGS: u32 = 1037;
+const TAG_FILEUSERNAME: u32 = 1039;
+const TAG_FILEGROUPNAME: u32 = 1040;
+const TAG_PROVIDENAME: u32 = 1047;
+const TAG_REQUIREFLAGS: u32 = 1048;
+const TAG_REQUIRENAME: u32 = 1049;
+const TAG_REQUIREVERSION: u32 = 1050;
+const TAG_FILEDEVICES: u32 = 1095;
+const TAG_FILEINODES: u32 = 1096;
+const TAG_FILELANGS: u32 = 1097;
+const TAG_PROVIDEFLAGS: u32 = 1112;
+const TAG_PROVIDEVERSION: u32 = 1113;
+const TAG_DIRINDEXES: u32 = 1116;
+const TAG_BASENAMES: u32 = 1117;
+const TAG_DIRNAMES: u32 = 1118;
+const TAG_PAYLOADFORMAT: u32 = 1124;
+const TAG_PAYLOADCOMPRESSOR: u32 = 1125;
+const TAG_PAYLOADFLAGS: u32 = 1126;
+const TAG_FILEDIGESTALGO: u32 = 5011;
+const TAG_PAYLOADDIGEST: u32 = 5092;
+const TAG_PAYLOADDIGESTALGO: u32 = 5093;
+
+const DIGEST_ALGO_SHA256: u32 = 8;
+
+const SENSE_LESS: u32 = 1 << 1;
+const SENSE_EQUAL: u32 = 1 << 3;
+const SENSE_RPMLIB: u32 = 1 << 24;
+
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+enum Value {
+    Int16(Vec<u16>),
+    Int32(Vec<u32>),
+    String(String),
+    Bin(Vec<u8>),
+    StringArray(Vec<String>),
+    I18nString(String),
+}
+
+impl Value {
+    fn ty(&self) -> u32 {
+        match self {
+            Self::Int16(_) => 3,
+            Self::Int32(_) => 4,
+            Self::String(_) => 6,
+            Self::Bin(_) => 7,
+            Self::StringArray(_) => 8,
+            Self::I18nString(_) => 9,
+        }
+    }
+
+    fn count(&self) -> usize {
+        match self {
+            Self::Int16(v) => v.len(),
+            Self::Int32(v) => v.len(),
+            Self::Bin(v) => v.len(),
+            Self::StringArray(v) => v.len(),
+            Self::String(_) | Self::I18nString(_) => 1,
+        }
+    }
+
+    fn alignment(&self) -> usize {
+        match self {
+            Self::Int16(_) => 2,
+            Self::Int32(_) => 4,
+            _ => 1,
+        }
+    }
+
+    fn write(&self, data: &mut Vec<u8>) {
+        match self {
+            Self::Int16(v) => v.iter().for_each(|v| data.extend(v.to_be_bytes())),
+            Self::Int32(v) => v.iter().for_each(|v| data.extend(v.to_be_bytes())),
+            Self::Bin(v) => data.extend(v),
+            Self::String(s) | Self::I18nString(s) => {
+                data.extend(s.as_bytes());
+                data.push(0);
+            }
+            Self::StringArray(v) => {
+                for s in v {
+                    data.extend(s.as_bytes());
+                    data.push(0);
+                }
+            }
+        }
+    }
+}
+
+/// Header structure used for both the signature and the main header.
+#[derive(Default)]
+struct Header {
+    entries: Vec<(u32, Value)>,
+}
+
+impl Header {
+    fn add(&mut self, tag: u32, value: Value) {
+        self.entries.push((tag, value));
+    }
+
+    /// Serializes the header with an immutable region tagged `region`.
+    fn finish(mut self, region: u32) -> Vec<u8> {
+        self.entries.sort_by_key(|(tag, _)| *tag);
+        let count = self.entries.len() as u32 + 1;
+        let mut index = vec![];
+        let mut data = vec![];
+        for (tag, value) in &self.entries {
+            while !data.len().is_multiple_of(value.alignment()) {
+                data.push(0);
+            }
+            for field in [*tag, value.ty(), data.len() as u32, value.count() as u32] {
+                index.extend(field.to_be_bytes());
+            }
+            value.write(&mut data);
+        }
+        let trailer = data.len() as u32;
+        for field in [region, 7, (-(count as i32 * 16)) as u32, 16] {
+            data.extend(field.to_be_bytes());
+        }
+
+        let mut bytes = vec![];
+        bytes.extend(HEADER_MAGIC);
+        bytes.extend([0; 4]);
+        bytes.extend(count.to_be_bytes());
+        bytes.extend((data.len() as u32).to_be_bytes());
+        for field in [region, 7, trailer, 16] {
+            bytes.extend(field.to_be_bytes());
+        }
+        bytes.extend(index);
+        bytes.extend(data);
+        bytes
+    }
+}
+
+impl AppImage {
+    /// Creates an rpm package at `out` from the contents of the AppDir.
+    /// `arch` is the rpm architecture, for example `x86_64`.
+    pub fn create_rpm(&self, out: &Path, arch: &str, config: &PackageConfig) -> Result<()> {
+        let version = config.version()?;
+        let release = "1";
+        let mtime = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+
+        // rpm packages don't own system directories like `/usr/bin`
+        let prefix = format!("/usr/lib/{}", self.name);
+        let entries = self
+            .package_entries()?
+            .into_iter()
+            .filter(|entry| {
+                !matches!(entry.kind, EntryKind::Dir)
+                    || entry.path == prefix
+                    || entry.path.starts_with(&format!("{}/", prefix))
+            })
+            .collect::<Vec<_>>();
+
+        let mut archive = vec![];
+        let mut sizes = vec![];
+        let mut modes = vec![];
+        let mut digests = vec![];
+        let mut links = vec![];
+        let mut dir_indexes = vec![];
+        let mut basenames = vec![];
+        let mut dirnames: Vec<String> = vec![];
+        let mut total_size = 0;
+        for (i, entry) in entries.iter().enumerate() {
+            let (mode, contents, digest, link) = match &entry.kind {
+                EntryKind::Dir => (S_IFDIR | entry.mode, vec![], String::new(), String::new()),
+                EntryKind::File(source) => {
+                    let contents = std::fs::read(source)?;
+                    let digest = hex(&Sha256::digest(&contents));
+                    (S_IFREG | entry.mode, contents, digest, String::new())
+                }
+                EntryKind::Symlink(target) => (
+                    S_IFLNK | entry.mode,
+                    target.as_bytes().to_vec(),
+                    String::new(),
+                    target.clone(),
+                ),
+            };
+            anyhow::ensure!(
+                contents.len() < u32::MAX as usize,
+                "{} is too large for an rpm package",
+                entry.path
+            );
+            let size = if let EntryKind::Dir = entry.kind {
+                4096
+            } else {
+                contents.len() as u32
+            };
+            write_cpio_entry(
+                &mut archive,
+                i as u32 + 1,
+                mode,
+                mtime,
+                &format!(".{}", entry.path),
+                &contents,
+            );
+            total_size += size;
+            sizes.push(size);
+            modes.push(mode as u16);
+            digests.push(digest);
+            links.push(link);
+            let (dirname, basename) = entry.path.rsplit_once('/').unwrap();
+            let dirname = format!("{}/", dirname);
+            let dir_index = match dirnames.iter().position(|d| *d == dirname) {
+                Some(index) => index,
+                None => {
+                    dirnames.push(dirname);
+                    dirnames.len() - 1
+                }
+            };
+            dir_indexes.push(dir_index as u32);
+            basenames.push(basename.to_string());
+        }
+        write_cpio_entry(&mut archive, 0, 0, 0, "TRAILER!!!", &[]);
+        let mut payload = GzEncoder::new(vec![], Compression::best());
+        payload.write_all(&archive)?;
+        let payload = payload.finish()?;
+
+        let n = entries.len();
+        let (summary, description) = config.summary(&self.name);
+        let description = if description.is_empty() {
+            summary.to_string()
+        } else {
+            description.join("\n")
+        };
+        let mut requires = vec![
+            ("rpmlib(CompressedFileNames)", "3.0.4-1"),
+            ("rpmlib(FileDigests)", "4.6.0-1"),
+            ("rpmlib(PayloadFilesHavePrefix)", "4.0-1"),
+        ];
+        let rpmlib_requires = requires.len();
+        requires.extend(config.requires.iter().map(|name| (name.as_str(), "")));
+        let require_flags = (0..requires.len())
+            .map(|i| {
+                if i < rpmlib_requires {
+                    SENSE_RPMLIB | SENSE_LESS | SENSE_EQUAL
+                } else {
+                    0
+                }
+            })
+            .collect();
+
+        let mut header = Header::default();
+        header.add(TAG_HEADERI18NTABLE, Value::StringArray(vec!["C".into()]));
+        header.add(TAG_NAME, Value::String(self.name.clone()));
+        header.add(TAG_VERSION, Value::String(version.clone()));
+        header.add(TAG_RELEASE, Value::String(release.into()));
+        header.add(TAG_SUMMARY, Value::I18nString(summary.into()));
+        header.add(TAG_DESCRIPTION, Value::I18nString(description));
+        header.add(TAG_BUILDTIME, Value::Int32(vec![mtime]));
+        header.add(TAG_SIZE, Value::Int32(vec![total_size]));
+        header.add(
+            TAG_LICENSE,
+            Value::String(config.license.clone().unwrap_or_else(|| "Unknown".into())),
+        );
+        header.add(TAG_GROUP, Value::I18nString("Unspecified".into()));
+        if let Some(homepage) = config.homepage.as_ref() {
+            header.add(TAG_URL, Value::String(homepage.clone()));
+        }
+        header.add(TAG_OS, Value::String("linux".into()));
+        header.add(TAG_ARCH, Value::String(arch.into()));
+        header.add(TAG_FILESIZES, Value::Int32(sizes));
+        header.add(TAG_FILEMODES, Value::Int16(modes));
+        header.add(TAG_FILERDEVS, Value::Int16(vec![0; n]));
+        header.add(TAG_FILEMTIMES, Value::Int32(vec![mtime; n]));
+        header.add(TAG_FILEDIGESTS, Value::StringArray(digests));
+        header.add(TAG_FILELINKTOS, Value::StringArray(links));
+        header.add(TAG_FILEFLAGS, Value::Int32(vec![0; n]));
+        header.add(TAG_FILEUSERNAME, Value::StringArray(vec!["root".into(); n]));
+        header.add(
+            TAG_FILEGROUPNAME,
+            Value::StringArray(vec!["root".into(); n]),
+        );
+        header.add(TAG_PROVIDENAME, Value::StringArray(vec![self.name.clone()]));
+        header.add(TAG_PROVIDEFLAGS, Value::Int32(vec![SENSE_EQUAL]));
+        header.add(
+            TAG_PROVIDEVERSION,
+            Value::StringArray(vec![format!("{}-{}", version, release)]),
+        );
+        header.add(TAG_REQUIREFLAGS, Value::Int32(require_flags));
+        header.add(
+            TAG_REQUIRENAME,
+            Value::StringArray(requires.iter().map(|r| r.0.to_string()).collect()),
+        );
+        header.add(
+            TAG_REQUIREVERSION,
+            Value::StringArray(requires.iter().map(|r| r.1.to_string()).collect()),
+        );
+        header.add(TAG_FILEDEVICES, Value::Int32(vec![1; n]));
+        header.add(TAG_FILEINODES, Value::Int32((1..=n as u32).collect()));
+        header.add(TAG_FILELANGS, Value::StringArray(vec![String::new(); n]));
+        header.add(TAG_DIRINDEXES, Value::Int32(dir_indexes));
+        header.add(TAG_BASENAMES, Value::StringArray(basenames));
+        header.add(TAG_DIRNAMES, Value::StringArray(dirnames));
+        header.add(TAG_PAYLOADFORMAT, Value::String("cpio".into()));
+        header.add(TAG_PAYLOADCOMPRESSOR, Value::String("gzip".into()));
+        header.add(TAG_PAYLOADFLAGS, Value::String("9".into()));
+        header.add(TAG_FILEDIGESTALGO, Value::Int32(vec![DIGEST_ALGO_SHA256]));
+        header.add(
+            TAG_PAYLOADDIGEST,
+            Value::StringArray(vec![hex(&Sha256::digest(&payload))]),
+        );
+        header.add(
+            TAG_PAYLOADDIGESTALGO,
+            Value::Int32(vec![DIGEST_ALGO_SHA256]),
+        );
+        let header = header.finish(TAG_HEADERIMMUTABLE);
+
+        let mut md5 = Md5::new();
+        md5.update(&header);
+        md5.update(&payload);
+        let mut signature = Header::default();
+        signature.add(SIGTAG_SHA1, Value::String(hex(&Sha1::digest(&header))));
+        signature.add(SIGTAG_SHA256, Value::String(hex(&Sha256::digest(&header))));
+        signature.add(
+            SIGTAG_SIZE,
+            Value::Int32(vec![(header.len() + payload.len()) as u32]),
+        );
+        signature.add(SIGTAG_MD5, Value::Bin(md5.finalize().to_vec()));
+        signature.add(SIGTAG_PAYLOADSIZE, Value::Int32(vec![archive.len() as u32]));
+        let mut signature = signature.finish(TAG_HEADERSIGNATURES);
+        while !signature.len().is_multiple_of(8) {
+            signature.push(0);
+        }
+
+        let mut lead = [0; 96];
+        lead[..4].copy_from_slice(&LEAD_MAGIC);
+        lead[4] = 3;
+        lead[8..10].copy_from_slice(&1u16.to_be_bytes());
+        let lead_name = format!("{}-{}-{}", self.name, version, release);
+        let len = lead_name.len().min(65);
+        lead[10..10 + len].copy_from_slice(&lead_name.as_bytes()[..len]);
+        lead[76..78].copy_from_slice(&1u16.to_be_bytes());
+        lead[78..80].copy_from_slice(&5u16.to_be_bytes());
+
+        let mut w = BufWriter::new(File::create(out)?);
+        w.write_all(&lead)?;
+        w.write_all(&signature)?;
+        w.write_all(&header)?;
+        w.write_all(&payload)?;
+        w.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes a `newc` cpio entry.
+fn write_cpio_entry(w: &mut Vec<u8>, ino: u32, mode: u32, mtime: u32, name: &str, data: &[u8]) {
+    let nlink = if mode & S_IFDIR == S_IFDIR { 2 } else { 1 };
+    let fields = [
+        ino,
+        mode,
+        0,
+        0,
+        nlink,
+        mtime,
+        data.len() as u32,
+        0,
+        0,
+        0,
+        0,
+        name.len() as u32 + 1,
+        0,
+    ];
+    w.extend(b"070701");
+    for field in fields {
+        w.extend(format!("{:08x}", field).as_bytes());
+    }
+    w.extend(name.as_bytes());
+    w.push(0);
+    while !w.len().is_multiple_of(4) {
+        w.push(0);
+    }
+    w.extend(data);
+    while !w.len().is_multiple_of(4) {
+        w.push(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DesktopEntry;
+    use std::io::Read;
+
+    fn read_u32(bytes: &[u8], pos: usize) -> u32 {
+        u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap())
+    }
+
+    /// Parses a header returning its tags, string values and length.
+    fn read_header(bytes: &[u8], region: u32) -> (Vec<(u32, String)>, usize) {
+        assert_eq!(bytes[..4], HEADER_MAGIC);
+        let count = read_u32(bytes, 8) as usize;
+        let data_len = read_u32(bytes, 12) as usize;
+        let data = &bytes[16 + count * 16..16 + count * 16 + data_len];
+        assert_eq!(read_u32(bytes, 16), region);
+        let trailer = read_u32(bytes, 24) as usize;
+        assert_eq!(trailer + 16, data_len);
+        assert_eq!(read_u32(data, trailer + 8) as i32, -(count as i32 * 16));
+        let mut tags = vec![];
+        for i in 1..count {
+            let entry = 16 + i * 16;
+            let tag = read_u32(bytes, entry);
+            let offset = read_u32(bytes, entry + 8) as usize;
+            let value = match read_u32(bytes, entry + 4) {
+                6 | 9 => {
+                    let end = data[offset..].iter().position(|b| *b == 0).unwrap();
+                    String::from_utf8(data[offset..offset + end].to_vec()).unwrap()
+                }
+                4 => read_u32(data, offset).to_string(),
+                _ => String::new(),
+            };
+            tags.push((tag, value));
+        }
+        let mut tag_order = tags.iter().map(|t| t.0).collect::<Vec<_>>();
+        tag_order.sort_unstable();
+        assert_eq!(tag_order, tags.iter().map(|t| t.0).collect::<Vec<_>>());
+        (tags, 16 + count * 16 + data_len)
+    }
+
+    #[test]
+    fn test_rpm() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path();
+        let exe = dir.join("exe");
+        std::fs::write(&exe, b"\x7fELF")?;
+
+        let appimage = AppImage::new(dir, "test".into())?;
+        appimage.add_desktop(&DesktopEntry::default())?;
+        appimage.add_file(&exe, Path::new("test"))?;
+        appimage.add_file(&exe, Path::new("lib/libfoo.so"))?;
+        let config = PackageConfig {
+            version: Some("1.0.0".into()),
+            description: Some("A test app".into()),
+            license: Some("MIT".into()),
+            ..Default::default()
+        };
+        let out = dir.join("test.rpm");
+        appimage.create_rpm(&out, "x86_64", &config)?;
+
+        let bytes = std::fs::read(&out)?;
+        assert_eq!(bytes[..4], LEAD_MAGIC);
+        assert!(bytes[10..].starts_with(b"test-1.0.0-1\0"));
+        let (signature, len) = read_header(&bytes[96..], TAG_HEADERSIGNATURES);
+        let header_start = 96 + len.div_ceil(8) * 8;
+        let (tags, len) = read_header(&bytes[header_start..], TAG_HEADERIMMUTABLE);
+        let header = &bytes[header_start..header_start + len];
+        let payload = &bytes[header_start + len..];
+
+        let sig = |tag| signature.iter().find(|t| t.0 == tag).unwrap().1.clone();
+        assert_eq!(sig(SIGTAG_SHA256), hex(&Sha256::digest(header)));
+        assert_eq!(sig(SIGTAG_SIZE), (header.len() + payload.len()).to_string());
+
+        let tag = |tag| tags.iter().find(|t| t.0 == tag).unwrap().1.clone();
+        assert_eq!(tag(TAG_NAME), "test");
+        assert_eq!(tag(TAG_VERSION), "1.0.0");
+        assert_eq!(tag(TAG_SUMMARY), "A test app");
+        assert_eq!(tag(TAG_LICENSE), "MIT");
+        assert_eq!(tag(TAG_ARCH), "x86_64");
+
+        let mut archive = vec![];
+        flate2::read::GzDecoder::new(payload).read_to_end(&mut archive)?;
+        assert_eq!(sig(SIGTAG_PAYLOADSIZE), archive.len().to_string());
+        let archive = String::from_utf8_lossy(&archive);
+        assert!(archive.starts_with("070701"));
+        for path in [
+            "./usr/bin/test\0",
+            "./usr/lib/test/test\0",
+            "./usr/lib/test/lib/libfoo.so\0",
+            "./usr/share/applications/test.desktop\0",
+            "TRAILER!!!\0",
+        ] {
+            assert!(archive.contains(path), "{}", path);
+        }
+        assert!(!archive.contains("./usr/bin\0"));
+        Ok(())
+    }
+}
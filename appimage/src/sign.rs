@@ -0,0 +1,173 @@
+use crate::pgp;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use xcommon::Signer;
+
+pub const UPDATE_INFO_SECTION: &str = ".upd_info";
+pub const SIGNATURE_SECTION: &str = ".sha256_sig";
+pub const KEY_SECTION: &str = ".sig_key";
+
+/// Upper bound for the section header and section name tables, the runtime
+/// only has a few dozen sections.
+const MAX_TABLE_SIZE: u64 = 1024 * 1024;
+
+/// Returns the file range of the section `name` in the runtime elf.
+pub fn section<R: Read + Seek>(r: &mut R, name: &str) -> Result<Range<u64>> {
+    let mut header = [0; 64];
+    r.seek(SeekFrom::Start(0))?;
+    r.read_exact(&mut header)?;
+    anyhow::ensure!(&header[..4] == b"\x7fELF", "not an elf file");
+    anyhow::ensure!(header[4] == 2 && header[5] == 1, "expected a 64-bit le elf");
+    let u16_at = |buf: &[u8], i: usize| u16::from_le_bytes([buf[i], buf[i + 1]]) as u64;
+    let u32_at = |buf: &[u8], i: usize| u32::from_le_bytes(buf[i..i + 4].try_into().unwrap());
+    let u64_at = |buf: &[u8], i: usize| u64::from_le_bytes(buf[i..i + 8].try_into().unwrap());
+    let shoff = u64_at(&header, 0x28);
+    let shentsize = u16_at(&header, 0x3a);
+    let shnum = u16_at(&header, 0x3c);
+    let shstrndx = u16_at(&header, 0x3e);
+    anyhow::ensure!(shentsize >= 64, "invalid section header size");
+
+    let sections_size = shentsize
+        .checked_mul(shnum)
+        .filter(|size| *size <= MAX_TABLE_SIZE)
+        .context("invalid section header table size")?;
+    let mut sections = vec![0; sections_size as usize];
+    r.seek(SeekFrom::Start(shoff))?;
+    r.read_exact(&mut sections)?;
+    let section = |i: u64| sections.get((i * shentsize) as usize..((i + 1) * shentsize) as usize);
+    let strtab = section(shstrndx).context("invalid section name table index")?;
+    let names_size = u64_at(strtab, 0x20);
+    anyhow::ensure!(
+        names_size <= MAX_TABLE_SIZE,
+        "invalid section name table size"
+    );
+    let mut names = vec![0; names_size as usize];
+    r.seek(SeekFrom::Start(u64_at(strtab, 0x18)))?;
+    r.read_exact(&mut names)?;
+
+    for i in 0..shnum {
+        let header = section(i).context("invalid section header")?;
+        let start = u32_at(header, 0) as usize;
+        let name_bytes = names.get(start..).context("invalid section name")?;
+        let end = name_bytes
+            .iter()
+            .position(|c| *c == 0)
+            .context("invalid section name")?;
+        if &name_bytes[..end] == name.as_bytes() {
+            let offset = u64_at(header, 0x18);
+            let size = u64_at(header, 0x20);
+            let end = offset.checked_add(size).context("invalid section size")?;
+            return Ok(offset..end);
+        }
+    }
+    anyhow::bail!("section {} not found", name);
+}
+
+/// Writes `data` to the section `name`, the rest of the section is zeroed.
+pub fn write_section<F: Read + Write + Seek>(f: &mut F, name: &str, data: &[u8]) -> Result<()> {
+    let range = section(f, name)?;
+    anyhow::ensure!(
+        data.len() as u64 <= range.end - range.start,
+        "{} doesn't fit into section {}",
+        data.len(),
+        name
+    );
+    let mut buf = vec![0; (range.end - range.start) as usize];
+    buf[..data.len()].copy_from_slice(data);
+    f.seek(SeekFrom::Start(range.start))?;
+    f.write_all(&buf)?;
+    Ok(())
+}
+
+/// Reads the contents of section `name` up to the first nul byte.
+pub fn read_section<R: Read + Seek>(r: &mut R, name: &str) -> Result<Vec<u8>> {
+    let range = section(r, name)?;
+    let mut buf = vec![0; (range.end - range.start) as usize];
+    r.seek(SeekFrom::Start(range.start))?;
+    r.read_exact(&mut buf)?;
+    let end = buf.iter().position(|c| *c == 0).unwrap_or(buf.len());
+    buf.truncate(end);
+    Ok(buf)
+}
+
+/// Computes the hex encoded sha256 of the AppImage with the signature
+/// sections zeroed.
+fn digest<R: Read + Seek>(r: &mut R) -> Result<String> {
+    let skip = [section(r, SIGNATURE_SECTION)?, section(r, KEY_SECTION)?];
+    r.seek(SeekFrom::Start(0))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut pos = 0;
+    loop {
+        let n = r.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        let chunk = &mut buf[..n];
+        for range in &skip {
+            let start = range.start.clamp(pos, pos + n as u64) - pos;
+            let end = range.end.clamp(pos, pos + n as u64) - pos;
+            chunk[start as usize..end as usize].fill(0);
+        }
+        hasher.update(chunk);
+        pos += n as u64;
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Signs the AppImage like appimagetool does. The hex encoded digest is
+/// signed with an ASCII-armored OpenPGP signature stored in the
+/// `.sha256_sig` section, the ASCII-armored public key is stored in the
+/// `.sig_key` section.
+pub fn sign<F: Read + Write + Seek>(f: &mut F, signer: &Signer) -> Result<()> {
+    write_section(f, SIGNATURE_SECTION, &[])?;
+    write_section(f, KEY_SECTION, &[])?;
+    let digest = digest(f)?;
+    let signature = pgp::sign(signer, digest.as_bytes())?;
+    let key = pgp::public_key(signer)?;
+    write_section(f, SIGNATURE_SECTION, signature.as_bytes())?;
+    write_section(f, KEY_SECTION, key.as_bytes())?;
+    Ok(())
+}
+
+/// Verifies the signature of an AppImage and returns the fingerprint of
+/// the signing key.
+pub fn verify<R: Read + Seek>(r: &mut R) -> Result<String> {
+    let key = read_section(r, KEY_SECTION)?;
+    anyhow::ensure!(!key.is_empty(), "AppImage is not signed");
+    let signature = read_section(r, SIGNATURE_SECTION)?;
+    let digest = digest(r)?;
+    pgp::verify(&key, &signature, digest.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn elf(shstrndx: u16, names_size: u64) -> Cursor<Vec<u8>> {
+        let mut elf = vec![0; 128];
+        elf[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        elf[0x28..0x30].copy_from_slice(&64u64.to_le_bytes());
+        elf[0x3a..0x3c].copy_from_slice(&64u16.to_le_bytes());
+        elf[0x3c..0x3e].copy_from_slice(&1u16.to_le_bytes());
+        elf[0x3e..0x40].copy_from_slice(&shstrndx.to_le_bytes());
+        elf[64..68].copy_from_slice(&100u32.to_le_bytes());
+        elf[64 + 0x18..64 + 0x20].copy_from_slice(&0u64.to_le_bytes());
+        elf[64 + 0x20..64 + 0x28].copy_from_slice(&names_size.to_le_bytes());
+        Cursor::new(elf)
+    }
+
+    #[test]
+    fn test_malformed_section() {
+        assert!(section(&mut elf(1, 16), KEY_SECTION).is_err());
+        assert!(section(&mut elf(0, u64::MAX), KEY_SECTION).is_err());
+        assert!(section(&mut elf(0, 16), KEY_SECTION).is_err());
+    }
+}
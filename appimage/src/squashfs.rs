@@ -0,0 +1,753 @@
+//! Writer for squashfs 4.0 images.
+//!
+//! Images are written without fragments, xattrs or an export table and all
+//! entries are owned by root.
+use anyhow::{Context, Result};
+use flate2::write::ZlibEncoder;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+const MAGIC: u32 = 0x7371_7368;
+const BLOCK_LOG: u16 = 17;
+const BLOCK_SIZE: usize = 1 << BLOCK_LOG;
+const METADATA_SIZE: usize = 8192;
+const SUPERBLOCK_SIZE: u64 = 96;
+const INVALID: u64 = u64::MAX;
+const INVALID_FRAGMENT: u32 = u32::MAX;
+const NO_FRAGMENTS: u16 = 0x0010;
+const NO_XATTRS: u16 = 0x0200;
+
+const DIR_TYPE: u16 = 1;
+const FILE_TYPE: u16 = 2;
+const SYMLINK_TYPE: u16 = 3;
+const LDIR_TYPE: u16 = 8;
+const LFILE_TYPE: u16 = 9;
+
+/// Compression of the squashfs file system.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    #[default]
+    Gzip,
+    /// Requires a runtime built with zstd support, for example the
+    /// static runtime of AppImage/type2-runtime. The embedded AppImageKit
+    /// runtimes only support gzip and xz.
+    Zstd,
+}
+
+impl Compression {
+    fn id(self) -> u16 {
+        match self {
+            Self::Gzip => 1,
+            Self::Zstd => 6,
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(match self {
+            Self::Gzip => {
+                let mut encoder = ZlibEncoder::new(vec![], flate2::Compression::best());
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Self::Zstd => zstd::bulk::compress(data, 19)?,
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Node {
+    Dir {
+        entries: BTreeMap<String, Node>,
+        mode: u16,
+        mtime: u32,
+    },
+    File {
+        source: PathBuf,
+        mode: u16,
+        mtime: u32,
+    },
+    Symlink {
+        target: String,
+        mtime: u32,
+    },
+}
+
+impl Node {
+    fn dir() -> Self {
+        Self::Dir {
+            entries: Default::default(),
+            mode: 0o755,
+            mtime: 0,
+        }
+    }
+
+    fn count(&self) -> u32 {
+        match self {
+            Self::Dir { entries, .. } => 1 + entries.values().map(Node::count).sum::<u32>(),
+            _ => 1,
+        }
+    }
+}
+
+/// Builds a squashfs image from files on disk.
+#[derive(Debug)]
+pub struct Squashfs {
+    root: Node,
+    compression: Compression,
+}
+
+impl Squashfs {
+    pub fn new(compression: Compression) -> Self {
+        Self {
+            root: Node::dir(),
+            compression,
+        }
+    }
+
+    fn entry(&mut self, path: &Path) -> Result<(&mut BTreeMap<String, Node>, String)> {
+        let mut components = vec![];
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => {
+                    components.push(name.to_str().context("invalid file name")?.to_string())
+                }
+                Component::CurDir => {}
+                _ => anyhow::bail!("invalid path {}", path.display()),
+            }
+        }
+        let name = components.pop().context("empty path")?;
+        let mut dir = &mut self.root;
+        for component in components {
+            let Node::Dir { entries, .. } = dir else {
+                anyhow::bail!("{} is not a directory", path.display());
+            };
+            dir = entries.entry(component).or_insert_with(Node::dir);
+        }
+        let Node::Dir { entries, .. } = dir else {
+            anyhow::bail!("{} is not a directory", path.display());
+        };
+        Ok((entries, name))
+    }
+
+    /// Adds the contents of `source` to the root of the image.
+    pub fn add_dir_all(&mut self, source: &Path) -> Result<()> {
+        let (mode, mtime) = metadata(source, &std::fs::metadata(source)?);
+        if let Node::Dir {
+            mode: root_mode,
+            mtime: root_mtime,
+            ..
+        } = &mut self.root
+        {
+            *root_mode = mode;
+            *root_mtime = mtime;
+        }
+        self.add_dir_entries(source, Path::new(""))
+    }
+
+    fn add_dir_entries(&mut self, source: &Path, dest: &Path) -> Result<()> {
+        for entry in std::fs::read_dir(source)? {
+            let entry = entry?;
+            let source = entry.path();
+            let dest = dest.join(entry.file_name());
+            let file_type = entry.file_type()?;
+            if file_type.is_symlink() {
+                let target = std::fs::read_link(&source)?;
+                let target = target.to_str().context("invalid symlink")?;
+                self.add_symlink(&dest, target)?;
+            } else if file_type.is_dir() {
+                let (mode, mtime) = metadata(&source, &entry.metadata()?);
+                let (entries, name) = self.entry(&dest)?;
+                entries.insert(
+                    name,
+                    Node::Dir {
+                        entries: Default::default(),
+                        mode,
+                        mtime,
+                    },
+                );
+                self.add_dir_entries(&source, &dest)?;
+            } else {
+                self.add_file(&source, &dest)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the file `source` at `dest`.
+    pub fn add_file(&mut self, source: &Path, dest: &Path) -> Result<()> {
+        let (mode, mtime) = metadata(source, &std::fs::metadata(source)?);
+        let (entries, name) = self.entry(dest)?;
+        entries.insert(
+            name,
+            Node::File {
+                source: source.to_path_buf(),
+                mode,
+                mtime,
+            },
+        );
+        Ok(())
+    }
+
+    /// Adds a symlink at `dest` pointing to `target`.
+    pub fn add_symlink(&mut self, dest: &Path, target: &str) -> Result<()> {
+        let (entries, name) = self.entry(dest)?;
+        entries.insert(
+            name,
+            Node::Symlink {
+                target: target.replace('\\', "/"),
+                mtime: 0,
+            },
+        );
+        Ok(())
+    }
+
+    /// Writes the image at the current position of `w`.
+    pub fn write<W: Write + Seek>(&self, w: &mut W) -> Result<()> {
+        let start = w.stream_position()?;
+        w.write_all(&[0; SUPERBLOCK_SIZE as usize])?;
+        let mut writer = Writer {
+            w,
+            position: SUPERBLOCK_SIZE,
+            compression: self.compression,
+            inodes: Metadata::new(self.compression),
+            dirs: Metadata::new(self.compression),
+        };
+        let inode_count = self.root.count();
+        let (root, _) = writer.write_node(&self.root, 1, inode_count + 1)?;
+        let Writer {
+            w,
+            mut position,
+            inodes,
+            dirs,
+            ..
+        } = writer;
+
+        let inode_table_start = position;
+        let inodes = inodes.finish()?;
+        w.write_all(&inodes)?;
+        position += inodes.len() as u64;
+
+        let directory_table_start = position;
+        let dirs = dirs.finish()?;
+        w.write_all(&dirs)?;
+        position += dirs.len() as u64;
+
+        // there are no fragments, so the fragment table is empty.
+        let fragment_table_start = position;
+
+        let mut ids = Metadata::new(self.compression);
+        ids.write(&0u32.to_le_bytes())?;
+        let ids = ids.finish()?;
+        let id_block = position;
+        w.write_all(&ids)?;
+        position += ids.len() as u64;
+        let id_table_start = position;
+        w.write_all(&id_block.to_le_bytes())?;
+        position += 8;
+        let bytes_used = position;
+
+        let padding = (4096 - bytes_used % 4096) % 4096;
+        w.write_all(&vec![0; padding as usize])?;
+        let end = w.stream_position()?;
+
+        let mut superblock = Vec::with_capacity(SUPERBLOCK_SIZE as usize);
+        superblock.extend_from_slice(&MAGIC.to_le_bytes());
+        superblock.extend_from_slice(&inode_count.to_le_bytes());
+        superblock.extend_from_slice(&self.root_mtime().to_le_bytes());
+        superblock.extend_from_slice(&(BLOCK_SIZE as u32).to_le_bytes());
+        // fragment count
+        superblock.extend_from_slice(&0u32.to_le_bytes());
+        superblock.extend_from_slice(&self.compression.id().to_le_bytes());
+        superblock.extend_from_slice(&BLOCK_LOG.to_le_bytes());
+        superblock.extend_from_slice(&(NO_FRAGMENTS | NO_XATTRS).to_le_bytes());
+        // id count
+        superblock.extend_from_slice(&1u16.to_le_bytes());
+        superblock.extend_from_slice(&4u16.to_le_bytes());
+        superblock.extend_from_slice(&0u16.to_le_bytes());
+        for value in [
+            root,
+            bytes_used,
+            id_table_start,
+            INVALID,
+            inode_table_start,
+            directory_table_start,
+            fragment_table_start,
+            INVALID,
+        ] {
+            superblock.extend_from_slice(&value.to_le_bytes());
+        }
+        w.seek(SeekFrom::Start(start))?;
+        w.write_all(&superblock)?;
+        w.seek(SeekFrom::Start(end))?;
+        Ok(())
+    }
+
+    fn root_mtime(&self) -> u32 {
+        match &self.root {
+            Node::Dir { mtime, .. } => *mtime,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn metadata(_path: &Path, metadata: &std::fs::Metadata) -> (u16, u32) {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    (
+        (metadata.permissions().mode() & 0o7777) as u16,
+        metadata.mtime().clamp(0, u32::MAX as i64) as u32,
+    )
+}
+
+#[cfg(not(unix))]
+fn metadata(path: &Path, metadata: &std::fs::Metadata) -> (u16, u32) {
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|mtime| mtime.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|mtime| mtime.as_secs() as u32)
+        .unwrap_or_default();
+    // there are no permissions to carry over, so elf files are assumed
+    // to be executable.
+    let mut magic = [0; 4];
+    let elf = std::fs::File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .map(|_| &magic == b"\x7fELF")
+        .unwrap_or_default();
+    let mode = if metadata.is_dir() || elf {
+        0o755
+    } else {
+        0o644
+    };
+    (mode, mtime)
+}
+
+/// Writer for a table of metadata blocks.
+struct Metadata {
+    compression: Compression,
+    buf: Vec<u8>,
+    out: Vec<u8>,
+}
+
+impl Metadata {
+    fn new(compression: Compression) -> Self {
+        Self {
+            compression,
+            buf: Vec::with_capacity(METADATA_SIZE),
+            out: vec![],
+        }
+    }
+
+    /// Returns a reference to the current position.
+    fn position(&self) -> u64 {
+        (self.out.len() as u64) << 16 | self.buf.len() as u64
+    }
+
+    fn write(&mut self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            let len = data.len().min(METADATA_SIZE - self.buf.len());
+            self.buf.extend_from_slice(&data[..len]);
+            data = &data[len..];
+            if self.buf.len() == METADATA_SIZE {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let compressed = self.compression.compress(&self.buf)?;
+        if compressed.len() < self.buf.len() {
+            self.out
+                .extend_from_slice(&(compressed.len() as u16).to_le_bytes());
+            self.out.extend_from_slice(&compressed);
+        } else {
+            self.out
+                .extend_from_slice(&(self.buf.len() as u16 | 0x8000).to_le_bytes());
+            self.out.extend_from_slice(&self.buf);
+        }
+        self.buf.clear();
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>> {
+        if !self.buf.is_empty() {
+            self.flush()?;
+        }
+        Ok(self.out)
+    }
+}
+
+struct DirEntry<'a> {
+    name: &'a str,
+    inode: u64,
+    number: u32,
+    ty: u16,
+}
+
+struct Writer<'a, W: Write> {
+    w: &'a mut W,
+    position: u64,
+    compression: Compression,
+    inodes: Metadata,
+    dirs: Metadata,
+}
+
+impl<'a, W: Write> Writer<'a, W> {
+    /// Writes a node numbered in post order starting at `first`, returns the
+    /// reference to its inode and its basic type.
+    fn write_node(&mut self, node: &Node, first: u32, parent: u32) -> Result<(u64, u16)> {
+        match node {
+            Node::Dir {
+                entries,
+                mode,
+                mtime,
+            } => {
+                let number = first + node.count() - 1;
+                let mut next = first;
+                let mut children = vec![];
+                let mut subdirs = 0;
+                for (name, child) in entries {
+                    let (inode, ty) = self.write_node(child, next, number)?;
+                    next += child.count();
+                    if ty == DIR_TYPE {
+                        subdirs += 1;
+                    }
+                    children.push(DirEntry {
+                        name,
+                        inode,
+                        number: next - 1,
+                        ty,
+                    });
+                }
+
+                let listing = self.dirs.position();
+                let size = self.write_listing(&children)? + 3;
+                let inode = self.inodes.position();
+                let nlink: u32 = 2 + subdirs;
+                if size <= u16::MAX as u32 {
+                    self.write_header(DIR_TYPE, *mode, *mtime, number)?;
+                    self.inodes.write(&((listing >> 16) as u32).to_le_bytes())?;
+                    self.inodes.write(&nlink.to_le_bytes())?;
+                    self.inodes.write(&(size as u16).to_le_bytes())?;
+                    self.inodes.write(&(listing as u16).to_le_bytes())?;
+                    self.inodes.write(&parent.to_le_bytes())?;
+                } else {
+                    self.write_header(LDIR_TYPE, *mode, *mtime, number)?;
+                    self.inodes.write(&nlink.to_le_bytes())?;
+                    self.inodes.write(&size.to_le_bytes())?;
+                    self.inodes.write(&((listing >> 16) as u32).to_le_bytes())?;
+                    self.inodes.write(&parent.to_le_bytes())?;
+                    // index count
+                    self.inodes.write(&0u16.to_le_bytes())?;
+                    self.inodes.write(&(listing as u16).to_le_bytes())?;
+                    self.inodes.write(&u32::MAX.to_le_bytes())?;
+                }
+                Ok((inode, DIR_TYPE))
+            }
+            Node::File {
+                source,
+                mode,
+                mtime,
+            } => {
+                let start = self.position;
+                let mut sizes = vec![];
+                let mut file_size = 0;
+                let mut f = std::fs::File::open(source)
+                    .with_context(|| format!("failed to open {}", source.display()))?;
+                let mut block = vec![0; BLOCK_SIZE];
+                loop {
+                    let len = read_block(&mut f, &mut block)?;
+                    if len == 0 {
+                        break;
+                    }
+                    file_size += len as u64;
+                    let data = &block[..len];
+                    let compressed = self.compression.compress(data)?;
+                    let size = if compressed.len() < len {
+                        self.w.write_all(&compressed)?;
+                        compressed.len() as u32
+                    } else {
+                        self.w.write_all(data)?;
+                        len as u32 | 1 << 24
+                    };
+                    self.position += (size & !(1 << 24)) as u64;
+                    sizes.push(size);
+                }
+
+                let inode = self.inodes.position();
+                if start <= u32::MAX as u64 && file_size <= u32::MAX as u64 {
+                    self.write_header(FILE_TYPE, *mode, *mtime, first)?;
+                    self.inodes.write(&(start as u32).to_le_bytes())?;
+                    self.inodes.write(&INVALID_FRAGMENT.to_le_bytes())?;
+                    self.inodes.write(&0u32.to_le_bytes())?;
+                    self.inodes.write(&(file_size as u32).to_le_bytes())?;
+                } else {
+                    self.write_header(LFILE_TYPE, *mode, *mtime, first)?;
+                    self.inodes.write(&start.to_le_bytes())?;
+                    self.inodes.write(&file_size.to_le_bytes())?;
+                    // sparse bytes
+                    self.inodes.write(&0u64.to_le_bytes())?;
+                    // nlink
+                    self.inodes.write(&1u32.to_le_bytes())?;
+                    self.inodes.write(&INVALID_FRAGMENT.to_le_bytes())?;
+                    self.inodes.write(&0u32.to_le_bytes())?;
+                    self.inodes.write(&u32::MAX.to_le_bytes())?;
+                }
+                for size in sizes {
+                    self.inodes.write(&size.to_le_bytes())?;
+                }
+                Ok((inode, FILE_TYPE))
+            }
+            Node::Symlink { target, mtime } => {
+                let inode = self.inodes.position();
+                self.write_header(SYMLINK_TYPE, 0o777, *mtime, first)?;
+                self.inodes.write(&1u32.to_le_bytes())?;
+                self.inodes.write(&(target.len() as u32).to_le_bytes())?;
+                self.inodes.write(target.as_bytes())?;
+                Ok((inode, SYMLINK_TYPE))
+            }
+        }
+    }
+
+    fn write_header(&mut self, ty: u16, mode: u16, mtime: u32, number: u32) -> Result<()> {
+        self.inodes.write(&ty.to_le_bytes())?;
+        self.inodes.write(&mode.to_le_bytes())?;
+        // uid and gid index
+        self.inodes.write(&0u16.to_le_bytes())?;
+        self.inodes.write(&0u16.to_le_bytes())?;
+        self.inodes.write(&mtime.to_le_bytes())?;
+        self.inodes.write(&number.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Writes a directory listing and returns its size.
+    fn write_listing(&mut self, entries: &[DirEntry]) -> Result<u32> {
+        let mut listing = vec![];
+        let mut i = 0;
+        while i < entries.len() {
+            let block = entries[i].inode >> 16;
+            let base = entries[i].number;
+            let run = entries[i..]
+                .iter()
+                .take(256)
+                .take_while(|entry| {
+                    entry.inode >> 16 == block
+                        && (entry.number as i64 - base as i64).abs() <= i16::MAX as i64
+                })
+                .count();
+            listing.extend_from_slice(&(run as u32 - 1).to_le_bytes());
+            listing.extend_from_slice(&(block as u32).to_le_bytes());
+            listing.extend_from_slice(&base.to_le_bytes());
+            for entry in &entries[i..i + run] {
+                anyhow::ensure!(
+                    !entry.name.is_empty() && entry.name.len() <= 256,
+                    "invalid file name {}",
+                    entry.name
+                );
+                listing.extend_from_slice(&(entry.inode as u16).to_le_bytes());
+                listing
+                    .extend_from_slice(&((entry.number as i32 - base as i32) as i16).to_le_bytes());
+                listing.extend_from_slice(&entry.ty.to_le_bytes());
+                listing.extend_from_slice(&(entry.name.len() as u16 - 1).to_le_bytes());
+                listing.extend_from_slice(entry.name.as_bytes());
+            }
+            i += run;
+        }
+        self.dirs.write(&listing)?;
+        Ok(listing.len() as u32)
+    }
+}
+
+fn read_block(f: &mut impl Read, block: &mut [u8]) -> Result<usize> {
+    let mut len = 0;
+    while len < block.len() {
+        let n = f.read(&mut block[len..])?;
+        if n == 0 {
+            break;
+        }
+        len += n;
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Minimal reader used to check the written images.
+    struct Image {
+        data: Vec<u8>,
+        compression: u16,
+    }
+
+    impl Image {
+        fn u16(&self, offset: usize) -> u16 {
+            u16::from_le_bytes(self.data[offset..offset + 2].try_into().unwrap())
+        }
+
+        fn u32(&self, offset: usize) -> u32 {
+            u32::from_le_bytes(self.data[offset..offset + 4].try_into().unwrap())
+        }
+
+        fn u64(&self, offset: usize) -> u64 {
+            u64::from_le_bytes(self.data[offset..offset + 8].try_into().unwrap())
+        }
+
+        fn decompress(&self, data: &[u8]) -> Vec<u8> {
+            let mut out = vec![];
+            if self.compression == 1 {
+                flate2::read::ZlibDecoder::new(data)
+                    .read_to_end(&mut out)
+                    .unwrap();
+            } else {
+                out = zstd::stream::decode_all(data).unwrap();
+            }
+            out
+        }
+
+        /// Decodes the metadata table starting at `start`.
+        fn table(&self, start: usize, end: usize) -> BTreeMap<usize, Vec<u8>> {
+            let mut blocks = BTreeMap::new();
+            let mut offset = start;
+            while offset < end {
+                let header = self.u16(offset);
+                let len = (header & 0x7fff) as usize;
+                let data = &self.data[offset + 2..offset + 2 + len];
+                let data = if header & 0x8000 != 0 {
+                    data.to_vec()
+                } else {
+                    self.decompress(data)
+                };
+                blocks.insert(offset - start, data);
+                offset += 2 + len;
+            }
+            blocks
+        }
+
+        fn read(table: &BTreeMap<usize, Vec<u8>>, reference: u64, len: usize) -> Vec<u8> {
+            let mut data = vec![];
+            for (_, block) in table.range((reference >> 16) as usize..) {
+                data.extend_from_slice(block);
+            }
+            let offset = (reference & 0xffff) as usize;
+            data[offset..offset + len].to_vec()
+        }
+    }
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn read_u16(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn check(compression: Compression) -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("lib"))?;
+        let big = (0..BLOCK_SIZE * 2 + 100)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect::<Vec<_>>();
+        std::fs::write(dir.join("app"), &big)?;
+        std::fs::write(dir.join("lib").join("libfoo.so"), b"foo")?;
+        for i in 0..300 {
+            std::fs::write(dir.join(format!("file{:03}", i)), b"")?;
+        }
+
+        let mut squashfs = Squashfs::new(compression);
+        squashfs.add_dir_all(dir)?;
+        squashfs.add_symlink(Path::new("AppRun"), "app")?;
+        let mut image = Cursor::new(b"runtime".to_vec());
+        image.seek(SeekFrom::End(0))?;
+        squashfs.write(&mut image)?;
+        let data = image.into_inner()[7..].to_vec();
+        assert_eq!(data.len() % 4096, 0);
+
+        let image = Image {
+            compression: read_u16(&data, 20),
+            data,
+        };
+        assert_eq!(image.u32(0), MAGIC);
+        assert_eq!(image.u32(4), 305);
+        assert_eq!(image.u16(20), compression.id());
+        assert_eq!(image.u16(28), 4);
+        let root = image.u64(32);
+        let id_table = image.u64(48) as usize;
+        let inode_table = image.u64(64) as usize;
+        let directory_table = image.u64(72) as usize;
+        let ids = image.table(image.u64(id_table) as usize, id_table);
+        assert_eq!(ids[&0], [0; 4]);
+        let inodes = image.table(inode_table, directory_table);
+        let dirs = image.table(directory_table, image.u64(80) as usize);
+
+        let list = |inode: u64| {
+            let header = Image::read(&inodes, inode, 32);
+            assert_eq!(read_u16(&header, 0), DIR_TYPE);
+            let listing = read_u32(&header, 16) as u64;
+            let size = read_u16(&header, 24) as usize - 3;
+            let offset = read_u16(&header, 26) as u64;
+            let listing = Image::read(&dirs, listing << 16 | offset, size);
+            let mut entries = BTreeMap::new();
+            let mut i = 0;
+            while i < listing.len() {
+                let count = read_u32(&listing, i) + 1;
+                let block = read_u32(&listing, i + 4) as u64;
+                i += 12;
+                for _ in 0..count {
+                    let offset = read_u16(&listing, i) as u64;
+                    let name_len = read_u16(&listing, i + 6) as usize + 1;
+                    let name = std::str::from_utf8(&listing[i + 8..i + 8 + name_len]).unwrap();
+                    entries.insert(name.to_string(), block << 16 | offset);
+                    i += 8 + name_len;
+                }
+            }
+            entries
+        };
+
+        let entries = list(root);
+        assert_eq!(entries.len(), 303);
+        let lib = list(entries["lib"]);
+        assert_eq!(lib.keys().collect::<Vec<_>>(), ["libfoo.so"]);
+
+        let apprun = Image::read(&inodes, entries["AppRun"], 24);
+        assert_eq!(read_u16(&apprun, 0), SYMLINK_TYPE);
+        assert_eq!(read_u32(&apprun, 20), 3);
+        assert_eq!(Image::read(&inodes, entries["AppRun"] + 24, 3), b"app");
+
+        let app = Image::read(&inodes, entries["app"], 44);
+        assert_eq!(read_u16(&app, 0), FILE_TYPE);
+        assert_eq!(read_u32(&app, 20), INVALID_FRAGMENT);
+        assert_eq!(read_u32(&app, 28) as usize, big.len());
+        let mut offset = read_u32(&app, 16) as usize;
+        let mut contents = vec![];
+        for i in 0..3 {
+            let size = read_u32(&app, 32 + i * 4);
+            let len = (size & !(1 << 24)) as usize;
+            let block = &image.data[offset..offset + len];
+            if size & 1 << 24 != 0 {
+                contents.extend_from_slice(block);
+            } else {
+                contents.extend(image.decompress(block));
+            }
+            offset += len;
+        }
+        assert_eq!(contents, big);
+        Ok(())
+    }
+
+    #[test]
+    fn test_squashfs_gzip() -> Result<()> {
+        check(Compression::Gzip)
+    }
+
+    #[test]
+    fn test_squashfs_zstd() -> Result<()> {
+        check(Compression::Zstd)
+    }
+}
@@ -0,0 +1,197 @@
+//! Writer for the `.zsync` control files used by AppImageUpdate.
+use anyhow::Result;
+use sha1::{Digest, Sha1};
+use std::io::{Read, Write};
+
+const BLOCK_SIZE: usize = 2048;
+
+/// Writes a zsync control file for the contents of `r`. `file_name` is
+/// used as the url, so the control file needs to be uploaded next to the
+/// AppImage.
+pub fn write_zsync<R: Read, W: Write>(r: &mut R, w: &mut W, file_name: &str) -> Result<()> {
+    let mut checksums = vec![];
+    let mut sha1 = Sha1::new();
+    let mut len = 0;
+    let mut block = [0; BLOCK_SIZE];
+    loop {
+        let n = read_block(r, &mut block)?;
+        if n == 0 {
+            break;
+        }
+        len += n as u64;
+        sha1.update(&block[..n]);
+        // the last block is padded with zeros
+        block[n..].fill(0);
+        let (a, b) = rsum(&block);
+        checksums.extend_from_slice(&a.to_be_bytes());
+        checksums.extend_from_slice(&b.to_be_bytes());
+        checksums.extend_from_slice(&md4(&block));
+    }
+    writeln!(w, "zsync: 0.6.2")?;
+    writeln!(w, "Filename: {}", file_name)?;
+    writeln!(w, "Blocksize: {}", BLOCK_SIZE)?;
+    writeln!(w, "Length: {}", len)?;
+    writeln!(w, "Hash-Lengths: 2,4,16")?;
+    writeln!(w, "URL: {}", file_name)?;
+    writeln!(w, "SHA-1: {}", hex(&sha1.finalize()))?;
+    writeln!(w)?;
+    w.write_all(&checksums)?;
+    Ok(())
+}
+
+fn read_block<R: Read>(r: &mut R, block: &mut [u8]) -> Result<usize> {
+    let mut len = 0;
+    while len < block.len() {
+        let n = r.read(&mut block[len..])?;
+        if n == 0 {
+            break;
+        }
+        len += n;
+    }
+    Ok(len)
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Rolling checksum of a block as defined by rsync.
+fn rsum(block: &[u8]) -> (u16, u16) {
+    let mut a = 0u16;
+    let mut b = 0u16;
+    let len = block.len();
+    for (i, c) in block.iter().enumerate() {
+        a = a.wrapping_add(*c as u16);
+        b = b.wrapping_add(((len - i) as u16).wrapping_mul(*c as u16));
+    }
+    (a, b)
+}
+
+/// MD4 as specified in RFC 1320, zsync uses it for the strong block
+/// checksums.
+fn md4(data: &[u8]) -> [u8; 16] {
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&((data.len() as u64) * 8).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for chunk in msg.chunks(64) {
+        let x = chunk
+            .chunks(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        let [mut a, mut b, mut c, mut d] = state;
+        let f = |x: u32, y: u32, z: u32| (x & y) | (!x & z);
+        let g = |x: u32, y: u32, z: u32| (x & y) | (x & z) | (y & z);
+        let h = |x: u32, y: u32, z: u32| x ^ y ^ z;
+        for i in [0, 4, 8, 12] {
+            a = a.wrapping_add(f(b, c, d)).wrapping_add(x[i]).rotate_left(3);
+            d = d
+                .wrapping_add(f(a, b, c))
+                .wrapping_add(x[i + 1])
+                .rotate_left(7);
+            c = c
+                .wrapping_add(f(d, a, b))
+                .wrapping_add(x[i + 2])
+                .rotate_left(11);
+            b = b
+                .wrapping_add(f(c, d, a))
+                .wrapping_add(x[i + 3])
+                .rotate_left(19);
+        }
+        for i in [0, 1, 2, 3] {
+            let k = 0x5a827999u32;
+            a = a
+                .wrapping_add(g(b, c, d))
+                .wrapping_add(x[i])
+                .wrapping_add(k)
+                .rotate_left(3);
+            d = d
+                .wrapping_add(g(a, b, c))
+                .wrapping_add(x[i + 4])
+                .wrapping_add(k)
+                .rotate_left(5);
+            c = c
+                .wrapping_add(g(d, a, b))
+                .wrapping_add(x[i + 8])
+                .wrapping_add(k)
+                .rotate_left(9);
+            b = b
+                .wrapping_add(g(c, d, a))
+                .wrapping_add(x[i + 12])
+                .wrapping_add(k)
+                .rotate_left(13);
+        }
+        for i in [0, 2, 1, 3] {
+            let k = 0x6ed9eba1u32;
+            a = a
+                .wrapping_add(h(b, c, d))
+                .wrapping_add(x[i])
+                .wrapping_add(k)
+                .rotate_left(3);
+            d = d
+                .wrapping_add(h(a, b, c))
+                .wrapping_add(x[i + 8])
+                .wrapping_add(k)
+                .rotate_left(9);
+            c = c
+                .wrapping_add(h(d, a, b))
+                .wrapping_add(x[i + 4])
+                .wrapping_add(k)
+                .rotate_left(11);
+            b = b
+                .wrapping_add(h(c, d, a))
+                .wrapping_add(x[i + 12])
+                .wrapping_add(k)
+                .rotate_left(15);
+        }
+        state[0] = state[0].wrapping_add(a);
+        state[1] = state[1].wrapping_add(b);
+        state[2] = state[2].wrapping_add(c);
+        state[3] = state[3].wrapping_add(d);
+    }
+    let mut digest = [0; 16];
+    for (i, word) in state.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_md4() {
+        assert_eq!(hex(&md4(b"")), "31d6cfe0d16ae931b73c59d7e0c089c0");
+        assert_eq!(hex(&md4(b"abc")), "a448017aaf21d8525fc10ae87aa6729d");
+        assert_eq!(
+            hex(&md4(
+                b"12345678901234567890123456789012345678901234567890123456789012345678901234567890"
+            )),
+            "e33b4ddc9c38f2199c3e7b164fcc0536"
+        );
+    }
+
+    #[test]
+    fn test_zsync() -> Result<()> {
+        let data = vec![1; BLOCK_SIZE + 10];
+        let mut zsync = vec![];
+        write_zsync(&mut &data[..], &mut zsync, "Test.AppImage")?;
+        let header_end = zsync.windows(2).position(|w| w == b"\n\n").unwrap() + 2;
+        let header = std::str::from_utf8(&zsync[..header_end])?;
+        assert!(header.contains("Length: 2058\n"));
+        assert!(header.contains("URL: Test.AppImage\n"));
+        assert!(header.contains(&format!("SHA-1: {}\n", hex(&Sha1::digest(&data)))));
+        let checksums = &zsync[header_end..];
+        assert_eq!(checksums.len(), 2 * 20);
+        assert_eq!(&checksums[..2], &(BLOCK_SIZE as u16).to_be_bytes());
+        let mut last = [0; BLOCK_SIZE];
+        last[..10].fill(1);
+        assert_eq!(&checksums[24..], &md4(&last));
+        Ok(())
+    }
+}
@@ -0,0 +1,322 @@
+//! Authenticode signing of PE executables and libraries.
+//!
+//! <https://download.microsoft.com/download/9/c/5/9c5b2167-8017-4bae-9fde-d599bac8184a/Authenticode_PE.docx>
+use crate::pkcs7::{build_pkcs7, verify_pkcs7, SPC_INDIRECT_DATA_OBJID};
+use anyhow::{Context, Result};
+use rasn::prelude::*;
+use rasn_cms::pkcs7_compat::{EncapsulatedContentInfo, SignedData};
+use rasn_cms::{AlgorithmIdentifier, ContentInfo, CONTENT_SIGNED_DATA};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use xcommon::{Certificate, Signer};
+
+pub const SPC_PE_IMAGE_DATA_OBJID: ConstOid = ConstOid(&[1, 3, 6, 1, 4, 1, 311, 2, 1, 15]);
+
+const WIN_CERT_REVISION_2_0: u16 = 0x0200;
+const WIN_CERT_TYPE_PKCS_SIGNED_DATA: u16 = 0x0002;
+
+/// Signs the PE image at `path` in place. An existing signature is replaced.
+pub fn sign(path: &Path, signer: &Signer) -> Result<()> {
+    let mut image = std::fs::read(path)?;
+    let pe = PeHeaders::parse(&image)?;
+    let (offset, size) = pe.certificate_table(&image);
+    if size > 0 {
+        anyhow::ensure!(
+            offset + size == image.len(),
+            "certificate table isn't at the end of the image"
+        );
+        image.truncate(offset);
+    }
+    image.resize(align8(image.len()), 0);
+    image[pe.certificate_table..pe.certificate_table + 8].fill(0);
+
+    let indirect_data = SpcIndirectDataContent::new(&pe.digest(&image));
+    let encap_content_info = EncapsulatedContentInfo {
+        content_type: SPC_INDIRECT_DATA_OBJID.into(),
+        content: Any::new(rasn::der::encode(&indirect_data).unwrap()),
+    };
+    let signed_data = build_pkcs7(signer, encap_content_info)?;
+    let content_info = ContentInfo {
+        content_type: CONTENT_SIGNED_DATA.into(),
+        content: Any::new(rasn::der::encode(&signed_data).unwrap()),
+    };
+    let der = rasn::der::encode(&content_info).unwrap();
+
+    let offset = image.len();
+    let size = align8(8 + der.len());
+    image.extend_from_slice(&(size as u32).to_le_bytes());
+    image.extend_from_slice(&WIN_CERT_REVISION_2_0.to_le_bytes());
+    image.extend_from_slice(&WIN_CERT_TYPE_PKCS_SIGNED_DATA.to_le_bytes());
+    image.extend_from_slice(&der);
+    image.resize(offset + size, 0);
+    image[pe.certificate_table..pe.certificate_table + 4]
+        .copy_from_slice(&(offset as u32).to_le_bytes());
+    image[pe.certificate_table + 4..pe.certificate_table + 8]
+        .copy_from_slice(&(size as u32).to_le_bytes());
+    let checksum = pe.checksum(&image);
+    image[pe.checksum..pe.checksum + 4].copy_from_slice(&checksum.to_le_bytes());
+    std::fs::write(path, image)?;
+    Ok(())
+}
+
+/// Verifies the signature and checksum of the PE image at `path` and
+/// returns the certificate of the signer.
+pub fn verify(path: &Path) -> Result<Certificate> {
+    let image = std::fs::read(path)?;
+    let pe = PeHeaders::parse(&image)?;
+    let (offset, size) = pe.certificate_table(&image);
+    anyhow::ensure!(size > 0, "image isn't signed");
+    let table = offset
+        .checked_add(size)
+        .and_then(|end| image.get(offset..end))
+        .filter(|table| table.len() >= 8)
+        .context("invalid certificate table")?;
+    let length = u32::from_le_bytes(table[..4].try_into().unwrap()) as usize;
+    let revision = u16::from_le_bytes([table[4], table[5]]);
+    let ty = u16::from_le_bytes([table[6], table[7]]);
+    anyhow::ensure!(
+        revision == WIN_CERT_REVISION_2_0 && ty == WIN_CERT_TYPE_PKCS_SIGNED_DATA,
+        "unsupported certificate revision {:#x} type {:#x}",
+        revision,
+        ty
+    );
+    let der = table.get(8..length).context("invalid certificate length")?;
+    let info = rasn::der::decode::<ContentInfo>(der).map_err(|err| anyhow::anyhow!("{}", err))?;
+    anyhow::ensure!(CONTENT_SIGNED_DATA == info.content_type);
+    let signed_data = rasn::der::decode::<SignedData>(info.content.as_bytes())
+        .map_err(|err| anyhow::anyhow!("{}", err))?;
+    let cert = verify_pkcs7(&signed_data)?;
+
+    let indirect_data = rasn::der::decode::<SpcIndirectDataContent>(
+        signed_data.encap_content_info.content.as_bytes(),
+    )
+    .map_err(|err| anyhow::anyhow!("{}", err))?;
+    anyhow::ensure!(
+        SPC_PE_IMAGE_DATA_OBJID == indirect_data.data.ty,
+        "not a pe image signature"
+    );
+    anyhow::ensure!(
+        indirect_data.message_digest.digest[..] == pe.digest(&image[..offset])[..],
+        "image doesn't match the signature"
+    );
+    let checksum = u32::from_le_bytes(image[pe.checksum..pe.checksum + 4].try_into().unwrap());
+    anyhow::ensure!(checksum == pe.checksum(&image), "invalid pe checksum");
+    Ok(cert)
+}
+
+fn align8(n: usize) -> usize {
+    (n + 7) & !7
+}
+
+/// Offsets of the fields in the PE headers that are excluded from the image hash.
+struct PeHeaders {
+    checksum: usize,
+    certificate_table: usize,
+}
+
+impl PeHeaders {
+    fn parse(image: &[u8]) -> Result<Self> {
+        anyhow::ensure!(image.get(..2) == Some(b"MZ"), "not a pe image");
+        let pe = u32::from_le_bytes(
+            image
+                .get(0x3c..0x40)
+                .context("invalid dos header")?
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        anyhow::ensure!(
+            image.get(pe..pe + 4) == Some(b"PE\0\0"),
+            "invalid pe signature"
+        );
+        let optional_header = pe + 24;
+        let magic = image
+            .get(optional_header..optional_header + 2)
+            .context("invalid optional header")?;
+        let data_directories = match u16::from_le_bytes([magic[0], magic[1]]) {
+            0x10b => optional_header + 96,
+            0x20b => optional_header + 112,
+            magic => anyhow::bail!("unsupported optional header magic {:#x}", magic),
+        };
+        let num_data_directories = u32::from_le_bytes(
+            image
+                .get(data_directories - 4..data_directories)
+                .context("invalid optional header")?
+                .try_into()
+                .unwrap(),
+        );
+        anyhow::ensure!(num_data_directories > 4, "missing certificate table");
+        let certificate_table = data_directories + 4 * 8;
+        anyhow::ensure!(
+            image.len() >= certificate_table + 8,
+            "invalid data directories"
+        );
+        Ok(Self {
+            checksum: optional_header + 64,
+            certificate_table,
+        })
+    }
+
+    /// Returns the file offset and size of the certificate table.
+    fn certificate_table(&self, image: &[u8]) -> (usize, usize) {
+        let entry = &image[self.certificate_table..self.certificate_table + 8];
+        let offset = u32::from_le_bytes(entry[..4].try_into().unwrap());
+        let size = u32::from_le_bytes(entry[4..].try_into().unwrap());
+        (offset as usize, size as usize)
+    }
+
+    /// Hashes the image without the checksum and the certificate table entry.
+    /// The image must not contain the certificate table.
+    fn digest(&self, image: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(&image[..self.checksum]);
+        hasher.update(&image[self.checksum + 4..self.certificate_table]);
+        hasher.update(&image[self.certificate_table + 8..]);
+        hasher.finalize().into()
+    }
+
+    fn checksum(&self, image: &[u8]) -> u32 {
+        let mut sum = 0u32;
+        for (i, word) in image.chunks(2).enumerate() {
+            if i * 2 == self.checksum || i * 2 == self.checksum + 2 {
+                continue;
+            }
+            let word = u16::from_le_bytes([word[0], word.get(1).copied().unwrap_or_default()]);
+            sum += word as u32;
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        sum.wrapping_add(image.len() as u32)
+    }
+}
+
+#[derive(AsnType, Clone, Debug, Decode, Encode, Eq, PartialEq)]
+struct SpcIndirectDataContent {
+    data: SpcAttributeTypeAndOptionalValue,
+    message_digest: DigestInfo,
+}
+
+impl SpcIndirectDataContent {
+    fn new(digest: &[u8; 32]) -> Self {
+        Self {
+            data: SpcAttributeTypeAndOptionalValue {
+                ty: SPC_PE_IMAGE_DATA_OBJID.into(),
+                value: SpcPeImageData {
+                    flags: BitString::new(),
+                    file: SpcLink::File(SpcString::Unicode(OctetString::from(
+                        "<<<Obsolete>>>"
+                            .encode_utf16()
+                            .flat_map(|c| c.to_be_bytes())
+                            .collect::<Vec<_>>(),
+                    ))),
+                },
+            },
+            message_digest: DigestInfo {
+                digest_algorithm: AlgorithmIdentifier {
+                    algorithm:
+                        Oid::JOINT_ISO_ITU_T_COUNTRY_US_ORGANIZATION_GOV_CSOR_NIST_ALGORITHMS_HASH_SHA256
+                            .into(),
+                    parameters: Some(Any::new(vec![5, 0])),
+                },
+                digest: OctetString::from(digest.to_vec()),
+            },
+        }
+    }
+}
+
+#[derive(AsnType, Clone, Debug, Decode, Encode, Eq, PartialEq)]
+struct SpcAttributeTypeAndOptionalValue {
+    ty: ObjectIdentifier,
+    value: SpcPeImageData,
+}
+
+#[derive(AsnType, Clone, Debug, Decode, Encode, Eq, PartialEq)]
+struct SpcPeImageData {
+    flags: BitString,
+    #[rasn(tag(explicit(0)))]
+    file: SpcLink,
+}
+
+#[derive(AsnType, Clone, Debug, Decode, Encode, Eq, PartialEq)]
+#[rasn(choice)]
+enum SpcLink {
+    #[rasn(tag(explicit(2)))]
+    File(SpcString),
+}
+
+#[derive(AsnType, Clone, Debug, Decode, Encode, Eq, PartialEq)]
+#[rasn(choice)]
+enum SpcString {
+    /// Big endian UTF-16.
+    #[rasn(tag(0))]
+    Unicode(OctetString),
+}
+
+#[derive(AsnType, Clone, Debug, Decode, Encode, Eq, PartialEq)]
+struct DigestInfo {
+    digest_algorithm: AlgorithmIdentifier,
+    digest: OctetString,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a minimal PE32+ image with a single section.
+    fn pe_image() -> Vec<u8> {
+        let mut image = vec![0; 0x400];
+        image[..2].copy_from_slice(b"MZ");
+        image[0x3c..0x40].copy_from_slice(&0x40u32.to_le_bytes());
+        image[0x40..0x44].copy_from_slice(b"PE\0\0");
+        // coff header: amd64, one section, optional header size
+        image[0x44..0x46].copy_from_slice(&0x8664u16.to_le_bytes());
+        image[0x46..0x48].copy_from_slice(&1u16.to_le_bytes());
+        image[0x54..0x56].copy_from_slice(&240u16.to_le_bytes());
+        // optional header: magic, size of headers, number of data directories
+        image[0x58..0x5a].copy_from_slice(&0x20bu16.to_le_bytes());
+        image[0x94..0x98].copy_from_slice(&0x200u32.to_le_bytes());
+        image[0xc4..0xc8].copy_from_slice(&16u32.to_le_bytes());
+        // section header
+        image[0x148..0x150].copy_from_slice(b".text\0\0\0");
+        image[0x158..0x15c].copy_from_slice(&0x200u32.to_le_bytes());
+        image[0x15c..0x160].copy_from_slice(&0x200u32.to_le_bytes());
+        image[0x200..0x210].copy_from_slice(b"section contents");
+        image
+    }
+
+    #[test]
+    fn test_sign_and_verify() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path();
+        let path = dir.join("test.exe");
+        std::fs::write(&path, pe_image())?;
+        assert!(verify(&path).is_err());
+
+        let signer = Signer::new(crate::DEBUG_PEM)?;
+        sign(&path, &signer)?;
+        assert_eq!(&verify(&path)?, signer.cert());
+        let signed = std::fs::read(&path)?;
+
+        // resigning replaces the signature
+        sign(&path, &signer)?;
+        assert_eq!(std::fs::read(&path)?, signed);
+
+        let mut corrupted = signed;
+        corrupted[0x205] ^= 1;
+        std::fs::write(&path, corrupted)?;
+        assert!(verify(&path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_certificate_table() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let path = tmp.path().join("test.exe");
+        for (offset, size) in [(0x200u32, 4u32), (u32::MAX, u32::MAX), (0x200, 0x1000)] {
+            let mut image = pe_image();
+            image[0xe8..0xec].copy_from_slice(&offset.to_le_bytes());
+            image[0xec..0xf0].copy_from_slice(&size.to_le_bytes());
+            std::fs::write(&path, image)?;
+            assert!(verify(&path).is_err());
+        }
+        Ok(())
+    }
+}
@@ -1,9 +1,18 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::io::Read;
+use std::collections::HashSet;
+use std::io::{Read, Seek};
 use std::path::Path;
 use zip::read::ZipFile;
+use zip::ZipArchive;
+
+/// Files that are added when signing and therefore not part of the block map.
+const FOOTPRINT_FILES: [&str; 3] = [
+    "[Content_Types].xml",
+    "AppxBlockMap.xml",
+    "AppxSignature.p7x",
+];
 
 pub struct BlockMapBuilder {
     block_map: AppxBlockMap,
@@ -27,21 +36,12 @@ impl BlockMapBuilder {
             .collect::<Vec<_>>()
             .join("\\");
         let size = f.size();
-        let mut file = File {
+        let file = File {
             lfh_size: 30 + name.len() as u16,
             name,
             size,
-            ..Default::default()
+            blocks: read_blocks(f, &mut self.buf)?,
         };
-        loop {
-            self.buf.clear();
-            f.take(self.buf.capacity() as u64)
-                .read_to_end(&mut self.buf)?;
-            file.blocks.push(Block::new(&self.buf));
-            if self.buf.len() != self.buf.capacity() {
-                break;
-            }
-        }
         self.block_map.files.push(file);
         Ok(())
     }
@@ -51,24 +51,82 @@ impl BlockMapBuilder {
     }
 }
 
+/// Splits the contents of `r` into 64kib blocks.
+fn read_blocks(mut r: impl Read, buf: &mut Vec<u8>) -> Result<Vec<Block>> {
+    let mut blocks = vec![];
+    loop {
+        buf.clear();
+        (&mut r).take(buf.capacity() as u64).read_to_end(buf)?;
+        blocks.push(Block::new(buf));
+        if buf.len() != buf.capacity() {
+            break;
+        }
+    }
+    Ok(blocks)
+}
+
 /// Defines the root element of the app package block map. The BlockMap element
 /// specifies the algorithm that is used to compute cryptographic hashes and
 /// contains a sequence of File child elements that are associated with each
 /// file that is stored in the package.
 #[derive(Clone, Debug, Deserialize, Serialize)]
-#[serde(rename(serialize = "BlockMap"))]
+#[serde(rename = "BlockMap")]
 pub struct AppxBlockMap {
-    #[serde(rename(serialize = "xmlns"))]
+    #[serde(rename = "xmlns")]
     #[serde(default = "default_namespace")]
     ns: String,
-    #[serde(rename(serialize = "HashMethod"))]
+    #[serde(rename = "HashMethod")]
     #[serde(default = "default_hash_method")]
     hash_method: String,
     /// Files in the package.
-    #[serde(rename(serialize = "File"))]
+    #[serde(default, rename = "File")]
     pub files: Vec<File>,
 }
 
+impl AppxBlockMap {
+    /// Checks that every file in `zip` except for the package footprint files
+    /// is described by the block map and matches its hashes.
+    pub fn verify<R: Read + Seek>(&self, zip: &mut ZipArchive<R>) -> Result<()> {
+        let mut buf = Vec::with_capacity(65_536);
+        let mut names = HashSet::new();
+        for file in &self.files {
+            let name = file.name.replace('\\', "/");
+            let f = zip
+                .by_name(&name)
+                .with_context(|| format!("missing file {}", name))?;
+            anyhow::ensure!(
+                f.size() == file.size,
+                "size of {} doesn't match the block map",
+                name
+            );
+            anyhow::ensure!(
+                f.data_start() - f.header_start() == file.lfh_size as u64,
+                "local file header size of {} doesn't match the block map",
+                name
+            );
+            let blocks = read_blocks(f, &mut buf)?;
+            anyhow::ensure!(
+                blocks.len() == file.blocks.len()
+                    && blocks
+                        .iter()
+                        .zip(&file.blocks)
+                        .all(|(a, b)| a.hash == b.hash),
+                "hash of {} doesn't match the block map",
+                name
+            );
+            names.insert(name);
+        }
+        for name in zip.file_names() {
+            anyhow::ensure!(
+                FOOTPRINT_FILES.contains(&name) || names.contains(name),
+                "{} is missing from the block map",
+                name
+            );
+        }
+        Ok(())
+    }
+}
+
 impl Default for AppxBlockMap {
     fn default() -> Self {
         Self {
@@ -83,18 +141,18 @@ impl Default for AppxBlockMap {
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct File {
     /// Root path and file name.
-    #[serde(rename(serialize = "Name"))]
+    #[serde(rename = "Name")]
     pub name: String,
     /// Size, in bytes, of the file's uncompressed data.
-    #[serde(rename(serialize = "Size"))]
+    #[serde(rename = "Size")]
     pub size: u64,
     /// Size, in bytes, of the file's Local File Header (LFH) structure in the
     /// package. For more info about file headers, see ZIP file format
     /// specification.
-    #[serde(rename(serialize = "LfhSize"))]
+    #[serde(rename = "LfhSize")]
     pub lfh_size: u16,
     /// Blocks that make up the file.
-    #[serde(rename(serialize = "Block"))]
+    #[serde(default, rename = "Block")]
     pub blocks: Vec<Block>,
 }
 
@@ -102,12 +160,12 @@ pub struct File {
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Block {
     /// The hash value of the uncompressed data block.
-    #[serde(rename(serialize = "Hash"))]
+    #[serde(rename = "Hash")]
     pub hash: String,
     /// The size, in bytes, of the data block when stored in the package. If
     /// the file data is compressed, the size of each compressed block
     /// potentially varies in size.
-    #[serde(rename(serialize = "Size"))]
+    #[serde(rename = "Size")]
     pub size: Option<u16>,
 }
 
@@ -0,0 +1,249 @@
+//! <https://learn.microsoft.com/en-us/uwp/schemas/bundlemanifestschema/bundle-manifest-schema-reference>
+use crate::manifest::Identity;
+use crate::{check_publisher, to_xml, AppxManifest, Msix, DEBUG_PEM};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use xcommon::{Signer, Zip, ZipFileOptions};
+use zip::ZipArchive;
+
+const BUNDLE_MANIFEST: &str = "AppxMetadata/AppxBundleManifest.xml";
+
+/// Combines per architecture msix packages into a `.msixbundle`.
+pub struct MsixBundle {
+    manifest: AppxBundleManifest,
+    path: PathBuf,
+    zip: Zip,
+    compress: bool,
+}
+
+impl MsixBundle {
+    pub fn new(path: PathBuf, identity: &Identity, compress: bool) -> Result<Self> {
+        let manifest = AppxBundleManifest {
+            identity: BundleIdentity {
+                name: identity.name.clone(),
+                publisher: identity.publisher.clone(),
+                version: identity.version.clone(),
+            },
+            ..Default::default()
+        };
+        Ok(Self {
+            manifest,
+            // packages are stored so that they can be read directly at their offset
+            zip: Zip::new(&path, false)?,
+            path,
+            compress,
+        })
+    }
+
+    /// Adds the package at `path` which was created with `manifest`.
+    pub fn add_package(&mut self, path: &Path, manifest: &AppxManifest) -> Result<()> {
+        let identity = &manifest.identity;
+        let arch = identity
+            .processor_architecture
+            .clone()
+            .context("package has no processor architecture")?;
+        anyhow::ensure!(
+            identity.name == self.manifest.identity.name,
+            "package {} doesn't match bundle {}",
+            identity.name,
+            self.manifest.identity.name
+        );
+        let file_name = format!("{}_{}_{}.msix", identity.name, identity.version, arch);
+        anyhow::ensure!(
+            self.manifest
+                .packages
+                .package
+                .iter()
+                .all(|package| package.file_name != file_name),
+            "duplicate package {}",
+            file_name
+        );
+        self.zip
+            .add_file(path, file_name.as_ref(), ZipFileOptions::Unaligned)?;
+        let resources = manifest
+            .resources
+            .resource
+            .iter()
+            .filter_map(|resource| resource.language.clone())
+            .map(|language| BundleResource { language })
+            .collect();
+        self.manifest.packages.package.push(Package {
+            ty: "application".into(),
+            version: identity.version.clone(),
+            architecture: arch,
+            file_name,
+            offset: 0,
+            size: 0,
+            resources: BundleResources {
+                resource: resources,
+            },
+        });
+        Ok(())
+    }
+
+    pub fn finish(mut self, signer: Option<Signer>) -> Result<()> {
+        anyhow::ensure!(
+            !self.manifest.packages.package.is_empty(),
+            "bundle has no packages"
+        );
+        let signer = signer.map(Ok).unwrap_or_else(|| Signer::new(DEBUG_PEM))?;
+        check_publisher(&mut self.manifest.identity.publisher, &signer)?;
+        self.zip.finish()?;
+
+        // the bundle manifest references the packages by their offset in the bundle
+        let mut zip = ZipArchive::new(BufReader::new(File::open(&self.path)?))?;
+        for package in &mut self.manifest.packages.package {
+            let file = zip.by_name(&package.file_name)?;
+            package.offset = file.data_start();
+            package.size = file.size();
+        }
+        let mut zip = Zip::append(&self.path, self.compress)?;
+        zip.create_file(
+            BUNDLE_MANIFEST.as_ref(),
+            ZipFileOptions::Compressed,
+            &to_xml(&self.manifest, true),
+        )?;
+        zip.finish()?;
+        Msix::sign(&self.path, Some(signer), self.compress)
+    }
+}
+
+/// <https://learn.microsoft.com/en-us/uwp/schemas/bundlemanifestschema/element-bundle>
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename = "Bundle", rename_all(serialize = "PascalCase"))]
+pub struct AppxBundleManifest {
+    #[serde(rename(serialize = "xmlns"))]
+    ns: String,
+    schema_version: String,
+    pub identity: BundleIdentity,
+    pub packages: Packages,
+}
+
+impl Default for AppxBundleManifest {
+    fn default() -> Self {
+        Self {
+            ns: "http://schemas.microsoft.com/appx/2013/bundle".into(),
+            schema_version: "5.0".into(),
+            identity: Default::default(),
+            packages: Default::default(),
+        }
+    }
+}
+
+/// <https://learn.microsoft.com/en-us/uwp/schemas/bundlemanifestschema/element-identity>
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all(serialize = "PascalCase"))]
+pub struct BundleIdentity {
+    pub name: String,
+    pub publisher: String,
+    pub version: String,
+}
+
+/// <https://learn.microsoft.com/en-us/uwp/schemas/bundlemanifestschema/element-packages>
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all(serialize = "PascalCase"))]
+pub struct Packages {
+    pub package: Vec<Package>,
+}
+
+/// <https://learn.microsoft.com/en-us/uwp/schemas/bundlemanifestschema/element-package>
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all(serialize = "PascalCase"))]
+pub struct Package {
+    #[serde(rename(serialize = "Type"))]
+    pub ty: String,
+    pub version: String,
+    pub architecture: String,
+    pub file_name: String,
+    /// Offset of the package data in the bundle.
+    pub offset: u64,
+    pub size: u64,
+    #[serde(skip_serializing_if = "BundleResources::is_empty")]
+    pub resources: BundleResources,
+}
+
+/// <https://learn.microsoft.com/en-us/uwp/schemas/bundlemanifestschema/element-resources>
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all(serialize = "PascalCase"))]
+pub struct BundleResources {
+    pub resource: Vec<BundleResource>,
+}
+
+impl BundleResources {
+    fn is_empty(&self) -> bool {
+        self.resource.is_empty()
+    }
+}
+
+/// <https://learn.microsoft.com/en-us/uwp/schemas/bundlemanifestschema/element-resource>
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all(serialize = "PascalCase"))]
+pub struct BundleResource {
+    pub language: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Seek, SeekFrom};
+
+    #[test]
+    fn test_bundle() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path();
+        let mut manifest = crate::tests::test_manifest();
+        manifest.identity.name = "com.example.App".into();
+        manifest.identity.version = "1.0.0.0".into();
+        let mut bundle = MsixBundle::new(dir.join("test.msixbundle"), &manifest.identity, true)?;
+        for arch in ["x64", "arm64"] {
+            let path = dir.join(format!("{}.msix", arch));
+            manifest.identity.processor_architecture = Some(arch.into());
+            let mut msix = Msix::new(path.clone(), manifest.clone(), true)?;
+            msix.add_file(
+                Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/src/bundle.rs")),
+                "bundle.rs".as_ref(),
+                ZipFileOptions::Compressed,
+            )?;
+            msix.finish(None)?;
+            bundle.add_package(&path, &manifest)?;
+        }
+        assert!(bundle
+            .add_package(&dir.join("arm64.msix"), &manifest)
+            .is_err());
+        bundle.finish(None)?;
+
+        let path = dir.join("test.msixbundle");
+        let mut zip = ZipArchive::new(File::open(&path)?)?;
+        for name in [
+            BUNDLE_MANIFEST,
+            "[Content_Types].xml",
+            "AppxBlockMap.xml",
+            "AppxSignature.p7x",
+        ] {
+            zip.by_name(name)?;
+        }
+        let mut xml = String::new();
+        zip.by_name(BUNDLE_MANIFEST)?.read_to_string(&mut xml)?;
+        let file_name = "com.example.App_1.0.0.0_arm64.msix";
+        let (offset, size) = {
+            let file = zip.by_name(file_name)?;
+            (file.data_start(), file.size())
+        };
+        let package = format!(
+            r#"<Package Type="application" Version="1.0.0.0" Architecture="arm64" FileName="{}" Offset="{}" Size="{}""#,
+            file_name, offset, size
+        );
+        assert!(xml.contains(&package), "{}", xml);
+
+        let mut f = File::open(&path)?;
+        f.seek(SeekFrom::Start(offset))?;
+        let mut data = vec![0; size as usize];
+        f.read_exact(&mut data)?;
+        assert_eq!(data, std::fs::read(dir.join("arm64.msix"))?);
+        Msix::verify(&path)?;
+        Ok(())
+    }
+}
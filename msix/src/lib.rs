@@ -1,21 +1,27 @@
-use crate::block_map::BlockMapBuilder;
+use crate::block_map::{AppxBlockMap, BlockMapBuilder};
 use crate::content_types::ContentTypesBuilder;
 use crate::p7x::Digests;
-use anyhow::Result;
+use anyhow::{Context, Result};
+use pri::PriBuilder;
 use serde::Serialize;
 use sha2::{Digest, Sha256};
 use std::fs::File;
-use std::io::{BufReader, Cursor, Read, Seek};
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
-use xcommon::{Scaler, ScalerOptsBuilder, Signer, Zip, ZipFileOptions, ZipInfo};
+use xcommon::{Certificate, Scaler, ScalerOptsBuilder, Signer, Zip, ZipFileOptions, ZipInfo};
 use zip::ZipArchive;
 
+pub mod authenticode;
 mod block_map;
+pub mod bundle;
 mod content_types;
 pub mod manifest;
 pub mod p7x;
 mod pkcs7;
+pub mod res;
+pub mod timestamp;
 
+pub use crate::bundle::MsixBundle;
 pub use crate::manifest::AppxManifest;
 
 const DEBUG_PEM: &str = include_str!("../assets/debug.pem");
@@ -36,11 +42,13 @@ pub struct Msix {
     path: PathBuf,
     zip: Zip,
     compress: bool,
+    pri: PriBuilder,
 }
 
 impl Msix {
     pub fn new(path: PathBuf, manifest: AppxManifest, compress: bool) -> Result<Self> {
         Ok(Self {
+            pri: PriBuilder::new(manifest.identity.name.clone()),
             manifest,
             zip: Zip::new(&path, compress)?,
             path,
@@ -48,8 +56,13 @@ impl Msix {
         })
     }
 
+    pub fn manifest(&self) -> &AppxManifest {
+        &self.manifest
+    }
+
+    /// Generates the logos at all scales from `path` and uses them for the
+    /// logos that aren't configured in the manifest.
     pub fn add_icon(&mut self, path: &Path) -> Result<()> {
-        dbg!(&path);
         let mut scaler = Scaler::open(path)?;
         scaler.optimize();
         let images = Path::new("Images");
@@ -63,14 +76,50 @@ impl Msix {
                     .build();
                 scaler.write(&mut Cursor::new(&mut buf), opts)?;
                 let name = format!("{}.scale-{}.png", base_name, (scale * 100.0) as u32);
-                dbg!(&name);
                 self.zip
-                    .create_file(&images.join(name), ZipFileOptions::Unaligned, &buf)?;
+                    .create_file(&images.join(&name), ZipFileOptions::Unaligned, &buf)?;
+                self.pri.add_file(&format!("Images\\{}", name))?;
             }
         }
+        self.set_default_logos();
         Ok(())
     }
 
+    /// Uses the generated images for the logos of the manifest that aren't
+    /// configured. They refer to the unqualified name, `resources.pri` maps
+    /// them to the best scale.
+    fn set_default_logos(&mut self) {
+        let logo = |name: &str| format!("Images\\{}.png", name);
+        let set = |value: &mut String, name: &str| {
+            if value.is_empty() {
+                *value = logo(name);
+            }
+        };
+        set(&mut self.manifest.properties.logo, "StoreLogo");
+        for app in &mut self.manifest.applications.application {
+            let visual_elements = &mut app.visual_elements;
+            set(&mut visual_elements.logo_150x150, "Square150x150Logo");
+            set(&mut visual_elements.logo_44x44, "Square44x44Logo");
+            let tile = visual_elements
+                .default_tile
+                .get_or_insert_with(Default::default);
+            tile.logo_71x71.get_or_insert_with(|| logo("SmallTile"));
+            tile.logo_310x310.get_or_insert_with(|| logo("LargeTile"));
+            tile.logo_310x150
+                .get_or_insert_with(|| logo("Wide310x150Logo"));
+            let splash_screen = visual_elements
+                .splash_screen
+                .get_or_insert_with(Default::default);
+            set(&mut splash_screen.image, "SplashScreen");
+        }
+    }
+
+    /// Adds a localized string to `resources.pri`, which can be referenced
+    /// as `ms-resource:<name>` in the manifest.
+    pub fn add_string(&mut self, name: &str, language: &str, value: &str) -> Result<()> {
+        self.pri.add_string(name, language, value)
+    }
+
     pub fn add_file(&mut self, source: &Path, dest: &Path, opts: ZipFileOptions) -> Result<()> {
         self.zip.add_file(source, dest, opts)
     }
@@ -85,15 +134,41 @@ impl Msix {
     }
 
     pub fn finish(mut self, signer: Option<Signer>) -> Result<()> {
-        dbg!(&self.manifest);
-        dbg!(quick_xml::se::to_string(&self.manifest)?);
+        let signer = signer.map(Ok).unwrap_or_else(|| Signer::new(DEBUG_PEM))?;
+        check_publisher(&mut self.manifest.identity.publisher, &signer)?;
+        self.check_logos()?;
+        if !self.pri.is_empty() {
+            let mut pri = vec![];
+            self.pri.build()?.write(&mut Cursor::new(&mut pri))?;
+            self.zip
+                .create_file("resources.pri".as_ref(), ZipFileOptions::Compressed, &pri)?;
+        }
         self.zip.create_file(
             "AppxManifest.xml".as_ref(),
             ZipFileOptions::Compressed,
             &to_xml(&self.manifest, true),
         )?;
         self.zip.finish()?;
-        Self::sign(&self.path, signer, self.compress)
+        Self::sign(&self.path, Some(signer), self.compress)
+    }
+
+    /// Windows refuses to install packages without logos.
+    fn check_logos(&self) -> Result<()> {
+        let hint = "add an `icon` to `manifest.yaml` or configure it in `windows.manifest`";
+        anyhow::ensure!(
+            !self.manifest.properties.logo.is_empty(),
+            "msix is missing the store logo, {}",
+            hint
+        );
+        for app in &self.manifest.applications.application {
+            let visual_elements = &app.visual_elements;
+            anyhow::ensure!(
+                !visual_elements.logo_150x150.is_empty() && !visual_elements.logo_44x44.is_empty(),
+                "msix is missing the square logos, {}",
+                hint
+            );
+        }
+        Ok(())
     }
 
     pub fn sign(path: &Path, signer: Option<Signer>, compress: bool) -> Result<()> {
@@ -148,7 +223,7 @@ impl Msix {
         };
 
         // sign zip
-        let sig = p7x::p7x(&signer, &digests);
+        let sig = p7x::p7x(&signer, &digests)?;
         let mut zip = Zip::append(path, compress)?;
         zip.create_file(
             "AppxSignature.p7x".as_ref(),
@@ -158,6 +233,133 @@ impl Msix {
         zip.finish()?;
         Ok(())
     }
+
+    /// Verifies the signature of a package or bundle and returns the
+    /// certificate of the signer. The digests of the zip, the content types
+    /// and the block map are recomputed and every file is checked against the
+    /// block map.
+    pub fn verify(path: &Path) -> Result<Certificate> {
+        let signed_data = p7x::read_p7x(path)?;
+        let cert = pkcs7::verify_pkcs7(&signed_data)?;
+        let signed = Digests::from_payload(signed_data.encap_content_info.content.as_bytes())?;
+
+        let (axpc, axcd) = unsigned_zip_digests(path)?;
+        anyhow::ensure!(
+            axpc == signed.axpc,
+            "zip contents don't match the signature"
+        );
+        anyhow::ensure!(
+            axcd == signed.axcd,
+            "zip central directory doesn't match the signature"
+        );
+        let mut zip = ZipArchive::new(BufReader::new(File::open(path)?))?;
+        let mut content_types = vec![];
+        zip.by_name("[Content_Types].xml")?
+            .read_to_end(&mut content_types)?;
+        anyhow::ensure!(
+            Sha256::digest(&content_types)[..] == signed.axct,
+            "content types don't match the signature"
+        );
+        let mut block_map = vec![];
+        zip.by_name("AppxBlockMap.xml")?
+            .read_to_end(&mut block_map)?;
+        anyhow::ensure!(
+            Sha256::digest(&block_map)[..] == signed.axbm,
+            "block map doesn't match the signature"
+        );
+        let block_map: AppxBlockMap = quick_xml::de::from_reader(&block_map[..])?;
+        block_map.verify(&mut zip)?;
+        Ok(cert)
+    }
+}
+
+/// Computes the zip digests of a signed package as they were before
+/// `AppxSignature.p7x` was appended, which is the last file of the zip.
+fn unsigned_zip_digests(path: &Path) -> Result<([u8; 32], [u8; 32])> {
+    let mut r = BufReader::new(File::open(path)?);
+    let info = ZipInfo::new(&mut r)?;
+    r.seek(SeekFrom::Start(info.cd_start))?;
+    let mut cd = vec![0; (info.cde_start - info.cd_start) as usize];
+    r.read_exact(&mut cd)?;
+    let mut cde = vec![];
+    r.read_to_end(&mut cde)?;
+    anyhow::ensure!(cde.len() >= 22, "invalid central directory end");
+
+    let mut pos = 0;
+    let mut last = None;
+    while pos < cd.len() {
+        let header = cd.get(pos..pos + 46).context("invalid central directory")?;
+        anyhow::ensure!(
+            header[..4] == 0x02014b50u32.to_le_bytes(),
+            "invalid central directory entry"
+        );
+        let u16_at = |i: usize| u16::from_le_bytes([header[i], header[i + 1]]) as usize;
+        let name_end = pos + 46 + u16_at(28);
+        let end = name_end + u16_at(30) + u16_at(32);
+        let offset = u32::from_le_bytes(header[42..46].try_into().unwrap());
+        last = Some((pos, end, &cd[pos + 46..name_end.min(cd.len())], offset));
+        pos = end;
+    }
+    let Some((start, end, b"AppxSignature.p7x", offset)) = last else {
+        anyhow::bail!("AppxSignature.p7x isn't the last file");
+    };
+    let offset = offset as u64;
+    anyhow::ensure!(end == cd.len(), "invalid central directory");
+
+    let mut hasher = Sha256::new();
+    r.rewind()?;
+    std::io::copy(&mut (&mut r).take(offset), &mut hasher)?;
+    let axpc = hasher.finalize_reset();
+
+    let entries = u16::from_le_bytes([cde[10], cde[11]])
+        .checked_sub(1)
+        .context("invalid central directory end")?;
+    cde[8..10].copy_from_slice(&entries.to_le_bytes());
+    cde[10..12].copy_from_slice(&entries.to_le_bytes());
+    cde[12..16].copy_from_slice(&(start as u32).to_le_bytes());
+    cde[16..20].copy_from_slice(&(offset as u32).to_le_bytes());
+    hasher.update(&cd[..start]);
+    hasher.update(&cde);
+    let axcd = hasher.finalize();
+    Ok((axpc.into(), axcd.into()))
+}
+
+/// Returns the certificate subject of `signer` in the order windows uses
+/// for the publisher of a package, for example `CN=Example, O=Example, C=US`.
+pub fn publisher(signer: &Signer) -> String {
+    signer
+        .subject()
+        .iter()
+        .rev()
+        .map(|(name, value)| {
+            if value.contains([',', '+', '=', '"', '<', '>', '#', ';', '\n'])
+                || value.trim() != value
+            {
+                format!("{}=\"{}\"", name, value.replace('"', "\"\""))
+            } else {
+                format!("{}={}", name, value)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Defaults `publisher` to the certificate subject of `signer`. Otherwise
+/// they need to match for windows to install the package.
+fn check_publisher(publisher: &mut String, signer: &Signer) -> Result<()> {
+    let expected = self::publisher(signer);
+    if publisher.is_empty() {
+        *publisher = expected;
+        return Ok(());
+    }
+    let normalize = |s: &str| s.split(',').map(str::trim).collect::<Vec<_>>().join(", ");
+    anyhow::ensure!(
+        normalize(publisher) == normalize(&expected),
+        "msix publisher `{}` doesn't match the certificate subject, set `windows.manifest.identity.publisher` to `{}`",
+        publisher,
+        expected
+    );
+    Ok(())
 }
 
 fn to_xml<T: Serialize + std::fmt::Debug>(xml: &T, standalone: bool) -> Vec<u8> {
@@ -170,8 +372,124 @@ fn to_xml<T: Serialize + std::fmt::Debug>(xml: &T, standalone: bool) -> Vec<u8>
         )
         .as_bytes(),
     );
-    // dbg!(&xml);
     quick_xml::se::to_writer(&mut buf, xml).unwrap();
-    // dbg!(&buf);
     buf
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::manifest::Application;
+
+    #[test]
+    fn test_default_logos() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path();
+        let mut manifest = AppxManifest::default();
+        manifest
+            .applications
+            .application
+            .push(Application::default());
+        manifest.applications.application[0]
+            .visual_elements
+            .logo_44x44 = "Assets\\Logo.png".into();
+        let mut msix = Msix::new(dir.join("test.msix"), manifest, false)?;
+        msix.set_default_logos();
+        assert_eq!(msix.manifest.properties.logo, "Images\\StoreLogo.png");
+        let visual_elements = &msix.manifest.applications.application[0].visual_elements;
+        assert_eq!(visual_elements.logo_44x44, "Assets\\Logo.png");
+        assert_eq!(
+            visual_elements.logo_150x150,
+            "Images\\Square150x150Logo.png"
+        );
+        let tile = visual_elements.default_tile.as_ref().unwrap();
+        assert_eq!(tile.logo_71x71.as_deref(), Some("Images\\SmallTile.png"));
+        assert_eq!(
+            visual_elements.splash_screen.as_ref().unwrap().image,
+            "Images\\SplashScreen.png"
+        );
+        Ok(())
+    }
+
+    pub(crate) fn test_manifest() -> AppxManifest {
+        let mut manifest = AppxManifest::default();
+        manifest.properties.logo = "Images\\StoreLogo.png".into();
+        manifest
+    }
+
+    #[test]
+    fn test_publisher() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path();
+        let signer = Signer::new(DEBUG_PEM)?;
+        let expected = signer
+            .subject()
+            .iter()
+            .rev()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join(", ");
+        assert_eq!(publisher(&signer), expected);
+
+        let mut publisher = String::new();
+        check_publisher(&mut publisher, &signer)?;
+        assert_eq!(publisher, expected);
+        let mut publisher = expected.replace(", ", ",");
+        check_publisher(&mut publisher, &signer)?;
+        let mut publisher = "CN=Other".to_string();
+        assert!(check_publisher(&mut publisher, &signer).is_err());
+
+        let mut manifest = test_manifest();
+        manifest.identity.publisher = "CN=Other".into();
+        let msix = Msix::new(dir.join("publisher.msix"), manifest, true)?;
+        assert!(msix.finish(None).is_err());
+        let msix = Msix::new(dir.join("logo.msix"), AppxManifest::default(), true)?;
+        assert!(msix.finish(None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path();
+        let path = dir.join("test.msix");
+        let mut msix = Msix::new(path.clone(), test_manifest(), true)?;
+        let source = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/src/lib.rs"));
+        msix.add_file(source, "lib.rs".as_ref(), ZipFileOptions::Compressed)?;
+        msix.add_file(source, "stored.rs".as_ref(), ZipFileOptions::Unaligned)?;
+        msix.finish(None)?;
+        let cert = Msix::verify(&path)?;
+        assert_eq!(&cert, Signer::new(DEBUG_PEM)?.cert());
+
+        // corrupt the stored file
+        let mut bytes = std::fs::read(&path)?;
+        let offset = {
+            let mut zip = ZipArchive::new(Cursor::new(&bytes))?;
+            let file = zip.by_name("stored.rs")?;
+            file.data_start() as usize
+        };
+        bytes[offset] ^= 1;
+        let corrupted = dir.join("corrupted.msix");
+        std::fs::write(&corrupted, &bytes)?;
+        assert!(Msix::verify(&corrupted).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_timestamp() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path();
+        let path = dir.join("test.msix");
+        let mut signer = Signer::new(DEBUG_PEM)?;
+        signer.set_timestamp_url(crate::timestamp::tests::tsa_stub(0));
+        Msix::new(path.clone(), test_manifest(), true)?.finish(Some(signer))?;
+        let signed_data = p7x::read_p7x(&path)?;
+        let signer_info = signed_data.signer_infos.iter().next().unwrap();
+        let unsigned_attrs = signer_info.unsigned_attrs.as_ref().unwrap();
+        assert!(unsigned_attrs
+            .iter()
+            .any(|attr| crate::timestamp::SZOID_RFC3161_COUNTER_SIGN == attr.r#type));
+        Msix::verify(&path)?;
+        Ok(())
+    }
+}
@@ -22,6 +22,12 @@ pub struct AppxManifest {
     #[serde(rename(serialize = "xmlns:rescap"))]
     #[serde(default = "default_rescap_namespace")]
     ns_rescap: String,
+    #[serde(rename(serialize = "xmlns:desktop"))]
+    #[serde(default = "default_desktop_namespace")]
+    ns_desktop: String,
+    #[serde(rename(serialize = "xmlns:com"))]
+    #[serde(default = "default_com_namespace")]
+    ns_com: String,
     pub identity: Identity,
     pub properties: Properties,
     #[serde(default)] // Not optional, but the only nested array may be empty
@@ -40,6 +46,8 @@ impl Default for AppxManifest {
             ns: default_namespace(),
             ns_uap: default_uap_namespace(),
             ns_rescap: default_rescap_namespace(),
+            ns_desktop: default_desktop_namespace(),
+            ns_com: default_com_namespace(),
             identity: Default::default(),
             properties: Default::default(),
             resources: Default::default(),
@@ -145,16 +153,24 @@ impl Default for TargetDeviceFamily {
 pub enum Capability {
     /// <https://learn.microsoft.com/en-us/uwp/schemas/appxpackage/uapmanifestschema/element-capability>
     #[serde(rename(deserialize = "capability"))]
+    #[serde(rename_all(serialize = "PascalCase"))]
     Capability { name: String },
+    /// <https://learn.microsoft.com/en-us/uwp/schemas/appxpackage/uapmanifestschema/element-uap-capability>
+    #[serde(rename(deserialize = "uap"))]
+    #[serde(rename(serialize = "uap:Capability"))]
+    #[serde(rename_all(serialize = "PascalCase"))]
+    Uap { name: String },
     /// <https://learn.microsoft.com/en-us/uwp/schemas/appxpackage/uapmanifestschema/element-rescap-capability>
     #[serde(rename(deserialize = "restricted"))]
     #[serde(rename(serialize = "rescap:Capability"))]
+    #[serde(rename_all(serialize = "PascalCase"))]
     Restricted { name: String },
     /// <https://learn.microsoft.com/en-us/uwp/schemas/appxpackage/uapmanifestschema/element-devicecapability>
     #[serde(rename(deserialize = "device"))]
     #[serde(rename(serialize = "DeviceCapability"))]
+    #[serde(rename_all(serialize = "PascalCase"))]
     Device { name: String },
-    // TODO: uap:Capability and mobile:Capability
+    // TODO: mobile:Capability
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
@@ -183,6 +199,225 @@ pub struct Application {
     pub entry_point: Option<String>,
     #[serde(rename(serialize = "uap:VisualElements"))]
     pub visual_elements: VisualElements,
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "serialize_element"
+    )]
+    pub extensions: Vec<Extension>,
+}
+
+/// <https://learn.microsoft.com/en-us/uwp/schemas/appxpackage/uapmanifestschema/element-1-extensions>
+///
+/// Each extension is serialized as an `Extension` element of its namespace
+/// with the matching `Category`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub enum Extension {
+    /// Registers the application as handler of an url scheme.
+    Protocol(Protocol),
+    /// Registers the application as handler of file types.
+    FileTypeAssociation(FileTypeAssociation),
+    /// Starts the application when the user logs in.
+    StartupTask(StartupTask),
+    /// Activates the application from toast notifications through the com
+    /// class `toast_activator_clsid`.
+    ToastNotificationActivation(ToastNotificationActivation),
+    /// Registers out of process com servers.
+    ComServer(ComServer),
+}
+
+impl Serialize for Extension {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Self::Protocol(protocol) => ExtensionElement::Uap {
+                category: "windows.protocol",
+                protocol: Some(protocol),
+                file_type_association: None,
+            },
+            Self::FileTypeAssociation(association) => ExtensionElement::Uap {
+                category: "windows.fileTypeAssociation",
+                protocol: None,
+                file_type_association: Some(association),
+            },
+            Self::StartupTask(task) => ExtensionElement::Desktop {
+                category: "windows.startupTask",
+                executable: task.executable.as_deref(),
+                entry_point: task.entry_point.as_deref(),
+                startup_task: Some(task),
+                toast_notification_activation: None,
+            },
+            Self::ToastNotificationActivation(activation) => ExtensionElement::Desktop {
+                category: "windows.toastNotificationActivation",
+                executable: None,
+                entry_point: None,
+                startup_task: None,
+                toast_notification_activation: Some(activation),
+            },
+            Self::ComServer(server) => ExtensionElement::Com {
+                category: "windows.comServer",
+                com_server: server,
+            },
+        }
+        .serialize(serializer)
+    }
+}
+
+/// The variant names become the element names inside of `Extensions`.
+#[derive(Serialize)]
+enum ExtensionElement<'a> {
+    /// <https://learn.microsoft.com/en-us/uwp/schemas/appxpackage/uapmanifestschema/element-uap-extension>
+    #[serde(rename = "uap:Extension", rename_all = "PascalCase")]
+    Uap {
+        category: &'static str,
+        #[serde(rename = "uap:Protocol", skip_serializing_if = "Option::is_none")]
+        protocol: Option<&'a Protocol>,
+        #[serde(
+            rename = "uap:FileTypeAssociation",
+            skip_serializing_if = "Option::is_none"
+        )]
+        file_type_association: Option<&'a FileTypeAssociation>,
+    },
+    /// <https://learn.microsoft.com/en-us/uwp/schemas/appxpackage/uapmanifestschema/element-desktop-extension>
+    #[serde(rename = "desktop:Extension", rename_all = "PascalCase")]
+    Desktop {
+        category: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        executable: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        entry_point: Option<&'a str>,
+        #[serde(
+            rename = "desktop:StartupTask",
+            skip_serializing_if = "Option::is_none"
+        )]
+        startup_task: Option<&'a StartupTask>,
+        #[serde(
+            rename = "desktop:ToastNotificationActivation",
+            skip_serializing_if = "Option::is_none"
+        )]
+        toast_notification_activation: Option<&'a ToastNotificationActivation>,
+    },
+    /// <https://learn.microsoft.com/en-us/uwp/schemas/appxpackage/uapmanifestschema/element-com-extension>
+    #[serde(rename = "com:Extension", rename_all = "PascalCase")]
+    Com {
+        category: &'static str,
+        #[serde(rename = "com:ComServer")]
+        com_server: &'a ComServer,
+    },
+}
+
+/// <https://learn.microsoft.com/en-us/uwp/schemas/appxpackage/uapmanifestschema/element-uap-protocol>
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, rename_all(serialize = "PascalCase"))]
+pub struct Protocol {
+    /// Url scheme without `://`.
+    pub name: String,
+    #[serde(
+        default,
+        rename(serialize = "uap:DisplayName"),
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_element"
+    )]
+    pub display_name: Option<String>,
+}
+
+/// <https://learn.microsoft.com/en-us/uwp/schemas/appxpackage/uapmanifestschema/element-uap-filetypeassociation>
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, rename_all(serialize = "PascalCase"))]
+pub struct FileTypeAssociation {
+    /// Lowercase name of the association.
+    pub name: String,
+    #[serde(
+        default,
+        rename(serialize = "uap:DisplayName"),
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_element"
+    )]
+    pub display_name: Option<String>,
+    /// File extensions including the leading dot.
+    #[serde(
+        rename(serialize = "uap:SupportedFileTypes"),
+        serialize_with = "serialize_file_types"
+    )]
+    pub supported_file_types: Vec<String>,
+}
+
+fn serialize_file_types<S>(file_types: &[String], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    #[derive(Serialize)]
+    struct FileType<'a> {
+        #[serde(rename = "$value")]
+        value: &'a str,
+    }
+
+    #[derive(Serialize)]
+    struct SupportedFileTypes<'a> {
+        #[serde(rename = "uap:FileType")]
+        file_type: Vec<FileType<'a>>,
+    }
+
+    SupportedFileTypes {
+        file_type: file_types.iter().map(|value| FileType { value }).collect(),
+    }
+    .serialize(serializer)
+}
+
+/// <https://learn.microsoft.com/en-us/uwp/schemas/appxpackage/uapmanifestschema/element-desktop-startuptask>
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, rename_all(serialize = "PascalCase"))]
+pub struct StartupTask {
+    /// Executable that is started, xbuild defaults it to the executable of the
+    /// application.
+    #[serde(default, skip_serializing)]
+    pub executable: Option<String>,
+    #[serde(default, skip_serializing)]
+    pub entry_point: Option<String>,
+    pub task_id: String,
+    /// Whether the task is enabled before the user changed it in the settings.
+    #[serde(default)]
+    pub enabled: bool,
+    pub display_name: String,
+}
+
+/// <https://learn.microsoft.com/en-us/uwp/schemas/appxpackage/uapmanifestschema/element-desktop-toastnotificationactivation>
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, rename_all(serialize = "PascalCase"))]
+pub struct ToastNotificationActivation {
+    #[serde(rename(serialize = "ToastActivatorCLSID"))]
+    pub toast_activator_clsid: String,
+}
+
+/// <https://learn.microsoft.com/en-us/uwp/schemas/appxpackage/uapmanifestschema/element-com-comserver>
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, rename_all(serialize = "PascalCase"))]
+pub struct ComServer {
+    #[serde(rename(serialize = "com:ExeServer"))]
+    pub exe_server: Vec<ExeServer>,
+}
+
+/// <https://learn.microsoft.com/en-us/uwp/schemas/appxpackage/uapmanifestschema/element-com-exeserver>
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, rename_all(serialize = "PascalCase"))]
+pub struct ExeServer {
+    pub executable: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<String>,
+    pub display_name: String,
+    #[serde(rename(serialize = "com:Class"))]
+    pub class: Vec<ComClass>,
+}
+
+/// <https://learn.microsoft.com/en-us/uwp/schemas/appxpackage/uapmanifestschema/element-com-exeserver-class>
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, rename_all(serialize = "PascalCase"))]
+pub struct ComClass {
+    pub id: String,
+    pub display_name: String,
 }
 
 /// <https://learn.microsoft.com/en-us/uwp/schemas/appxpackage/uapmanifestschema/element-uap-visualelements>
@@ -257,6 +492,14 @@ fn default_rescap_namespace() -> String {
         .to_string()
 }
 
+fn default_desktop_namespace() -> String {
+    "http://schemas.microsoft.com/appx/manifest/desktop/windows10".to_string()
+}
+
+fn default_com_namespace() -> String {
+    "http://schemas.microsoft.com/appx/manifest/com/windows10".to_string()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -347,6 +590,7 @@ mod tests {
                             notification: "badge".into(),
                         }),
                     },
+                    extensions: vec![],
                 }],
             },
             ..Default::default()
@@ -354,4 +598,57 @@ mod tests {
         let xml = quick_xml::se::to_string(&manifest).unwrap();
         println!("{}", xml);
     }
+
+    #[test]
+    fn test_extensions() {
+        let app = Application {
+            id: "App".into(),
+            extensions: vec![
+                Extension::Protocol(Protocol {
+                    name: "myapp".into(),
+                    display_name: None,
+                }),
+                Extension::FileTypeAssociation(FileTypeAssociation {
+                    name: "proj".into(),
+                    display_name: Some("Project".into()),
+                    supported_file_types: vec![".proj".into()],
+                }),
+                Extension::StartupTask(StartupTask {
+                    executable: Some("app.exe".into()),
+                    entry_point: Some("Windows.FullTrustApplication".into()),
+                    task_id: "AppStartup".into(),
+                    enabled: true,
+                    display_name: "App".into(),
+                }),
+                Extension::ComServer(ComServer {
+                    exe_server: vec![ExeServer {
+                        executable: "app.exe".into(),
+                        arguments: Some("-ToastActivated".into()),
+                        display_name: "Toast activator".into(),
+                        class: vec![ComClass {
+                            id: "6b3b5e4c-4e3c-4fd5-8d6f-1a0b2c3d4e5f".into(),
+                            display_name: "Toast activator".into(),
+                        }],
+                    }],
+                }),
+            ],
+            ..Default::default()
+        };
+        let xml = quick_xml::se::to_string(&app).unwrap();
+        for extension in [
+            r#"<uap:Extension Category="windows.protocol"><uap:Protocol Name="myapp"/></uap:Extension>"#,
+            r#"<uap:Extension Category="windows.fileTypeAssociation"><uap:FileTypeAssociation Name="proj"><uap:DisplayName>Project</uap:DisplayName><uap:SupportedFileTypes><uap:FileType>.proj</uap:FileType></uap:SupportedFileTypes></uap:FileTypeAssociation></uap:Extension>"#,
+            r#"<desktop:Extension Category="windows.startupTask" Executable="app.exe" EntryPoint="Windows.FullTrustApplication"><desktop:StartupTask TaskId="AppStartup" Enabled="true" DisplayName="App"/></desktop:Extension>"#,
+            r#"<com:Extension Category="windows.comServer"><com:ComServer><com:ExeServer Executable="app.exe" Arguments="-ToastActivated" DisplayName="Toast activator"><com:Class Id="6b3b5e4c-4e3c-4fd5-8d6f-1a0b2c3d4e5f" DisplayName="Toast activator"/></com:ExeServer></com:ComServer></com:Extension>"#,
+        ] {
+            assert!(xml.contains(extension), "{}", xml);
+        }
+        assert!(xml.contains("<uap:VisualElements/><Extensions>"), "{}", xml);
+
+        let capability = Capability::Uap {
+            name: "documentsLibrary".into(),
+        };
+        let xml = quick_xml::se::to_string(&capability).unwrap();
+        assert_eq!(xml, r#"<uap:Capability Name="documentsLibrary"/>"#);
+    }
 }
@@ -1,6 +1,6 @@
 use super::pkcs7::{build_pkcs7, SPC_INDIRECT_DATA_OBJID, SPC_SIPINFO_OBJID};
 use crate::Signer;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use byteorder::{BigEndian, ReadBytesExt};
 use rasn::prelude::*;
 use rasn_cms::pkcs7_compat::{EncapsulatedContentInfo, SignedData};
@@ -27,13 +27,13 @@ pub fn read_p7x(path: &Path) -> Result<SignedData> {
     Ok(data)
 }
 
-pub fn p7x(signer: &Signer, digests: &Digests) -> Vec<u8> {
+pub fn p7x(signer: &Signer, digests: &Digests) -> Result<Vec<u8>> {
     let payload = Payload::encode(digests);
     let encap_content_info = EncapsulatedContentInfo {
         content_type: SPC_INDIRECT_DATA_OBJID.into(),
         content: Any::new(payload),
     };
-    let signed_data = build_pkcs7(signer, encap_content_info);
+    let signed_data = build_pkcs7(signer, encap_content_info)?;
     let content_info = ContentInfo {
         content_type: CONTENT_SIGNED_DATA.into(),
         content: Any::new(rasn::der::encode(&signed_data).unwrap()),
@@ -41,7 +41,7 @@ pub fn p7x(signer: &Signer, digests: &Digests) -> Vec<u8> {
     let mut p7x = vec![];
     p7x.extend_from_slice(&P7X_MAGIC.to_be_bytes());
     p7x.extend(rasn::der::encode(&content_info).unwrap());
-    p7x
+    Ok(p7x)
 }
 
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
@@ -58,6 +58,30 @@ pub struct Digests {
     pub axci: [u8; 32],
 }
 
+impl Digests {
+    /// Reads the digests from the encoded `SpcIndirectData` of a signature.
+    pub fn from_payload(payload: &[u8]) -> Result<Self> {
+        let start = payload
+            .windows(4)
+            .position(|magic| magic == b"APPX")
+            .context("missing APPX digests")?;
+        let mut digests = Self::default();
+        for chunk in payload[start + 4..].chunks(36) {
+            anyhow::ensure!(chunk.len() == 36, "truncated digest");
+            let digest = match &chunk[..4] {
+                b"AXPC" => &mut digests.axpc,
+                b"AXCD" => &mut digests.axcd,
+                b"AXCT" => &mut digests.axct,
+                b"AXBM" => &mut digests.axbm,
+                b"AXCI" => &mut digests.axci,
+                tag => anyhow::bail!("unknown digest {}", String::from_utf8_lossy(tag)),
+            };
+            digest.copy_from_slice(&chunk[4..]);
+        }
+        Ok(digests)
+    }
+}
+
 #[derive(AsnType, Clone, Debug, Eq, Encode, PartialEq)]
 #[rasn(tag(context, 0))]
 struct Payload {
@@ -239,6 +263,7 @@ mod tests {
         assert!(rem.is_empty());
         println!("{:#?}", res);
         assert_eq!(indirect_data, orig_indirect_data);
+        assert_eq!(Digests::from_payload(&indirect_data).unwrap(), HASHES);
     }
 
     #[test]
@@ -1,8 +1,13 @@
-use crate::Signer;
+use crate::{timestamp, Signer};
+use anyhow::{Context, Result};
 use rasn::prelude::*;
 use rasn_cms::pkcs7_compat::{EncapsulatedContentInfo, SignedData};
-use rasn_cms::{AlgorithmIdentifier, IssuerAndSerialNumber, SignerIdentifier, SignerInfo};
-use rasn_pkix::Attribute;
+use rasn_cms::{
+    AlgorithmIdentifier, CertificateChoices, IssuerAndSerialNumber, SignerIdentifier, SignerInfo,
+};
+use rasn_pkix::{Attribute, Certificate};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::{PaddingScheme, PublicKey, RsaPublicKey};
 use sha2::{Digest, Sha256};
 use std::collections::BTreeSet;
 
@@ -10,10 +15,34 @@ pub const SPC_INDIRECT_DATA_OBJID: ConstOid = ConstOid(&[1, 3, 6, 1, 4, 1, 311,
 pub const SPC_SP_OPUS_INFO_OBJID: ConstOid = ConstOid(&[1, 3, 6, 1, 4, 1, 311, 2, 1, 12]);
 pub const SPC_SIPINFO_OBJID: ConstOid = ConstOid(&[1, 3, 6, 1, 4, 1, 311, 2, 1, 30]);
 
+/// Returns the part of the encapsulated content that is covered by the message
+/// digest, which excludes the header of the content sequence. The msix payload
+/// additionally wraps the sequence in a context tag.
+fn signed_content(mut content: &[u8]) -> Result<&[u8]> {
+    let skip_header = |bytes: &[u8]| -> Result<usize> {
+        let len = *bytes.get(1).context("truncated content")?;
+        Ok(if len & 0x80 == 0 {
+            2
+        } else {
+            2 + (len & 0x7f) as usize
+        })
+    };
+    if content.first() == Some(&0xa0) {
+        content = content
+            .get(skip_header(content)?..)
+            .context("truncated content")?;
+    }
+    content
+        .get(skip_header(content)?..)
+        .context("truncated content")
+}
+
 #[allow(clippy::mutable_key_type)]
-pub fn build_pkcs7(signer: &Signer, encap_content_info: EncapsulatedContentInfo) -> SignedData {
-    let digest = Sha256::digest(&encap_content_info.content.as_bytes()[8..]);
-    let signature = signer.sign(&encap_content_info.content.as_bytes()[8..]);
+pub fn build_pkcs7(
+    signer: &Signer,
+    encap_content_info: EncapsulatedContentInfo,
+) -> Result<SignedData> {
+    let digest = Sha256::digest(signed_content(encap_content_info.content.as_bytes())?);
     let cert = signer.cert();
 
     let digest_algorithm = AlgorithmIdentifier {
@@ -21,6 +50,43 @@ pub fn build_pkcs7(signer: &Signer, encap_content_info: EncapsulatedContentInfo)
             Oid::JOINT_ISO_ITU_T_COUNTRY_US_ORGANIZATION_GOV_CSOR_NIST_ALGORITHMS_HASH_SHA256.into(),
         parameters: Some(Any::new(vec![5, 0])),
     };
+    let mut signed_attrs = SetOf::default();
+    signed_attrs.insert(Attribute {
+        r#type: Oid::ISO_MEMBER_BODY_US_RSADSI_PKCS9_CONTENT_TYPE.into(),
+        values: {
+            let oid = ObjectIdentifier::from(SPC_INDIRECT_DATA_OBJID);
+            let mut content_type = BTreeSet::default();
+            content_type.insert(Any::new(rasn::der::encode(&oid).unwrap()));
+            content_type
+        },
+    });
+    signed_attrs.insert(Attribute {
+        r#type: Oid::ISO_MEMBER_BODY_US_RSADSI_PKCS9_MESSAGE_DIGEST.into(),
+        values: {
+            let digest = OctetString::from(digest.to_vec());
+            let mut digests = BTreeSet::default();
+            digests.insert(Any::new(rasn::der::encode(&digest).unwrap()));
+            digests
+        },
+    });
+    signed_attrs.insert(Attribute {
+        r#type: SPC_SP_OPUS_INFO_OBJID.into(),
+        values: Default::default(),
+    });
+    // when signed attributes are present the signature covers them instead of the content
+    let signature = signer.sign(&rasn::der::encode(&signed_attrs).unwrap());
+    let mut unsigned_attrs = SetOf::default();
+    if let Some(url) = signer.timestamp_url() {
+        let token = timestamp::timestamp(url, &signature)?;
+        unsigned_attrs.insert(Attribute {
+            r#type: timestamp::SZOID_RFC3161_COUNTER_SIGN.into(),
+            values: {
+                let mut values = BTreeSet::default();
+                values.insert(Any::new(rasn::der::encode(&token).unwrap()));
+                values
+            },
+        });
+    }
     let signer_info = SignerInfo {
         version: 1.into(),
         sid: SignerIdentifier::IssuerAndSerialNumber(IssuerAndSerialNumber {
@@ -28,43 +94,15 @@ pub fn build_pkcs7(signer: &Signer, encap_content_info: EncapsulatedContentInfo)
             serial_number: cert.tbs_certificate.serial_number.clone(),
         }),
         digest_algorithm: digest_algorithm.clone(),
-        signed_attrs: Some({
-            let mut signed_attrs = SetOf::default();
-            signed_attrs.insert(Attribute {
-                r#type: Oid::ISO_MEMBER_BODY_US_RSADSI_PKCS9_CONTENT_TYPE.into(),
-                values: {
-                    let oid = ObjectIdentifier::from(SPC_INDIRECT_DATA_OBJID);
-                    let mut content_type = BTreeSet::default();
-                    content_type.insert(Any::new(rasn::der::encode(&oid).unwrap()));
-                    content_type
-                },
-            });
-            signed_attrs.insert(Attribute {
-                r#type: Oid::ISO_MEMBER_BODY_US_RSADSI_PKCS9_MESSAGE_DIGEST.into(),
-                values: {
-                    let digest = OctetString::from(digest.to_vec());
-                    let mut digests = BTreeSet::default();
-                    digests.insert(Any::new(rasn::der::encode(&digest).unwrap()));
-                    digests
-                },
-            });
-            signed_attrs.insert(Attribute {
-                r#type: SPC_SP_OPUS_INFO_OBJID.into(),
-                values: Default::default(),
-            });
-            signed_attrs
-        }),
+        signed_attrs: Some(signed_attrs),
         signature_algorithm: AlgorithmIdentifier {
             algorithm: Oid::ISO_MEMBER_BODY_US_RSADSI_PKCS1.into(),
             parameters: Some(Any::new(vec![5, 0])),
         },
         signature: OctetString::from(signature.to_vec()),
-        unsigned_attrs: Some({
-            // TODO: 1.3.6.1.4.1.311.3.3.1 timestamp? optional?
-            SetOf::default()
-        }),
+        unsigned_attrs: Some(unsigned_attrs),
     };
-    SignedData {
+    Ok(SignedData {
         version: 1.into(),
         digest_algorithms: {
             let mut digest_algorithms = SetOf::default();
@@ -72,12 +110,98 @@ pub fn build_pkcs7(signer: &Signer, encap_content_info: EncapsulatedContentInfo)
             digest_algorithms
         },
         encap_content_info,
-        certificates: Some(SetOf::default()),
+        certificates: Some({
+            let mut certificates = SetOf::default();
+            certificates.insert(CertificateChoices::Certificate(Box::new(cert.clone())));
+            certificates
+        }),
         crls: None,
         signer_infos: {
             let mut signer_infos = SetOf::default();
             signer_infos.insert(signer_info);
             signer_infos
         },
+    })
+}
+
+/// Verifies the signature of `signed_data` and returns the certificate of the signer.
+pub fn verify_pkcs7(signed_data: &SignedData) -> Result<Certificate> {
+    anyhow::ensure!(
+        SPC_INDIRECT_DATA_OBJID == signed_data.encap_content_info.content_type,
+        "unexpected content type {:?}",
+        signed_data.encap_content_info.content_type
+    );
+    anyhow::ensure!(
+        signed_data.signer_infos.len() == 1,
+        "expected one signer, found {}",
+        signed_data.signer_infos.len()
+    );
+    let signer_info = signed_data.signer_infos.iter().next().unwrap();
+    anyhow::ensure!(
+        Oid::JOINT_ISO_ITU_T_COUNTRY_US_ORGANIZATION_GOV_CSOR_NIST_ALGORITHMS_HASH_SHA256
+            == signer_info.digest_algorithm.algorithm,
+        "unsupported digest algorithm {:?}",
+        signer_info.digest_algorithm.algorithm
+    );
+    let SignerIdentifier::IssuerAndSerialNumber(sid) = &signer_info.sid else {
+        anyhow::bail!("unsupported signer identifier");
+    };
+    let cert = signed_data
+        .certificates
+        .iter()
+        .flatten()
+        .find_map(|cert| match cert {
+            CertificateChoices::Certificate(cert)
+                if cert.tbs_certificate.issuer == sid.issuer
+                    && cert.tbs_certificate.serial_number == sid.serial_number =>
+            {
+                Some(cert)
+            }
+            _ => None,
+        })
+        .context("missing signer certificate")?;
+    let signed_attrs = signer_info
+        .signed_attrs
+        .as_ref()
+        .context("missing signed attributes")?;
+    let message_digest = signed_attrs
+        .iter()
+        .find(|attr| Oid::ISO_MEMBER_BODY_US_RSADSI_PKCS9_MESSAGE_DIGEST == attr.r#type)
+        .and_then(|attr| attr.values.iter().next())
+        .context("missing message digest")?;
+    let message_digest = rasn::der::decode::<OctetString>(message_digest.as_bytes())
+        .map_err(|err| anyhow::anyhow!("{}", err))?;
+    let digest = Sha256::digest(signed_content(
+        signed_data.encap_content_info.content.as_bytes(),
+    )?);
+    anyhow::ensure!(
+        message_digest[..] == digest[..],
+        "content doesn't match the signed message digest"
+    );
+    let spki = rasn::der::encode(&cert.tbs_certificate.subject_public_key_info)
+        .map_err(|err| anyhow::anyhow!("{}", err))?;
+    let pubkey = RsaPublicKey::from_public_key_der(&spki)?;
+    let digest = Sha256::digest(rasn::der::encode(signed_attrs).unwrap());
+    let padding = PaddingScheme::new_pkcs1v15_sign::<sha2::Sha256>();
+    pubkey
+        .verify(padding, &digest, &signer_info.signature)
+        .context("invalid signature")?;
+    Ok((**cert).clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signed_content() {
+        assert_eq!(signed_content(&[0x30, 0x01, 0xff]).unwrap(), [0xff]);
+        assert_eq!(
+            signed_content(&[0xa0, 0x03, 0x30, 0x01, 0xff]).unwrap(),
+            [0xff]
+        );
+        assert!(signed_content(&[]).is_err());
+        assert!(signed_content(&[0xa0]).is_err());
+        assert!(signed_content(&[0x30, 0x84, 0x00]).is_err());
     }
 }
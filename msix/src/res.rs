@@ -0,0 +1,598 @@
+//! Resources embedded in windows executables.
+//!
+//! Instead of compiling an `.rc` script and converting the resulting `.res`
+//! file with `cvtres`, the resources are written directly as a COFF object
+//! containing the `.rsrc` sections, which can be passed to the linker like
+//! any other object.
+//!
+//! <https://learn.microsoft.com/en-us/windows/win32/debug/pe-format#the-rsrc-section>
+use anyhow::Result;
+use byteorder::{LittleEndian, WriteBytesExt};
+use serde::Deserialize;
+use std::path::Path;
+use xcommon::{Scaler, ScalerOptsBuilder};
+
+const RT_ICON: u32 = 3;
+const RT_GROUP_ICON: u32 = 14;
+const RT_VERSION: u32 = 16;
+const RT_MANIFEST: u32 = 24;
+const CREATEPROCESS_MANIFEST_RESOURCE_ID: u32 = 1;
+/// en-US
+const LANGUAGE: u32 = 0x0409;
+/// UTF-16LE
+const CODE_PAGE: u16 = 1200;
+
+const ICON_SIZES: [u32; 6] = [16, 24, 32, 48, 64, 256];
+
+/// Target machine of the resource object.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Machine {
+    X64,
+    Arm64,
+}
+
+impl Machine {
+    fn id(self) -> u16 {
+        match self {
+            Self::X64 => 0x8664,
+            Self::Arm64 => 0xaa64,
+        }
+    }
+
+    /// `IMAGE_REL_*_ADDR32NB` relocation type.
+    fn addr32nb(self) -> u16 {
+        match self {
+            Self::X64 => 0x0003,
+            Self::Arm64 => 0x0002,
+        }
+    }
+}
+
+/// Application manifest settings.
+///
+/// <https://learn.microsoft.com/en-us/windows/win32/sbscs/application-manifests>
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExeManifest {
+    #[serde(default)]
+    pub dpi_awareness: DpiAwareness,
+    /// Allows paths longer than `MAX_PATH` when enabled system wide.
+    #[serde(default = "default_long_path_aware")]
+    pub long_path_aware: bool,
+    /// UAC execution level.
+    #[serde(default)]
+    pub execution_level: ExecutionLevel,
+}
+
+fn default_long_path_aware() -> bool {
+    true
+}
+
+impl Default for ExeManifest {
+    fn default() -> Self {
+        Self {
+            dpi_awareness: Default::default(),
+            long_path_aware: default_long_path_aware(),
+            execution_level: Default::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DpiAwareness {
+    Unaware,
+    System,
+    PerMonitor,
+    #[default]
+    PerMonitorV2,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutionLevel {
+    #[default]
+    AsInvoker,
+    HighestAvailable,
+    RequireAdministrator,
+}
+
+impl ExeManifest {
+    pub fn to_xml(&self) -> String {
+        let execution_level = match self.execution_level {
+            ExecutionLevel::AsInvoker => "asInvoker",
+            ExecutionLevel::HighestAvailable => "highestAvailable",
+            ExecutionLevel::RequireAdministrator => "requireAdministrator",
+        };
+        let (dpi_aware, dpi_awareness) = match self.dpi_awareness {
+            DpiAwareness::Unaware => ("false", "unaware"),
+            DpiAwareness::System => ("true", "system"),
+            DpiAwareness::PerMonitor => ("true/pm", "PerMonitor"),
+            DpiAwareness::PerMonitorV2 => ("true/pm", "PerMonitorV2, PerMonitor"),
+        };
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<assembly xmlns="urn:schemas-microsoft-com:asm.v1" manifestVersion="1.0">
+  <trustInfo xmlns="urn:schemas-microsoft-com:asm.v3">
+    <security>
+      <requestedPrivileges>
+        <requestedExecutionLevel level="{}" uiAccess="false"/>
+      </requestedPrivileges>
+    </security>
+  </trustInfo>
+  <compatibility xmlns="urn:schemas-microsoft-com:compatibility.v1">
+    <application>
+      <supportedOS Id="{{8e0f7a12-bfb3-4fe8-b9a5-48fd50a15a9a}}"/>
+    </application>
+  </compatibility>
+  <application xmlns="urn:schemas-microsoft-com:asm.v3">
+    <windowsSettings>
+      <dpiAware xmlns="http://schemas.microsoft.com/SMI/2005/WindowsSettings">{}</dpiAware>
+      <dpiAwareness xmlns="http://schemas.microsoft.com/SMI/2016/WindowsSettings">{}</dpiAwareness>
+      <longPathAware xmlns="http://schemas.microsoft.com/SMI/2016/WindowsSettings">{}</longPathAware>
+    </windowsSettings>
+  </application>
+</assembly>
+"#,
+            execution_level, dpi_aware, dpi_awareness, self.long_path_aware
+        )
+    }
+}
+
+/// `VERSIONINFO` resource shown in the details tab of the file properties.
+///
+/// <https://learn.microsoft.com/en-us/windows/win32/menurc/versioninfo-resource>
+#[derive(Clone, Debug, Default)]
+pub struct VersionInfo {
+    pub version: [u16; 4],
+    /// `StringFileInfo` entries like `FileDescription` or `ProductName`.
+    pub strings: Vec<(String, String)>,
+}
+
+impl VersionInfo {
+    /// Creates a version info from a dotted version like `1.2.3.0`. Trailing
+    /// non numeric parts of a component like pre-release tags are ignored.
+    pub fn new(version: &str) -> Self {
+        let mut parts = [0; 4];
+        for (part, component) in parts.iter_mut().zip(version.split('.')) {
+            let digits = component
+                .find(|c: char| !c.is_ascii_digit())
+                .map(|end| &component[..end])
+                .unwrap_or(component);
+            *part = digits.parse().unwrap_or_default();
+        }
+        Self {
+            version: parts,
+            strings: vec![
+                ("FileVersion".into(), version.into()),
+                ("ProductVersion".into(), version.into()),
+            ],
+        }
+    }
+
+    pub fn add_string(&mut self, key: &str, value: &str) {
+        self.strings.push((key.into(), value.into()));
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let [a, b, c, d] = self.version.map(u32::from);
+        let ms = a << 16 | b;
+        let ls = c << 16 | d;
+        // VS_FIXEDFILEINFO
+        let mut fixed = vec![];
+        for value in [
+            0xfeef04bd, // signature
+            0x0001_0000,
+            ms,
+            ls,
+            ms,
+            ls,
+            0x3f,        // VS_FFI_FILEFLAGSMASK
+            0,           // flags
+            0x0004_0004, // VOS_NT_WINDOWS32
+            1,           // VFT_APP
+            0,
+            0,
+            0,
+        ] {
+            fixed.write_u32::<LittleEndian>(value).unwrap();
+        }
+        let strings = self
+            .strings
+            .iter()
+            .map(|(key, value)| {
+                let value = utf16z(value);
+                version_node(key, 1, (value.len() / 2) as u16, &value, &[])
+            })
+            .collect::<Vec<_>>();
+        let language = format!("{:04x}{:04x}", LANGUAGE, CODE_PAGE);
+        let string_table = version_node(&language, 1, 0, &[], &strings);
+        let string_file_info = version_node("StringFileInfo", 1, 0, &[], &[string_table]);
+        let mut translation = vec![];
+        translation
+            .write_u16::<LittleEndian>(LANGUAGE as u16)
+            .unwrap();
+        translation.write_u16::<LittleEndian>(CODE_PAGE).unwrap();
+        let var = version_node("Translation", 0, 4, &translation, &[]);
+        let var_file_info = version_node("VarFileInfo", 1, 0, &[], &[var]);
+        version_node(
+            "VS_VERSION_INFO",
+            0,
+            fixed.len() as u16,
+            &fixed,
+            &[string_file_info, var_file_info],
+        )
+    }
+}
+
+/// Encodes a version info block. All blocks share the same layout of a
+/// header, a key, a value and the child blocks, each aligned to 4 bytes.
+fn version_node(key: &str, ty: u16, value_len: u16, value: &[u8], children: &[Vec<u8>]) -> Vec<u8> {
+    let mut buf = vec![0; 6];
+    buf.extend(utf16z(key));
+    pad(&mut buf, 4);
+    buf.extend(value);
+    for child in children {
+        pad(&mut buf, 4);
+        buf.extend(child);
+    }
+    let len = buf.len() as u16;
+    buf[..2].copy_from_slice(&len.to_le_bytes());
+    buf[2..4].copy_from_slice(&value_len.to_le_bytes());
+    buf[4..6].copy_from_slice(&ty.to_le_bytes());
+    buf
+}
+
+fn utf16z(s: &str) -> Vec<u8> {
+    s.encode_utf16()
+        .chain(std::iter::once(0))
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+fn pad(buf: &mut Vec<u8>, align: usize) {
+    buf.resize(buf.len().div_ceil(align) * align, 0);
+}
+
+/// Icon, version info and application manifest of an executable.
+#[derive(Clone, Debug, Default)]
+pub struct ExeResources {
+    /// Png encoded icons by size.
+    icons: Vec<(u32, Vec<u8>)>,
+    version_info: Option<VersionInfo>,
+    manifest: Option<ExeManifest>,
+}
+
+impl ExeResources {
+    pub fn add_icon(&mut self, path: &Path) -> Result<()> {
+        let scaler = Scaler::open(path)?;
+        self.icons = ICON_SIZES
+            .iter()
+            .map(|size| {
+                (
+                    *size,
+                    scaler.to_vec(ScalerOptsBuilder::new(*size, *size).build()),
+                )
+            })
+            .collect();
+        Ok(())
+    }
+
+    pub fn set_version_info(&mut self, version_info: VersionInfo) {
+        self.version_info = Some(version_info);
+    }
+
+    pub fn set_manifest(&mut self, manifest: ExeManifest) {
+        self.manifest = Some(manifest);
+    }
+
+    /// Writes the resources as a COFF object for `machine` to `path`.
+    pub fn write(&self, path: &Path, machine: Machine) -> Result<()> {
+        std::fs::write(path, self.to_coff(machine))?;
+        Ok(())
+    }
+
+    /// Returns the resources as `(type, id, data)` sorted by type and id.
+    fn entries(&self) -> Vec<(u32, u32, Vec<u8>)> {
+        let mut entries = vec![];
+        if !self.icons.is_empty() {
+            // GRPICONDIR
+            let mut group = vec![];
+            group.write_u16::<LittleEndian>(0).unwrap();
+            group.write_u16::<LittleEndian>(1).unwrap();
+            group
+                .write_u16::<LittleEndian>(self.icons.len() as u16)
+                .unwrap();
+            for (i, (size, png)) in self.icons.iter().enumerate() {
+                let id = i as u32 + 1;
+                // a size of 0 means 256
+                group.push(*size as u8);
+                group.push(*size as u8);
+                group.push(0);
+                group.push(0);
+                group.write_u16::<LittleEndian>(1).unwrap();
+                group.write_u16::<LittleEndian>(32).unwrap();
+                group.write_u32::<LittleEndian>(png.len() as u32).unwrap();
+                group.write_u16::<LittleEndian>(id as u16).unwrap();
+                entries.push((RT_ICON, id, png.clone()));
+            }
+            entries.push((RT_GROUP_ICON, 1, group));
+        }
+        if let Some(version_info) = self.version_info.as_ref() {
+            entries.push((RT_VERSION, 1, version_info.to_bytes()));
+        }
+        if let Some(manifest) = self.manifest.as_ref() {
+            entries.push((
+                RT_MANIFEST,
+                CREATEPROCESS_MANIFEST_RESOURCE_ID,
+                manifest.to_xml().into_bytes(),
+            ));
+        }
+        entries
+    }
+
+    pub fn to_coff(&self, machine: Machine) -> Vec<u8> {
+        let entries = self.entries();
+        let mut types: Vec<(u32, Vec<usize>)> = vec![];
+        for (i, (ty, _, _)) in entries.iter().enumerate() {
+            match types.last_mut() {
+                Some((last, ids)) if last == ty => ids.push(i),
+                _ => types.push((*ty, vec![i])),
+            }
+        }
+
+        // `.rsrc$01` contains the directory tree followed by the data
+        // entries, `.rsrc$02` the resource data. The directory tree has
+        // three levels, type, id and language.
+        let dir_len = |entries: usize| 16 + 8 * entries;
+        let types_len = dir_len(types.len());
+        let ids_len: usize = types.iter().map(|(_, ids)| dir_len(ids.len())).sum();
+        let languages_len = entries.len() * dir_len(1);
+        let data_entries = types_len + ids_len + languages_len;
+
+        let mut rsrc01 = vec![];
+        write_dir(&mut rsrc01, types.len());
+        let mut offset = types_len;
+        for (ty, ids) in &types {
+            write_dir_entry(&mut rsrc01, *ty, offset as u32 | 0x8000_0000);
+            offset += dir_len(ids.len());
+        }
+        for (_, ids) in &types {
+            write_dir(&mut rsrc01, ids.len());
+            for i in ids {
+                let language_dir = types_len + ids_len + i * dir_len(1);
+                write_dir_entry(
+                    &mut rsrc01,
+                    entries[*i].1,
+                    language_dir as u32 | 0x8000_0000,
+                );
+            }
+        }
+        for i in 0..entries.len() {
+            write_dir(&mut rsrc01, 1);
+            write_dir_entry(&mut rsrc01, LANGUAGE, (data_entries + i * 16) as u32);
+        }
+        let mut rsrc02 = vec![];
+        let mut relocations = vec![];
+        for (_, _, data) in &entries {
+            relocations.push(rsrc01.len() as u32);
+            // the linker adds the rva of `.rsrc$02`
+            rsrc01
+                .write_u32::<LittleEndian>(rsrc02.len() as u32)
+                .unwrap();
+            rsrc01.write_u32::<LittleEndian>(data.len() as u32).unwrap();
+            rsrc01.write_u32::<LittleEndian>(0).unwrap();
+            rsrc01.write_u32::<LittleEndian>(0).unwrap();
+            rsrc02.extend(data);
+            pad(&mut rsrc02, 8);
+        }
+
+        let header_len = 20 + 2 * 40;
+        let rsrc01_start = header_len;
+        let relocations_start = rsrc01_start + rsrc01.len();
+        let rsrc02_start = relocations_start + relocations.len() * 10;
+        let symbols_start = rsrc02_start + rsrc02.len();
+        let rsrc01_len = rsrc01.len();
+
+        let mut coff = vec![];
+        // IMAGE_FILE_HEADER
+        coff.write_u16::<LittleEndian>(machine.id()).unwrap();
+        coff.write_u16::<LittleEndian>(2).unwrap();
+        coff.write_u32::<LittleEndian>(0).unwrap();
+        coff.write_u32::<LittleEndian>(symbols_start as u32)
+            .unwrap();
+        coff.write_u32::<LittleEndian>(4).unwrap();
+        coff.write_u16::<LittleEndian>(0).unwrap();
+        coff.write_u16::<LittleEndian>(0).unwrap();
+        write_section_header(
+            &mut coff,
+            b".rsrc$01",
+            rsrc01_len,
+            rsrc01_start,
+            relocations_start,
+            relocations.len(),
+        );
+        write_section_header(&mut coff, b".rsrc$02", rsrc02.len(), rsrc02_start, 0, 0);
+        coff.extend(rsrc01);
+        for offset in &relocations {
+            coff.write_u32::<LittleEndian>(*offset).unwrap();
+            // symbol of `.rsrc$02`
+            coff.write_u32::<LittleEndian>(2).unwrap();
+            coff.write_u16::<LittleEndian>(machine.addr32nb()).unwrap();
+        }
+        coff.extend(&rsrc02);
+        write_section_symbol(&mut coff, b".rsrc$01", 1, rsrc01_len, relocations.len());
+        write_section_symbol(&mut coff, b".rsrc$02", 2, rsrc02.len(), 0);
+        // empty string table
+        coff.write_u32::<LittleEndian>(4).unwrap();
+        coff
+    }
+}
+
+/// IMAGE_RESOURCE_DIRECTORY with only id entries.
+fn write_dir(buf: &mut Vec<u8>, entries: usize) {
+    buf.extend([0; 12]);
+    buf.write_u16::<LittleEndian>(0).unwrap();
+    buf.write_u16::<LittleEndian>(entries as u16).unwrap();
+}
+
+fn write_dir_entry(buf: &mut Vec<u8>, id: u32, offset: u32) {
+    buf.write_u32::<LittleEndian>(id).unwrap();
+    buf.write_u32::<LittleEndian>(offset).unwrap();
+}
+
+fn write_section_header(
+    buf: &mut Vec<u8>,
+    name: &[u8; 8],
+    len: usize,
+    start: usize,
+    relocations_start: usize,
+    relocations: usize,
+) {
+    buf.extend(name);
+    buf.write_u32::<LittleEndian>(0).unwrap();
+    buf.write_u32::<LittleEndian>(0).unwrap();
+    buf.write_u32::<LittleEndian>(len as u32).unwrap();
+    buf.write_u32::<LittleEndian>(start as u32).unwrap();
+    buf.write_u32::<LittleEndian>(relocations_start as u32)
+        .unwrap();
+    buf.write_u32::<LittleEndian>(0).unwrap();
+    buf.write_u16::<LittleEndian>(relocations as u16).unwrap();
+    buf.write_u16::<LittleEndian>(0).unwrap();
+    // IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ
+    buf.write_u32::<LittleEndian>(0x4000_0040).unwrap();
+}
+
+/// Static section symbol followed by its auxiliary record.
+fn write_section_symbol(
+    buf: &mut Vec<u8>,
+    name: &[u8; 8],
+    section: u16,
+    len: usize,
+    relocations: usize,
+) {
+    buf.extend(name);
+    buf.write_u32::<LittleEndian>(0).unwrap();
+    buf.write_u16::<LittleEndian>(section).unwrap();
+    buf.write_u16::<LittleEndian>(0).unwrap();
+    // IMAGE_SYM_CLASS_STATIC
+    buf.push(3);
+    buf.push(1);
+    buf.write_u32::<LittleEndian>(len as u32).unwrap();
+    buf.write_u16::<LittleEndian>(relocations as u16).unwrap();
+    buf.extend([0; 12]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(buf: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(buf: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Resolves the resources in `coff` like the linker, returning
+    /// `(type, id, language, data)`.
+    fn read_resources(coff: &[u8]) -> Vec<(u32, u32, u32, Vec<u8>)> {
+        assert_eq!(u16_at(coff, 2), 2);
+        let section = |i: usize| {
+            let header = &coff[20 + i * 40..];
+            let len = u32_at(header, 16) as usize;
+            let start = u32_at(header, 20) as usize;
+            (
+                &coff[start..start + len],
+                u32_at(header, 24) as usize,
+                u16_at(header, 32) as usize,
+            )
+        };
+        let (rsrc01, relocations_start, relocations) = section(0);
+        let (rsrc02, _, _) = section(1);
+        let entries = |offset: usize| {
+            let n = u16_at(rsrc01, offset + 12) + u16_at(rsrc01, offset + 14);
+            (0..n as usize)
+                .map(|i| {
+                    let entry = offset + 16 + i * 8;
+                    (u32_at(rsrc01, entry), u32_at(rsrc01, entry + 4))
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut resources = vec![];
+        for (ty, offset) in entries(0) {
+            assert_ne!(offset & 0x8000_0000, 0);
+            for (id, offset) in entries((offset & 0x7fff_ffff) as usize) {
+                assert_ne!(offset & 0x8000_0000, 0);
+                for (language, offset) in entries((offset & 0x7fff_ffff) as usize) {
+                    let offset = offset as usize;
+                    assert!((0..relocations)
+                        .any(|i| { u32_at(coff, relocations_start + i * 10) as usize == offset }));
+                    let start = u32_at(rsrc01, offset) as usize;
+                    let len = u32_at(rsrc01, offset + 4) as usize;
+                    resources.push((ty, id, language, rsrc02[start..start + len].to_vec()));
+                }
+            }
+        }
+        resources
+    }
+
+    #[test]
+    fn test_resources() {
+        let mut resources = ExeResources {
+            icons: vec![(16, vec![1; 3]), (256, vec![2; 5])],
+            ..Default::default()
+        };
+        let mut version_info = VersionInfo::new("1.2.3-beta.0");
+        version_info.add_string("ProductName", "Example");
+        resources.set_version_info(version_info);
+        resources.set_manifest(Default::default());
+        let coff = resources.to_coff(Machine::Arm64);
+        assert_eq!(u16_at(&coff, 0), 0xaa64);
+
+        let resources = read_resources(&coff);
+        let types = resources
+            .iter()
+            .map(|(ty, id, _, _)| (*ty, *id))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                (RT_ICON, 1),
+                (RT_ICON, 2),
+                (RT_GROUP_ICON, 1),
+                (RT_VERSION, 1),
+                (RT_MANIFEST, 1)
+            ]
+        );
+        assert!(resources
+            .iter()
+            .all(|(_, _, language, _)| *language == LANGUAGE));
+        assert_eq!(resources[1].3, [2; 5]);
+
+        let group = &resources[2].3;
+        assert_eq!(u16_at(group, 4), 2);
+        // 256 px icons are stored with a size of 0
+        assert_eq!(group[6 + 14], 0);
+        assert_eq!(u32_at(group, 6 + 14 + 8), 5);
+        assert_eq!(u16_at(group, 6 + 14 + 12), 2);
+
+        let version = &resources[3].3;
+        assert_eq!(u16_at(version, 0) as usize, version.len());
+        assert_eq!(u16_at(version, 2), 52);
+        // VS_FIXEDFILEINFO starts after the key and padding
+        assert_eq!(u32_at(version, 40), 0xfeef04bd);
+        assert_eq!(u32_at(version, 48), 1 << 16 | 2);
+        assert_eq!(u32_at(version, 52), 3 << 16);
+        let product_name = utf16z("Example");
+        assert!(version
+            .windows(product_name.len())
+            .any(|window| window == product_name));
+
+        let manifest = String::from_utf8(resources[4].3.clone()).unwrap();
+        assert!(manifest.contains(r#"level="asInvoker""#));
+        assert!(manifest.contains(">PerMonitorV2, PerMonitor</dpiAwareness>"));
+        assert!(manifest.contains(">true</longPathAware>"));
+    }
+}
@@ -0,0 +1,240 @@
+//! RFC 3161 timestamps.
+//!
+//! <https://www.rfc-editor.org/rfc/rfc3161>
+// triggered by the derived encoding of optional explicitly tagged fields
+#![allow(clippy::redundant_pattern_matching)]
+use anyhow::{Context, Result};
+use rasn::prelude::*;
+use rasn_cms::{AlgorithmIdentifier, ContentInfo, SignedData, CONTENT_SIGNED_DATA};
+use rasn_pkix::{Extensions, GeneralName};
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Unsigned attribute containing an RFC 3161 timestamp token in authenticode
+/// and msix signatures.
+pub const SZOID_RFC3161_COUNTER_SIGN: ConstOid = ConstOid(&[1, 3, 6, 1, 4, 1, 311, 3, 3, 1]);
+pub const TST_INFO_OBJID: ConstOid = ConstOid(&[1, 2, 840, 113549, 1, 9, 16, 1, 4]);
+
+/// Requests a timestamp token for `signature` from the timestamp authority at
+/// `url`. The token is checked against the request, the signature of the
+/// timestamp authority is verified by the platform when the signature is
+/// validated.
+pub fn timestamp(url: &str, signature: &[u8]) -> Result<ContentInfo> {
+    let nonce = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+    let request = TimeStampReq {
+        version: 1.into(),
+        message_imprint: MessageImprint::new(signature),
+        req_policy: None,
+        nonce: Some(nonce.into()),
+        cert_req: true,
+        extensions: None,
+    };
+    let body = rasn::der::encode(&request).map_err(|err| anyhow::anyhow!("{}", err))?;
+    let response = reqwest::blocking::Client::new()
+        .post(url)
+        .header("Content-Type", "application/timestamp-query")
+        .body(body)
+        .send()
+        .with_context(|| format!("failed to request timestamp from {}", url))?
+        .error_for_status()?
+        .bytes()?;
+    let response = rasn::der::decode::<TimeStampResp>(&response)
+        .map_err(|err| anyhow::anyhow!("invalid timestamp response: {}", err))?;
+    // granted or granted with modifications
+    anyhow::ensure!(
+        response.status.status <= 1.into(),
+        "timestamp request rejected with status {}",
+        response.status.status
+    );
+    let token = response
+        .time_stamp_token
+        .context("timestamp response is missing the token")?;
+    anyhow::ensure!(CONTENT_SIGNED_DATA == token.content_type);
+    let signed_data = rasn::der::decode::<SignedData>(token.content.as_bytes())
+        .map_err(|err| anyhow::anyhow!("{}", err))?;
+    anyhow::ensure!(TST_INFO_OBJID == signed_data.encap_content_info.content_type);
+    let tst_info = signed_data
+        .encap_content_info
+        .content
+        .as_ref()
+        .context("timestamp token is missing the tst info")?;
+    let tst_info =
+        rasn::der::decode::<TstInfo>(tst_info).map_err(|err| anyhow::anyhow!("{}", err))?;
+    anyhow::ensure!(
+        tst_info.message_imprint == request.message_imprint,
+        "timestamp doesn't match the signature"
+    );
+    anyhow::ensure!(
+        tst_info.nonce.is_none() || tst_info.nonce == request.nonce,
+        "timestamp nonce doesn't match the request"
+    );
+    Ok(token)
+}
+
+#[derive(AsnType, Clone, Debug, Decode, Encode, Eq, PartialEq)]
+struct TimeStampReq {
+    version: Integer,
+    message_imprint: MessageImprint,
+    req_policy: Option<ObjectIdentifier>,
+    nonce: Option<Integer>,
+    #[rasn(default)]
+    cert_req: bool,
+    #[rasn(tag(0))]
+    extensions: Option<Extensions>,
+}
+
+#[derive(AsnType, Clone, Debug, Decode, Encode, Eq, PartialEq)]
+struct MessageImprint {
+    hash_algorithm: AlgorithmIdentifier,
+    hashed_message: OctetString,
+}
+
+impl MessageImprint {
+    fn new(message: &[u8]) -> Self {
+        Self {
+            hash_algorithm: AlgorithmIdentifier {
+                algorithm:
+                    Oid::JOINT_ISO_ITU_T_COUNTRY_US_ORGANIZATION_GOV_CSOR_NIST_ALGORITHMS_HASH_SHA256
+                        .into(),
+                parameters: Some(Any::new(vec![5, 0])),
+            },
+            hashed_message: OctetString::from(Sha256::digest(message).to_vec()),
+        }
+    }
+}
+
+#[derive(AsnType, Clone, Debug, Decode, Encode, Eq, PartialEq)]
+struct TimeStampResp {
+    status: PkiStatusInfo,
+    time_stamp_token: Option<ContentInfo>,
+}
+
+#[derive(AsnType, Clone, Debug, Decode, Encode, Eq, PartialEq)]
+struct PkiStatusInfo {
+    status: Integer,
+    status_string: Option<SequenceOf<Utf8String>>,
+    fail_info: Option<BitString>,
+}
+
+#[derive(AsnType, Clone, Debug, Decode, Encode, Eq, PartialEq)]
+struct TstInfo {
+    version: Integer,
+    policy: ObjectIdentifier,
+    message_imprint: MessageImprint,
+    serial_number: Integer,
+    gen_time: GeneralizedTime,
+    accuracy: Option<Accuracy>,
+    #[rasn(default)]
+    ordering: bool,
+    nonce: Option<Integer>,
+    #[rasn(tag(explicit(0)))]
+    tsa: Option<GeneralName>,
+    #[rasn(tag(1))]
+    extensions: Option<Extensions>,
+}
+
+#[derive(AsnType, Clone, Debug, Decode, Encode, Eq, PartialEq)]
+struct Accuracy {
+    seconds: Option<Integer>,
+    #[rasn(tag(0))]
+    millis: Option<Integer>,
+    #[rasn(tag(1))]
+    micros: Option<Integer>,
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use rasn_cms::EncapsulatedContentInfo;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    /// Starts a timestamp authority that answers a single request with
+    /// `status` and returns its url.
+    pub fn tsa_stub(status: u32) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut r = BufReader::new(stream);
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                r.read_line(&mut line).unwrap();
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                let (name, value) = line.split_once(':').unwrap_or((line, ""));
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; content_length];
+            r.read_exact(&mut body).unwrap();
+            let request = rasn::der::decode::<TimeStampReq>(&body).unwrap();
+            let response = TimeStampResp {
+                status: PkiStatusInfo {
+                    status: status.into(),
+                    status_string: None,
+                    fail_info: None,
+                },
+                time_stamp_token: (status <= 1).then(|| token(&request)),
+            };
+            let body = rasn::der::encode(&response).unwrap();
+            let mut stream = r.into_inner();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/timestamp-reply\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(&body).unwrap();
+        });
+        url
+    }
+
+    fn token(request: &TimeStampReq) -> ContentInfo {
+        let tst_info = TstInfo {
+            version: 1.into(),
+            policy:
+                Oid::JOINT_ISO_ITU_T_COUNTRY_US_ORGANIZATION_GOV_CSOR_NIST_ALGORITHMS_HASH_SHA256
+                    .into(),
+            message_imprint: request.message_imprint.clone(),
+            serial_number: 1.into(),
+            gen_time: rasn::der::decode(b"\x18\x0f20260101000000Z").unwrap(),
+            accuracy: None,
+            ordering: false,
+            nonce: request.nonce.clone(),
+            tsa: None,
+            extensions: None,
+        };
+        let signed_data = SignedData {
+            version: 3.into(),
+            digest_algorithms: Default::default(),
+            encap_content_info: EncapsulatedContentInfo {
+                content_type: TST_INFO_OBJID.into(),
+                content: Some(OctetString::from(rasn::der::encode(&tst_info).unwrap())),
+            },
+            certificates: None,
+            crls: None,
+            signer_infos: Default::default(),
+        };
+        ContentInfo {
+            content_type: CONTENT_SIGNED_DATA.into(),
+            content: Any::new(rasn::der::encode(&signed_data).unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_timestamp() -> Result<()> {
+        let token = timestamp(&tsa_stub(0), b"signature")?;
+        let signed_data = rasn::der::decode::<SignedData>(token.content.as_bytes()).unwrap();
+        let tst_info = signed_data.encap_content_info.content.unwrap();
+        let tst_info = rasn::der::decode::<TstInfo>(&tst_info).unwrap();
+        assert_eq!(tst_info.message_imprint, MessageImprint::new(b"signature"));
+
+        assert!(timestamp(&tsa_stub(2), b"signature").is_err());
+        Ok(())
+    }
+}
@@ -1,19 +1,23 @@
-/*use anyhow::Result;
+use anyhow::Result;
+use pri::PriFile;
 use std::io::Cursor;
-use xmsix::pri::PriFile;
 
-const PRI_BYTES: &'static [u8] = include_bytes!("resources.pri");
+const PRI_BYTES: &[u8] = include_bytes!("resources.pri");
 
 #[test]
 fn test_read() -> Result<()> {
-    let mut cursor = Cursor::new(PRI_BYTES);
-    let pri = PriFile::read(&mut cursor)?;
+    let pri = PriFile::read(&mut Cursor::new(PRI_BYTES))?;
     let mut pri2 = vec![];
-    let mut cursor = Cursor::new(&mut pri2);
-    pri.write(&mut cursor)?;
-    let mut cursor = Cursor::new(&pri2);
-    let _pri2 = PriFile::read(&mut cursor)?;
-    //println!("{:#?}", pri);
-    //assert!(false);
+    pri.write(&mut Cursor::new(&mut pri2))?;
+    let pri2 = PriFile::read(&mut Cursor::new(&pri2))?;
+    assert_eq!(pri.num_sections(), pri2.num_sections());
+    for i in 0..pri.num_sections() {
+        assert_eq!(pri.section(i), pri2.section(i));
+    }
+    let mut dump = vec![];
+    pri.dump(&mut dump)?;
+    let mut dump2 = vec![];
+    pri2.dump(&mut dump2)?;
+    assert_eq!(dump, dump2);
     Ok(())
-}*/
+}
@@ -0,0 +1,492 @@
+use crate::{
+    CandidateInfo, CandidateValue, DataItem, Decision, DecisionInfo, HierarchicalSchema, ItemInfo,
+    ItemInfoGroup, ItemToItemInfoGroup, PriDescriptor, PriDescriptorFlags, PriFile, Qualifier,
+    QualifierSet, QualifierType, ResourceMap, ResourceMapEntry, ResourceValueType, Section,
+    SectionData,
+};
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+
+/// Scope of the package files.
+const FILES: &str = "Files";
+/// Scope of the string resources.
+const RESOURCES: &str = "resources";
+
+const DECISION_INFO_SECTION: u16 = 0;
+const HIERARCHICAL_SCHEMA_SECTION: u16 = 2;
+const RESOURCE_MAP_SECTION: u16 = 3;
+const DATA_ITEM_SECTION: u16 = 4;
+
+#[derive(Clone, Debug)]
+struct Candidate {
+    qualifiers: Vec<(QualifierType, String)>,
+    ty: ResourceValueType,
+    value: String,
+}
+
+#[derive(Clone, Debug)]
+struct Resource {
+    path: Vec<String>,
+    candidates: Vec<Candidate>,
+}
+
+/// Builds a `resources.pri` containing qualified files and localized strings,
+/// like `makepri new` does for a package.
+#[derive(Clone, Debug)]
+pub struct PriBuilder {
+    package_name: String,
+    default_language: Option<String>,
+    /// Resources keyed by their lowercase path, as names are case insensitive.
+    resources: BTreeMap<Vec<String>, Resource>,
+}
+
+impl PriBuilder {
+    pub fn new(package_name: String) -> Self {
+        Self {
+            package_name,
+            default_language: None,
+            resources: Default::default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.resources.is_empty()
+    }
+
+    /// Adds a file of the package. Qualifiers in the file name, like in
+    /// `Images\StoreLogo.scale-200.png`, or directory names, like in
+    /// `Strings\lang-de-DE\Help.html`, are removed from the resource name
+    /// and added to the candidate. Every qualifier type is recognized by its
+    /// makepri name, like `lang`, `layoutdir`, `altform` or `config`.
+    pub fn add_file(&mut self, path: &str) -> Result<()> {
+        let mut path = path
+            .split(['/', '\\'])
+            .filter(|c| !c.is_empty())
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>();
+        anyhow::ensure!(!path.is_empty(), "invalid path");
+        let value = path.join("\\");
+        let mut qualifiers = vec![];
+        let file_name = path.pop().unwrap();
+        path.retain(|dir| match parse_qualifiers(dir) {
+            Some(dir_qualifiers) => {
+                qualifiers.extend(dir_qualifiers);
+                false
+            }
+            None => true,
+        });
+        if let Some((name, file_qualifiers)) = parse_file_name(&file_name) {
+            qualifiers.extend(file_qualifiers);
+            path.push(name);
+        } else {
+            path.push(file_name);
+        }
+        path.insert(0, FILES.into());
+        let ty = if value.is_ascii() {
+            ResourceValueType::AsciiPath
+        } else {
+            ResourceValueType::Utf8Path
+        };
+        self.add_candidate(
+            path,
+            Candidate {
+                qualifiers,
+                ty,
+                value,
+            },
+        )
+    }
+
+    /// Adds a string for `language`, which can be referenced as
+    /// `ms-resource:<name>` in the manifest. The first language added is
+    /// used when none of the languages match.
+    pub fn add_string(&mut self, name: &str, language: &str, value: &str) -> Result<()> {
+        anyhow::ensure!(!name.is_empty(), "invalid string name");
+        self.default_language
+            .get_or_insert_with(|| language.to_string());
+        self.add_candidate(
+            vec![RESOURCES.into(), name.into()],
+            Candidate {
+                qualifiers: vec![(QualifierType::Language, language.into())],
+                ty: ResourceValueType::Utf8String,
+                value: value.into(),
+            },
+        )
+    }
+
+    fn add_candidate(&mut self, path: Vec<String>, candidate: Candidate) -> Result<()> {
+        let key = path.iter().map(|c| c.to_lowercase()).collect::<Vec<_>>();
+        let resource = self.resources.entry(key).or_insert_with(|| Resource {
+            path,
+            candidates: vec![],
+        });
+        let duplicate = resource.candidates.iter().any(|c| {
+            c.qualifiers.len() == candidate.qualifiers.len()
+                && c.qualifiers
+                    .iter()
+                    .all(|q| candidate.qualifiers.contains(q))
+        });
+        anyhow::ensure!(
+            !duplicate,
+            "duplicate candidate {} for {}",
+            candidate.value,
+            resource.path.join("\\")
+        );
+        resource.candidates.push(candidate);
+        Ok(())
+    }
+
+    fn qualifier(&self, qualifier_type: QualifierType, value: &str) -> Qualifier {
+        let is_default = match qualifier_type {
+            QualifierType::Language => self.default_language.as_deref() == Some(value),
+            QualifierType::Scale => value == "100",
+            QualifierType::Contrast => value.eq_ignore_ascii_case("standard"),
+            QualifierType::LayoutDirection => value.eq_ignore_ascii_case("ltr"),
+            _ => false,
+        };
+        let (priority, fallback_score) = match qualifier_type {
+            QualifierType::DeviceFamily => (900, 0.0),
+            QualifierType::Language => (700, 0.0),
+            QualifierType::Contrast => (600, 0.0),
+            QualifierType::HomeRegion => (500, 0.0),
+            QualifierType::LayoutDirection => (400, 0.0),
+            QualifierType::TargetSize => (300, 0.5),
+            QualifierType::Scale => (200, 0.0),
+            QualifierType::Theme => (200, 0.0),
+            QualifierType::DXFeatureLevel => (200, 0.0),
+            QualifierType::AlternateForm | QualifierType::Configuration | QualifierType::Custom => {
+                (100, 0.0)
+            }
+        };
+        Qualifier {
+            qualifier_type,
+            priority,
+            fallback_score: if is_default { 1.0 } else { fallback_score },
+            value: value.into(),
+        }
+    }
+
+    pub fn build(&self) -> Result<PriFile> {
+        let mut schema = HierarchicalSchema::new(
+            format!("ms-appx://{}/", self.package_name),
+            self.package_name.clone(),
+        );
+        let mut scopes = HashMap::new();
+        scopes.insert(vec![], schema.add_scope(ResourceMapEntry::default()));
+        // the resources are sorted by path, so scopes are numbered depth first
+        for (key, resource) in &self.resources {
+            for i in 1..key.len() {
+                if scopes.contains_key(&key[..i]) {
+                    continue;
+                }
+                let scope = schema.add_scope(ResourceMapEntry {
+                    parent: Some(scopes[&key[..i - 1]]),
+                    name: resource.path[i - 1].clone(),
+                });
+                scopes.insert(key[..i].to_vec(), scope);
+            }
+        }
+
+        let mut decision_info = DecisionInfo::default();
+        let mut qualifiers = HashMap::new();
+        let mut qualifier_sets = HashMap::new();
+        let mut decisions = HashMap::new();
+        let neutral = decision_info.add_qualifier_set(QualifierSet { qualifiers: vec![] });
+        qualifier_sets.insert(vec![], neutral);
+
+        let mut data_item = DataItem::default();
+        let mut item_infos = Vec::with_capacity(self.resources.len());
+        let mut candidate_infos = vec![];
+        for (key, resource) in &self.resources {
+            schema.add_item(ResourceMapEntry {
+                parent: Some(scopes[&key[..key.len() - 1]]),
+                name: resource.path.last().unwrap().clone(),
+            });
+            // candidates are ordered like the qualifier sets of the decision
+            let mut candidates = resource.candidates.iter().collect::<Vec<_>>();
+            candidates.sort_by_key(|c| std::cmp::Reverse(c.qualifiers.len()));
+            let mut decision = Vec::with_capacity(candidates.len());
+            let first_candidate = candidate_infos.len() as u32;
+            for candidate in candidates {
+                let mut qualifier_set = Vec::with_capacity(candidate.qualifiers.len());
+                for (qualifier_type, value) in &candidate.qualifiers {
+                    let qualifier = *qualifiers
+                        .entry((*qualifier_type, value.clone()))
+                        .or_insert_with(|| {
+                            decision_info.add_qualifier(self.qualifier(*qualifier_type, value))
+                        });
+                    qualifier_set.push(qualifier);
+                }
+                let qualifier_set =
+                    *qualifier_sets
+                        .entry(qualifier_set.clone())
+                        .or_insert_with(|| {
+                            decision_info.add_qualifier_set(QualifierSet {
+                                qualifiers: qualifier_set,
+                            })
+                        });
+                decision.push(qualifier_set);
+                let data_item_index = u16::try_from(data_item.add_string(&candidate.value))
+                    .map_err(|_| {
+                        anyhow::anyhow!(
+                            "pri files support at most {} resource candidates",
+                            u16::MAX
+                        )
+                    })?;
+                candidate_infos.push(CandidateInfo {
+                    resource_value_type: candidate.ty as u32,
+                    value: CandidateValue::DataItem {
+                        source_file_index: 0,
+                        data_item_section: DATA_ITEM_SECTION,
+                        data_item_index,
+                    },
+                });
+            }
+            let decision = *decisions.entry(decision.clone()).or_insert_with(|| {
+                decision_info.add_decision(Decision {
+                    qualifier_sets: decision,
+                })
+            });
+            item_infos.push(ItemInfo {
+                decision: decision as u32,
+                first_candidate,
+            });
+        }
+
+        let resource_map = ResourceMap {
+            hierarchical_schema_section: HIERARCHICAL_SCHEMA_SECTION,
+            decision_info_section: DECISION_INFO_SECTION,
+            item_to_item_info_groups: vec![ItemToItemInfoGroup {
+                first_item: 0,
+                item_info_group: 0,
+            }],
+            item_info_groups: vec![ItemInfoGroup {
+                group_size: item_infos.len() as u32,
+                first_item_info: 0,
+            }],
+            item_infos,
+            candidate_infos,
+            ..Default::default()
+        };
+        let descriptor = PriDescriptor {
+            pri_flags: PriDescriptorFlags::IsDeploymentMergeable as u16,
+            included_file_list_section: true,
+            hierarchical_schema_sections: vec![HIERARCHICAL_SCHEMA_SECTION],
+            decision_info_sections: vec![DECISION_INFO_SECTION],
+            resource_map_sections: vec![RESOURCE_MAP_SECTION],
+            primary_resource_map_section: Some(RESOURCE_MAP_SECTION),
+            referenced_file_sections: vec![],
+            data_item_sections: vec![DATA_ITEM_SECTION],
+        };
+        let mut pri = PriFile::default();
+        for data in [
+            SectionData::DecisionInfo(decision_info),
+            SectionData::PriDescriptor(descriptor),
+            SectionData::HierarchicalSchema(schema),
+            SectionData::ResourceMap(resource_map),
+            SectionData::DataItem(data_item),
+        ] {
+            pri.add_section(Section {
+                section_qualifier: 0,
+                flags: 0,
+                section_flags: 0,
+                data,
+            });
+        }
+        Ok(pri)
+    }
+}
+
+/// Splits `StoreLogo.scale-200_altform-unplated.png` into `StoreLogo.png`
+/// and its qualifiers. Returns `None` if the file name has no qualifiers.
+fn parse_file_name(file_name: &str) -> Option<(String, Vec<(QualifierType, String)>)> {
+    let (stem, extension) = file_name.rsplit_once('.')?;
+    let (name, qualifiers) = stem.rsplit_once('.')?;
+    let qualifiers = parse_qualifiers(qualifiers)?;
+    Some((format!("{}.{}", name, extension), qualifiers))
+}
+
+/// Parses qualifiers like `scale-200_contrast-high` of a file or directory name.
+fn parse_qualifiers(qualifiers: &str) -> Option<Vec<(QualifierType, String)>> {
+    qualifiers.split('_').map(parse_qualifier).collect()
+}
+
+fn parse_qualifier(qualifier: &str) -> Option<(QualifierType, String)> {
+    let (name, value) = qualifier.split_once('-')?;
+    let qualifier_type = match name.to_lowercase().as_str() {
+        "lang" | "language" => QualifierType::Language,
+        "contrast" => QualifierType::Contrast,
+        "scale" => QualifierType::Scale,
+        "homeregion" => QualifierType::HomeRegion,
+        "targetsize" => QualifierType::TargetSize,
+        "layoutdir" | "layoutdirection" => QualifierType::LayoutDirection,
+        "theme" => QualifierType::Theme,
+        "altform" | "alternateform" => QualifierType::AlternateForm,
+        "dxfeaturelevel" => QualifierType::DXFeatureLevel,
+        "config" | "configuration" => QualifierType::Configuration,
+        "devicefamily" => QualifierType::DeviceFamily,
+        "custom" => QualifierType::Custom,
+        _ => return None,
+    };
+    if value.is_empty() {
+        return None;
+    }
+    if matches!(
+        qualifier_type,
+        QualifierType::Scale | QualifierType::TargetSize
+    ) {
+        value.parse::<u32>().ok()?;
+    }
+    Some((qualifier_type, value.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_parse_file_name() {
+        assert_eq!(
+            parse_file_name("StoreLogo.scale-200.png"),
+            Some((
+                "StoreLogo.png".into(),
+                vec![(QualifierType::Scale, "200".into())]
+            ))
+        );
+        assert_eq!(
+            parse_file_name("Logo.targetsize-16_altform-unplated.png"),
+            Some((
+                "Logo.png".into(),
+                vec![
+                    (QualifierType::TargetSize, "16".into()),
+                    (QualifierType::AlternateForm, "unplated".into())
+                ]
+            ))
+        );
+        assert_eq!(parse_file_name("StoreLogo.backup.png"), None);
+        assert_eq!(parse_file_name("StoreLogo.png"), None);
+    }
+
+    #[test]
+    fn test_too_many_candidates() -> Result<()> {
+        let mut builder = PriBuilder::new("com.example.App".into());
+        for i in 0..=u16::MAX as usize + 1 {
+            builder.add_string(&format!("String{}", i), "en-US", "")?;
+        }
+        assert!(builder.build().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_directory_qualifiers() -> Result<()> {
+        let mut builder = PriBuilder::new("com.example.App".into());
+        builder.add_file("Strings\\Help.html")?;
+        builder.add_file("Strings\\lang-de-DE\\Help.html")?;
+        builder.add_file("Strings/contrast-high_lang-de-DE/Help.html")?;
+        assert_eq!(builder.resources.len(), 1);
+        let resource = builder.resources.values().next().unwrap();
+        assert_eq!(resource.path, ["Files", "Strings", "Help.html"]);
+        assert_eq!(
+            resource.candidates[2].qualifiers,
+            [
+                (QualifierType::Contrast, "high".into()),
+                (QualifierType::Language, "de-DE".into())
+            ]
+        );
+        assert!(builder.add_file("Strings/lang-de-DE/Help.html").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_build() -> Result<()> {
+        let mut builder = PriBuilder::new("com.example.App".into());
+        for scale in [100, 200, 400] {
+            builder.add_file(&format!("Images\\StoreLogo.scale-{}.png", scale))?;
+            builder.add_file(&format!("Images/SmallTile.scale-{}.png", scale))?;
+        }
+        builder.add_file("app.exe")?;
+        builder.add_string("AppName", "en-US", "App")?;
+        builder.add_string("AppName", "de-DE", "Anwendung")?;
+        assert!(builder.add_file("Images\\StoreLogo.scale-100.png").is_err());
+
+        let pri = builder.build()?;
+        let mut buf = vec![];
+        pri.write(&mut Cursor::new(&mut buf))?;
+        let pri = PriFile::read(&mut Cursor::new(&buf))?;
+        assert_eq!(pri.num_sections(), 5);
+
+        let SectionData::HierarchicalSchema(schema) = &pri.section(2).unwrap().data else {
+            panic!("expected hierarchical schema");
+        };
+        assert_eq!(schema.unique_name(), "ms-appx://com.example.App/");
+        let scopes = (0..schema.num_scopes())
+            .map(|i| schema.scope(i).unwrap().name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(scopes, ["", "Files", "Images", "resources"]);
+        let items = (0..schema.num_items())
+            .map(|i| schema.item(i).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[0].name, "app.exe");
+        assert_eq!(items[0].parent, Some(1));
+        assert_eq!(items[2].name, "StoreLogo.png");
+        assert_eq!(items[2].parent, Some(2));
+        assert_eq!(items[3].name, "AppName");
+        assert_eq!(items[3].parent, Some(3));
+
+        let SectionData::DecisionInfo(decision_info) = &pri.section(0).unwrap().data else {
+            panic!("expected decision info");
+        };
+        let SectionData::ResourceMap(resource_map) = &pri.section(3).unwrap().data else {
+            panic!("expected resource map");
+        };
+        let SectionData::DataItem(data_item) = &pri.section(4).unwrap().data else {
+            panic!("expected data item");
+        };
+        let item = resource_map.item_infos[2];
+        let decision = decision_info.decision(item.decision as usize).unwrap();
+        assert_eq!(decision.qualifier_sets.len(), 3);
+        for (i, qualifier_set) in decision.qualifier_sets.iter().enumerate() {
+            let qualifier_set = decision_info.qualifier_set(*qualifier_set).unwrap();
+            let qualifier = decision_info
+                .qualifier(qualifier_set.qualifiers[0])
+                .unwrap();
+            assert_eq!(qualifier.qualifier_type, QualifierType::Scale);
+            let candidate = resource_map.candidate_infos[item.first_candidate as usize + i];
+            let CandidateValue::DataItem {
+                data_item_index, ..
+            } = candidate.value
+            else {
+                panic!("expected data item candidate");
+            };
+            assert_eq!(
+                data_item.string(data_item_index as usize),
+                Some(format!("Images\\StoreLogo.scale-{}.png", qualifier.value).as_str())
+            );
+        }
+
+        let item = resource_map.item_infos[3];
+        let decision = decision_info.decision(item.decision as usize).unwrap();
+        let qualifier_set = decision_info
+            .qualifier_set(decision.qualifier_sets[1])
+            .unwrap();
+        let qualifier = decision_info
+            .qualifier(qualifier_set.qualifiers[0])
+            .unwrap();
+        assert_eq!(qualifier.value, "de-DE");
+        assert_eq!(qualifier.fallback_score, 0.0);
+        let candidate = resource_map.candidate_infos[item.first_candidate as usize + 1];
+        let CandidateValue::DataItem {
+            data_item_index, ..
+        } = candidate.value
+        else {
+            panic!("expected data item candidate");
+        };
+        assert_eq!(
+            data_item.string(data_item_index as usize),
+            Some("Anwendung")
+        );
+        Ok(())
+    }
+}
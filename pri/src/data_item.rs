@@ -67,6 +67,18 @@ impl DataItem {
     }
 
     pub fn write(&self, w: &mut impl Write) -> Result<()> {
+        ensure!(
+            self.string_spans.len() <= u16::MAX as usize
+                && self.blob_spans.len() <= u16::MAX as usize,
+            "data item section exceeds {} strings or blobs",
+            u16::MAX
+        );
+        // string offsets are 16 bit
+        ensure!(
+            self.string_data.len() <= u16::MAX as usize,
+            "data item strings exceed {} bytes",
+            u16::MAX
+        );
         w.write_u32::<LE>(0)?;
         w.write_u16::<LE>(self.string_spans.len() as u16)?;
         w.write_u16::<LE>(self.blob_spans.len() as u16)?;
@@ -95,6 +107,12 @@ impl DataItem {
         std::str::from_utf8(bytes).ok()
     }
 
+    /// Returns the raw bytes of a string including the terminating nul.
+    pub fn string_bytes(&self, index: usize) -> Option<&[u8]> {
+        let span = self.string_spans.get(index)?;
+        self.string_data.get(span.start()..span.end())
+    }
+
     pub fn num_blobs(&self) -> usize {
         self.blob_spans.len()
     }
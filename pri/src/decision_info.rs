@@ -1,4 +1,4 @@
-use anyhow::{ensure, Result};
+use anyhow::{ensure, Context, Result};
 use byteorder::{ReadBytesExt, WriteBytesExt, LE};
 use std::collections::hash_map::{Entry, HashMap};
 use std::io::{Read, Seek, SeekFrom, Write};
@@ -70,25 +70,29 @@ impl DecisionInfo {
         let data_start = r.stream_position()?;
         let mut qualifiers = Vec::with_capacity(num_qualifiers);
         for info in &qualifier_infos {
-            let distinct_info = &distinct_qualifier_infos[info.index];
-            if let Some(qualifier_type) = QualifierType::from_u16(distinct_info.qualifier_type) {
-                let string_start = data_start + distinct_info.operand_value_offset as u64 * 2;
-                r.seek(SeekFrom::Start(string_start))?;
-                let mut value = String::with_capacity(15);
-                loop {
-                    let c = r.read_u16::<LE>()?;
-                    if c == 0 {
-                        break;
-                    }
-                    value.push(char::from_u32(c as u32).unwrap());
+            let distinct_info = distinct_qualifier_infos
+                .get(info.index)
+                .context("invalid qualifier index")?;
+            let qualifier_type = QualifierType::from_u16(distinct_info.qualifier_type)
+                .with_context(|| {
+                    format!("unknown qualifier type {}", distinct_info.qualifier_type)
+                })?;
+            let string_start = data_start + distinct_info.operand_value_offset as u64 * 2;
+            r.seek(SeekFrom::Start(string_start))?;
+            let mut value = Vec::with_capacity(15);
+            loop {
+                let c = r.read_u16::<LE>()?;
+                if c == 0 {
+                    break;
                 }
-                qualifiers.push(Qualifier {
-                    qualifier_type,
-                    priority: info.priority,
-                    fallback_score: info.fallback_score as f32 / 1000.0,
-                    value,
-                });
+                value.push(c);
             }
+            qualifiers.push(Qualifier {
+                qualifier_type,
+                priority: info.priority,
+                fallback_score: info.fallback_score as f32 / 1000.0,
+                value: String::from_utf16_lossy(&value),
+            });
         }
         let mut qualifier_sets = Vec::with_capacity(num_qualifier_sets);
         for info in &qualifier_set_infos {
@@ -129,8 +133,8 @@ impl DecisionInfo {
             let index = distinct_qualifier_infos.len();
             if let Entry::Vacant(_) = entry {
                 let current_offset = values.len() / 2;
-                for c in qualifier.value.chars() {
-                    values.write_u16::<LE>(c as u16)?;
+                for c in qualifier.value.encode_utf16() {
+                    values.write_u16::<LE>(c)?;
                 }
                 values.write_u16::<LE>(0)?;
                 distinct_qualifier_infos.push(DistinctQualifierInfo {
@@ -175,8 +179,7 @@ impl DecisionInfo {
         w.write_u16::<LE>(qualifier_set_infos.len() as u16)?;
         w.write_u16::<LE>(decision_infos.len() as u16)?;
         w.write_u16::<LE>(index_table.len() as u16)?;
-        w.write_u16::<LE>(0)?;
-        let start = w.stream_position()?;
+        w.write_u16::<LE>(values.len() as u16 / 2)?;
         for info in decision_infos {
             w.write_u16::<LE>(info.first_qualifier_set_index_index as u16)?;
             w.write_u16::<LE>(info.num_qualifier_sets_in_decision as u16)?;
@@ -192,20 +195,17 @@ impl DecisionInfo {
             w.write_u16::<LE>(0)?;
         }
         for info in distinct_qualifier_infos {
-            w.write_u16::<LE>(0)?;
+            // unknown fields, set to the values written by makepri
+            w.write_u16::<LE>(2)?;
             w.write_u16::<LE>(info.qualifier_type)?;
             w.write_u16::<LE>(0)?;
-            w.write_u16::<LE>(0)?;
+            w.write_u16::<LE>(10)?;
             w.write_u32::<LE>(info.operand_value_offset)?;
         }
         for index in index_table {
             w.write_u16::<LE>(index)?;
         }
         w.write_all(&values)?;
-        let end = w.stream_position()?;
-        w.seek(SeekFrom::Start(start - 2))?;
-        w.write_u16::<LE>((end - start) as u16)?;
-        w.seek(SeekFrom::Start(end))?;
         Ok(())
     }
 
@@ -291,6 +291,7 @@ pub struct Decision {
     pub qualifier_sets: Vec<usize>,
 }
 
+/// Qualifier types in the order of their ids in the decision info section.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 #[repr(u16)]
 pub enum QualifierType {
@@ -327,3 +328,9 @@ impl QualifierType {
         })
     }
 }
+
+impl std::fmt::Display for QualifierType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
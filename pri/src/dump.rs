@@ -0,0 +1,355 @@
+use crate::{
+    CandidateInfo, CandidateValue, DecisionInfo, HierarchicalSchema, PriDescriptorFlags, PriFile,
+    ResourceMap, ResourceValueType, SectionData,
+};
+use anyhow::{bail, ensure, Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+impl PriFile {
+    /// Writes the resource maps as xml, similar to `makepri dump`. Each named
+    /// resource lists its candidates with their qualifiers and values.
+    pub fn dump(&self, w: &mut impl Write) -> Result<()> {
+        let descriptor = self
+            .sections
+            .iter()
+            .find_map(|section| match &section.data {
+                SectionData::PriDescriptor(descriptor) => Some(descriptor),
+                _ => None,
+            })
+            .context("missing pri descriptor")?;
+        writeln!(
+            w,
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#
+        )?;
+        writeln!(w, "<PriInfo>")?;
+        writeln!(w, "  <PriHeader>")?;
+        for (name, flag) in [
+            ("AutoMerge", PriDescriptorFlags::AutoMerge),
+            (
+                "IsDeploymentMergeable",
+                PriDescriptorFlags::IsDeploymentMergeable,
+            ),
+            (
+                "IsDeploymentMergeResult",
+                PriDescriptorFlags::IsDeploymentMergeResult,
+            ),
+            (
+                "IsAutomergeMergeResult",
+                PriDescriptorFlags::IsAutomergeMergeResult,
+            ),
+        ] {
+            let value = descriptor.pri_flags & flag as u16 != 0;
+            writeln!(w, "    <{0}>{1}</{0}>", name, value)?;
+        }
+        writeln!(w, "  </PriHeader>")?;
+        for index in &descriptor.resource_map_sections {
+            let SectionData::ResourceMap(map) = self.section_data(*index)? else {
+                bail!("section {} is not a resource map", index);
+            };
+            let SectionData::HierarchicalSchema(schema) =
+                self.section_data(map.hierarchical_schema_section)?
+            else {
+                bail!(
+                    "section {} is not a hierarchical schema",
+                    map.hierarchical_schema_section
+                );
+            };
+            let SectionData::DecisionInfo(decision_info) =
+                self.section_data(map.decision_info_section)?
+            else {
+                bail!(
+                    "section {} is not a decision info",
+                    map.decision_info_section
+                );
+            };
+            let dump = Dump::new(self, schema, decision_info, map);
+            writeln!(
+                w,
+                r#"  <ResourceMap name="{}" primary="{}">"#,
+                escape(schema.name()),
+                descriptor.primary_resource_map_section == Some(*index)
+            )?;
+            dump.qualifiers(w)?;
+            if schema.num_scopes() > 0 {
+                dump.scope(w, 0, &mut vec![], &mut HashSet::new())?;
+            }
+            writeln!(w, "  </ResourceMap>")?;
+        }
+        writeln!(w, "</PriInfo>")?;
+        Ok(())
+    }
+
+    fn section_data(&self, index: u16) -> Result<&SectionData> {
+        Ok(&self
+            .sections
+            .get(index as usize)
+            .with_context(|| format!("missing section {}", index))?
+            .data)
+    }
+}
+
+/// A scope or item below a scope, as `(is_scope, index, name)`.
+type Child<'a> = (bool, usize, &'a str);
+
+struct Dump<'a> {
+    pri: &'a PriFile,
+    schema: &'a HierarchicalSchema,
+    decision_info: &'a DecisionInfo,
+    map: &'a ResourceMap,
+    children: HashMap<usize, Vec<Child<'a>>>,
+}
+
+impl<'a> Dump<'a> {
+    fn new(
+        pri: &'a PriFile,
+        schema: &'a HierarchicalSchema,
+        decision_info: &'a DecisionInfo,
+        map: &'a ResourceMap,
+    ) -> Self {
+        let mut children: HashMap<usize, Vec<Child<'a>>> = HashMap::new();
+        for i in 1..schema.num_scopes() {
+            let entry = schema.scope(i).unwrap();
+            if let Some(parent) = entry.parent {
+                children
+                    .entry(parent)
+                    .or_default()
+                    .push((true, i, entry.name.as_str()));
+            }
+        }
+        for i in 0..schema.num_items() {
+            let entry = schema.item(i).unwrap();
+            if let Some(parent) = entry.parent {
+                children
+                    .entry(parent)
+                    .or_default()
+                    .push((false, i, entry.name.as_str()));
+            }
+        }
+        for children in children.values_mut() {
+            children.sort_by_key(|(is_scope, _, name)| (!is_scope, name.to_lowercase()));
+        }
+        Self {
+            pri,
+            schema,
+            decision_info,
+            map,
+            children,
+        }
+    }
+
+    fn qualifiers(&self, w: &mut impl Write) -> Result<()> {
+        writeln!(w, "    <Qualifiers>")?;
+        for i in 0..self.decision_info.num_qualifiers() {
+            let qualifier = self.decision_info.qualifier(i).unwrap();
+            writeln!(
+                w,
+                r#"      <Qualifier name="{}" value="{}" priority="{}" scoreAsDefault="{:.1}" index="{}"/>"#,
+                qualifier.qualifier_type,
+                escape(&qualifier.value),
+                qualifier.priority,
+                qualifier.fallback_score,
+                i,
+            )?;
+        }
+        writeln!(w, "    </Qualifiers>")?;
+        Ok(())
+    }
+
+    fn scope(
+        &self,
+        w: &mut impl Write,
+        scope: usize,
+        path: &mut Vec<&'a str>,
+        visited: &mut HashSet<usize>,
+    ) -> Result<()> {
+        ensure!(visited.insert(scope), "scope {} is its own ancestor", scope);
+        let children = self.children.get(&scope).map_or(&[][..], |c| &c[..]);
+        let indent = "  ".repeat(path.len() + 2);
+        for &(is_scope, index, name) in children {
+            path.push(name);
+            if is_scope {
+                writeln!(
+                    w,
+                    r#"{}<ResourceMapSubtree name="{}">"#,
+                    indent,
+                    escape(name)
+                )?;
+                self.scope(w, index, path, visited)?;
+                writeln!(w, "{}</ResourceMapSubtree>", indent)?;
+            } else {
+                writeln!(
+                    w,
+                    r#"{}<NamedResource name="{}" uri="ms-resource://{}/{}">"#,
+                    indent,
+                    escape(name),
+                    escape(self.schema.name()),
+                    escape(&path.join("/"))
+                )?;
+                self.candidates(w, index, &indent)?;
+                writeln!(w, "{}</NamedResource>", indent)?;
+            }
+            path.pop();
+        }
+        Ok(())
+    }
+
+    fn candidates(&self, w: &mut impl Write, item: usize, indent: &str) -> Result<()> {
+        let info = self
+            .map
+            .item_info(item as u32)
+            .with_context(|| format!("missing item info for item {}", item))?;
+        let decision = self
+            .decision_info
+            .decision(info.decision as usize)
+            .with_context(|| format!("missing decision {}", info.decision))?;
+        for (i, qualifier_set) in decision.qualifier_sets.iter().enumerate() {
+            let qualifier_set = self
+                .decision_info
+                .qualifier_set(*qualifier_set)
+                .with_context(|| format!("missing qualifier set {}", qualifier_set))?;
+            let mut qualifiers = vec![];
+            let mut is_default = true;
+            for qualifier in &qualifier_set.qualifiers {
+                let qualifier = self
+                    .decision_info
+                    .qualifier(*qualifier)
+                    .with_context(|| format!("missing qualifier {}", qualifier))?;
+                qualifiers.push(format!("{}-{}", qualifier.qualifier_type, qualifier.value));
+                is_default &= qualifier.fallback_score > 0.0;
+            }
+            let candidate = self
+                .map
+                .candidate_infos
+                .get(info.first_candidate as usize + i)
+                .context("missing candidate")?;
+            let ty = ResourceValueType::from_u32(candidate.resource_value_type)
+                .context("invalid resource value type")?;
+            let type_name = match ty {
+                ResourceValueType::String
+                | ResourceValueType::AsciiString
+                | ResourceValueType::Utf8String => "String",
+                ResourceValueType::Path
+                | ResourceValueType::AsciiPath
+                | ResourceValueType::Utf8Path => "Path",
+                ResourceValueType::EmbeddedData => "EmbeddedData",
+            };
+            write!(w, "{}  <Candidate", indent)?;
+            if !qualifiers.is_empty() {
+                write!(w, r#" qualifiers="{}""#, escape(&qualifiers.join(", ")))?;
+            }
+            writeln!(w, r#" isDefault="{}" type="{}">"#, is_default, type_name)?;
+            match self.value(candidate, ty)? {
+                Value::Text(value) => {
+                    writeln!(w, "{}    <Value>{}</Value>", indent, escape(&value))?
+                }
+                Value::Data(length) => writeln!(w, r#"{}    <Data length="{}"/>"#, indent, length)?,
+            }
+            writeln!(w, "{}  </Candidate>", indent)?;
+        }
+        Ok(())
+    }
+
+    fn value(&self, candidate: &CandidateInfo, ty: ResourceValueType) -> Result<Value> {
+        let bytes = match candidate.value {
+            CandidateValue::DataItem {
+                data_item_section,
+                data_item_index,
+                ..
+            } => {
+                let SectionData::DataItem(data_item) = self.pri.section_data(data_item_section)?
+                else {
+                    bail!("section {} is not a data item", data_item_section);
+                };
+                let index = data_item_index as usize;
+                if ty == ResourceValueType::EmbeddedData {
+                    data_item.blob(index)
+                } else {
+                    data_item.string_bytes(index)
+                }
+                .with_context(|| format!("missing data item {}", index))?
+            }
+            CandidateValue::Inline { offset, length } => self
+                .map
+                .data
+                .get(offset as usize..offset as usize + length as usize)
+                .context("invalid inline candidate")?,
+        };
+        Ok(match ty {
+            ResourceValueType::String | ResourceValueType::Path => {
+                let chars = bytes
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .take_while(|c| *c != 0)
+                    .collect::<Vec<_>>();
+                Value::Text(String::from_utf16_lossy(&chars))
+            }
+            ResourceValueType::EmbeddedData => Value::Data(bytes.len()),
+            _ => {
+                let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+                Value::Text(String::from_utf8_lossy(&bytes[..end]).into_owned())
+            }
+        })
+    }
+}
+
+enum Value {
+    Text(String),
+    Data(usize),
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PriBuilder;
+    use std::io::Cursor;
+
+    static RESOURCES: &[u8] = include_bytes!("../assets/resources.pri");
+
+    #[test]
+    fn test_dump() -> Result<()> {
+        let pri = PriFile::read(&mut Cursor::new(RESOURCES))?;
+        let mut buf = vec![];
+        pri.dump(&mut buf)?;
+        let dump = String::from_utf8(buf)?;
+        assert!(dump.contains(r#"<ResourceMap name="com.flutter.fluttertodoapp" primary="true">"#));
+        assert!(dump.contains(r#"<NamedResource name="StoreLogo.png" uri="ms-resource://com.flutter.fluttertodoapp/Files/Images/StoreLogo.png">"#));
+
+        let mut builder = PriBuilder::new("com.example.App".into());
+        builder.add_file("Images\\StoreLogo.scale-100.png")?;
+        builder.add_file("Images\\StoreLogo.scale-200.png")?;
+        builder.add_string("AppName", "en-US", "App & Co")?;
+        let mut buf = vec![];
+        builder.build()?.dump(&mut buf)?;
+        let dump = String::from_utf8(buf)?;
+        let expected = r#"    <ResourceMapSubtree name="Files">
+      <ResourceMapSubtree name="Images">
+        <NamedResource name="StoreLogo.png" uri="ms-resource://com.example.App/Files/Images/StoreLogo.png">
+          <Candidate qualifiers="Scale-100" isDefault="true" type="Path">
+            <Value>Images\StoreLogo.scale-100.png</Value>
+          </Candidate>
+          <Candidate qualifiers="Scale-200" isDefault="false" type="Path">
+            <Value>Images\StoreLogo.scale-200.png</Value>
+          </Candidate>
+        </NamedResource>
+      </ResourceMapSubtree>
+    </ResourceMapSubtree>
+    <ResourceMapSubtree name="resources">
+      <NamedResource name="AppName" uri="ms-resource://com.example.App/resources/AppName">
+        <Candidate qualifiers="Language-en-US" isDefault="true" type="String">
+          <Value>App &amp; Co</Value>
+        </Candidate>
+      </NamedResource>
+    </ResourceMapSubtree>
+"#;
+        assert!(dump.contains(expected), "{}", dump);
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::{ensure, Result};
+use anyhow::{ensure, Context, Result};
 use byteorder::{ReadBytesExt, WriteBytesExt, LE};
 use std::io::{Read, Seek, SeekFrom, Write};
 
@@ -97,10 +97,15 @@ impl HierarchicalSchema {
                     }
                 }
             }
-            let parent = if info.parent != 0xffff {
-                Some(info.parent)
-            } else {
+            // the root scope is its own parent
+            let parent = if info.is_scope && info.index == 0 {
                 None
+            } else {
+                let parent = scope_and_item_infos
+                    .get(info.parent)
+                    .filter(|parent| parent.is_scope)
+                    .context("invalid parent scope")?;
+                Some(parent.index)
             };
             let entry = ResourceMapEntry { parent, name };
             if info.is_scope {
@@ -118,6 +123,81 @@ impl HierarchicalSchema {
     }
 
     pub fn write<W: Write + Seek>(&self, w: &mut W) -> Result<()> {
+        ensure!(
+            self.scopes.first().map(|root| root.parent.is_none()) == Some(true),
+            "the first scope must be the root scope"
+        );
+        let num_entries = self.scopes.len() + self.items.len();
+
+        // Children of a scope are stored contiguously and sorted by name.
+        // The blocks of children are stored in the order of the scopes.
+        let mut children = vec![vec![]; self.scopes.len()];
+        for (i, scope) in self.scopes.iter().enumerate().skip(1) {
+            let parent = scope.parent.context("scope without parent")?;
+            children
+                .get_mut(parent)
+                .context("invalid parent scope")?
+                .push((true, i));
+        }
+        for (i, item) in self.items.iter().enumerate() {
+            let parent = item.parent.unwrap_or(0);
+            children
+                .get_mut(parent)
+                .context("invalid parent scope")?
+                .push((false, i));
+        }
+        let mut entries = Vec::with_capacity(num_entries);
+        entries.push((true, 0));
+        let mut scope_ex_infos = Vec::with_capacity(self.scopes.len());
+        for (i, children) in children.iter_mut().enumerate() {
+            children
+                .sort_by_key(|(is_scope, index)| self.entry(*is_scope, *index).name.to_lowercase());
+            scope_ex_infos.push(ScopeExInfo {
+                scope_index: i as u16,
+                child_count: children.len() as u16,
+                first_child_index: entries.len() as u16,
+            });
+            entries.extend_from_slice(children);
+        }
+        ensure!(entries.len() == num_entries, "scopes contain a cycle");
+        let mut scope_positions = vec![0; self.scopes.len()];
+        let mut item_index_property_to_index = vec![0; self.items.len()];
+        for (position, (is_scope, index)) in entries.iter().enumerate() {
+            if *is_scope {
+                scope_positions[*index] = position;
+            } else {
+                item_index_property_to_index[*index] = position as u16;
+            }
+        }
+
+        let mut scope_and_item_infos = Vec::with_capacity(num_entries);
+        let mut unicode_strings = vec![];
+        let mut max_full_path_length = 0;
+        for (is_scope, index) in entries {
+            let entry = self.entry(is_scope, index);
+            let full_path_length = self.full_path_length(entry)?;
+            max_full_path_length = max_full_path_length.max(full_path_length);
+            scope_and_item_infos.push(ScopeAndItemInfo {
+                parent: scope_positions[entry.parent.unwrap_or(0)],
+                full_path_length,
+                uppercase_first_char: entry
+                    .name
+                    .chars()
+                    .next()
+                    .map(|c| c.to_uppercase().next().unwrap() as u16)
+                    .unwrap_or_default(),
+                name_length: entry.name.encode_utf16().count().min(255) as u8,
+                is_scope,
+                name_in_ascii: false,
+                name_offset: unicode_strings.len() as u64 / 2,
+                index,
+            });
+            for c in entry.name.encode_utf16() {
+                unicode_strings.write_u16::<LE>(c)?;
+            }
+            unicode_strings.write_u16::<LE>(0)?;
+        }
+
         w.write_u16::<LE>(1)?;
         w.write_u16::<LE>(self.unique_name.len() as u16 + 1)?;
         w.write_u16::<LE>(self.name.len() as u16 + 1)?;
@@ -130,71 +210,25 @@ impl HierarchicalSchema {
         w.write_u32::<LE>(0)?;
         w.write_u32::<LE>(self.scopes.len() as u32)?;
         w.write_u32::<LE>(self.items.len() as u32)?;
-        for c in self.unique_name.chars() {
-            w.write_u16::<LE>(c as u16)?;
+        for c in self.unique_name.encode_utf16() {
+            w.write_u16::<LE>(c)?;
         }
         w.write_u16::<LE>(0)?;
-        for c in self.name.chars() {
-            w.write_u16::<LE>(c as u16)?;
+        for c in self.name.encode_utf16() {
+            w.write_u16::<LE>(c)?;
         }
         w.write_u16::<LE>(0)?;
         w.write_u16::<LE>(0)?;
-        // TODO: max full path length
-        w.write_u16::<LE>(256)?;
+        w.write_u16::<LE>(max_full_path_length as u16)?;
         w.write_u16::<LE>(0)?;
-        w.write_u32::<LE>((self.scopes.len() + self.items.len()) as u32)?;
+        w.write_u32::<LE>(num_entries as u32)?;
         w.write_u32::<LE>(self.scopes.len() as u32)?;
         w.write_u32::<LE>(self.items.len() as u32)?;
-        // TODO: unicode_data_length
-        w.write_u32::<LE>(0)?;
+        w.write_u32::<LE>(unicode_strings.len() as u32 / 2)?;
         // TODO: what's this for
         w.write_u32::<LE>(0)?;
-        // TODO: what's this for
+        // ascii_data_length
         w.write_u32::<LE>(0)?;
-
-        let mut scope_and_item_infos = Vec::with_capacity(self.scopes.len() + self.items.len());
-        let mut scope_ex_infos = Vec::with_capacity(self.scopes.len());
-        let mut item_index_property_to_index = Vec::with_capacity(self.items.len());
-        let mut unicode_strings = vec![];
-        for (i, scope) in self.scopes.iter().enumerate() {
-            let scope_info = ScopeAndItemInfo {
-                parent: scope.parent.unwrap_or(0xffff),
-                full_path_length: scope.name.len(),
-                is_scope: true,
-                name_in_ascii: false,
-                name_offset: unicode_strings.len() as u64 / 2,
-                index: i,
-            };
-            scope_and_item_infos.push(scope_info);
-            scope_ex_infos.push(ScopeExInfo {
-                scope_index: i as u16,
-                // TODO: child_count
-                child_count: 0,
-                // TODO: first_child_index
-                first_child_index: 0,
-            });
-            for c in scope.name.chars() {
-                unicode_strings.write_u16::<LE>(c as u16)?;
-            }
-            unicode_strings.write_u16::<LE>(0)?;
-        }
-        for (i, item) in self.items.iter().enumerate() {
-            let item_info = ScopeAndItemInfo {
-                parent: item.parent.unwrap_or(0xffff),
-                full_path_length: item.name.len(),
-                is_scope: false,
-                name_in_ascii: false,
-                name_offset: unicode_strings.len() as u64 / 2,
-                index: i,
-            };
-            scope_and_item_infos.push(item_info);
-            // TODO: item_index_property_to_index
-            item_index_property_to_index.push(0);
-            for c in item.name.chars() {
-                unicode_strings.write_u16::<LE>(c as u16)?;
-            }
-            unicode_strings.write_u16::<LE>(0)?;
-        }
         for scope_and_item_info in scope_and_item_infos {
             scope_and_item_info.write(w)?;
         }
@@ -207,11 +241,81 @@ impl HierarchicalSchema {
         w.write_all(&unicode_strings)?;
         Ok(())
     }
+
+    fn entry(&self, is_scope: bool, index: usize) -> &ResourceMapEntry {
+        if is_scope {
+            &self.scopes[index]
+        } else {
+            &self.items[index]
+        }
+    }
+
+    /// Length of the path from the root scope to `entry`, separated by `\\`.
+    fn full_path_length(&self, entry: &ResourceMapEntry) -> Result<usize> {
+        let mut length = entry.name.encode_utf16().count();
+        let mut parent = entry.parent;
+        let mut depth = 0;
+        while let Some(scope) = parent.filter(|scope| *scope != 0) {
+            let scope = self.scopes.get(scope).context("invalid parent scope")?;
+            length += scope.name.encode_utf16().count() + 1;
+            parent = scope.parent;
+            depth += 1;
+            ensure!(depth <= self.scopes.len(), "scopes contain a cycle");
+        }
+        Ok(length)
+    }
+
+    pub fn new(unique_name: String, name: String) -> Self {
+        Self {
+            unique_name,
+            name,
+            ..Default::default()
+        }
+    }
+
+    pub fn unique_name(&self) -> &str {
+        &self.unique_name
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn num_scopes(&self) -> usize {
+        self.scopes.len()
+    }
+
+    pub fn scope(&self, index: usize) -> Option<&ResourceMapEntry> {
+        self.scopes.get(index)
+    }
+
+    /// Adds a scope. The first scope is the root scope and has no parent.
+    pub fn add_scope(&mut self, scope: ResourceMapEntry) -> usize {
+        let index = self.scopes.len();
+        self.scopes.push(scope);
+        index
+    }
+
+    pub fn num_items(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn item(&self, index: usize) -> Option<&ResourceMapEntry> {
+        self.items.get(index)
+    }
+
+    pub fn add_item(&mut self, item: ResourceMapEntry) -> usize {
+        let index = self.items.len();
+        self.items.push(item);
+        index
+    }
 }
 
 struct ScopeAndItemInfo {
     parent: usize,
     full_path_length: usize,
+    uppercase_first_char: u16,
+    name_length: u8,
     is_scope: bool,
     name_in_ascii: bool,
     name_offset: u64,
@@ -222,16 +326,18 @@ impl ScopeAndItemInfo {
     pub fn read(r: &mut impl Read) -> Result<Self> {
         let parent = r.read_u16::<LE>()? as usize;
         let full_path_length = r.read_u16::<LE>()? as usize;
-        let _uppercase_first_char = r.read_u16::<LE>()?;
-        let _name_length_2 = r.read_u8()?;
+        let uppercase_first_char = r.read_u16::<LE>()?;
+        let name_length = r.read_u8()?;
         let flags = r.read_u8()?;
-        let name_offset = r.read_u16::<LE>()? as u64 | (flags as u64 & 0xf << 16);
+        let name_offset = r.read_u16::<LE>()? as u64 | ((flags as u64 & 0xf) << 16);
         let index = r.read_u16::<LE>()? as usize;
         let is_scope = flags & 0x10 > 0;
         let name_in_ascii = flags & 0x20 > 0;
         Ok(Self {
             parent,
             full_path_length,
+            uppercase_first_char,
+            name_length,
             name_offset,
             index,
             is_scope,
@@ -242,8 +348,8 @@ impl ScopeAndItemInfo {
     pub fn write(&self, w: &mut impl Write) -> Result<()> {
         w.write_u16::<LE>(self.parent as u16)?;
         w.write_u16::<LE>(self.full_path_length as u16)?;
-        w.write_u16::<LE>(0)?;
-        w.write_u8(0)?;
+        w.write_u16::<LE>(self.uppercase_first_char)?;
+        w.write_u8(self.name_length)?;
         let mut flags = (self.name_offset >> 16) as u8 & 0xf;
         if self.is_scope {
             flags |= 0x10;
@@ -4,19 +4,25 @@ use std::fs::File;
 use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::Path;
 
+mod builder;
 mod data_item;
 mod decision_info;
+mod dump;
 mod hierarchical_schema;
 mod pri_descriptor;
 mod resource_map;
 
+pub use builder::PriBuilder;
 pub use data_item::DataItem;
 pub use decision_info::{Decision, DecisionInfo, Qualifier, QualifierSet, QualifierType};
 pub use hierarchical_schema::{HierarchicalSchema, ResourceMapEntry};
 pub use pri_descriptor::{PriDescriptor, PriDescriptorFlags};
-pub use resource_map::{ResourceMap, ResourceValueType};
+pub use resource_map::{
+    Candidate, CandidateInfo, CandidateSet, CandidateValue, ItemInfo, ItemInfoGroup,
+    ItemToItemInfoGroup, ResourceMap, ResourceValueType,
+};
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct PriFile {
     sections: Vec<Section>,
 }
@@ -78,7 +84,7 @@ impl PriFile {
         w.write_u16::<LE>(0)?;
         w.write_u16::<LE>(1)?;
         w.write_u32::<LE>(0)?;
-        let toc_offset = 30;
+        let toc_offset = 32;
         w.write_u32::<LE>(toc_offset)?;
         let section_start_offset = self.sections.len() as u64 * 32 + toc_offset as u64;
         w.write_u32::<LE>(section_start_offset as u32)?;
@@ -226,7 +232,11 @@ impl Section {
         w.write_u32::<LE>(0)?;
         let start = w.stream_position()?;
         self.data.write(w)?;
-        let end = w.stream_position()?;
+        let mut end = w.stream_position()?;
+        while !(end - start).is_multiple_of(8) {
+            w.write_u8(0)?;
+            end += 1;
+        }
         let section_length = (end - start) as u32 + 40;
         w.write_u32::<LE>(0xdef5fade)?;
         w.write_u32::<LE>(section_length)?;
@@ -25,7 +25,7 @@ impl PriDescriptor {
         let num_decision_info_sections = r.read_u16::<LE>()? as usize;
         let num_resource_map_sections = r.read_u16::<LE>()? as usize;
         let primary_resource_map_section = r.read_u16::<LE>()?;
-        let primary_resource_map_section = if primary_resource_map_section == 0xfff {
+        let primary_resource_map_section = if primary_resource_map_section == 0xffff {
             None
         } else {
             Some(primary_resource_map_section)
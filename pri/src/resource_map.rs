@@ -1,37 +1,47 @@
-use anyhow::{ensure, Result};
+use anyhow::{bail, ensure, Context, Result};
 use byteorder::{ReadBytesExt, WriteBytesExt, LE};
 use std::collections::BTreeSet;
 use std::io::{Read, Write};
 
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct ResourceMap {
+    pub num_environment_references: u16,
+    /// Raw environment references, these are only used by pri files for
+    /// older versions of Windows.
+    pub environment_references: Vec<u8>,
     pub hierarchical_schema_section: u16,
+    /// Raw reference to the hierarchical schema, used when the schema is not
+    /// part of the pri file.
+    pub hierarchical_schema_reference: Vec<u8>,
     pub decision_info_section: u16,
     pub item_to_item_info_groups: Vec<ItemToItemInfoGroup>,
     pub item_info_groups: Vec<ItemInfoGroup>,
     pub item_infos: Vec<ItemInfo>,
     pub candidate_infos: Vec<CandidateInfo>,
+    /// Values of inline candidates.
+    pub data: Vec<u8>,
 }
 
 impl ResourceMap {
     pub const IDENTIFIER: &'static [u8; 16] = b"[mrm_res_map2_]\0";
 
     pub fn read(r: &mut impl Read) -> Result<Self> {
-        let environment_references_length = r.read_u16::<LE>()?;
+        let environment_references_length = r.read_u16::<LE>()? as usize;
         let num_environment_references = r.read_u16::<LE>()?;
-        ensure!(environment_references_length == 0);
-        ensure!(num_environment_references == 0);
         let hierarchical_schema_section = r.read_u16::<LE>()?;
-        let _hierarchical_schema_reference_length = r.read_u16::<LE>()?;
+        let hierarchical_schema_reference_length = r.read_u16::<LE>()? as usize;
         let decision_info_section = r.read_u16::<LE>()?;
         let resource_value_type_table_size = r.read_u16::<LE>()? as usize;
         let item_to_item_info_group_count = r.read_u16::<LE>()? as usize;
         let item_info_group_count = r.read_u16::<LE>()? as usize;
         let item_info_count = r.read_u32::<LE>()? as usize;
         let num_candidates = r.read_u32::<LE>()? as usize;
-        let _data_length = r.read_u32::<LE>()?;
+        let data_length = r.read_u32::<LE>()? as usize;
         let large_table_length = r.read_u32::<LE>()?;
-        ensure!(large_table_length == 0);
+        let mut environment_references = vec![0; environment_references_length];
+        r.read_exact(&mut environment_references)?;
+        let mut hierarchical_schema_reference = vec![0; hierarchical_schema_reference_length];
+        r.read_exact(&mut hierarchical_schema_reference)?;
         let mut resource_value_type_table = Vec::with_capacity(resource_value_type_table_size);
         for _ in 0..resource_value_type_table_size {
             ensure!(r.read_u32::<LE>()? == 4);
@@ -65,10 +75,17 @@ impl ResourceMap {
                 first_candidate,
             });
         }
-        /*if large_table_length > 0 {
+        if large_table_length > 0 {
             let item_to_item_info_group_count_large = r.read_u32::<LE>()?;
             let item_info_group_count_large = r.read_u32::<LE>()?;
             let item_info_count_large = r.read_u32::<LE>()?;
+            let count = item_to_item_info_group_count_large as u64
+                + item_info_group_count_large as u64
+                + item_info_count_large as u64;
+            ensure!(
+                large_table_length as u64 == 12 + 8 * count,
+                "invalid large table length"
+            );
             for _ in 0..item_to_item_info_group_count_large {
                 let first_item = r.read_u32::<LE>()?;
                 let item_info_group = r.read_u32::<LE>()?;
@@ -93,44 +110,53 @@ impl ResourceMap {
                     first_candidate,
                 });
             }
-        }*/
+        }
         let mut candidate_infos = Vec::with_capacity(num_candidates);
         for _ in 0..num_candidates {
-            ensure!(r.read_u8()? == 0x01);
-            let resource_value_type = resource_value_type_table[r.read_u8()? as usize];
-            let source_file_index = r.read_u16::<LE>()?;
-            let data_item_index = r.read_u16::<LE>()?;
-            let data_item_section = r.read_u16::<LE>()?;
+            let kind = r.read_u8()?;
+            let resource_value_type = *resource_value_type_table
+                .get(r.read_u8()? as usize)
+                .context("invalid resource value type")?;
+            let value = match kind {
+                0x00 => {
+                    let length = r.read_u16::<LE>()?;
+                    let offset = r.read_u32::<LE>()?;
+                    ensure!(
+                        offset as usize + length as usize <= data_length,
+                        "invalid inline candidate"
+                    );
+                    CandidateValue::Inline { offset, length }
+                }
+                0x01 => {
+                    let source_file_index = r.read_u16::<LE>()?;
+                    let data_item_index = r.read_u16::<LE>()?;
+                    let data_item_section = r.read_u16::<LE>()?;
+                    CandidateValue::DataItem {
+                        source_file_index,
+                        data_item_section,
+                        data_item_index,
+                    }
+                }
+                _ => bail!("unknown candidate type {}", kind),
+            };
             candidate_infos.push(CandidateInfo {
                 resource_value_type,
-                source_file_index,
-                data_item_index,
-                data_item_section,
+                value,
             });
-            /*let candidate_sets = HashMap::new();
-            for item_to_item_info_group in &item_to_item_info_groups {
-                let item_info_group = if item_to_item_info_group.item_info_group < item_info_groups.len() {
-                    item_info_groups[item_to_item_info_group.item_info_group]
-                } else {
-                    ItemInfoGroup {
-                        group_size: 1,
-                        first_item_info: item_to_item_info_group.item_info_group - item_info_groups.len(),
-                    }
-                };
-                for i in 0..item_info_group.group_size {
-                    let item_info = item_infos[item_info_group.first_item_info + i];
-                    let decision =
-
-                }
-            }*/
         }
+        let mut data = vec![0; data_length];
+        r.read_exact(&mut data)?;
         Ok(Self {
+            num_environment_references,
+            environment_references,
             hierarchical_schema_section,
+            hierarchical_schema_reference,
             decision_info_section,
             item_to_item_info_groups,
             item_info_groups,
             item_infos,
             candidate_infos,
+            data,
         })
     }
 
@@ -139,49 +165,130 @@ impl ResourceMap {
         for candidate in &self.candidate_infos {
             resource_value_type_table.insert(candidate.resource_value_type);
         }
-        w.write_u16::<LE>(0)?;
-        w.write_u16::<LE>(0)?;
+        // tables that don't fit into u16 are written to the large tables
+        let small = self.item_to_item_info_groups.len() < 0xffff
+            && self.item_info_groups.len() < 0xffff
+            && self
+                .item_to_item_info_groups
+                .iter()
+                .all(|g| g.first_item <= 0xffff && g.item_info_group <= 0xffff)
+            && self
+                .item_info_groups
+                .iter()
+                .all(|g| g.group_size <= 0xffff && g.first_item_info <= 0xffff)
+            && self
+                .item_infos
+                .iter()
+                .all(|i| i.decision <= 0xffff && i.first_candidate <= 0xffff);
+        let small_len = |len: usize| if small { len } else { 0 };
+        let large_table_length = if small {
+            0
+        } else {
+            let len = self.item_to_item_info_groups.len()
+                + self.item_info_groups.len()
+                + self.item_infos.len();
+            u32::try_from(12 + 8 * len as u64).context("resource map too large")?
+        };
+        w.write_u16::<LE>(self.environment_references.len() as u16)?;
+        w.write_u16::<LE>(self.num_environment_references)?;
         w.write_u16::<LE>(self.hierarchical_schema_section)?;
-        // TODO: hierarchical_schema_reference_length
-        w.write_u16::<LE>(0)?;
+        w.write_u16::<LE>(self.hierarchical_schema_reference.len() as u16)?;
         w.write_u16::<LE>(self.decision_info_section)?;
         w.write_u16::<LE>(resource_value_type_table.len() as _)?;
-        w.write_u16::<LE>(self.item_to_item_info_groups.len() as _)?;
-        w.write_u16::<LE>(self.item_info_groups.len() as _)?;
-        w.write_u32::<LE>(self.item_infos.len() as _)?;
+        w.write_u16::<LE>(small_len(self.item_to_item_info_groups.len()) as _)?;
+        w.write_u16::<LE>(small_len(self.item_info_groups.len()) as _)?;
+        w.write_u32::<LE>(small_len(self.item_infos.len()) as _)?;
         w.write_u32::<LE>(self.candidate_infos.len() as _)?;
-        // TODO: data_length
-        w.write_u32::<LE>(0)?;
-        w.write_u32::<LE>(0)?;
+        w.write_u32::<LE>(self.data.len() as _)?;
+        w.write_u32::<LE>(large_table_length)?;
+        w.write_all(&self.environment_references)?;
+        w.write_all(&self.hierarchical_schema_reference)?;
         for resource_value_type in &resource_value_type_table {
             w.write_u32::<LE>(4)?;
             w.write_u32::<LE>(*resource_value_type)?;
         }
-        for item_to_item_info_group in &self.item_to_item_info_groups {
-            w.write_u16::<LE>(item_to_item_info_group.first_item as u16)?;
-            w.write_u16::<LE>(item_to_item_info_group.item_info_group as u16)?;
-        }
-        for item_info_group in &self.item_info_groups {
-            w.write_u16::<LE>(item_info_group.group_size as u16)?;
-            w.write_u16::<LE>(item_info_group.first_item_info as u16)?;
-        }
-        for item_info in &self.item_infos {
-            w.write_u16::<LE>(item_info.decision as u16)?;
-            w.write_u16::<LE>(item_info.first_candidate as u16)?;
+        if small {
+            for group in &self.item_to_item_info_groups {
+                w.write_u16::<LE>(group.first_item as u16)?;
+                w.write_u16::<LE>(group.item_info_group as u16)?;
+            }
+            for group in &self.item_info_groups {
+                w.write_u16::<LE>(group.group_size as u16)?;
+                w.write_u16::<LE>(group.first_item_info as u16)?;
+            }
+            for item_info in &self.item_infos {
+                w.write_u16::<LE>(item_info.decision as u16)?;
+                w.write_u16::<LE>(item_info.first_candidate as u16)?;
+            }
+        } else {
+            w.write_u32::<LE>(self.item_to_item_info_groups.len() as u32)?;
+            w.write_u32::<LE>(self.item_info_groups.len() as u32)?;
+            w.write_u32::<LE>(self.item_infos.len() as u32)?;
+            for group in &self.item_to_item_info_groups {
+                w.write_u32::<LE>(group.first_item)?;
+                w.write_u32::<LE>(group.item_info_group)?;
+            }
+            for group in &self.item_info_groups {
+                w.write_u32::<LE>(group.group_size)?;
+                w.write_u32::<LE>(group.first_item_info)?;
+            }
+            for item_info in &self.item_infos {
+                w.write_u32::<LE>(item_info.decision)?;
+                w.write_u32::<LE>(item_info.first_candidate)?;
+            }
         }
         for candidate in &self.candidate_infos {
-            w.write_u8(0x01)?;
             let resource_value_type_index = resource_value_type_table
                 .iter()
                 .position(|t| *t == candidate.resource_value_type)
                 .unwrap();
-            w.write_u8(resource_value_type_index as u8)?;
-            w.write_u16::<LE>(candidate.source_file_index)?;
-            w.write_u16::<LE>(candidate.data_item_index)?;
-            w.write_u16::<LE>(candidate.data_item_section)?;
+            match candidate.value {
+                CandidateValue::Inline { offset, length } => {
+                    w.write_u8(0x00)?;
+                    w.write_u8(resource_value_type_index as u8)?;
+                    w.write_u16::<LE>(length)?;
+                    w.write_u32::<LE>(offset)?;
+                }
+                CandidateValue::DataItem {
+                    source_file_index,
+                    data_item_section,
+                    data_item_index,
+                } => {
+                    w.write_u8(0x01)?;
+                    w.write_u8(resource_value_type_index as u8)?;
+                    w.write_u16::<LE>(source_file_index)?;
+                    w.write_u16::<LE>(data_item_index)?;
+                    w.write_u16::<LE>(data_item_section)?;
+                }
+            }
         }
+        w.write_all(&self.data)?;
         Ok(())
     }
+
+    /// Looks up the item info of an item of the hierarchical schema.
+    pub fn item_info(&self, item: u32) -> Option<ItemInfo> {
+        let group = self
+            .item_to_item_info_groups
+            .iter()
+            .filter(|group| group.first_item <= item)
+            .max_by_key(|group| group.first_item)?;
+        let offset = item - group.first_item;
+        let index =
+            if let Some(info_group) = self.item_info_groups.get(group.item_info_group as usize) {
+                if offset >= info_group.group_size {
+                    return None;
+                }
+                info_group.first_item_info + offset
+            } else {
+                // groups past the item info groups contain a single item info
+                if offset > 0 {
+                    return None;
+                }
+                group.item_info_group - self.item_info_groups.len() as u32
+            };
+        self.item_infos.get(index as usize).copied()
+    }
 }
 
 #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
@@ -205,9 +312,20 @@ pub struct ItemInfo {
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct CandidateInfo {
     pub resource_value_type: u32,
-    pub source_file_index: u16,
-    pub data_item_index: u16,
-    pub data_item_section: u16,
+    pub value: CandidateValue,
+}
+
+/// Location of the value of a candidate.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum CandidateValue {
+    /// String or blob of a data item section.
+    DataItem {
+        source_file_index: u16,
+        data_item_section: u16,
+        data_item_index: u16,
+    },
+    /// Range of [`ResourceMap::data`].
+    Inline { offset: u32, length: u16 },
 }
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
@@ -221,6 +339,21 @@ pub enum ResourceValueType {
     Utf8Path,
 }
 
+impl ResourceValueType {
+    pub fn from_u32(ty: u32) -> Option<Self> {
+        Some(match ty {
+            0 => Self::String,
+            1 => Self::Path,
+            2 => Self::EmbeddedData,
+            3 => Self::AsciiString,
+            4 => Self::Utf8String,
+            5 => Self::AsciiPath,
+            6 => Self::Utf8Path,
+            _ => return None,
+        })
+    }
+}
+
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct CandidateSet {
     pub resource_map_item: u32,
@@ -235,3 +368,131 @@ pub struct Candidate {
     pub data_item_section: u16,
     pub data_item_index: u16,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn round_trip(map: &ResourceMap) -> Result<ResourceMap> {
+        let mut buf = vec![];
+        map.write(&mut buf)?;
+        ResourceMap::read(&mut Cursor::new(buf))
+    }
+
+    #[test]
+    fn test_large_tables() -> Result<()> {
+        let num_items = 0x10010;
+        let map = ResourceMap {
+            item_to_item_info_groups: vec![ItemToItemInfoGroup {
+                first_item: 0,
+                item_info_group: 0,
+            }],
+            item_info_groups: vec![ItemInfoGroup {
+                group_size: num_items,
+                first_item_info: 0,
+            }],
+            item_infos: (0..num_items)
+                .map(|i| ItemInfo {
+                    decision: 1,
+                    first_candidate: i,
+                })
+                .collect(),
+            candidate_infos: (0..num_items)
+                .map(|i| CandidateInfo {
+                    resource_value_type: ResourceValueType::AsciiPath as u32,
+                    value: CandidateValue::DataItem {
+                        source_file_index: 0,
+                        data_item_section: 4 + (i >> 16) as u16,
+                        data_item_index: i as u16,
+                    },
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let map2 = round_trip(&map)?;
+        assert_eq!(map, map2);
+        assert_eq!(
+            map2.item_info(0xffff + 2).unwrap().first_candidate,
+            0xffff + 2
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_table_length_overflow() -> Result<()> {
+        let map = ResourceMap {
+            item_infos: vec![ItemInfo {
+                decision: 0x10000,
+                first_candidate: 0,
+            }],
+            ..Default::default()
+        };
+        let mut buf = vec![];
+        map.write(&mut buf)?;
+        // 12 + 8 * 0x2000_0000 wraps around to 12 in u32
+        buf[28..32].copy_from_slice(&12u32.to_le_bytes());
+        buf[32..36].copy_from_slice(&0x2000_0000u32.to_le_bytes());
+        buf[36..44].fill(0);
+        assert!(ResourceMap::read(&mut Cursor::new(buf)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_environment_references_and_inline_candidates() -> Result<()> {
+        let map = ResourceMap {
+            num_environment_references: 1,
+            environment_references: vec![1, 2, 3, 4],
+            hierarchical_schema_section: 2,
+            hierarchical_schema_reference: vec![5, 6],
+            item_to_item_info_groups: vec![
+                ItemToItemInfoGroup {
+                    first_item: 0,
+                    item_info_group: 0,
+                },
+                ItemToItemInfoGroup {
+                    first_item: 1,
+                    item_info_group: 2,
+                },
+            ],
+            item_info_groups: vec![ItemInfoGroup {
+                group_size: 1,
+                first_item_info: 0,
+            }],
+            item_infos: vec![
+                ItemInfo {
+                    decision: 1,
+                    first_candidate: 0,
+                },
+                ItemInfo {
+                    decision: 1,
+                    first_candidate: 1,
+                },
+            ],
+            candidate_infos: vec![
+                CandidateInfo {
+                    resource_value_type: ResourceValueType::Utf8String as u32,
+                    value: CandidateValue::Inline {
+                        offset: 0,
+                        length: 6,
+                    },
+                },
+                CandidateInfo {
+                    resource_value_type: ResourceValueType::AsciiPath as u32,
+                    value: CandidateValue::DataItem {
+                        source_file_index: 0,
+                        data_item_section: 4,
+                        data_item_index: 0,
+                    },
+                },
+            ],
+            data: b"hello\0".to_vec(),
+            ..Default::default()
+        };
+        assert_eq!(map, round_trip(&map)?);
+        // groups past the item info groups map to a single item info
+        assert_eq!(map.item_info(1).unwrap().first_candidate, 1);
+        assert_eq!(map.item_info(2), None);
+        Ok(())
+    }
+}
@@ -18,6 +18,9 @@ pub enum Inheritable<T> {
 pub struct Manifest {
     pub workspace: Option<Workspace>,
     pub package: Option<Package>,
+    pub lib: Option<Target>,
+    #[serde(default)]
+    pub bin: Vec<Target>,
 }
 
 impl Manifest {
@@ -70,6 +73,29 @@ impl Manifest {
         Ok(all_members)
     }
 
+    /// Returns whether the package has a library target, either declared with `[lib]` or
+    /// discovered at `src/lib.rs`.
+    pub fn has_lib(&self, package_root: &Path) -> bool {
+        self.lib.is_some() || package_root.join("src").join("lib.rs").exists()
+    }
+
+    /// Returns the names of the binary targets declared with `[[bin]]`, and the package
+    /// name if `src/main.rs` isn't claimed by one of them.
+    pub fn bins(&self, package_root: &Path) -> Vec<String> {
+        let mut bins: Vec<String> = self.bin.iter().filter_map(|bin| bin.name.clone()).collect();
+        let main = Path::new("src").join("main.rs");
+        if let Some(package) = &self.package {
+            let claimed = self.bin.iter().any(|bin| {
+                bin.path.as_deref() == Some(main.as_path())
+                    || bin.name.as_ref() == Some(&package.name)
+            });
+            if !claimed && package_root.join(&main).exists() {
+                bins.push(package.name.clone());
+            }
+        }
+        bins
+    }
+
     /// Returns `self` if it contains `[package]` but not `[workspace]`, (i.e. it cannot be
     /// a workspace nor a virtual manifest), and describes a package named `name` if not [`None`].
     pub fn map_nonvirtual_package(
@@ -131,3 +157,37 @@ pub struct Package {
     pub version: Inheritable<String>,
     pub description: Option<Inheritable<String>>,
 }
+
+/// A `[lib]` or `[[bin]]` target.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Target {
+    pub name: Option<String>,
+    pub path: Option<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_targets() -> Result<()> {
+        let manifest: Manifest = toml::from_str(
+            r#"
+            [package]
+            name = "app"
+            version = "0.1.0"
+
+            [lib]
+            path = "lib.rs"
+
+            [[bin]]
+            name = "app-cli"
+            path = "src/main.rs"
+            "#,
+        )?;
+        let root = Path::new("does-not-exist");
+        assert!(manifest.has_lib(root));
+        assert_eq!(manifest.bins(root), vec!["app-cli".to_string()]);
+        Ok(())
+    }
+}
@@ -12,7 +12,7 @@ pub use artifact::{Artifact, CrateType};
 
 use self::config::LocalizedConfig;
 use self::manifest::Manifest;
-use crate::{CompileTarget, Opt};
+use crate::{Arch, CompileTarget, Opt};
 
 pub enum FeatureSpecification {
     List(Vec<String>),
@@ -164,6 +164,7 @@ impl Cargo {
             self.package_root(),
             target_dir,
             self.offline,
+            self.manifest.has_lib(self.package_root()),
         )
     }
 
@@ -207,6 +208,10 @@ impl Cargo {
         let build_deps_dir = opt_dir.join("build");
 
         let mut paths = vec![];
+        // only created when a dependency has a build script
+        if !build_deps_dir.exists() {
+            return Ok(paths);
+        }
 
         for dep_dir in build_deps_dir.read_dir().with_context(|| {
             format!(
@@ -247,6 +252,9 @@ pub struct CargoBuild {
     c_flags: String,
     cxx_flags: String,
     rust_flags: Vec<String>,
+    has_lib: bool,
+    bin: Option<String>,
+    bin_link_args: Vec<String>,
 }
 
 impl CargoBuild {
@@ -257,6 +265,7 @@ impl CargoBuild {
         root_dir: &Path,
         target_dir: &Path,
         offline: bool,
+        has_lib: bool,
     ) -> Result<Self> {
         let triple = if target.is_host()? {
             None
@@ -301,6 +310,9 @@ impl CargoBuild {
             c_flags: Default::default(),
             cxx_flags: Default::default(),
             rust_flags: Default::default(),
+            has_lib,
+            bin: None,
+            bin_link_args: Default::default(),
         })
     }
 
@@ -335,20 +347,48 @@ impl CargoBuild {
         self.cmd.env("XBUILD_ANDROID_JAR", path);
     }
 
+    /// Cross compiles with the llvm toolchain against an xwin style sdk.
     pub fn use_windows_sdk(&mut self, path: &Path) -> Result<()> {
         let path = dunce::canonicalize(path)?;
+        let arch = match self.target.arch() {
+            Arch::Arm64 => "aarch64",
+            Arch::X64 => "x86_64",
+        };
+        validate_windows_sdk(&path, arch)?;
+        which::which("lld-link")
+            .context("lld-link not found, install llvm and check `x doctor`")?;
         self.cfg_tool(Tool::Cc, "clang-cl");
         self.cfg_tool(Tool::Cxx, "clang-cl");
         self.cfg_tool(Tool::Ar, "llvm-lib");
-        self.cfg_tool(Tool::Linker, "rust-lld"); // Rust defaults to link.exe, use its rust-lld binary instead
+        self.cfg_tool(Tool::Rc, "llvm-rc");
+        self.cfg_tool(Tool::Linker, "lld-link");
         self.add_msvc_include_dir(&path.join("crt").join("include"));
         self.add_msvc_include_dir(&path.join("sdk").join("include").join("um"));
         self.add_msvc_include_dir(&path.join("sdk").join("include").join("ucrt"));
         self.add_msvc_include_dir(&path.join("sdk").join("include").join("shared"));
-        self.add_lib_dir(&path.join("crt").join("lib").join("x86_64"));
-        self.add_lib_dir(&path.join("sdk").join("lib").join("um").join("x86_64"));
-        self.add_lib_dir(&path.join("sdk").join("lib").join("ucrt").join("x86_64"));
-        dbg!(&self);
+        self.add_lib_dir(&path.join("crt").join("lib").join(arch));
+        self.add_lib_dir(&path.join("sdk").join("lib").join("um").join(arch));
+        self.add_lib_dir(&path.join("sdk").join("lib").join("ucrt").join(arch));
+        Ok(())
+    }
+
+    pub fn use_linux_sysroot(&mut self, path: &Path) -> Result<()> {
+        let path = dunce::canonicalize(path)?;
+        let triple = self.target.rust_triple()?;
+        let gnu_triple = match self.target.arch() {
+            Arch::Arm64 => "aarch64-linux-gnu",
+            Arch::X64 => "x86_64-linux-gnu",
+        };
+        self.cfg_tool(Tool::Cc, "clang");
+        self.cfg_tool(Tool::Cxx, "clang++");
+        self.cfg_tool(Tool::Ar, "llvm-ar");
+        self.cfg_tool(Tool::Linker, "clang");
+        self.use_ld("lld");
+        self.set_sysroot(&path);
+        self.add_cflag(&format!("--target={}", triple));
+        self.add_link_arg(&format!("--target={}", triple));
+        self.add_lib_dir(&path.join("lib").join(gnu_triple));
+        self.add_lib_dir(&path.join("usr").join("lib").join(gnu_triple));
         Ok(())
     }
 
@@ -403,7 +443,7 @@ impl CargoBuild {
 
     pub fn cfg_tool<P: AsRef<Path>>(&mut self, tool: Tool, path: P) {
         match tool {
-            Tool::Cc | Tool::Cxx | Tool::Ar => {
+            Tool::Cc | Tool::Cxx | Tool::Ar | Tool::Rc => {
                 self.cc_triple_env(&tool.to_string(), path.as_ref().to_str().unwrap());
             }
             Tool::Linker => {
@@ -469,6 +509,13 @@ impl CargoBuild {
         self.rust_flags.push(format!("-Clink-arg={}", link_arg));
     }
 
+    /// Passes `link_arg` only when linking the binary `bin`. The library is
+    /// built by a separate cargo invocation without it.
+    pub fn add_bin_link_arg(&mut self, bin: &str, link_arg: &str) {
+        self.bin = Some(bin.to_string());
+        self.bin_link_args.push(format!("-Clink-arg={}", link_arg));
+    }
+
     pub fn add_define(&mut self, name: &str, value: &str) {
         self.c_flags.push_str(&format!("-D{}={} ", name, value));
     }
@@ -528,7 +575,41 @@ impl CargoBuild {
         self.cc_triple_env("CFLAGS", &self.c_flags.clone());
         // These strings already end with a space if they're non-empty:
         self.cc_triple_env("CXXFLAGS", &format!("{}{}", self.c_flags, self.cxx_flags));
-        if !self.cmd.status()?.success() {
+        if let Some(bin) = self.bin.as_ref() {
+            // `cargo rustc` is like `cargo build`, but rustc args can only be
+            // passed to a single target
+            if self.has_lib {
+                Self::run(self.rustc(["--lib"]))?;
+            }
+            let mut cmd = self.rustc(["--bin", bin, "--"]);
+            cmd.args(&self.bin_link_args);
+            Self::run(cmd)
+        } else {
+            Self::run(self.cmd)
+        }
+    }
+
+    /// Returns a copy of the cargo invocation using the `rustc` subcommand
+    /// instead of `build`, with `args` appended.
+    fn rustc<'a>(&self, args: impl IntoIterator<Item = &'a str>) -> Command {
+        let mut cmd = Command::new(self.cmd.get_program());
+        if let Some(dir) = self.cmd.get_current_dir() {
+            cmd.current_dir(dir);
+        }
+        for (key, value) in self.cmd.get_envs() {
+            match value {
+                Some(value) => cmd.env(key, value),
+                None => cmd.env_remove(key),
+            };
+        }
+        cmd.arg("rustc")
+            .args(self.cmd.get_args().skip(1))
+            .args(args);
+        cmd
+    }
+
+    fn run(mut cmd: Command) -> Result<()> {
+        if !cmd.status()?.success() {
             std::process::exit(1);
         }
         Ok(())
@@ -541,6 +622,8 @@ pub enum Tool {
     Cxx,
     Linker,
     Ar,
+    /// Resource compiler used by crates like `embed-resource`.
+    Rc,
 }
 
 impl std::fmt::Display for Tool {
@@ -550,6 +633,60 @@ impl std::fmt::Display for Tool {
             Self::Cxx => write!(f, "CXX"),
             Self::Linker => write!(f, "LINKER"),
             Self::Ar => write!(f, "AR"),
+            Self::Rc => write!(f, "RC"),
         }
     }
 }
+
+/// Checks that the sdk at `path` has the headers and the libraries for `arch`
+/// in the layout produced by `xwin splat`.
+fn validate_windows_sdk(path: &Path, arch: &str) -> Result<()> {
+    let crt = path.join("crt");
+    let sdk = path.join("sdk");
+    let headers = [
+        (crt.join("include"), "vcruntime.h"),
+        (sdk.join("include").join("ucrt"), "stdio.h"),
+        (sdk.join("include").join("um"), "windows.h"),
+        (sdk.join("include").join("shared"), "winapifamily.h"),
+    ];
+    let libs = [
+        (crt.join("lib").join(arch), "msvcrt.lib"),
+        (sdk.join("lib").join("um").join(arch), "kernel32.lib"),
+        (sdk.join("lib").join("ucrt").join(arch), "ucrt.lib"),
+    ];
+    // the sdk doesn't use consistent casing
+    let contains = |dir: &Path, file: &str| {
+        std::fs::read_dir(dir)
+            .map(|entries| {
+                entries.filter_map(|entry| entry.ok()).any(|entry| {
+                    entry
+                        .file_name()
+                        .to_str()
+                        .map(|name| name.eq_ignore_ascii_case(file))
+                        .unwrap_or_default()
+                })
+            })
+            .unwrap_or_default()
+    };
+    for (dir, file) in headers {
+        anyhow::ensure!(
+            contains(&dir, file),
+            "Windows SDK at `{}` is incomplete, `{}` is missing in `{}`. Remove the sdk and build without `--offline` to download it again.",
+            path.display(),
+            file,
+            dir.display()
+        );
+    }
+    for (dir, file) in libs {
+        anyhow::ensure!(
+            contains(&dir, file),
+            "Windows SDK at `{}` doesn't contain the {} libraries, `{}` is missing in `{}`. The SDK published with this xbuild release doesn't include {}, build for another `--arch`, with msvc on windows or update xbuild.",
+            path.display(),
+            arch,
+            file,
+            dir.display(),
+            arch
+        );
+    }
+    Ok(())
+}
@@ -1,14 +1,14 @@
 use crate::cargo::CrateType;
 use crate::download::DownloadManager;
 use crate::task::TaskRunner;
-use crate::{BuildEnv, Format, Opt, Platform};
+use crate::{Arch, BuildEnv, CompileTarget, Format, Opt, Platform, Store};
 use anyhow::{ensure, Context, Result};
 use apk::Apk;
 use appbundle::AppBundle;
-use appimage::AppImage;
-use msix::Msix;
-use std::collections::HashSet;
-use std::ffi::OsStr;
+use appimage::{AppImage, Compression};
+use msix::{AppxManifest, Msix, MsixBundle};
+use std::collections::{BTreeSet, HashSet};
+use std::ffi::{OsStr, OsString};
 use std::path::Path;
 use xcommon::{Zip, ZipFileOptions};
 
@@ -54,22 +54,128 @@ pub fn build(env: &BuildEnv) -> Result<()> {
             let target = env.target().compile_targets().next().unwrap();
             let arch_dir = platform_dir.join(target.arch().to_string());
 
-            let appimage = AppImage::new(&arch_dir, env.name().to_string())?;
+            let linux = env.config().linux();
+            let mut appimage = AppImage::new(&arch_dir, env.name().to_string())?;
+            if let Some(runtime) = linux.runtime.as_ref() {
+                appimage.set_runtime(env.cargo().package_root().join(runtime));
+            } else if let Some(runtime) = env.appimage_runtime(target.arch()) {
+                ensure!(
+                    runtime.exists(),
+                    "AppImage runtime not found at `{}`, build without `--offline` to download it",
+                    runtime.display()
+                );
+                appimage.set_runtime(runtime);
+            }
+            ensure!(
+                linux.compression != Compression::Zstd || linux.runtime.is_some(),
+                "zstd compression requires a runtime built with zstd support, \
+                 set `linux.runtime` in manifest.yaml or use gzip compression"
+            );
+            appimage.set_compression(linux.compression);
+            if let Some(update_information) = linux.update_information.as_ref() {
+                appimage.set_update_information(update_information.clone());
+            }
+            if let Some(id) = linux.metainfo.id.as_ref() {
+                appimage.set_id(id.clone());
+            }
             appimage.add_apprun()?;
-            appimage.add_desktop()?;
+            appimage.add_desktop(&linux.desktop)?;
+            appimage.add_metainfo(&linux.desktop, &linux.metainfo)?;
             if let Some(icon) = env.icon() {
                 appimage.add_icon(icon)?;
             }
 
-            let main = env.cargo_artefact(&arch_dir.join("cargo"), &target, CrateType::Bin)?;
+            let cargo_dir = arch_dir.join("cargo");
+            let main = env.cargo_artefact(&cargo_dir, &target, CrateType::Bin)?;
             appimage.add_file(&main, Path::new(env.name()))?;
 
+            let mut libs = BTreeSet::new();
+            let mut explicit_libs = vec![main];
             if has_lib {
-                let lib =
-                    env.cargo_artefact(&arch_dir.join("cargo"), &target, CrateType::Cdylib)?;
+                let lib = env.cargo_artefact(&cargo_dir, &target, CrateType::Cdylib)?;
+                libs.insert(lib.clone());
+                explicit_libs.push(lib);
+            }
+
+            let deps_dir = {
+                let arch_dir = if target.is_host()? {
+                    cargo_dir.to_path_buf()
+                } else {
+                    cargo_dir.join(target.rust_triple()?)
+                };
+                arch_dir.join(target.opt().to_string()).join("deps")
+            };
+            let mut search_paths = env
+                .cargo()
+                .lib_search_paths(&cargo_dir, &target)
+                .with_context(|| {
+                    format!(
+                        "Finding libraries in `{}` for {:?}",
+                        cargo_dir.display(),
+                        target
+                    )
+                })?;
+            search_paths.push(deps_dir);
+            let search_paths = search_paths.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+
+            // Libraries provided by the host system are not bundled
+            let provided_libs = appimage::EXCLUDED_LIBS
+                .iter()
+                .map(OsString::from)
+                .collect::<HashSet<_>>();
+            ensure!(
+                which::which("llvm-readobj").is_ok(),
+                "llvm-readobj not found. install llvm to find the shared libraries to bundle"
+            );
+            for lib in &explicit_libs {
+                let (extra_libs, _) = xcommon::llvm::list_needed_linux_libs_recursively(
+                    lib,
+                    &search_paths,
+                    &provided_libs,
+                )
+                .with_context(|| {
+                    format!(
+                        "Failed to collect all required libraries for `{}` with `{:?}` shippable libraries",
+                        lib.display(),
+                        search_paths
+                    )
+                })?;
+                libs.extend(extra_libs);
+            }
+            for lib in libs {
                 appimage.add_file(&lib, &Path::new("lib").join(lib.file_name().unwrap()))?;
             }
 
+            if env.target().format() == Format::Flatpak {
+                let arch = match target.arch() {
+                    Arch::Arm64 => "aarch64",
+                    Arch::X64 => "x86_64",
+                };
+                let dir = arch_dir.join("flatpak");
+                appimage.create_flatpak_dir(&dir, arch, &linux.flatpak)?;
+                if linux.flatpak.bundle {
+                    ensure!(
+                        which::which("flatpak").is_ok(),
+                        "flatpak not found. install it or set `linux.flatpak.bundle = false` to only create the build directory at {}",
+                        dir.display()
+                    );
+                    appimage.create_flatpak_bundle(&dir, arch, &env.output())?;
+                }
+            }
+            if env.target().format() == Format::Deb {
+                let arch = match target.arch() {
+                    Arch::Arm64 => "arm64",
+                    Arch::X64 => "amd64",
+                };
+                appimage.create_deb(&env.output(), arch, &linux.package)?;
+            }
+            if env.target().format() == Format::Rpm {
+                let arch = match target.arch() {
+                    Arch::Arm64 => "aarch64",
+                    Arch::X64 => "x86_64",
+                };
+                appimage.create_rpm(&env.output(), arch, &linux.package)?;
+            }
             if env.target().format() == Format::Appimage {
                 let out = arch_dir.join(format!("{}.AppImage", env.name()));
                 appimage.build(&out, env.target().signer().cloned())?;
@@ -257,7 +363,7 @@ pub fn build(env: &BuildEnv) -> Result<()> {
             let target = env.target().compile_targets().next().unwrap();
             let arch_dir = platform_dir.join(target.arch().to_string());
 
-            let mut app = AppBundle::new(&arch_dir, env.config().macos().info.clone())?;
+            let mut app = AppBundle::new(&arch_dir, env.info_plist(Platform::Macos)?)?;
             if let Some(icon) = env.icon() {
                 app.add_icon(icon)?;
             }
@@ -277,7 +383,11 @@ pub fn build(env: &BuildEnv) -> Result<()> {
             }
             if env.target().format() == Format::Dmg {
                 let out = arch_dir.join(format!("{}.dmg", env.name()));
-                apple_dmg::create_dmg(app.appdir(), &out, env.name(), 0x40000)?;
+                let mut layout = env.config().macos().dmg.clone();
+                if let Some(background) = layout.background.as_mut() {
+                    *background = env.cargo().package_root().join(&*background);
+                }
+                app.create_dmg(&out, &layout)?;
                 if let Some(signer) = env.target().signer() {
                     app.sign_dmg(&out, signer)?;
                     if let Some(api_key) = env.target().api_key() {
@@ -285,12 +395,23 @@ pub fn build(env: &BuildEnv) -> Result<()> {
                     }
                 }
             }
+            if env.target().format() == Format::Pkg {
+                let out = arch_dir.join(format!("{}.pkg", env.name()));
+                let signer = env.target().installer_signer();
+                app.create_pkg(&out, signer)?;
+                // app store packages are reviewed instead of notarized
+                if signer.is_some() && env.target().store() != Some(Store::Apple) {
+                    if let Some(api_key) = env.target().api_key() {
+                        appbundle::notarize(&out, api_key)?;
+                    }
+                }
+            }
         }
         Platform::Ios => {
             let target = env.target().compile_targets().next().unwrap();
             let arch_dir = platform_dir.join(target.arch().to_string());
             std::fs::create_dir_all(&arch_dir)?;
-            let mut app = AppBundle::new(&arch_dir, env.config().ios().info.clone())?;
+            let mut app = AppBundle::new(&arch_dir, env.info_plist(Platform::Ios)?)?;
             if let Some(icon) = env.icon() {
                 app.add_icon(icon)?;
             }
@@ -319,53 +440,29 @@ pub fn build(env: &BuildEnv) -> Result<()> {
             let target = env.target().compile_targets().next().unwrap();
             let arch_dir = platform_dir.join(target.arch().to_string());
             std::fs::create_dir_all(&arch_dir)?;
-            let out = arch_dir.join(format!("{}.{}", env.name(), env.target().format()));
-            let main = env.cargo_artefact(&arch_dir.join("cargo"), &target, CrateType::Bin)?;
             match env.target().format() {
                 Format::Exe => {
-                    std::fs::copy(&main, &out)?;
+                    let out = arch_dir.join(format!("{}.exe", env.name()));
+                    let main =
+                        env.cargo_artefact(&arch_dir.join("cargo"), &target, CrateType::Bin)?;
+                    copy_pe(env, &main, &out)?;
                 }
                 Format::Msix => {
-                    let mut msix = Msix::new(
-                        out,
-                        env.config().windows().manifest.clone(),
-                        *target.opt() != Opt::Debug,
-                    )?;
-                    if let Some(icon) = env.icon() {
-                        dbg!(icon);
-                        msix.add_icon(icon)?;
-                        assert_eq!(
-                            &env.config.windows().manifest.properties.logo,
-                            "Images/StoreLogo.scale-100.png",
-                        );
-                    }
-                    // TODO: *.pri
-
-                    msix.add_file(
-                        &main,
-                        format!("{}.exe", env.name()).as_ref(),
-                        ZipFileOptions::Compressed,
-                    )?;
-
-                    // TODO: Investigate use-cases for `.dll`s in MSIX (Rust compiles static self-contained binaries)
-                    if has_lib {
-                        match env.cargo_artefact(
-                            &arch_dir.join("cargo"),
-                            &target,
-                            CrateType::Cdylib,
-                        ) {
-                            Ok(lib) => msix.add_file(
-                                &lib,
-                                Path::new(lib.file_name().unwrap()),
-                                ZipFileOptions::Compressed,
-                            )?,
-                            Err(e) => log::error!(
-                                "Failed to retrieve library artifact, skipping `.dll`: {e:?}"
-                            ),
-                        }
+                    let out = arch_dir.join(format!("{}.msix", env.name()));
+                    create_msix(env, &target, &out, has_lib)?;
+                }
+                Format::Msixbundle => {
+                    let identity = &env.config().windows().manifest.identity;
+                    let mut bundle =
+                        MsixBundle::new(env.output(), identity, *target.opt() != Opt::Debug)?;
+                    for target in env.target().compile_targets() {
+                        let arch_dir = platform_dir.join(target.arch().to_string());
+                        std::fs::create_dir_all(&arch_dir)?;
+                        let out = arch_dir.join(format!("{}.msix", env.name()));
+                        let manifest = create_msix(env, &target, &out, has_lib)?;
+                        bundle.add_package(&out, &manifest)?;
                     }
-
-                    msix.finish(env.target().signer().cloned())?;
+                    bundle.finish(env.target().signer().cloned())?;
                 }
                 _ => {
                     anyhow::bail!("unsupported windows format");
@@ -377,3 +474,64 @@ pub fn build(env: &BuildEnv) -> Result<()> {
 
     Ok(())
 }
+
+/// Creates an msix for `target` at `out` and returns its manifest.
+fn create_msix(
+    env: &BuildEnv,
+    target: &CompileTarget,
+    out: &Path,
+    has_lib: bool,
+) -> Result<AppxManifest> {
+    let arch_dir = env.arch_dir(target.arch());
+    let main = env.cargo_artefact(&arch_dir.join("cargo"), target, CrateType::Bin)?;
+    let mut manifest = env.config().windows().manifest.clone();
+    manifest.identity.processor_architecture = Some(
+        match target.arch() {
+            Arch::X64 => "x64",
+            Arch::Arm64 => "arm64",
+        }
+        .into(),
+    );
+    let mut msix = Msix::new(out.to_path_buf(), manifest, *target.opt() != Opt::Debug)?;
+    if let Some(icon) = env.icon() {
+        msix.add_icon(icon)?;
+    }
+
+    let exe = format!("{}.exe", env.name());
+    copy_pe(env, &main, &arch_dir.join(&exe))?;
+    msix.add_file(
+        &arch_dir.join(&exe),
+        exe.as_ref(),
+        ZipFileOptions::Compressed,
+    )?;
+
+    // TODO: Investigate use-cases for `.dll`s in MSIX (Rust compiles static self-contained binaries)
+    if has_lib {
+        match env.cargo_artefact(&arch_dir.join("cargo"), target, CrateType::Cdylib) {
+            Ok(lib) => {
+                let dll = lib.file_name().unwrap();
+                copy_pe(env, &lib, &arch_dir.join(dll))?;
+                msix.add_file(
+                    &arch_dir.join(dll),
+                    Path::new(dll),
+                    ZipFileOptions::Compressed,
+                )?
+            }
+            Err(e) => log::error!("Failed to retrieve library artifact, skipping `.dll`: {e:?}"),
+        }
+    }
+
+    let manifest = msix.manifest().clone();
+    msix.finish(env.target().signer().cloned())?;
+    Ok(manifest)
+}
+
+/// Copies an executable or library and signs it with authenticode if a signer
+/// is configured.
+fn copy_pe(env: &BuildEnv, source: &Path, dest: &Path) -> Result<()> {
+    std::fs::copy(source, dest)?;
+    if let Some(signer) = env.target().signer() {
+        msix::authenticode::sign(dest, signer)?;
+    }
+    Ok(())
+}
@@ -24,6 +24,8 @@ impl Default for Doctor {
                         Check::new("llvm-readobj", Some(VersionCheck::new("--version", 1, 4))),
                         Check::new("lld", Some(VersionCheck::new("-flavor ld --version", 0, 1))),
                         Check::new("lld-link", Some(VersionCheck::new("--version", 0, 1))),
+                        Check::new("clang-cl", Some(VersionCheck::new("--version", 0, 2))),
+                        Check::new("llvm-rc", None),
                         Check::new("lldb", Some(VersionCheck::new("--version", 0, 2))),
                         Check::new("lldb-server", None),
                     ],
@@ -66,8 +68,8 @@ impl Default for Doctor {
                 Group {
                     name: "linux",
                     checks: vec![Check::new(
-                        "mksquashfs",
-                        Some(VersionCheck::new("-version", 0, 2)),
+                        "flatpak",
+                        Some(VersionCheck::new("--version", 0, 1)),
                     )],
                 },
             ],
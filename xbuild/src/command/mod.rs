@@ -1,7 +1,7 @@
 use crate::cargo::CrateType;
 use crate::devices::Device;
-use crate::{BuildEnv, CompileTarget, Platform};
-use anyhow::Result;
+use crate::{BuildEnv, CompileTarget, Format, Platform, Store};
+use anyhow::{Context, Result};
 use app_store_connect::UnifiedApiKey;
 use std::path::Path;
 
@@ -26,6 +26,24 @@ pub fn devices() -> Result<()> {
     Ok(())
 }
 
+pub fn inspect(path: &Path) -> Result<()> {
+    let cert = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("pri") => {
+            let pri = pri::PriFile::open(path)?;
+            pri.dump(&mut std::io::stdout().lock())?;
+            return Ok(());
+        }
+        Some("dll" | "exe") => msix::authenticode::verify(path)?,
+        Some("appx" | "msix" | "msixbundle") => msix::Msix::verify(path)?,
+        _ => anyhow::bail!("unsupported file {}", path.display()),
+    };
+    println!(
+        "signature ok, signed by certificate {:x}",
+        cert.tbs_certificate.serial_number
+    );
+    Ok(())
+}
+
 pub fn run(env: &BuildEnv, launch_args: &[String]) -> Result<()> {
     let out = env.executable();
     if let Some(device) = env.target().device() {
@@ -58,6 +76,8 @@ pub fn lldb(env: &BuildEnv) -> Result<()> {
         };
         let lldb_server = match target.platform() {
             Platform::Android => Some(env.lldb_server(target)?),
+            // A local lldb-server can only be copied to remote hosts of the same platform and arch
+            _ if !device.is_host() && target.is_host()? => env.lldb_server(target).ok(),
             _ => None,
         };
         device.lldb(env, &executable, lldb_server.as_deref())?;
@@ -67,6 +87,28 @@ pub fn lldb(env: &BuildEnv) -> Result<()> {
     Ok(())
 }
 
+pub fn publish(env: &BuildEnv) -> Result<()> {
+    let out = env.output();
+    match env.target().store() {
+        Some(Store::Apple) => {
+            anyhow::ensure!(
+                matches!(env.target().format(), Format::Ipa | Format::Pkg),
+                "uploading {} to the apple store is not supported",
+                env.target().format()
+            );
+            let api_key = env
+                .target()
+                .api_key()
+                .context("uploading to the apple store requires an --api-key")?;
+            let info = env.info_plist(env.target().platform())?;
+            appbundle::upload(&out, api_key, &info)?;
+        }
+        Some(store) => anyhow::bail!("publishing to the {} store is not supported", store),
+        None => anyhow::bail!("publishing requires a --store"),
+    }
+    Ok(())
+}
+
 pub fn create_apple_api_key(
     issuer_id: &str,
     key_id: &str,
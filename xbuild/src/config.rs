@@ -3,8 +3,10 @@ use crate::{Opt, Platform};
 use anyhow::{Context, Result};
 use apk::manifest::{Activity, AndroidManifest, IntentFilter, MetaData};
 use apk::VersionCode;
-use appbundle::InfoPlist;
-use msix::manifest::Application;
+use appbundle::{DmgLayout, InfoPlist};
+use appimage::{Compression, DesktopEntry, FlatpakConfig, Metainfo, PackageConfig};
+use msix::manifest::{Application, Extension};
+use msix::res::ExeManifest;
 use msix::AppxManifest;
 use serde::Deserialize;
 use std::collections::HashMap;
@@ -235,18 +237,9 @@ impl Config {
         info.cf_bundle_supported_platforms
             .get_or_insert_with(|| vec!["iPhoneOS".into()]);
 
+        // The remaining `DT*` keys are derived from the sdk, see `BuildEnv::info_plist`.
         info.dt_compiler
             .get_or_insert_with(|| "com.apple.compilers.llvm.clang.1_0".into());
-        info.dt_platform_build.get_or_insert_with(|| "19C51".into());
-        info.dt_platform_name
-            .get_or_insert_with(|| "iphoneos".into());
-        info.dt_platform_version
-            .get_or_insert_with(|| "15.2".into());
-        info.dt_sdk_build.get_or_insert_with(|| "19C51".into());
-        info.dt_sdk_name
-            .get_or_insert_with(|| "iphoneos15.2".into());
-        info.dt_xcode.get_or_insert_with(|| "1321".into());
-        info.dt_xcode_build.get_or_insert_with(|| "13C100".into());
 
         info.ls_requires_ios.get_or_insert(true);
 
@@ -276,6 +269,55 @@ impl Config {
             .get_or_insert_with(|| package_version.clone());
         info.ls_minimum_system_version
             .get_or_insert_with(|| "10.11".to_string());
+        info.dt_compiler
+            .get_or_insert_with(|| "com.apple.compilers.llvm.clang.1_0".into());
+
+        // linux
+        let android_package = self.android.manifest.package.as_deref().unwrap();
+        let linux = &mut self.linux;
+        linux
+            .desktop
+            .name
+            .get_or_insert_with(|| manifest_package.name.clone());
+        linux.metainfo.id.get_or_insert_with(|| {
+            // flatpak requires at least three components
+            if android_package.split('.').count() < 3 {
+                format!(
+                    "{}.{}",
+                    android_package,
+                    manifest_package.name.replace('-', "_")
+                )
+            } else {
+                android_package.to_string()
+            }
+        });
+        linux
+            .metainfo
+            .version
+            .get_or_insert_with(|| package_version.clone());
+        if !package_description.is_empty() {
+            linux
+                .metainfo
+                .summary
+                .get_or_insert_with(|| package_description.clone());
+            linux
+                .package
+                .description
+                .get_or_insert_with(|| package_description.clone());
+        }
+        let package = &mut linux.package;
+        package
+            .version
+            .get_or_insert_with(|| package_version.clone());
+        if package.maintainer.is_none() {
+            package.maintainer = linux.metainfo.developer_name.clone();
+        }
+        if package.homepage.is_none() {
+            package.homepage = linux.metainfo.homepage.clone();
+        }
+        if package.license.is_none() {
+            package.license = linux.metainfo.project_license.clone();
+        }
 
         // windows
         // self.windows
@@ -290,9 +332,14 @@ impl Config {
         //     .identity
         //     .version
         //     .get_or_insert(package_version);
-        assert!(self.windows.manifest.identity.version.is_empty());
-        // Windows wants a.b.c.d:
-        self.windows.manifest.identity.version = format!("{}.0", package_version);
+        if self.windows.manifest.identity.name.is_empty() {
+            // identity names only allow alphanumerics, periods and dashes
+            self.windows.manifest.identity.name = manifest_package.name.replace('_', "-");
+        }
+        if self.windows.manifest.identity.version.is_empty() {
+            // Windows wants a.b.c.d:
+            self.windows.manifest.identity.version = format!("{}.0", package_version);
+        }
         self.windows
             .manifest
             .properties
@@ -327,6 +374,36 @@ impl Config {
             log::info!("No target device family configured. Using default {app:?}");
             self.windows.manifest.applications.application.push(app);
         }
+        let properties = &self.windows.manifest.properties;
+        let display_name = if properties.display_name.is_empty() {
+            manifest_package.name.clone()
+        } else {
+            properties.display_name.clone()
+        };
+        let description = properties
+            .description
+            .clone()
+            .unwrap_or_else(|| display_name.clone());
+        for app in &mut self.windows.manifest.applications.application {
+            let visual_elements = &mut app.visual_elements;
+            if visual_elements.display_name.is_empty() {
+                visual_elements.display_name = display_name.clone();
+            }
+            if visual_elements.description.is_empty() {
+                visual_elements.description = description.clone();
+            }
+            if visual_elements.background_color.is_empty() {
+                visual_elements.background_color = "transparent".into();
+            }
+            for extension in &mut app.extensions {
+                if let Extension::StartupTask(task) = extension {
+                    if task.executable.is_none() {
+                        task.executable = app.executable.clone();
+                        task.entry_point = app.entry_point.clone();
+                    }
+                }
+            }
+        }
 
         Ok(())
     }
@@ -500,6 +577,8 @@ pub struct MacosConfig {
     #[serde(flatten)]
     generic: GenericConfig,
     pub info: InfoPlist,
+    #[serde(default)]
+    pub dmg: DmgLayout,
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
@@ -507,6 +586,25 @@ pub struct MacosConfig {
 pub struct LinuxConfig {
     #[serde(flatten)]
     generic: GenericConfig,
+    /// AppImage update information, for example
+    /// `gh-releases-zsync|user|repo|latest|App-*-x86_64.AppImage.zsync`.
+    pub update_information: Option<String>,
+    /// Compression of the AppImage file system, `gzip` or `zstd`. `zstd`
+    /// requires a `runtime` built with zstd support.
+    #[serde(default)]
+    pub compression: Compression,
+    /// AppImage runtime to use instead of the embedded one, relative to
+    /// the package root.
+    pub runtime: Option<PathBuf>,
+    #[serde(default)]
+    pub desktop: DesktopEntry,
+    #[serde(default)]
+    pub metainfo: Metainfo,
+    #[serde(default)]
+    pub flatpak: FlatpakConfig,
+    /// Metadata of deb and rpm packages.
+    #[serde(default)]
+    pub package: PackageConfig,
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
@@ -515,4 +613,7 @@ pub struct WindowsConfig {
     #[serde(flatten)]
     generic: GenericConfig,
     pub manifest: AppxManifest,
+    /// Application manifest embedded in the executable.
+    #[serde(default)]
+    pub exe_manifest: ExeManifest,
 }
@@ -0,0 +1,564 @@
+//! Client for the smart socket protocol of the adb server.
+//!
+//! <https://android.googlesource.com/platform/packages/modules/adb/+/refs/heads/main/OVERVIEW.TXT>
+//!
+//! <https://android.googlesource.com/platform/packages/modules/adb/+/refs/heads/main/SERVICES.TXT>
+//!
+//! <https://android.googlesource.com/platform/packages/modules/adb/+/refs/heads/main/SYNC.TXT>
+use anyhow::{Context, Result};
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DEFAULT_PORT: u16 = 5037;
+/// Maximum size of a sync `DATA` packet.
+const SYNC_DATA_MAX: usize = 64 * 1024;
+
+const SHELL_STDOUT: u8 = 1;
+const SHELL_STDERR: u8 = 2;
+const SHELL_EXIT: u8 = 3;
+
+/// Appended to commands run with `shell:`, which doesn't report the exit
+/// code. The newline separates it from output without a trailing newline.
+const STATUS_SUFFIX: &str = "; __xstatus=$?; echo; echo __XSTATUS$__xstatus";
+const STATUS_MARKER: &[u8] = b"\n__XSTATUS";
+
+#[derive(Clone, Debug)]
+pub(crate) struct AdbClient {
+    addr: SocketAddr,
+}
+
+impl AdbClient {
+    pub fn new(port: u16) -> Self {
+        Self {
+            addr: (Ipv4Addr::LOCALHOST, port).into(),
+        }
+    }
+
+    /// Port of the adb server, configurable with `ANDROID_ADB_SERVER_PORT`
+    /// like for the `adb` binary.
+    pub fn server_port() -> u16 {
+        std::env::var("ANDROID_ADB_SERVER_PORT")
+            .ok()
+            .and_then(|port| port.parse().ok())
+            .unwrap_or(DEFAULT_PORT)
+    }
+
+    fn connect(&self) -> Result<TcpStream> {
+        TcpStream::connect(self.addr)
+            .with_context(|| format!("failed to connect to the adb server at {}", self.addr))
+    }
+
+    /// Sends a request and checks the status of the response.
+    fn request(&self, stream: &mut TcpStream, request: &str) -> Result<()> {
+        write!(stream, "{:04x}{}", request.len(), request)?;
+        read_status(stream).with_context(|| format!("adb request `{}` failed", request))
+    }
+
+    /// Connects to the server and switches to the transport of `serial`, the
+    /// following requests are handled by the adb daemon on the device.
+    fn transport(&self, serial: &str) -> Result<TcpStream> {
+        let mut stream = self.connect()?;
+        self.request(&mut stream, &format!("host:transport:{}", serial))?;
+        Ok(stream)
+    }
+
+    pub fn version(&self) -> Result<u32> {
+        let mut stream = self.connect()?;
+        self.request(&mut stream, "host:version")?;
+        Ok(u32::from_str_radix(&read_string(&mut stream)?, 16)?)
+    }
+
+    /// Returns the serial and state of the connected devices.
+    pub fn devices(&self) -> Result<Vec<(String, String)>> {
+        let mut stream = self.connect()?;
+        self.request(&mut stream, "host:devices")?;
+        Ok(read_string(&mut stream)?
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .map(|(serial, state)| (serial.to_string(), state.to_string()))
+            .collect())
+    }
+
+    /// Returns the features supported by the device and the adb server, for
+    /// example `shell_v2`.
+    pub fn features(&self, serial: &str) -> Result<Vec<String>> {
+        let mut stream = self.connect()?;
+        self.request(&mut stream, &format!("host-serial:{}:features", serial))?;
+        Ok(read_string(&mut stream)?
+            .split(',')
+            .map(|feature| feature.to_string())
+            .collect())
+    }
+
+    /// Starts `command` with the shell protocol, which unlike `shell:`
+    /// separates stdout from stderr and reports the exit code. Devices that
+    /// don't support it use `shell:`.
+    pub fn shell_stream(&self, serial: &str, command: &str) -> Result<ShellStream> {
+        let shell_v2 = self.features(serial)?.iter().any(|f| f == "shell_v2");
+        self.open_shell(serial, command, shell_v2)
+    }
+
+    fn open_shell(&self, serial: &str, command: &str, shell_v2: bool) -> Result<ShellStream> {
+        let mut stream = self.transport(serial)?;
+        if shell_v2 {
+            self.request(&mut stream, &format!("shell,v2,raw:{}", command))?;
+        } else {
+            self.request(&mut stream, &format!("shell:{}", command))?;
+        }
+        Ok(ShellStream {
+            stream,
+            shell_v2,
+            remaining: 0,
+            stderr: vec![],
+            exit_code: None,
+        })
+    }
+
+    pub fn shell(&self, serial: &str, command: &str) -> Result<ShellOutput> {
+        let shell_v2 = self.features(serial)?.iter().any(|f| f == "shell_v2");
+        if !shell_v2 {
+            let mut shell =
+                self.open_shell(serial, &format!("{}{}", command, STATUS_SUFFIX), false)?;
+            let mut stdout = vec![];
+            shell.read_to_end(&mut stdout)?;
+            // older devices run the command in a pty, which translates newlines
+            let mut stdout = stdout
+                .iter()
+                .enumerate()
+                .filter(|(i, b)| **b != b'\r' || stdout.get(i + 1) != Some(&b'\n'))
+                .map(|(_, b)| *b)
+                .collect::<Vec<_>>();
+            let start = stdout
+                .windows(STATUS_MARKER.len())
+                .rposition(|w| w == STATUS_MARKER)
+                .with_context(|| format!("`{}` exited without exit code", command))?;
+            let status = std::str::from_utf8(&stdout[start + STATUS_MARKER.len()..])?
+                .trim_end()
+                .parse()
+                .with_context(|| format!("`{}` exited without exit code", command))?;
+            stdout.truncate(start);
+            return Ok(ShellOutput {
+                status,
+                stdout,
+                stderr: vec![],
+            });
+        }
+        let mut shell = self.open_shell(serial, command, true)?;
+        let mut stdout = vec![];
+        shell.read_to_end(&mut stdout)?;
+        let status = shell
+            .exit_code
+            .with_context(|| format!("`{}` exited without exit code", command))?;
+        Ok(ShellOutput {
+            status,
+            stdout,
+            stderr: shell.stderr,
+        })
+    }
+
+    /// Pushes `data` to the file `remote` with permissions `mode`.
+    pub fn push(&self, serial: &str, data: &mut impl Read, remote: &str, mode: u32) -> Result<()> {
+        let mut stream = self.transport(serial)?;
+        self.request(&mut stream, "sync:")?;
+        write_sync(
+            &mut stream,
+            b"SEND",
+            format!("{},{}", remote, mode).as_bytes(),
+        )?;
+        let mut buf = vec![0; SYNC_DATA_MAX];
+        loop {
+            let n = data.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            write_sync(&mut stream, b"DATA", &buf[..n])?;
+        }
+        let mtime = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+        stream.write_all(b"DONE")?;
+        stream.write_all(&mtime.to_le_bytes())?;
+        let (id, len) = read_sync(&mut stream)?;
+        match &id {
+            b"OKAY" => {}
+            b"FAIL" => anyhow::bail!(
+                "failed to push {}: {}",
+                remote,
+                read_sync_message(&mut stream, len)?
+            ),
+            _ => anyhow::bail!("unexpected sync response {:?}", id),
+        }
+        write_sync(&mut stream, b"QUIT", &[])?;
+        Ok(())
+    }
+
+    /// Returns the content of the file `remote`.
+    #[allow(dead_code)] // xbuild doesn't pull files from devices yet
+    pub fn pull(&self, serial: &str, remote: &str) -> Result<Vec<u8>> {
+        let mut stream = self.transport(serial)?;
+        self.request(&mut stream, "sync:")?;
+        write_sync(&mut stream, b"RECV", remote.as_bytes())?;
+        let mut data = vec![];
+        loop {
+            let (id, len) = read_sync(&mut stream)?;
+            match &id {
+                b"DATA" => {
+                    let start = data.len();
+                    data.resize(start + len as usize, 0);
+                    stream.read_exact(&mut data[start..])?;
+                }
+                b"DONE" => break,
+                b"FAIL" => anyhow::bail!(
+                    "failed to pull {}: {}",
+                    remote,
+                    read_sync_message(&mut stream, len)?
+                ),
+                _ => anyhow::bail!("unexpected sync response {:?}", id),
+            }
+        }
+        write_sync(&mut stream, b"QUIT", &[])?;
+        Ok(data)
+    }
+
+    /// Forwards the host socket `local` to `remote` on the device, for
+    /// example `tcp:0` to `tcp:10086`. Returns the allocated port if `local`
+    /// is `tcp:0`.
+    pub fn forward(&self, serial: &str, local: &str, remote: &str) -> Result<Option<u16>> {
+        let mut stream = self.connect()?;
+        self.request(
+            &mut stream,
+            &format!("host-serial:{}:forward:{};{}", serial, local, remote),
+        )?;
+        // the first status is for the host service, the second for the forward
+        read_status(&mut stream)?;
+        if local == "tcp:0" {
+            Ok(Some(read_string(&mut stream)?.parse()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Forwards the device socket `remote` to `local` on the host.
+    pub fn reverse(&self, serial: &str, remote: &str, local: &str) -> Result<()> {
+        let mut stream = self.transport(serial)?;
+        self.request(
+            &mut stream,
+            &format!("reverse:forward:{};{}", remote, local),
+        )?;
+        read_status(&mut stream)
+    }
+}
+
+pub(crate) struct ShellOutput {
+    pub status: u8,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// Stdout of a running shell command. With the shell protocol stderr is
+/// collected and the exit code is available after stdout reached the end.
+pub(crate) struct ShellStream {
+    stream: TcpStream,
+    /// Whether the stream uses the shell protocol or is the raw stdout.
+    shell_v2: bool,
+    /// Remaining bytes of the current stdout packet.
+    remaining: usize,
+    stderr: Vec<u8>,
+    exit_code: Option<u8>,
+}
+
+impl Read for ShellStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if !self.shell_v2 {
+            return self.stream.read(buf);
+        }
+        while self.remaining == 0 {
+            if self.exit_code.is_some() {
+                return Ok(0);
+            }
+            let mut header = [0; 5];
+            match self.stream.read_exact(&mut header) {
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(0),
+                res => res?,
+            }
+            let len = u32::from_le_bytes(header[1..].try_into().unwrap()) as usize;
+            match header[0] {
+                SHELL_STDOUT => self.remaining = len,
+                SHELL_STDERR => {
+                    let start = self.stderr.len();
+                    self.stderr.resize(start + len, 0);
+                    self.stream.read_exact(&mut self.stderr[start..])?;
+                }
+                id => {
+                    let mut data = vec![0; len];
+                    self.stream.read_exact(&mut data)?;
+                    if id == SHELL_EXIT {
+                        self.exit_code = data.first().copied();
+                    }
+                }
+            }
+        }
+        let len = buf.len().min(self.remaining);
+        let n = self.stream.read(&mut buf[..len])?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= n;
+        Ok(n)
+    }
+}
+
+fn read_status(stream: &mut TcpStream) -> Result<()> {
+    let mut status = [0; 4];
+    stream.read_exact(&mut status)?;
+    match &status {
+        b"OKAY" => Ok(()),
+        b"FAIL" => anyhow::bail!("{}", read_string(stream)?),
+        _ => anyhow::bail!("unexpected adb status {:?}", status),
+    }
+}
+
+/// Reads a string prefixed with its length as four hex digits.
+fn read_string(stream: &mut TcpStream) -> Result<String> {
+    let mut len = [0; 4];
+    stream.read_exact(&mut len)?;
+    let len = usize::from_str_radix(std::str::from_utf8(&len)?, 16)?;
+    let mut buf = vec![0; len];
+    stream.read_exact(&mut buf)?;
+    Ok(String::from_utf8(buf)?)
+}
+
+fn write_sync(stream: &mut TcpStream, id: &[u8; 4], data: &[u8]) -> Result<()> {
+    stream.write_all(id)?;
+    stream.write_all(&(data.len() as u32).to_le_bytes())?;
+    stream.write_all(data)?;
+    Ok(())
+}
+
+fn read_sync(stream: &mut TcpStream) -> Result<([u8; 4], u32)> {
+    let mut header = [0; 8];
+    stream.read_exact(&mut header)?;
+    Ok((
+        header[..4].try_into().unwrap(),
+        u32::from_le_bytes(header[4..].try_into().unwrap()),
+    ))
+}
+
+fn read_sync_message(stream: &mut TcpStream, len: u32) -> Result<String> {
+    let mut buf = vec![0; len as usize];
+    stream.read_exact(&mut buf)?;
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    pub const SERIAL: &str = "emulator-5554";
+    /// Device without support for the shell protocol.
+    pub const LEGACY_SERIAL: &str = "emulator-5556";
+
+    type Files = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    /// Shell command handler returning the exit code and stdout.
+    pub type Shell = fn(&str) -> (u8, Vec<u8>);
+
+    /// Starts an adb server with a single device `SERIAL` and returns a
+    /// client connected to it and the files on the device.
+    pub fn fake_adb(shell: Shell) -> (AdbClient, Files) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let client = AdbClient::new(listener.local_addr().unwrap().port());
+        let files = Files::default();
+        let device_files = files.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let files = device_files.clone();
+                std::thread::spawn(move || handle(stream.unwrap(), shell, files));
+            }
+        });
+        (client, files)
+    }
+
+    fn read_request(stream: &mut TcpStream) -> String {
+        read_string(stream).unwrap()
+    }
+
+    fn okay(stream: &mut TcpStream) {
+        stream.write_all(b"OKAY").unwrap();
+    }
+
+    fn write_string(stream: &mut TcpStream, s: &str) {
+        write!(stream, "{:04x}{}", s.len(), s).unwrap();
+    }
+
+    fn handle(mut stream: TcpStream, shell: Shell, files: Files) {
+        let request = read_request(&mut stream);
+        let forward = format!("host-serial:{}:forward:", SERIAL);
+        if request == "host:version" {
+            okay(&mut stream);
+            write_string(&mut stream, "0029");
+        } else if request == "host:devices" {
+            okay(&mut stream);
+            write_string(&mut stream, &format!("{}\tdevice\n", SERIAL));
+        } else if let Some(sockets) = request.strip_prefix(&forward) {
+            okay(&mut stream);
+            okay(&mut stream);
+            if sockets.starts_with("tcp:0;") {
+                write_string(&mut stream, "34567");
+            }
+        } else if request == format!("host-serial:{}:features", SERIAL) {
+            okay(&mut stream);
+            write_string(&mut stream, "shell_v2,cmd,stat_v2");
+        } else if request == format!("host-serial:{}:features", LEGACY_SERIAL) {
+            okay(&mut stream);
+            write_string(&mut stream, "cmd");
+        } else if request == format!("host:transport:{}", SERIAL)
+            || request == format!("host:transport:{}", LEGACY_SERIAL)
+        {
+            okay(&mut stream);
+            handle_device(stream, shell, files);
+        } else {
+            stream.write_all(b"FAIL").unwrap();
+            write_string(&mut stream, "unknown host service");
+        }
+    }
+
+    fn handle_device(mut stream: TcpStream, shell: Shell, files: Files) {
+        let request = read_request(&mut stream);
+        if let Some(command) = request.strip_prefix("shell,v2,raw:") {
+            okay(&mut stream);
+            let (status, stdout) = shell(command);
+            for (id, data) in [
+                (SHELL_STDOUT, &stdout[..stdout.len() / 2]),
+                (SHELL_STDERR, b"warning".as_slice()),
+                (SHELL_STDOUT, &stdout[stdout.len() / 2..]),
+                (SHELL_EXIT, &[status]),
+            ] {
+                stream.write_all(&[id]).unwrap();
+                stream
+                    .write_all(&(data.len() as u32).to_le_bytes())
+                    .unwrap();
+                stream.write_all(data).unwrap();
+            }
+        } else if let Some(command) = request.strip_prefix("shell:") {
+            okay(&mut stream);
+            let echo_status = command.strip_suffix(STATUS_SUFFIX);
+            let (status, stdout) = shell(echo_status.unwrap_or(command));
+            stream.write_all(&stdout).unwrap();
+            if echo_status.is_some() {
+                write!(stream, "\r\n__XSTATUS{}\r\n", status).unwrap();
+            }
+        } else if request == "sync:" {
+            okay(&mut stream);
+            handle_sync(stream, files);
+        } else if request.starts_with("reverse:forward:") {
+            okay(&mut stream);
+            okay(&mut stream);
+        } else {
+            stream.write_all(b"FAIL").unwrap();
+            write_string(&mut stream, "unknown device service");
+        }
+    }
+
+    fn handle_sync(mut stream: TcpStream, files: Files) {
+        loop {
+            let (id, len) = read_sync(&mut stream).unwrap();
+            let mut data = vec![0; len as usize];
+            stream.read_exact(&mut data).unwrap();
+            let data = String::from_utf8(data).unwrap();
+            match &id {
+                b"SEND" => {
+                    let (path, mode) = data.rsplit_once(',').unwrap();
+                    assert!(mode.parse::<u32>().is_ok());
+                    let mut file = vec![];
+                    loop {
+                        let (id, len) = read_sync(&mut stream).unwrap();
+                        if &id == b"DONE" {
+                            break;
+                        }
+                        assert_eq!(&id, b"DATA");
+                        let start = file.len();
+                        file.resize(start + len as usize, 0);
+                        stream.read_exact(&mut file[start..]).unwrap();
+                    }
+                    files.lock().unwrap().insert(path.to_string(), file);
+                    write_sync(&mut stream, b"OKAY", &[]).unwrap();
+                }
+                b"RECV" => match files.lock().unwrap().get(&data) {
+                    Some(file) => {
+                        for chunk in file.chunks(3) {
+                            write_sync(&mut stream, b"DATA", chunk).unwrap();
+                        }
+                        stream.write_all(b"DONE\0\0\0\0").unwrap();
+                    }
+                    None => {
+                        write_sync(&mut stream, b"FAIL", b"No such file or directory").unwrap();
+                    }
+                },
+                b"QUIT" => return,
+                _ => panic!("unexpected sync request {:?}", id),
+            }
+        }
+    }
+
+    fn shell(command: &str) -> (u8, Vec<u8>) {
+        match command {
+            "getprop ro.product.cpu.abi" => (0, b"arm64-v8a\n".to_vec()),
+            "printf 1" => (0, b"1".to_vec()),
+            _ => (127, vec![]),
+        }
+    }
+
+    #[test]
+    fn test_host_services() -> Result<()> {
+        let (client, _) = fake_adb(shell);
+        assert_eq!(client.version()?, 0x29);
+        assert_eq!(
+            client.devices()?,
+            [(SERIAL.to_string(), "device".to_string())]
+        );
+        assert_eq!(client.forward(SERIAL, "tcp:0", "tcp:10086")?, Some(34567));
+        assert_eq!(client.forward(SERIAL, "tcp:1234", "tcp:10086")?, None);
+        client.reverse(SERIAL, "tcp:8080", "tcp:8080")?;
+        let err = client.shell("unknown", "true").err().unwrap();
+        assert!(format!("{:#}", err).contains("unknown host service"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_shell() -> Result<()> {
+        let (client, _) = fake_adb(shell);
+        let output = client.shell(SERIAL, "getprop ro.product.cpu.abi")?;
+        assert_eq!(output.status, 0);
+        assert_eq!(output.stdout, b"arm64-v8a\n");
+        assert_eq!(output.stderr, b"warning");
+        assert_eq!(client.shell(SERIAL, "false")?.status, 127);
+
+        let output = client.shell(LEGACY_SERIAL, "getprop ro.product.cpu.abi")?;
+        assert_eq!(output.status, 0);
+        assert_eq!(output.stdout, b"arm64-v8a\n");
+        assert_eq!(client.shell(LEGACY_SERIAL, "false")?.status, 127);
+        let output = client.shell(LEGACY_SERIAL, "printf 1")?;
+        assert_eq!(output.status, 0);
+        assert_eq!(output.stdout, b"1");
+        let mut stdout = String::new();
+        client
+            .shell_stream(LEGACY_SERIAL, "getprop ro.product.cpu.abi")?
+            .read_to_string(&mut stdout)?;
+        assert_eq!(stdout, "arm64-v8a\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync() -> Result<()> {
+        let (client, files) = fake_adb(shell);
+        let data = (0..SYNC_DATA_MAX + 10).map(|i| i as u8).collect::<Vec<_>>();
+        client.push(SERIAL, &mut &data[..], "/data/local/tmp/app.apk", 0o644)?;
+        assert_eq!(files.lock().unwrap()["/data/local/tmp/app.apk"], data);
+        assert_eq!(client.pull(SERIAL, "/data/local/tmp/app.apk")?, data);
+        assert!(client.pull(SERIAL, "/data/local/tmp/missing").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,414 @@
+use self::client::{AdbClient, ShellStream};
+use crate::config::AndroidDebugConfig;
+use crate::devices::{Backend, Device};
+use crate::{Arch, Platform};
+use anyhow::{Context, Result};
+use apk::Apk;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+mod client;
+
+/// Talks to the adb server directly, the `adb` binary is only used to start
+/// the server.
+#[derive(Clone, Debug)]
+pub(crate) struct Adb(AdbClient);
+
+impl Adb {
+    pub fn which() -> Result<PathBuf> {
+        const ADB: &str = exe!("adb");
+
+        match which::which(ADB) {
+            Err(which::Error::CannotFindBinaryPath) => {
+                let sdk_path = {
+                    let sdk_path = std::env::var("ANDROID_SDK_ROOT").ok();
+                    if sdk_path.is_some() {
+                        eprintln!(
+                            "Warning: Environment variable ANDROID_SDK_ROOT is deprecated \
+                    (https://developer.android.com/studio/command-line/variables#envar). \
+                    It will be used until it is unset and replaced by ANDROID_HOME."
+                        );
+                    }
+
+                    PathBuf::from(
+                        sdk_path
+                            .or_else(|| std::env::var("ANDROID_HOME").ok())
+                            .context(
+                            "Cannot find `adb` on in PATH nor is ANDROID_HOME/ANDROID_SDK_ROOT set",
+                        )?,
+                    )
+                };
+
+                let adb_path = sdk_path.join("platform-tools").join(ADB);
+                anyhow::ensure!(
+                    adb_path.exists(),
+                    "Expected `adb` at `{}`",
+                    adb_path.display()
+                );
+                Ok(adb_path)
+            }
+            r => r.context("Could not find `adb` in PATH"),
+        }
+    }
+
+    pub fn new() -> Result<Self> {
+        let client = AdbClient::new(AdbClient::server_port());
+        if client.version().is_err() {
+            let status = Command::new(Self::which()?).arg("start-server").status()?;
+            anyhow::ensure!(status.success(), "adb start-server failed");
+        }
+        Ok(Self(client))
+    }
+
+    /// Runs `command` in the shell of the device and returns its stdout.
+    fn shell(&self, device: &str, command: &str) -> Result<String> {
+        let output = self.0.shell(device, command)?;
+        anyhow::ensure!(
+            output.status == 0,
+            "`adb shell {}` exited with code {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        Ok(String::from_utf8(output.stdout)?.trim().to_string())
+    }
+
+    /// Pushes the file at `path` to `/data/local/tmp` and returns the path on
+    /// the device.
+    fn push(&self, device: &str, path: &Path, mode: u32) -> Result<String> {
+        let file_name = path.file_name().unwrap().to_str().unwrap();
+        let remote = format!("/data/local/tmp/{}", file_name);
+        let mut file = File::open(path)?;
+        self.0.push(device, &mut file, &remote, mode)?;
+        Ok(remote)
+    }
+
+    pub fn devices(&self, devices: &mut Vec<Device>) -> Result<()> {
+        for (id, _state) in self.0.devices()? {
+            devices.push(Device {
+                backend: Backend::Adb(self.clone()),
+                id,
+            });
+        }
+        Ok(())
+    }
+
+    fn getprop(&self, device: &str, prop: &str) -> Result<String> {
+        self.shell(device, &format!("getprop {}", prop))
+    }
+
+    fn install(&self, device: &str, path: &Path) -> Result<()> {
+        let remote = self.push(device, path, 0o644)?;
+        self.shell(device, &format!("pm install {}", remote))?;
+        Ok(())
+    }
+
+    /// To run a native activity use "android.app.NativeActivity" as the activity name
+    fn start(
+        &self,
+        device: &str,
+        package: &str,
+        activity: &str,
+        launch_args: &[String],
+    ) -> Result<()> {
+        // Quote arguments for `am` so that they don't get split by the shell.
+        let launch_args = shlex::try_join(launch_args.iter().map(String::as_str))
+            .context("Failed to re-quote launch arguments")?;
+        self.shell(
+            device,
+            &format!(
+                "am start -a android.intent.action.MAIN -n {}/{} {}",
+                package, activity, launch_args
+            ),
+        )?;
+        Ok(())
+    }
+
+    fn stop(&self, device: &str, id: &str) -> Result<()> {
+        self.shell(device, &format!("am force-stop {}", id))?;
+        Ok(())
+    }
+
+    fn forward_reverse(&self, device: &str, debug_config: &AndroidDebugConfig) -> Result<()> {
+        for (local, remote) in &debug_config.forward {
+            self.0.forward(device, local, remote)?;
+        }
+        for (remote, local) in &debug_config.reverse {
+            self.0.reverse(device, remote, local)?;
+        }
+        Ok(())
+    }
+
+    fn set_debug_app(&self, device: &str, package: &str) -> Result<()> {
+        self.shell(device, &format!("am set-debug-app -w {}", package))?;
+        Ok(())
+    }
+
+    fn clear_debug_app(&self, device: &str) -> Result<()> {
+        self.shell(device, "am clear-debug-app")?;
+        Ok(())
+    }
+
+    /// Returns the current device date and time in logcat timestamp format
+    fn current_date_time(&self, device: &str) -> Result<String> {
+        self.shell(
+            device,
+            &format!("date {}", shlex::try_quote("+%m-%d %T.000")?),
+        )
+    }
+
+    fn uidof(&self, device: &str, id: &str) -> Result<u32> {
+        let output = self.shell(device, &format!("pm list package -U {}", id))?;
+        let (_package, uid) = output
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            // `pm list package` uses the id as a substring filter; make sure
+            // we select the right package in case it returns multiple matches:
+            .find(|(package, _uid)| package.strip_prefix("package:") == Some(id))
+            .with_context(|| format!("Could not find `package:{id}` in output `{output}`"))?;
+        let uid = uid
+            .strip_prefix("uid:")
+            .with_context(|| format!("Could not find `uid:` in output `{output}`"))?;
+        Ok(uid.parse()?)
+    }
+
+    fn logcat(&self, device: &str, uid: u32, since: &str) -> Result<Logcat> {
+        let stream = self.0.shell_stream(
+            device,
+            &format!("logcat -B -T {} --uid={}", shlex::try_quote(since)?, uid),
+        )?;
+        Ok(Logcat::new(stream))
+    }
+
+    pub fn forward(&self, device: &str, port: u16) -> Result<u16> {
+        self.0
+            .forward(device, "tcp:0", &format!("tcp:{}", port))?
+            .context("adb didn't allocate a port")
+    }
+
+    pub fn lldb(&self, device: &str, lldb_server: &Path, executable: &Path) -> Result<()> {
+        self.push(device, lldb_server, 0o755)?;
+        // the server is stopped when the connection is closed
+        let lldb_server = self.0.shell_stream(
+            device,
+            "cd /data/local/tmp && ./lldb-server platform --listen '*:10086' --server",
+        )?;
+        std::thread::sleep(Duration::from_millis(100));
+        self.forward(device, 10086)?;
+        let status = Command::new("lldb")
+            .arg("-O")
+            .arg("platform select remote-android")
+            .arg("-O")
+            .arg(format!("platform connect connect://{}:10086", device))
+            .arg(executable)
+            .status()?;
+        anyhow::ensure!(status.success(), "lldb exited with nonzero exit code.");
+        drop(lldb_server);
+        Ok(())
+    }
+
+    pub fn run(
+        &self,
+        device: &str,
+        path: &Path,
+        launch_args: &[String],
+        debug_config: &AndroidDebugConfig,
+        debug: bool,
+    ) -> Result<()> {
+        let entry_point = Apk::entry_point(path)?;
+        let package = &entry_point.package;
+        let activity = &entry_point.activity;
+        self.stop(device, package)?;
+        if debug {
+            self.set_debug_app(device, package)?;
+        } else {
+            self.clear_debug_app(device)?;
+        }
+        self.install(device, path)?;
+        self.forward_reverse(device, debug_config)?;
+        let since = self.current_date_time(device)?;
+        self.start(device, package, activity, launch_args)?;
+        let uid = self.uidof(device, package)?;
+        let logcat = self.logcat(device, uid, &since)?;
+        for line in logcat {
+            print!("{}", line);
+        }
+        Ok(())
+    }
+
+    pub fn name(&self, device: &str) -> Result<String> {
+        self.getprop(device, "ro.product.device")
+    }
+
+    pub fn platform(&self, _device: &str) -> Result<Platform> {
+        Ok(Platform::Android)
+    }
+
+    pub fn arch(&self, device: &str) -> Result<Arch> {
+        let arch = match self.getprop(device, "ro.product.cpu.abi")?.as_str() {
+            "arm64-v8a" => Arch::Arm64,
+            //"armeabi-v7a" => Arch::Arm,
+            "x86_64" => Arch::X64,
+            //"x86" => Arch::X86,
+            abi => anyhow::bail!("unrecognized abi {}", abi),
+        };
+        Ok(arch)
+    }
+
+    pub fn details(&self, device: &str) -> Result<String> {
+        let release = self.getprop(device, "ro.build.version.release")?;
+        let sdk = self.getprop(device, "ro.build.version.sdk")?;
+        Ok(format!("Android {} (API {})", release, sdk))
+    }
+}
+
+/// Log entries of `logcat -B`, formatted like `logcat -v color` without the
+/// date and time.
+pub struct Logcat {
+    reader: BufReader<ShellStream>,
+}
+
+impl Logcat {
+    fn new(stream: ShellStream) -> Self {
+        Self {
+            reader: BufReader::new(stream),
+        }
+    }
+
+    /// Reads a `logger_entry` followed by the priority, tag and message.
+    fn read_entry(&mut self) -> Result<String> {
+        let mut header = [0; 4];
+        self.reader.read_exact(&mut header)?;
+        let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+        // the header size is only set since v2 of the entry format
+        let header_size = match u16::from_le_bytes([header[2], header[3]]) {
+            0 => 20,
+            size => size as usize,
+        };
+        // the header contains at least the length, header size, pid and tid
+        anyhow::ensure!(
+            header_size >= 12,
+            "invalid log entry header size {}",
+            header_size
+        );
+        let mut header = vec![0; header_size - 4];
+        self.reader.read_exact(&mut header)?;
+        let pid = i32::from_le_bytes(header[..4].try_into().unwrap());
+        let tid = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        let mut payload = vec![0; len];
+        self.reader.read_exact(&mut payload)?;
+        let (priority, payload) = payload.split_first().context("empty log entry")?;
+        let mut strings = payload.split(|b| *b == 0);
+        let tag = String::from_utf8_lossy(strings.next().unwrap_or_default());
+        let message = String::from_utf8_lossy(strings.next().unwrap_or_default());
+        let (level, color) = match priority {
+            2 => ('V', None),
+            3 => ('D', Some(4)),
+            4 => ('I', Some(2)),
+            5 => ('W', Some(3)),
+            6 => ('E', Some(1)),
+            7 => ('F', Some(1)),
+            _ => ('?', None),
+        };
+        let mut lines = String::new();
+        for line in message.trim_end().lines() {
+            if let Some(color) = color {
+                lines.push_str(&format!("\x1b[3{}m", color));
+            }
+            lines.push_str(&format!("{:5} {:5} {} {}: {}", pid, tid, level, tag, line));
+            if color.is_some() {
+                lines.push_str("\x1b[0m");
+            }
+            lines.push('\n');
+        }
+        Ok(lines)
+    }
+}
+
+impl Iterator for Logcat {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_entry().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::adb::client::tests::{fake_adb, SERIAL};
+
+    fn log_entry(priority: u8, tag: &str, message: &str) -> Vec<u8> {
+        let mut payload = vec![priority];
+        payload.extend(tag.as_bytes());
+        payload.push(0);
+        payload.extend(message.as_bytes());
+        payload.push(0);
+        // v4 header with uid
+        let mut entry = vec![];
+        entry.extend((payload.len() as u16).to_le_bytes());
+        entry.extend(28u16.to_le_bytes());
+        for field in [42, 43, 0, 0, 0, 10123] {
+            entry.extend((field as u32).to_le_bytes());
+        }
+        entry.extend(payload);
+        entry
+    }
+
+    fn shell(command: &str) -> (u8, Vec<u8>) {
+        let stdout = match command {
+            "getprop ro.product.cpu.abi" => "x86_64\n",
+            "getprop ro.build.version.release" => "14\n",
+            "getprop ro.build.version.sdk" => "34\n",
+            "pm list package -U com.example.app" => {
+                "package:com.example.app.debug uid:10124\npackage:com.example.app uid:10123\n"
+            }
+            "logcat -B -T '01-01 00:00:00.000' --uid=10123" => {
+                let mut stdout = log_entry(4, "app", "started\nsecond line\n");
+                stdout.extend(log_entry(6, "app", "failed"));
+                return (0, stdout);
+            }
+            "logcat -B -T '01-01 00:00:00.000' --uid=10124" => {
+                // header size smaller than the pid and tid
+                let mut stdout = log_entry(4, "app", "invalid");
+                stdout[2..4].copy_from_slice(&8u16.to_le_bytes());
+                return (0, stdout);
+            }
+            _ => return (1, vec![]),
+        };
+        (0, stdout.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_adb() -> Result<()> {
+        let (client, _) = fake_adb(shell);
+        let adb = Adb(client);
+        let mut devices = vec![];
+        adb.devices(&mut devices)?;
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].id, SERIAL);
+        assert_eq!(adb.arch(SERIAL)?, Arch::X64);
+        assert_eq!(adb.details(SERIAL)?, "Android 14 (API 34)");
+        assert_eq!(adb.uidof(SERIAL, "com.example.app")?, 10123);
+        assert_eq!(adb.forward(SERIAL, 10086)?, 34567);
+        let err = adb.stop(SERIAL, "com.example.app").err().unwrap();
+        assert!(err.to_string().contains("exited with code 1"), "{}", err);
+
+        let lines = adb
+            .logcat(SERIAL, 10123, "01-01 00:00:00.000")?
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                "\x1b[32m   42    43 I app: started\x1b[0m\n\x1b[32m   42    43 I app: second line\x1b[0m\n",
+                "\x1b[31m   42    43 E app: failed\x1b[0m\n",
+            ]
+        );
+        let mut logcat = adb.logcat(SERIAL, 10124, "01-01 00:00:00.000")?;
+        assert!(logcat.read_entry().is_err());
+        Ok(())
+    }
+}
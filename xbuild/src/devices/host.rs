@@ -29,13 +29,7 @@ impl Host {
     pub fn details(&self) -> Result<String> {
         if cfg!(target_os = "linux") {
             let os_release = std::fs::read_to_string("/etc/os-release")?;
-            let mut distro = os_release
-                .lines()
-                .filter_map(|line| line.split_once('='))
-                .filter(|(k, _)| *k == "NAME")
-                .map(|(_, v)| v.trim_matches('"').to_string())
-                .next()
-                .unwrap_or_default();
+            let mut distro = os_release_name(&os_release);
             let output = Command::new("uname").arg("-r").output()?;
             anyhow::ensure!(output.status.success(), "uname failed");
             distro.push(' ');
@@ -56,3 +50,14 @@ impl Host {
         Ok(())
     }
 }
+
+/// Returns the `NAME` of the distribution from the contents of `/etc/os-release`.
+pub(crate) fn os_release_name(os_release: &str) -> String {
+    os_release
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .filter(|(k, _)| *k == "NAME")
+        .map(|(_, v)| v.trim_matches('"').to_string())
+        .next()
+        .unwrap_or_default()
+}
@@ -1,6 +1,7 @@
 use crate::devices::adb::Adb;
 use crate::devices::host::Host;
 use crate::devices::imd::IMobileDevice;
+use crate::devices::ssh::Ssh;
 use crate::{Arch, BuildEnv, Platform};
 use anyhow::Result;
 use std::path::Path;
@@ -8,12 +9,14 @@ use std::path::Path;
 pub(crate) mod adb;
 pub(crate) mod host;
 pub(crate) mod imd;
+pub(crate) mod ssh;
 
 #[derive(Clone, Debug)]
 enum Backend {
     Adb(Adb),
     Imd(IMobileDevice),
     Host(Host),
+    Ssh(Ssh),
 }
 
 #[derive(Clone, Debug)]
@@ -33,6 +36,7 @@ impl std::str::FromStr for Device {
             let backend = match backend {
                 "adb" => Backend::Adb(Adb::new()?),
                 "imd" => Backend::Imd(IMobileDevice::which()?),
+                "ssh" => Backend::Ssh(Ssh::which()?),
                 _ => anyhow::bail!("unsupported backend {}", backend),
             };
             Ok(Self {
@@ -51,6 +55,7 @@ impl std::fmt::Display for Device {
             Backend::Adb(_) => write!(f, "adb:{}", &self.id),
             Backend::Host(_) => write!(f, "{}", &self.id),
             Backend::Imd(_) => write!(f, "imd:{}", &self.id),
+            Backend::Ssh(_) => write!(f, "ssh:{}", &self.id),
         }
     }
 }
@@ -83,6 +88,7 @@ impl Device {
             Backend::Adb(adb) => adb.name(&self.id),
             Backend::Host(host) => host.name(),
             Backend::Imd(imd) => imd.name(&self.id),
+            Backend::Ssh(ssh) => ssh.name(&self.id),
         }
     }
 
@@ -91,6 +97,7 @@ impl Device {
             Backend::Adb(adb) => adb.platform(&self.id),
             Backend::Host(host) => host.platform(),
             Backend::Imd(imd) => imd.platform(&self.id),
+            Backend::Ssh(ssh) => ssh.platform(&self.id),
         }
     }
 
@@ -99,6 +106,7 @@ impl Device {
             Backend::Adb(adb) => adb.arch(&self.id),
             Backend::Host(host) => host.arch(),
             Backend::Imd(imd) => imd.arch(&self.id),
+            Backend::Ssh(ssh) => ssh.arch(&self.id),
         }
     }
 
@@ -107,6 +115,7 @@ impl Device {
             Backend::Adb(adb) => adb.details(&self.id),
             Backend::Host(host) => host.details(),
             Backend::Imd(imd) => imd.details(&self.id),
+            Backend::Ssh(ssh) => ssh.details(&self.id),
         }
     }
 
@@ -121,6 +130,7 @@ impl Device {
             ),
             Backend::Host(host) => host.run(path, launch_args),
             Backend::Imd(imd) => imd.run(env, &self.id, path, launch_args),
+            Backend::Ssh(ssh) => ssh.run(&self.id, path, launch_args),
         }?;
         Ok(())
     }
@@ -141,6 +151,7 @@ impl Device {
             }
             Backend::Host(host) => host.lldb(executable),
             Backend::Imd(imd) => imd.lldb(env, &self.id, executable),
+            Backend::Ssh(ssh) => ssh.lldb(&self.id, executable, lldb_server),
         }
     }
 
@@ -0,0 +1,231 @@
+use crate::devices::host::os_release_name;
+use crate::{Arch, Platform};
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+const REMOTE_DIR: &str = "/tmp/xbuild";
+const LLDB_PORT: u16 = 10086;
+const GDBSERVER_PORT: u16 = 10087;
+
+#[derive(Clone, Debug)]
+pub(crate) struct Ssh {
+    ssh: PathBuf,
+    scp: PathBuf,
+}
+
+impl Ssh {
+    pub fn which() -> Result<Self> {
+        Ok(Self {
+            ssh: which::which(exe!("ssh"))?,
+            scp: which::which(exe!("scp"))?,
+        })
+    }
+
+    fn ssh(&self, device: &str) -> Command {
+        let mut cmd = Command::new(&self.ssh);
+        cmd.arg(device);
+        cmd
+    }
+
+    fn output(&self, device: &str, command: &str) -> Result<String> {
+        let output = self.ssh(device).arg(command).output()?;
+        anyhow::ensure!(
+            output.status.success(),
+            "`{}` on {} failed: {}",
+            command,
+            device,
+            std::str::from_utf8(&output.stderr)?.trim()
+        );
+        Ok(std::str::from_utf8(&output.stdout)?.trim().to_string())
+    }
+
+    /// Copies the file or directory at `path` to the device and returns the
+    /// remote path.
+    fn upload(&self, device: &str, path: &Path) -> Result<String> {
+        let file_name = path
+            .file_name()
+            .context("invalid path")?
+            .to_str()
+            .context("invalid path")?;
+        let remote = format!("{}/{}", REMOTE_DIR, file_name);
+        self.output(
+            device,
+            &format!("mkdir -p {} && rm -rf {}", REMOTE_DIR, quote(&remote)),
+        )?;
+        let status = Command::new(&self.scp)
+            .arg("-q")
+            .arg("-r")
+            .arg(path)
+            .arg(format!("{}:{}", device, remote))
+            .status()?;
+        anyhow::ensure!(status.success(), "failed to run scp");
+        Ok(remote)
+    }
+
+    pub fn name(&self, device: &str) -> Result<String> {
+        self.output(device, "uname -n")
+    }
+
+    pub fn platform(&self, device: &str) -> Result<Platform> {
+        parse_platform(&self.output(device, "uname -s")?)
+    }
+
+    pub fn arch(&self, device: &str) -> Result<Arch> {
+        parse_arch(&self.output(device, "uname -m")?)
+    }
+
+    pub fn details(&self, device: &str) -> Result<String> {
+        // macOS doesn't have an `/etc/os-release`
+        let (mut details, version) = match self.platform(device)? {
+            Platform::Macos => (
+                self.output(device, "sw_vers -productName")?,
+                self.output(device, "sw_vers -productVersion")?,
+            ),
+            _ => (
+                os_release_name(&self.output(device, "cat /etc/os-release")?),
+                self.output(device, "uname -r")?,
+            ),
+        };
+        details.push(' ');
+        details.push_str(&version);
+        Ok(details)
+    }
+
+    /// Copies the AppImage or AppDir to the device and runs it. An AppDir is
+    /// passed as the path to its `AppRun`.
+    pub fn run(&self, device: &str, path: &Path, launch_args: &[String]) -> Result<()> {
+        let (upload, apprun) = if path.file_name() == Some("AppRun".as_ref()) {
+            (path.parent().context("invalid path")?, true)
+        } else {
+            (path, false)
+        };
+        let remote = self.upload(device, upload)?;
+        let executable = if apprun {
+            format!("{}/AppRun", remote)
+        } else {
+            remote
+        };
+        let mut command = quote(&executable);
+        for arg in launch_args {
+            command.push(' ');
+            command.push_str(&quote(arg));
+        }
+        let status = self
+            .ssh(device)
+            .arg("-t")
+            .arg(format!("chmod +x {} && {}", quote(&executable), command))
+            .status()?;
+        anyhow::ensure!(status.success(), "{} exited with {}", executable, status);
+        Ok(())
+    }
+
+    /// Starts `lldb-server` on the device and connects `lldb` to it through
+    /// forwarded ports. If the device doesn't provide an `lldb-server`, the
+    /// local `lldb_server` is copied over.
+    pub fn lldb(&self, device: &str, executable: &Path, lldb_server: Option<&Path>) -> Result<()> {
+        let lldb_server = if self.output(device, "command -v lldb-server").is_ok() {
+            "lldb-server".to_string()
+        } else {
+            let lldb_server =
+                lldb_server.with_context(|| format!("lldb-server not found on {}", device))?;
+            let remote = self.upload(device, lldb_server)?;
+            self.output(device, &format!("chmod +x {}", quote(&remote)))?;
+            remote
+        };
+        // Uploading the AppDir allows the executable to find its libraries.
+        let working_dir = match executable.parent() {
+            Some(dir) if dir.join("AppRun").exists() => self.upload(device, dir)?,
+            _ => REMOTE_DIR.to_string(),
+        };
+        let platform = match self.platform(device)? {
+            Platform::Macos => "remote-macosx",
+            _ => "remote-linux",
+        };
+        let mut server = self
+            .ssh(device)
+            .arg("-L")
+            .arg(format!("{0}:localhost:{0}", LLDB_PORT))
+            .arg("-L")
+            .arg(format!("{0}:localhost:{0}", GDBSERVER_PORT))
+            .arg(format!(
+                "{} platform --listen 'localhost:{}' --server --min-gdbserver-port {} --max-gdbserver-port {}",
+                quote(&lldb_server),
+                LLDB_PORT,
+                GDBSERVER_PORT,
+                GDBSERVER_PORT + 1,
+            ))
+            .stdin(Stdio::null())
+            .spawn()?;
+        std::thread::sleep(Duration::from_millis(1000));
+        let status = Command::new("lldb")
+            .arg("-O")
+            .arg(format!("platform select {}", platform))
+            .arg("-O")
+            .arg(format!(
+                "platform connect connect://localhost:{}",
+                LLDB_PORT
+            ))
+            .arg("-O")
+            .arg(format!(
+                "platform settings -w {}",
+                shlex::try_quote(&working_dir)?
+            ))
+            .arg(executable)
+            .status()?;
+        server.kill()?;
+        anyhow::ensure!(status.success(), "lldb exited with nonzero exit code.");
+        Ok(())
+    }
+}
+
+/// Quotes `arg` for the remote shell.
+fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+fn parse_platform(os: &str) -> Result<Platform> {
+    match os {
+        "Linux" => Ok(Platform::Linux),
+        "Darwin" => Ok(Platform::Macos),
+        os => anyhow::bail!("unsupported os {}", os),
+    }
+}
+
+fn parse_arch(arch: &str) -> Result<Arch> {
+    match arch {
+        "x86_64" | "amd64" => Ok(Arch::X64),
+        "aarch64" | "arm64" => Ok(Arch::Arm64),
+        arch => anyhow::bail!("unsupported arch {}", arch),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_platform("Linux").unwrap(), Platform::Linux);
+        assert_eq!(parse_platform("Darwin").unwrap(), Platform::Macos);
+        assert!(parse_platform("FreeBSD").is_err());
+        assert_eq!(parse_arch("x86_64").unwrap(), Arch::X64);
+        assert_eq!(parse_arch("arm64").unwrap(), Arch::Arm64);
+        assert!(parse_arch("riscv64").is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_quote() -> Result<()> {
+        for arg in ["simple", "with space", "it's", "$HOME `id` \\ \"", ""] {
+            let output = Command::new("sh")
+                .arg("-c")
+                .arg(format!("printf %s {}", quote(arg)))
+                .output()?;
+            assert!(output.status.success());
+            assert_eq!(std::str::from_utf8(&output.stdout)?, arg);
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{BuildEnv, Platform};
+use crate::{Arch, BuildEnv, Format, Platform};
 use anyhow::Result;
 use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
 use mvn::Download;
@@ -130,8 +130,15 @@ impl<'a> DownloadManager<'a> {
         }
 
         match self.env().target().platform() {
-            Platform::Linux if Platform::host()? != Platform::Linux => {
-                anyhow::bail!("cross compiling to linux is not yet supported");
+            Platform::Linux => {
+                for target in self.env().target().compile_targets() {
+                    if !target.is_host()? {
+                        self.linux_sysroot(target.arch())?;
+                    }
+                    if self.env().target().format() == Format::Appimage {
+                        self.appimage_runtime(target.arch())?;
+                    }
+                }
             }
             Platform::Windows if Platform::host()? != Platform::Windows => {
                 self.windows_sdk()?;
@@ -272,6 +279,27 @@ impl DownloadManager<'_> {
         self.fetch(item)
     }
 
+    pub fn linux_sysroot(&self, arch: Arch) -> Result<()> {
+        let output = self.env.linux_sysroot(arch);
+        let arch = match arch {
+            Arch::Arm64 => "aarch64",
+            Arch::X64 => "x86_64",
+        };
+        let mut item = WorkItem::xbuild_release(output, &format!("Linux-{}.sdk.tar.zst", arch));
+        if !cfg!(target_os = "linux") {
+            item.no_symlinks();
+        }
+        self.fetch(item)
+    }
+
+    pub fn appimage_runtime(&self, arch: Arch) -> Result<()> {
+        if let Some(output) = self.env.appimage_runtime(arch) {
+            let artifact = output.file_name().unwrap().to_str().unwrap().to_string();
+            self.fetch(WorkItem::xbuild_release(output, &artifact))?;
+        }
+        Ok(())
+    }
+
     pub fn android_ndk(&self) -> Result<()> {
         let output = self.env.android_ndk();
         let item = WorkItem::xbuild_release(output, "Android.ndk.tar.zst");
@@ -2,8 +2,10 @@ use crate::cargo::{Cargo, CargoBuild, CrateType};
 use crate::config::Config;
 use crate::devices::Device;
 use anyhow::{ensure, Result};
+use appbundle::{InfoPlist, SdkInfo};
 use cargo::FeatureSpecification;
 use clap::Parser;
+use msix::res::{ExeResources, Machine, VersionInfo};
 use std::path::{Path, PathBuf};
 use xcommon::Signer;
 
@@ -164,10 +166,15 @@ pub enum Format {
     Appbundle,
     Appdir,
     Appimage,
+    Deb,
     Dmg,
     Exe,
+    Flatpak,
     Ipa,
     Msix,
+    Msixbundle,
+    Pkg,
+    Rpm,
 }
 
 impl std::fmt::Display for Format {
@@ -178,10 +185,15 @@ impl std::fmt::Display for Format {
             Self::Appbundle => write!(f, "appbundle"),
             Self::Appdir => write!(f, "appdir"),
             Self::Appimage => write!(f, "appimage"),
+            Self::Deb => write!(f, "deb"),
             Self::Dmg => write!(f, "dmg"),
             Self::Exe => write!(f, "exe"),
+            Self::Flatpak => write!(f, "flatpak"),
             Self::Ipa => write!(f, "ipa"),
             Self::Msix => write!(f, "msix"),
+            Self::Msixbundle => write!(f, "msixbundle"),
+            Self::Pkg => write!(f, "pkg"),
+            Self::Rpm => write!(f, "rpm"),
         }
     }
 }
@@ -189,17 +201,23 @@ impl std::fmt::Display for Format {
 impl std::str::FromStr for Format {
     type Err = anyhow::Error;
 
-    fn from_str(arch: &str) -> Result<Self> {
-        Ok(match arch {
+    fn from_str(format: &str) -> Result<Self> {
+        Ok(match format {
             "aab" => Self::Aab,
             "apk" => Self::Apk,
             "appbundle" => Self::Appbundle,
             "appdir" => Self::Appdir,
             "appimage" => Self::Appimage,
+            "deb" => Self::Deb,
             "dmg" => Self::Dmg,
+            "exe" => Self::Exe,
+            "flatpak" => Self::Flatpak,
             "ipa" => Self::Ipa,
             "msix" => Self::Msix,
-            _ => anyhow::bail!("unsupported arch {}", arch),
+            "msixbundle" => Self::Msixbundle,
+            "pkg" => Self::Pkg,
+            "rpm" => Self::Rpm,
+            _ => anyhow::bail!("unsupported format {}", format),
         })
     }
 }
@@ -216,7 +234,7 @@ impl Format {
             (Platform::Macos, Opt::Debug) => Self::Appbundle,
             (Platform::Macos, _) => Self::Dmg,
             (Platform::Windows, Opt::Debug) => Self::Exe,
-            (Platform::Windows, _) => Self::Exe, // TODO: Msix
+            (Platform::Windows, _) => Self::Msix,
         }
     }
 
@@ -227,15 +245,20 @@ impl Format {
             Self::Appbundle => "app",
             Self::Appdir => "AppDir",
             Self::Appimage => "AppImage",
+            Self::Deb => "deb",
             Self::Dmg => "dmg",
             Self::Exe => "exe",
+            Self::Flatpak => "flatpak",
             Self::Ipa => "ipa",
             Self::Msix => "msix",
+            Self::Msixbundle => "msixbundle",
+            Self::Pkg => "pkg",
+            Self::Rpm => "rpm",
         }
     }
 
     pub fn supports_multiarch(self) -> bool {
-        matches!(self, Self::Aab | Self::Apk)
+        matches!(self, Self::Aab | Self::Apk | Self::Msixbundle)
     }
 }
 
@@ -328,6 +351,7 @@ impl CompileTarget {
             (Arch::X64, Platform::Android) => "x86_64-linux-android",
             (Arch::X64, Platform::Linux) => "x86_64-unknown-linux-gnu",
             (Arch::X64, Platform::Macos) => "x86_64-apple-darwin",
+            (Arch::Arm64, Platform::Windows) => "aarch64-pc-windows-msvc",
             (Arch::X64, Platform::Windows) => "x86_64-pc-windows-msvc",
             (arch, platform) => anyhow::bail!(
                 "unsupported arch/platform combination {} {}",
@@ -424,8 +448,8 @@ pub struct BuildTargetArgs {
     #[clap(long, conflicts_with = "store")]
     device: Option<String>,
     /// Build artifacts with format. Can be one of `aab`,
-    /// `apk`, `appbundle`, `appdir`, `appimage`, `dmg`,
-    /// `exe`, `ipa`, `msix`.
+    /// `apk`, `appbundle`, `appdir`, `appimage`, `deb`, `dmg`,
+    /// `exe`, `flatpak`, `ipa`, `msix`, `msixbundle`, `pkg`, `rpm`.
     #[clap(long, conflicts_with = "store")]
     format: Option<Format>,
     /// Build artifacts for target app store. Can be one of
@@ -436,6 +460,15 @@ pub struct BuildTargetArgs {
     /// used to sign artifacts.
     #[clap(long)]
     pem: Option<PathBuf>,
+    /// Path to a PEM encoded RSA2048 signing key and installer
+    /// certificate used to sign macOS installer packages.
+    #[clap(long)]
+    installer_pem: Option<PathBuf>,
+    /// Url of an RFC 3161 timestamp authority used to timestamp signatures.
+    /// Only supported by the `appbundle`, `dmg`, `exe`, `ipa`, `msix`,
+    /// `msixbundle` and `pkg` formats.
+    #[clap(long)]
+    timestamp_url: Option<String>,
     /// Path to an apple provisioning profile.
     #[clap(long)]
     provisioning_profile: Option<PathBuf>,
@@ -452,7 +485,7 @@ pub struct BuildTargetArgs {
 
 impl BuildTargetArgs {
     pub fn build_target(self, config: &Config) -> Result<BuildTarget> {
-        let signer = if let Some(pem) = self.pem.as_ref() {
+        let mut signer = if let Some(pem) = self.pem.as_ref() {
             anyhow::ensure!(pem.exists(), "pem file doesn't exist {}", pem.display());
             Some(Signer::from_path(pem)?)
         } else if let Ok(pem) = std::env::var("X_PEM") {
@@ -460,6 +493,19 @@ impl BuildTargetArgs {
         } else {
             None
         };
+        let mut installer_signer = if let Some(pem) = self.installer_pem.as_ref() {
+            anyhow::ensure!(pem.exists(), "pem file doesn't exist {}", pem.display());
+            Some(Signer::from_path(pem)?)
+        } else if let Ok(pem) = std::env::var("X_INSTALLER_PEM") {
+            Some(Signer::new(&pem)?)
+        } else {
+            None
+        };
+        if let Some(url) = self.timestamp_url.as_ref() {
+            for signer in signer.iter_mut().chain(installer_signer.iter_mut()) {
+                signer.set_timestamp_url(url.clone());
+            }
+        }
         let store = self.store;
         let device = if self.platform.is_none() && store.is_none() && self.device.is_none() {
             Some(Device::host())
@@ -487,8 +533,9 @@ impl BuildTargetArgs {
             vec![arch]
         } else if let Some(store) = store {
             match store {
+                Store::Apple if platform == Platform::Ios => vec![Arch::Arm64],
                 Store::Apple => vec![Arch::X64, Arch::Arm64],
-                Store::Microsoft => vec![Arch::X64],
+                Store::Microsoft => vec![Arch::X64, Arch::Arm64],
                 Store::Play => vec![Arch::Arm64],
                 Store::Sideload => anyhow::bail!("sideload store requires arch arg"),
             }
@@ -516,11 +563,31 @@ impl BuildTargetArgs {
             format
         } else if store == Some(Store::Play) {
             Format::Aab
+        } else if store == Some(Store::Apple) && platform == Platform::Macos {
+            Format::Pkg
+        } else if store == Some(Store::Microsoft) {
+            Format::Msixbundle
         } else {
             let user_wants_gradle = config.android().gradle.unwrap_or(false);
             Format::platform_default(platform, &opt, user_wants_gradle)
         };
 
+        ensure!(
+            self.timestamp_url.is_none()
+                || matches!(
+                    format,
+                    Format::Appbundle
+                        | Format::Dmg
+                        | Format::Exe
+                        | Format::Ipa
+                        | Format::Msix
+                        | Format::Msixbundle
+                        | Format::Pkg
+                ),
+            "`--timestamp-url` isn't supported for {} artifacts",
+            format
+        );
+
         let android_gradle = config.android().gradle.unwrap_or(format == Format::Aab);
 
         ensure!(
@@ -551,6 +618,7 @@ impl BuildTargetArgs {
             device,
             store,
             signer,
+            installer_signer,
             provisioning_profile,
             api_key,
             android_gradle,
@@ -568,6 +636,7 @@ pub struct BuildTarget {
     device: Option<Device>,
     store: Option<Store>,
     signer: Option<Signer>,
+    installer_signer: Option<Signer>,
     provisioning_profile: Option<Vec<u8>>,
     api_key: Option<PathBuf>,
     android_gradle: bool,
@@ -616,6 +685,10 @@ impl BuildTarget {
         self.signer.as_ref()
     }
 
+    pub fn installer_signer(&self) -> Option<&Signer> {
+        self.installer_signer.as_ref()
+    }
+
     pub fn provisioning_profile(&self) -> Option<&[u8]> {
         self.provisioning_profile.as_deref()
     }
@@ -766,6 +839,24 @@ impl BuildEnv {
         self.cache_dir().join("MacOSX.sdk")
     }
 
+    /// Glibc sysroot used when cross compiling to linux.
+    pub fn linux_sysroot(&self, arch: Arch) -> PathBuf {
+        let arch = match arch {
+            Arch::Arm64 => "aarch64",
+            Arch::X64 => "x86_64",
+        };
+        self.cache_dir().join(format!("Linux-{}.sdk", arch))
+    }
+
+    /// AppImage runtime for `arch`, the x86_64 runtime is embedded in the
+    /// `appimage` crate.
+    pub fn appimage_runtime(&self, arch: Arch) -> Option<PathBuf> {
+        match arch {
+            Arch::Arm64 => Some(self.cache_dir().join("runtime-aarch64")),
+            Arch::X64 => None,
+        }
+    }
+
     pub fn android_sdk(&self) -> PathBuf {
         self.cache_dir().join("Android.sdk")
     }
@@ -780,6 +871,77 @@ impl BuildEnv {
         self.cache_dir().join("iPhoneOS.sdk")
     }
 
+    /// Returns the apple sdk for `platform`. Prefers the downloaded sdk and
+    /// falls back to the sdk of the selected Xcode on macos hosts.
+    pub fn apple_sdk(&self, platform: Platform) -> Option<PathBuf> {
+        let (sdk, name) = match platform {
+            Platform::Ios => (self.ios_sdk(), "iphoneos"),
+            Platform::Macos => (self.macos_sdk(), "macosx"),
+            _ => return None,
+        };
+        if sdk.exists() {
+            return Some(sdk);
+        }
+        if !cfg!(target_os = "macos") {
+            return None;
+        }
+        let output = std::process::Command::new("xcrun")
+            .arg("--sdk")
+            .arg(name)
+            .arg("--show-sdk-path")
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        let sdk = PathBuf::from(std::str::from_utf8(&output.stdout).ok()?.trim());
+        Some(sdk).filter(|sdk| sdk.exists())
+    }
+
+    /// Returns the `Info.plist` of `platform` with the build metadata
+    /// of the sdk applied.
+    pub fn info_plist(&self, platform: Platform) -> Result<InfoPlist> {
+        let mut info = match platform {
+            Platform::Ios => self.config().ios().info.clone(),
+            Platform::Macos => self.config().macos().info.clone(),
+            _ => anyhow::bail!("{} doesn't use an Info.plist", platform),
+        };
+        let sdk = if let Some(path) = self.apple_sdk(platform) {
+            let sdk = SdkInfo::from_path(&path)?;
+            anyhow::ensure!(
+                sdk.xcode.is_some()
+                    || info.dt_xcode.is_some()
+                    || self.target().store() != Some(Store::Apple),
+                "{} sdk at `{}` doesn't record its xcode version, which is required for the \
+                 DTXcode Info.plist keys of app store builds, remove it and build without \
+                 `--offline` to download it again",
+                platform,
+                path.display()
+            );
+            sdk
+        } else {
+            anyhow::ensure!(
+                self.target().store() != Some(Store::Apple),
+                "{} sdk not found, it is required for the DT* Info.plist keys of app store \
+                 builds, build without `--offline` to download it",
+                platform
+            );
+            let name = if platform == Platform::Ios {
+                "iphoneos"
+            } else {
+                "macosx"
+            };
+            log::warn!(
+                "{} sdk not found, using the build metadata of the {} sdk from xcode 13.2.1",
+                platform,
+                name
+            );
+            SdkInfo::fallback(name).unwrap()
+        };
+        sdk.apply(&mut info);
+        Ok(info)
+    }
+
     pub fn developer_disk_image(&self, major: u32, minor: u32) -> PathBuf {
         self.cache_dir()
             .join("iPhoneOS.platform")
@@ -805,8 +967,20 @@ impl BuildEnv {
     pub fn cargo_build(&self, target: CompileTarget, target_dir: &Path) -> Result<CargoBuild> {
         let mut cargo = self.cargo.build(target.clone(), target_dir)?;
         if target.platform() == Platform::Linux {
+            if !target.is_host()? {
+                let sysroot = self.linux_sysroot(target.arch());
+                anyhow::ensure!(
+                    sysroot.exists(),
+                    "Linux sysroot not found at `{}`, build without `--offline` to download it",
+                    sysroot.display()
+                );
+                cargo.use_linux_sysroot(&sysroot)?;
+            }
             cargo.add_link_arg("-Wl,-rpath");
             cargo.add_link_arg("-Wl,$ORIGIN/lib");
+            // DT_RPATH unlike DT_RUNPATH is also used for the dependencies
+            // of bundled libraries.
+            cargo.add_link_arg("-Wl,--disable-new-dtags");
         }
         if target.platform() == Platform::Android {
             let ndk = self.android_ndk();
@@ -822,21 +996,34 @@ impl BuildEnv {
         }
         if target.platform() == Platform::Windows {
             let sdk = self.windows_sdk();
-            dbg!(&sdk, sdk.exists());
             if sdk.exists() {
                 cargo.use_windows_sdk(&sdk)?;
+            } else {
+                // msvc is used when building on windows
+                ensure!(
+                    Platform::host()? == Platform::Windows,
+                    "Windows SDK not found at `{}`, build without `--offline` to download it",
+                    sdk.display()
+                );
+            }
+            let res = target_dir.join("resources.obj");
+            std::fs::create_dir_all(target_dir)?;
+            let machine = match target.arch() {
+                Arch::X64 => Machine::X64,
+                Arch::Arm64 => Machine::Arm64,
+            };
+            self.windows_resources()?.write(&res, machine)?;
+            // the resources describe the executable, a dll needs its own manifest
+            let bins = self.cargo().manifest().bins(self.root_dir());
+            if bins.iter().any(|bin| bin == self.name()) {
+                cargo.add_bin_link_arg(self.name(), res.to_str().unwrap());
             }
         }
         if target.platform() == Platform::Macos {
             let sdk = self.macos_sdk();
             if sdk.exists() {
-                let minimum_version = self
-                    .config()
-                    .macos()
-                    .info
-                    .ls_minimum_system_version
-                    .as_ref()
-                    .unwrap();
+                let info = self.info_plist(Platform::Macos)?;
+                let minimum_version = info.ls_minimum_system_version.as_ref().unwrap();
                 cargo.use_macos_sdk(&sdk, minimum_version)?;
             } else {
                 cargo.add_link_arg("-rpath");
@@ -846,19 +1033,45 @@ impl BuildEnv {
         if target.platform() == Platform::Ios {
             let sdk = self.ios_sdk();
             if sdk.exists() {
-                let minimum_version = self
-                    .config()
-                    .ios()
-                    .info
-                    .minimum_os_version
-                    .as_ref()
-                    .unwrap();
+                let info = self.info_plist(Platform::Ios)?;
+                let minimum_version = info.minimum_os_version.as_ref().unwrap();
                 cargo.use_ios_sdk(&sdk, minimum_version)?;
             }
         }
         Ok(cargo)
     }
 
+    /// Icon, version info and application manifest embedded in windows
+    /// executables.
+    fn windows_resources(&self) -> Result<ExeResources> {
+        let windows = self.config().windows();
+        let properties = &windows.manifest.properties;
+        let mut resources = ExeResources::default();
+        if let Some(icon) = self.icon() {
+            resources.add_icon(icon)?;
+        }
+        let mut version_info = VersionInfo::new(&windows.manifest.identity.version);
+        let product_name = if properties.display_name.is_empty() {
+            self.name()
+        } else {
+            &properties.display_name
+        };
+        version_info.add_string("ProductName", product_name);
+        // shown as the process name in the task manager
+        version_info.add_string("FileDescription", product_name);
+        version_info.add_string("InternalName", self.name());
+        version_info.add_string("OriginalFilename", &format!("{}.exe", self.name()));
+        if !properties.publisher_display_name.is_empty() {
+            version_info.add_string("CompanyName", &properties.publisher_display_name);
+        }
+        if let Some(description) = properties.description.as_ref() {
+            version_info.add_string("Comments", description);
+        }
+        resources.set_version_info(version_info);
+        resources.set_manifest(windows.exe_manifest.clone());
+        Ok(resources)
+    }
+
     pub fn cargo_artefact(
         &self,
         target_dir: &Path,
@@ -868,3 +1081,32 @@ impl BuildEnv {
         self.cargo.artifact(target_dir, target, None, crate_type)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_from_str() -> Result<()> {
+        for format in [
+            Format::Aab,
+            Format::Apk,
+            Format::Appbundle,
+            Format::Appdir,
+            Format::Appimage,
+            Format::Deb,
+            Format::Dmg,
+            Format::Exe,
+            Format::Flatpak,
+            Format::Ipa,
+            Format::Msix,
+            Format::Msixbundle,
+            Format::Pkg,
+            Format::Rpm,
+        ] {
+            assert_eq!(format.to_string().parse::<Format>()?, format);
+        }
+        assert!("zip".parse::<Format>().is_err());
+        Ok(())
+    }
+}
@@ -62,6 +62,17 @@ enum Commands {
         #[clap(flatten)]
         args: BuildArgs,
     },
+    /// Build an app and upload it to the app store given by `--store`
+    Publish {
+        #[clap(flatten)]
+        args: BuildArgs,
+    },
+    /// Print the contents of a resource file, like `makepri dump` for `.pri` files,
+    /// or verify the signature of an msix package or windows executable
+    Inspect {
+        /// Path to the file
+        path: PathBuf,
+    },
     /// Generates a PEM encoded RSA2048 signing key
     GenerateKey {
         /// Path to unified api key.
@@ -124,6 +135,12 @@ impl Commands {
                 command::build(&env)?;
                 command::lldb(&env)?;
             }
+            Self::Publish { args } => {
+                let env = BuildEnv::new(args)?;
+                command::build(&env)?;
+                command::publish(&env)?;
+            }
+            Self::Inspect { path } => command::inspect(&path)?,
             Self::GenerateKey {
                 api_key,
                 r#type,
@@ -146,6 +146,7 @@ pub struct Signer {
     key: RsaPrivateKey,
     pubkey: RsaPublicKey,
     cert: Certificate,
+    timestamp_url: Option<String>,
 }
 
 impl Signer {
@@ -171,7 +172,12 @@ impl Signer {
             anyhow::bail!("no certificate found");
         };
         let pubkey = RsaPublicKey::from(&key);
-        Ok(Self { key, pubkey, cert })
+        Ok(Self {
+            key,
+            pubkey,
+            cert,
+            timestamp_url: None,
+        })
     }
 
     pub fn from_path(path: &Path) -> Result<Self> {
@@ -195,6 +201,58 @@ impl Signer {
     pub fn cert(&self) -> &Certificate {
         &self.cert
     }
+
+    /// Returns the string attributes of the certificate subject in the
+    /// order they appear in the certificate, as pairs of their short name
+    /// and value, for example `("CN", "Example")`.
+    pub fn subject(&self) -> Vec<(&'static str, String)> {
+        let rasn_pkix::Name::RdnSequence(rdns) = &self.cert.tbs_certificate.subject;
+        rdns.iter()
+            .flatten()
+            .filter_map(|attr| {
+                let name = match attr.r#type.as_ref() {
+                    [2, 5, 4, 3] => "CN",
+                    [2, 5, 4, 6] => "C",
+                    [2, 5, 4, 7] => "L",
+                    [2, 5, 4, 8] => "S",
+                    [2, 5, 4, 10] => "O",
+                    [2, 5, 4, 11] => "OU",
+                    [1, 2, 840, 113549, 1, 9, 1] => "E",
+                    _ => return None,
+                };
+                Some((name, der_string(attr.value.as_bytes())?))
+            })
+            .collect()
+    }
+
+    /// Sets the url of an RFC 3161 timestamp authority used to timestamp
+    /// signatures, so that they remain valid after the certificate expires.
+    pub fn set_timestamp_url(&mut self, url: String) {
+        self.timestamp_url = Some(url);
+    }
+
+    pub fn timestamp_url(&self) -> Option<&str> {
+        self.timestamp_url.as_deref()
+    }
+}
+
+/// Decodes a DER encoded UTF8String, PrintableString, IA5String or
+/// TeletexString.
+fn der_string(der: &[u8]) -> Option<String> {
+    let (tag, rest) = der.split_first()?;
+    if ![0x0c, 0x13, 0x14, 0x16].contains(tag) {
+        return None;
+    }
+    let (len, rest) = match rest.split_first()? {
+        (len, rest) if *len < 0x80 => (*len as usize, rest),
+        (0x81, rest) => (*rest.first()? as usize, rest.get(1..)?),
+        (0x82, rest) => (
+            u16::from_be_bytes([*rest.first()?, *rest.get(1)?]) as usize,
+            rest.get(2..)?,
+        ),
+        _ => return None,
+    };
+    String::from_utf8(rest.get(..len)?.to_vec()).ok()
 }
 
 impl std::fmt::Debug for Signer {
@@ -202,6 +260,7 @@ impl std::fmt::Debug for Signer {
         f.debug_struct("Signer")
             .field("pubkey", &self.pubkey)
             .field("cert", &self.cert)
+            .field("timestamp_url", &self.timestamp_url)
             .finish_non_exhaustive()
     }
 }
@@ -492,4 +551,21 @@ mod tests {
     fn create_signer() {
         Signer::new(PEM).unwrap();
     }
+
+    #[test]
+    fn test_subject() -> Result<()> {
+        let signer = Signer::new(PEM)?;
+        assert_eq!(
+            signer.subject(),
+            [
+                ("C", "US".to_string()),
+                ("S", "Some-State".to_string()),
+                ("O", "Internet Widgits Pty Ltd".to_string()),
+            ]
+        );
+        assert_eq!(der_string(b"\x0c\x02ab").as_deref(), Some("ab"));
+        assert_eq!(der_string(b"\x0c\x03ab"), None);
+        assert_eq!(der_string(b"\x04\x01a"), None);
+        Ok(())
+    }
 }
@@ -16,6 +16,27 @@ pub fn list_needed_libs_recursively(
     lib: &Path,
     search_paths: &[&Path],
     provided_libs: &HashSet<OsString>,
+) -> Result<(HashSet<PathBuf>, bool)> {
+    needed_libs_recursively(lib, search_paths, provided_libs, find_library_path)
+}
+
+/// Like [`list_needed_libs_recursively`], but the returned libraries keep
+/// the file name they are needed by instead of the name symlinks resolve
+/// to. The Linux dynamic loader looks libraries up by their soname, which
+/// usually is a symlink to a versioned file.
+pub fn list_needed_linux_libs_recursively(
+    lib: &Path,
+    search_paths: &[&Path],
+    provided_libs: &HashSet<OsString>,
+) -> Result<(HashSet<PathBuf>, bool)> {
+    needed_libs_recursively(lib, search_paths, provided_libs, find_library_soname_path)
+}
+
+fn needed_libs_recursively(
+    lib: &Path,
+    search_paths: &[&Path],
+    provided_libs: &HashSet<OsString>,
+    find_library: fn(&[&Path], &str) -> Result<Option<PathBuf>>,
 ) -> Result<(HashSet<PathBuf>, bool)> {
     let mut to_copy = HashSet::new();
     let mut needs_cpp_shared = false;
@@ -33,7 +54,7 @@ pub fn list_needed_libs_recursively(
                 //  we need to copy it, once
                 needs_cpp_shared = true;
             } else if !provided_libs.contains(OsStr::new(&need)) {
-                if let Some(path) = find_library_path(search_paths, &need).with_context(|| {
+                if let Some(path) = find_library(search_paths, &need).with_context(|| {
                     format!(
                         "Could not iterate one or more search directories in `{:?}` while searching for library `{}`",
                         search_paths, need
@@ -101,3 +122,40 @@ fn find_library_path(paths: &[&Path], library: &str) -> Result<Option<PathBuf>>
     }
     Ok(None)
 }
+
+/// Resolves native library using search paths, keeping the file name so
+/// that symlinked sonames aren't resolved to the versioned library.
+fn find_library_soname_path(paths: &[&Path], library: &str) -> Result<Option<PathBuf>> {
+    for path in paths {
+        let lib_path = path.join(library);
+        if lib_path.exists() {
+            return Ok(Some(dunce::canonicalize(path)?.join(library)));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_find_library_soname_path() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = dunce::canonicalize(tmp.path())?;
+        std::fs::write(dir.join("libfoo.so.1.2.3"), b"\x7fELF")?;
+        std::os::unix::fs::symlink("libfoo.so.1.2.3", dir.join("libfoo.so.1"))?;
+        let paths = [dir.as_path()];
+        assert_eq!(
+            find_library_soname_path(&paths, "libfoo.so.1")?,
+            Some(dir.join("libfoo.so.1"))
+        );
+        assert_eq!(
+            find_library_path(&paths, "libfoo.so.1")?,
+            Some(dir.join("libfoo.so.1.2.3"))
+        );
+        assert_eq!(find_library_soname_path(&paths, "libbar.so")?, None);
+        Ok(())
+    }
+}